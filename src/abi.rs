@@ -0,0 +1,84 @@
+// Detect soname (ABI) bumps between two snapshots
+
+use std::path::Path;
+
+use crate::libs;
+use crate::ownership;
+use crate::package_diff::PackageChange;
+
+/// A changed package whose shared-library sonames changed
+#[derive(Debug, Clone)]
+pub struct AbiBreak {
+    pub package: String,
+    /// Sonames that existed in the good snapshot and are gone in the bad one
+    pub removed: Vec<String>,
+    /// Sonames of the same libraries that replaced them
+    pub added: Vec<String>,
+    /// Dependents that did not change between the snapshots (not rebuilt)
+    pub stale_dependents: Vec<String>,
+}
+
+/// Compare the sonames provided by each changed package in both roots
+pub fn detect(good_root: &str, bad_root: &str, changes: &[PackageChange]) -> Vec<AbiBreak> {
+    let changed: Vec<&str> = changes.iter().map(|c| c.name()).collect();
+    let mut breaks = Vec::new();
+
+    for change in changes {
+        // Added packages can't break anything that linked against them before
+        if matches!(change, PackageChange::Added(_)) {
+            continue;
+        }
+
+        let package = change.name();
+        let old_sonames = sonames_in(good_root, package);
+        if old_sonames.is_empty() {
+            continue;
+        }
+
+        let removed: Vec<String> = old_sonames
+            .into_iter()
+            .filter(|s| libs::find_in(bad_root, s).is_none())
+            .collect();
+        if removed.is_empty() {
+            continue;
+        }
+
+        let mut added = Vec::new();
+        for soname in &removed {
+            for sibling in libs::sibling_sonames(bad_root, soname) {
+                if !added.contains(&sibling) {
+                    added.push(sibling);
+                }
+            }
+        }
+
+        // Dependents from the good snapshot that were left untouched still expect the old soname
+        let stale_dependents = ownership::dependents_in(good_root, package, &removed)
+            .into_iter()
+            .filter(|d| !changed.contains(&d.as_str()))
+            .collect();
+
+        breaks.push(AbiBreak {
+            package: package.to_string(),
+            removed,
+            added,
+            stale_dependents,
+        });
+    }
+
+    breaks
+}
+
+/// Versioned sonames shipped by `package` inside `root`
+pub fn sonames_in(root: &str, package: &str) -> Vec<String> {
+    let mut sonames: Vec<String> = ownership::package_files_in(root, package)
+        .iter()
+        .filter_map(|f| Path::new(f).file_name())
+        .map(|n| n.to_string_lossy().to_string())
+        .filter(|n| libs::is_soname(n))
+        .collect();
+
+    sonames.sort();
+    sonames.dedup();
+    sonames
+}
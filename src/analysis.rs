@@ -0,0 +1,264 @@
+// AI-assisted culprit analysis (Eshu Premium)
+//
+// After a bisect, the culprit's changelog since the last good version, the bug references
+// in it, community reports and the user's symptom go to an OpenAI-compatible chat
+// completions endpoint (`ai_endpoint` in config.json; a local Ollama or llama.cpp server
+// works), which answers with a plain-language explanation and workarounds. Nothing leaves
+// the machine with `--no-ai`, `"ai_analysis": false`, or without an endpoint, and unless
+// `ai_analysis` is true the user sees what would be sent and is asked first.
+
+use anyhow::{Context, Result};
+use colored::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::bisect;
+use crate::config::{self, Config};
+use crate::exec::CommandExt;
+use crate::http;
+use crate::package_diff::PackageChange;
+use crate::premium::{Feature, FeatureGate};
+use crate::priors;
+
+const DEFAULT_MODEL: &str = "llama3.1";
+const DEFAULT_KEY_ENV: &str = "ESHU_TRACE_AI_KEY";
+
+/// Changelog lines sent at most; the newest entries come first in every format
+const MAX_CHANGELOG_LINES: usize = 80;
+
+/// Local models can take a while to answer
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+const INSTRUCTIONS: &str = "You help a Linux user whose system broke after a package update. \
+A binary search over their package changes identified the culprit below. Using the changelog, \
+bug references and symptom, explain in plain language what most likely broke and why, then \
+suggest workarounds short of downgrading (configuration changes, kernel parameters, alternative \
+packages), most promising first. Say so when the evidence is thin. Answer only with JSON: \
+{\"explanation\": \"...\", \"workarounds\": [\"...\"]}";
+
+/// Everything the analysis is based on; shown to the user before it is sent
+#[derive(Debug, Serialize)]
+pub struct Evidence {
+    pub change: String,
+    pub distro: String,
+    pub symptom: Option<String>,
+    pub changelog: Vec<String>,
+    /// Bug and CVE references from the changelog
+    pub bugs: Vec<String>,
+    /// Times the package was reported as a culprit
+    pub community_reports: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Analysis {
+    pub explanation: String,
+    #[serde(default)]
+    pub workarounds: Vec<String>,
+}
+
+/// Offer the analysis for a culprit found on the system at `root`; failures only warn
+pub fn offer(culprit: &PackageChange, root: &str, distro: &str, symptom: Option<&str>, gate: &dyn FeatureGate) {
+    if let Err(e) = try_offer(culprit, root, distro, symptom, gate) {
+        println!("{} AI analysis skipped: {:#}", "⚠".yellow(), e);
+    }
+}
+
+fn try_offer(culprit: &PackageChange, root: &str, distro: &str, symptom: Option<&str>, gate: &dyn FeatureGate) -> Result<()> {
+    let config = config::load()?;
+    if config.ai_analysis == Some(false) || !http::available() {
+        return Ok(());
+    }
+    if !gate.is_enabled(Feature::AiAnalysis) {
+        println!("{}", "💡 Eshu Premium explains culprits in plain language and suggests workarounds".dimmed());
+        return Ok(());
+    }
+    let Some(endpoint) = endpoint(&config) else {
+        println!("{}", "💡 Set ai_endpoint in config.json for a plain-language analysis of the culprit".dimmed());
+        return Ok(());
+    };
+
+    let evidence = gather(culprit, root, distro, symptom);
+    if config.ai_analysis != Some(true) {
+        println!();
+        println!("{} AI analysis would send to {}:", "🤖".bold(), endpoint.cyan());
+        println!("  • {} on {}", evidence.change, evidence.distro);
+        if let Some(symptom) = &evidence.symptom {
+            println!("  • Symptom: {}", symptom);
+        }
+        println!("  • {} changelog lines, {} bug references", evidence.changelog.len(), evidence.bugs.len());
+        let send = dialoguer::Confirm::new()
+            .with_prompt("Ask for an analysis?")
+            .default(false)
+            .interact()?;
+        if !send {
+            return Ok(());
+        }
+    }
+
+    println!("{} Analyzing {}...", "🤖".bold(), culprit.name());
+    match analyze(&evidence, &endpoint, &config) {
+        Ok(analysis) => print(&analysis),
+        Err(e) => println!("{} AI analysis failed: {:#}", "⚠".yellow(), e),
+    }
+    Ok(())
+}
+
+/// AI endpoint from ESHU_TRACE_AI_ENDPOINT, falling back to the config file
+fn endpoint(config: &Config) -> Option<String> {
+    std::env::var("ESHU_TRACE_AI_ENDPOINT")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .or_else(|| config.ai_endpoint.clone())
+}
+
+/// Collect the evidence for `culprit` from the package database under `root`
+pub fn gather(culprit: &PackageChange, root: &str, distro: &str, symptom: Option<&str>) -> Evidence {
+    let since = match culprit {
+        PackageChange::Upgraded(_, old, _) | PackageChange::Downgraded(_, old, _) => Some(old.as_str()),
+        PackageChange::Added(_) | PackageChange::Removed(_) => None,
+    };
+    let changelog = changelog(root, culprit.name())
+        .map(|text| entries_since(&text, since))
+        .unwrap_or_default();
+
+    Evidence {
+        change: bisect::describe(culprit),
+        distro: distro.to_string(),
+        symptom: symptom.map(str::trim).filter(|s| !s.is_empty()).map(str::to_string),
+        bugs: bug_references(&changelog),
+        community_reports: priors::community_reports().get(culprit.name()).copied().unwrap_or(0),
+        changelog,
+    }
+}
+
+/// Send the evidence to the endpoint and parse its answer
+pub fn analyze(evidence: &Evidence, endpoint: &str, config: &Config) -> Result<Analysis> {
+    let request = json!({
+        "model": config.ai_model.as_deref().unwrap_or(DEFAULT_MODEL),
+        "temperature": 0.2,
+        "messages": [
+            { "role": "system", "content": INSTRUCTIONS },
+            { "role": "user", "content": serde_json::to_string_pretty(evidence)? },
+        ],
+    });
+
+    // Not retried: a slow model would keep the user waiting several timeouts long
+    let key_env = config.ai_api_key_env.as_deref().unwrap_or(DEFAULT_KEY_ENV);
+    let key = std::env::var(key_env).ok().filter(|key| !key.is_empty());
+    let response = http::post_json_once(endpoint, &request, key.as_deref(), TIMEOUT)?;
+    if !response.is_success() {
+        anyhow::bail!("{} answered HTTP {}", endpoint, response.status);
+    }
+
+    let body: serde_json::Value = response.json().context("AI endpoint did not answer with JSON")?;
+    let content = body["choices"][0]["message"]["content"]
+        .as_str()
+        .context("AI endpoint answer has no choices[0].message.content")?;
+    Ok(parse_answer(content))
+}
+
+/// The model's JSON answer, possibly in a code fence; free text becomes the explanation
+fn parse_answer(content: &str) -> Analysis {
+    let trimmed = content.trim();
+    let unfenced = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.strip_suffix("```"))
+        .unwrap_or(trimmed);
+
+    serde_json::from_str(unfenced.trim()).unwrap_or_else(|_| Analysis {
+        explanation: trimmed.to_string(),
+        workarounds: Vec::new(),
+    })
+}
+
+fn print(analysis: &Analysis) {
+    println!();
+    println!("{}", "What probably happened:".cyan().bold());
+    for line in analysis.explanation.lines() {
+        println!("  {}", line);
+    }
+    if !analysis.workarounds.is_empty() {
+        println!();
+        println!("{}", "Workarounds to try:".cyan().bold());
+        for (i, workaround) in analysis.workarounds.iter().enumerate() {
+            println!("  {}. {}", i + 1, workaround);
+        }
+    }
+    println!("{}", "   AI-generated; check before acting on it.".dimmed());
+    println!();
+}
+
+/// The package's changelog, newest entries first, from whichever package database `root` has
+fn changelog(root: &str, package: &str) -> Option<String> {
+    let root_path = Path::new(root);
+
+    // pacman keeps the changelog next to desc, for packages that ship one
+    if let Ok(entries) = fs::read_dir(root_path.join("var/lib/pacman/local")) {
+        return entries.flatten().find_map(|entry| {
+            let desc = fs::read_to_string(entry.path().join("desc")).ok()?;
+            let name = desc.lines().skip_while(|l| *l != "%NAME%").nth(1)?;
+            if name != package {
+                return None;
+            }
+            fs::read_to_string(entry.path().join("changelog")).ok()
+        });
+    }
+
+    if root_path.join("var/lib/dpkg/status").exists() {
+        let doc = root_path.join("usr/share/doc").join(package);
+        let gz = ["changelog.Debian.gz", "changelog.gz"].iter().map(|f| doc.join(f)).find(|f| f.is_file())?;
+        return command_text(Command::new("zcat").arg(gz));
+    }
+
+    if root_path.join("var/lib/rpm").exists() || root_path.join("usr/lib/sysimage/rpm").exists() {
+        return command_text(Command::new("rpm").arg("--root").arg(root).args(["-q", "--changelog", package]));
+    }
+
+    None
+}
+
+fn command_text(cmd: &mut Command) -> Option<String> {
+    let output = cmd.run_output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Changelog lines up to the first entry that mentions the version it changed from
+fn entries_since(changelog: &str, since: Option<&str>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (i, line) in changelog.lines().enumerate() {
+        if i > 0 && since.is_some_and(|old| line.contains(old)) {
+            break;
+        }
+        if lines.len() == MAX_CHANGELOG_LINES {
+            break;
+        }
+        lines.push(line.to_string());
+    }
+    lines
+}
+
+/// Bug tracker and CVE references ("Closes: #123", "LP: #123", rhbz#, bsc#, boo#, FS#), in
+/// order of first mention. Bare "#123" is left out; upstream changelogs use it for anything.
+fn bug_references(changelog: &[String]) -> Vec<String> {
+    let pattern = Regex::new(r"(?i)\b(?:closes:\s*#\d+(?:,\s*#\d+)*|lp:\s*#\d+|rhbz\s*#\d+|bsc\s*#\d+|boo\s*#\d+|fs#\d+|cve-\d{4}-\d+)")
+        .expect("valid regex");
+
+    let mut references: Vec<String> = Vec::new();
+    for line in changelog {
+        for found in pattern.find_iter(line) {
+            if !references.iter().any(|r| r == found.as_str()) {
+                references.push(found.as_str().to_string());
+            }
+        }
+    }
+    references
+}
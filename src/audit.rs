@@ -0,0 +1,107 @@
+// Append-only record of every command that modifies the system
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+
+use crate::exec;
+use crate::paths;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: String,
+    /// What eshu-trace was doing (e.g. "fix: downgrade mesa")
+    pub action: String,
+    pub command: String,
+    /// None when the process was killed by a signal
+    pub exit_code: Option<i32>,
+    /// SHA-256 of stdout followed by stderr; empty when the output went to the terminal
+    pub output_sha256: String,
+    pub user: String,
+}
+
+pub fn log_path() -> PathBuf {
+    paths::state_file("audit.log")
+}
+
+/// Run `cmd`, passing its output through to the terminal, and record it in the audit log
+/// (with `--dry-run` it is only printed, and not recorded)
+pub fn run(action: &str, cmd: &mut Command) -> Result<ExitStatus> {
+    let runner = exec::runner();
+    let (status, captured) = runner
+        .modify(cmd)
+        .with_context(|| format!("Failed to run {}", exec::command_line(cmd)))?;
+    if !runner.applies_changes() {
+        return Ok(status);
+    }
+
+    record(&entry(action, cmd, status, format!("{:x}", Sha256::digest(&captured))))?;
+    Ok(status)
+}
+
+/// Like [`run`], but with the terminal attached for commands that prompt (a nested
+/// interactive session). The output is not captured, so no hash is recorded.
+pub fn run_interactive(action: &str, cmd: &mut Command) -> Result<ExitStatus> {
+    let runner = exec::runner();
+    if !runner.applies_changes() {
+        return Ok(runner
+            .modify(cmd)
+            .with_context(|| format!("Failed to run {}", exec::command_line(cmd)))?
+            .0);
+    }
+
+    let status = runner
+        .status(cmd)
+        .with_context(|| format!("Failed to run {}", exec::command_line(cmd)))?;
+    record(&entry(action, cmd, status, String::new()))?;
+    Ok(status)
+}
+
+fn entry(action: &str, cmd: &Command, status: ExitStatus, output_sha256: String) -> AuditEntry {
+    AuditEntry {
+        time: chrono::Local::now().to_rfc3339(),
+        action: action.to_string(),
+        command: exec::command_line(cmd),
+        exit_code: status.code(),
+        output_sha256,
+        user: std::env::var("SUDO_USER")
+            .or_else(|_| std::env::var("USER"))
+            .unwrap_or_else(|_| "unknown".to_string()),
+    }
+}
+
+/// Entries in the order they were written
+pub fn entries() -> Result<Vec<AuditEntry>> {
+    let file = match fs::File::open(log_path()) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("Failed to open audit log"),
+    };
+
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect())
+}
+
+fn record(entry: &AuditEntry) -> Result<()> {
+    let path = log_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Opened in append mode only, so existing entries are never rewritten
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context("Failed to open audit log")?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+// Rebuilding AUR packages that link against a downgraded library
+//
+// Foreign packages (AUR, makepkg) are built against whatever library version was
+// installed at the time. After the culprit library goes back, the ones built against the
+// broken release can still fail to load or misbehave, so the fix isn't complete until
+// they are rebuilt. An AUR helper (paru, yay) rebuilds them in place; without one, the
+// devtools clean chroot builds them with the downgraded packages installed into it, so
+// the result links against the versions that are actually on the system.
+
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::abi;
+use crate::audit;
+use crate::origin;
+use crate::ownership;
+use crate::test_runner::which;
+
+const AUR_GIT: &str = "https://aur.archlinux.org";
+const PACMAN_CACHE: &str = "/var/cache/pacman/pkg";
+
+/// How the rebuild will run
+pub enum Builder {
+    /// paru or yay
+    Helper(&'static str),
+    /// devtools' clean chroot (extra-x86_64-build)
+    CleanChroot,
+}
+
+impl Builder {
+    pub fn detect() -> Option<Self> {
+        if let Some(helper) = ["paru", "yay"].into_iter().find(|h| which(h)) {
+            return Some(Builder::Helper(helper));
+        }
+        (which("extra-x86_64-build") && which("git")).then_some(Builder::CleanChroot)
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Builder::Helper(helper) => helper.to_string(),
+            Builder::CleanChroot => "makepkg in a clean chroot".to_string(),
+        }
+    }
+}
+
+/// Foreign packages inside `root` that depend on one of `libraries` or link against its
+/// sonames, and were built after the installed release of it arrived. Read before the
+/// downgrade, while the database still describes the broken release.
+pub fn affected(root: &str, libraries: &[String]) -> Vec<String> {
+    let foreign = origin::foreign_packages(root);
+    if foreign.is_empty() {
+        return Vec::new();
+    }
+
+    let mut affected = Vec::new();
+    for library in libraries {
+        let sonames = abi::sonames_in(root, library);
+        // Only libraries: a foreign package that merely calls a program is not linked to it
+        if sonames.is_empty() {
+            continue;
+        }
+        let installed_at = desc_time(root, library, "%INSTALLDATE%");
+        for dependent in ownership::dependents_in(root, library, &sonames) {
+            if !foreign.contains(&dependent) || affected.contains(&dependent) {
+                continue;
+            }
+            // Built before the broken release arrived, so against the one being restored
+            let built_at = desc_time(root, &dependent, "%BUILDDATE%");
+            if installed_at.zip(built_at).is_some_and(|(installed, built)| built < installed) {
+                continue;
+            }
+            affected.push(dependent);
+        }
+    }
+    affected.sort();
+    affected
+}
+
+/// A Unix time field of `package`'s pacman database entry
+fn desc_time(root: &str, package: &str, field: &str) -> Option<i64> {
+    ownership::desc_field(&ownership::pacman_desc_in(root, package)?, field)?.trim().parse().ok()
+}
+
+/// Rebuild and reinstall `packages`; `downgraded` are the package versions the clean
+/// chroot has to build against. Returns the packages that failed.
+pub fn rebuild(builder: &Builder, packages: &[String], downgraded: &[(String, String)]) -> Result<Vec<String>> {
+    match builder {
+        Builder::Helper(helper) => {
+            // Helpers refuse to run as root and call sudo themselves for the install
+            let status = audit::run(
+                &format!("aur: rebuild {}", packages.join(" ")),
+                Command::new(helper).args(["-S", "--rebuild"]).args(packages),
+            )?;
+            Ok(if status.success() { Vec::new() } else { packages.to_vec() })
+        }
+        Builder::CleanChroot => {
+            let installs: Vec<String> = downgraded
+                .iter()
+                .filter_map(|(name, version)| cached_file(name, version))
+                .flat_map(|file| ["-I".to_string(), file])
+                .collect();
+            let workdir = tempfile::tempdir()?;
+
+            let mut failed = Vec::new();
+            for package in packages {
+                let base = package_base(package);
+                let dir = workdir.path().join(&base);
+                let script = format!(
+                    "git clone --depth 1 {aur}/{base}.git {dir} && cd {dir} && extra-x86_64-build -- {installs} && sudo pacman -U --noconfirm {dir}/*.pkg.tar.zst",
+                    aur = AUR_GIT,
+                    base = base,
+                    dir = dir.display(),
+                    installs = installs.join(" "),
+                );
+                let status = audit::run(
+                    &format!("aur: rebuild {} in a clean chroot", package),
+                    Command::new("sh").arg("-c").arg(&script),
+                )?;
+                if !status.success() {
+                    failed.push(package.clone());
+                }
+            }
+            Ok(failed)
+        }
+    }
+}
+
+/// The AUR repository of a split package is named after its pkgbase
+fn package_base(package: &str) -> String {
+    ownership::pacman_desc_in("/", package)
+        .and_then(|desc| ownership::desc_field(&desc, "%BASE%"))
+        .unwrap_or_else(|| package.to_string())
+}
+
+/// The package file for `name` `version` in the pacman cache
+fn cached_file(name: &str, version: &str) -> Option<String> {
+    let prefix = format!("{}-{}-", name, version);
+    fs::read_dir(PACMAN_CACHE).ok()?.flatten().find_map(|entry| {
+        let file = entry.file_name().to_string_lossy().to_string();
+        let rest = file.strip_prefix(&prefix)?;
+        // The rest must be just the arch, or foo would also match foo-libs
+        (!rest.contains('-') && rest.contains(".pkg.tar.") && !rest.ends_with(".sig"))
+            .then(|| Path::new(PACMAN_CACHE).join(&file).to_string_lossy().to_string())
+    })
+}
@@ -1,18 +1,113 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use colored::*;
 use dialoguer::Confirm;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
 
+use crate::bisect_engine::{contradicting, BisectEngine, Tester, Verdict};
 use crate::snapshot::Snapshot;
-use crate::package_diff::{compute_diff, PackageChange};
+use crate::ownership;
+use crate::paths;
+use crate::package_diff::{compute_diff, sort_canonical, InstallReason, PackageChange, PackageDiff};
+use crate::presets::{self, coupling_key, BisectScope};
+use crate::priors;
+use crate::driver::TestDriver;
+use crate::hooks::{self, Hook};
+use crate::notify;
+use crate::test_runner::TestRunner;
+
+/// Chance that any one verdict is wrong (a flaky test, a misjudged symptom)
+const VERDICT_ERROR_RATE: f64 = 0.02;
+
+/// Alternatives kept in a bisect assessment
+const MAX_ALTERNATIVES: usize = 5;
+
+/// Culprits kept in the history shown by `status`
+const HISTORY_LIMIT: usize = 20;
 
 pub struct BisectSession {
     good_snapshot: Snapshot,
     bad_snapshot: Snapshot,
-    package_changes: Vec<PackageChange>,
-    current_low: usize,
-    current_high: usize,
-    current_mid: usize,
+    engine: BisectEngine,
+    test_runner: Option<TestRunner>,
     found_culprit: Option<PackageChange>,
+    history: Vec<BisectStep>,
+}
+
+/// One verdict of a package bisect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BisectStep {
+    /// Number of candidate changes applied for this step
+    pub applied: usize,
+    pub issue: bool,
+    /// The step could not be tested; `issue` is meaningless
+    #[serde(default)]
+    pub skipped: bool,
+    pub at: String,
+}
+
+/// Progress of a package bisect, written after every verdict so an interrupted run can resume
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub good: String,
+    pub bad: String,
+    pub package_changes: Vec<PackageChange>,
+    pub low: usize,
+    pub high: usize,
+    /// Splits that could not be tested
+    #[serde(default)]
+    pub skipped: Vec<usize>,
+    /// Prior culprit weights, so a resumed bisect picks the same splits
+    #[serde(default)]
+    pub weights: Vec<f64>,
+    #[serde(default)]
+    pub history: Vec<BisectStep>,
+}
+
+/// A culprit found by a finished bisect, kept after the next bisect replaces the report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CulpritRecord {
+    pub culprit: PackageChange,
+    pub good: String,
+    pub bad: String,
+    pub finished_at: String,
+}
+
+/// Another change that could explain the issue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alternative {
+    pub change: PackageChange,
+    /// Verdicts that would have to be wrong for this to be the culprit
+    pub verdicts_against: usize,
+    pub evidence: String,
+}
+
+/// How sure a finished bisect is of its answer, and what else could explain the issue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Assessment {
+    /// 0.0 to 1.0, for the culprit's coupled set (for each remaining candidate without a culprit)
+    pub confidence: f64,
+    /// Most likely first
+    pub alternatives: Vec<Alternative>,
+    /// What lowered the confidence
+    pub caveats: Vec<String>,
+}
+
+/// Outcome of the last finished package bisect
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BisectReport {
+    pub good: String,
+    pub bad: String,
+    pub culprit: Option<PackageChange>,
+    /// Packages that move together with the culprit
+    pub group: Vec<PackageChange>,
+    pub history: Vec<BisectStep>,
+    pub finished_at: String,
+    #[serde(default)]
+    pub assessment: Option<Assessment>,
 }
 
 impl BisectSession {
@@ -21,32 +116,340 @@ impl BisectSession {
     }
 
     pub fn new(good_snapshot: Snapshot, bad_snapshot: Snapshot) -> Result<Self> {
+        // Guix installs generations whole; `guix::bisect_generations` steps through them instead
+        #[cfg(feature = "guix")]
+        if [&good_snapshot, &bad_snapshot].iter().any(|s| s.kind.as_deref() == Some(crate::guix::GENERATION)) {
+            anyhow::bail!(
+                "Guix generations can't be bisected package by package; \
+                 run `eshu-trace bisect -g {} -b {}` to bisect the generations between them",
+                good_snapshot.id,
+                bad_snapshot.id
+            );
+        }
+
         let diff = compute_diff(&good_snapshot, &bad_snapshot)?;
-        let package_changes = diff.all_changes();
+        let package_changes = group_coupled(prefer_explicit(&diff, bad_snapshot.path.as_deref()));
 
         if package_changes.is_empty() {
             anyhow::bail!("No package changes detected between snapshots");
         }
 
-        let total = package_changes.len();
-
         Ok(Self {
             good_snapshot,
             bad_snapshot,
-            package_changes,
-            current_low: 0,
-            current_high: total,
-            current_mid: total / 2,
+            engine: BisectEngine::new(package_changes),
+            test_runner: None,
             found_culprit: None,
+            history: Vec::new(),
         })
     }
 
+    pub fn set_test_runner(&mut self, runner: TestRunner) {
+        self.test_runner = Some(runner);
+    }
+
+    /// Narrow the candidates to the packages covered by a bisect preset
+    pub fn restrict_to(&mut self, scope: BisectScope) -> Result<()> {
+        if !self.engine.restrict(|c| scope.matches(c.name())) {
+            anyhow::bail!("No {} packages changed between snapshots", scope.description());
+        }
+        Ok(())
+    }
+
+    /// Narrow the candidates to the named suspect packages (e.g. from `blame`)
+    pub fn restrict_to_packages(&mut self, names: &[String]) -> Result<()> {
+        if !self.engine.restrict(|c| names.iter().any(|n| n == c.name())) {
+            anyhow::bail!("None of the suspect packages changed between snapshots");
+        }
+        Ok(())
+    }
+
+    /// Split by prior likelihood (package category, culprit reports, the described
+    /// symptom) instead of count. Returns the most likely changes, most likely first.
+    pub fn weigh(&mut self, symptom: &str) -> Vec<&PackageChange> {
+        let weights = priors::weights(self.engine.changes(), symptom);
+        self.engine.set_weights(weights);
+
+        let weights = self.engine.weights();
+        let mean = weights.iter().sum::<f64>() / weights.len().max(1) as f64;
+        let mut likely: Vec<usize> = (0..weights.len()).filter(|&i| weights[i] > mean * 1.5).collect();
+        likely.sort_by(|&a, &b| weights[b].total_cmp(&weights[a]).then(a.cmp(&b)));
+        likely.into_iter().map(|i| &self.engine.changes()[i]).collect()
+    }
+
+    /// The culprit together with the packages that must move with it
+    pub fn get_culprit_group(&self) -> Vec<PackageChange> {
+        let culprit = match &self.found_culprit {
+            Some(c) => c,
+            None => return Vec::new(),
+        };
+
+        match coupling_key(culprit.name()) {
+            Some(key) => self
+                .engine
+                .changes()
+                .iter()
+                .filter(|c| coupling_key(c.name()) == Some(key))
+                .cloned()
+                .collect(),
+            None => vec![culprit.clone()],
+        }
+    }
+
+    /// Rate the outcome. Each verdict is assumed right with a fixed probability, so the
+    /// confidence drops with every step, with untestable steps that left several
+    /// candidates, and when every test gave the same answer (a test that can't tell good
+    /// from bad "finds" the first or last change). Alternatives are the changes that the
+    /// fewest verdicts rule out, nearest to the final range first.
+    pub fn assess(&self) -> Assessment {
+        let changes = self.engine.changes();
+        let (low, high) = self.engine.range();
+        let verdicts: Vec<(usize, Verdict)> = self
+            .history
+            .iter()
+            .map(|s| (s.applied, if s.skipped { Verdict::Skip } else { Verdict::from_issue(s.issue) }))
+            .collect();
+        let decisive: Vec<Verdict> = verdicts.iter().map(|(_, v)| *v).filter(|v| *v != Verdict::Skip).collect();
+
+        // Coupled sets are never split, so they count once
+        let unit = |c: &PackageChange| coupling_key(c.name()).unwrap_or(c.name()).to_string();
+        let mut remaining: Vec<String> = changes[low..high].iter().map(unit).collect();
+        remaining.dedup();
+
+        let mut confidence = (1.0 - VERDICT_ERROR_RATE).powi(decisive.len() as i32) / remaining.len().max(1) as f64;
+        let mut caveats = Vec::new();
+        if remaining.len() > 1 {
+            let skipped = verdicts.len() - decisive.len();
+            caveats.push(format!("{} untestable step(s) left {} candidates", skipped, remaining.len()));
+        }
+        if decisive.len() >= 2 && decisive.iter().all(|v| *v == decisive[0]) {
+            confidence /= 2.0;
+            caveats.push(match decisive[0] {
+                Verdict::Bad => "every test showed the issue; check that the test passes on a working system".to_string(),
+                _ => "no test showed the issue; check that the test fails on a broken system".to_string(),
+            });
+        }
+        let culprit_unit = self.found_culprit.as_ref().map(unit);
+        if let Some(culprit) = &self.found_culprit {
+            let group = self.get_culprit_group();
+            if group.len() > 1 {
+                caveats.push(format!(
+                    "{} was only tested together with the {} package(s) that move with it",
+                    culprit.name(),
+                    group.len() - 1
+                ));
+            }
+        }
+
+        let distance = |i: usize| {
+            if i < low {
+                low - i
+            } else {
+                i.saturating_sub(high - 1)
+            }
+        };
+        let mut ranked: Vec<(usize, Vec<usize>)> = (0..changes.len())
+            .filter(|&i| culprit_unit.as_ref() != Some(&unit(&changes[i])))
+            .map(|i| (i, contradicting(i, &verdicts)))
+            .filter(|(_, against)| against.len() <= 1)
+            .collect();
+        ranked.sort_by_key(|(i, against)| (against.len(), distance(*i), *i));
+
+        let mut seen = Vec::new();
+        let mut alternatives = Vec::new();
+        for (i, against) in ranked {
+            let change = &changes[i];
+            if seen.contains(&unit(change)) {
+                continue;
+            }
+            seen.push(unit(change));
+
+            let evidence = match against.first() {
+                None if verdicts.len() > decisive.len() => "not ruled out: the steps that would decide it were skipped".to_string(),
+                None => "consistent with every verdict".to_string(),
+                Some(&pos) => {
+                    let step = &self.history[pos];
+                    format!(
+                        "ruled out only by step {} ({} with {} changes applied); a flaky result there would point here",
+                        pos + 1,
+                        if step.issue { "issue" } else { "ok" },
+                        step.applied
+                    )
+                }
+            };
+            alternatives.push(Alternative {
+                change: change.clone(),
+                verdicts_against: against.len(),
+                evidence,
+            });
+            if alternatives.len() == MAX_ALTERNATIVES.max(remaining.len()) {
+                break;
+            }
+        }
+
+        Assessment {
+            confidence,
+            alternatives,
+            caveats,
+        }
+    }
+
+    /// Package changes the bisect searches (after any scope restriction), in bisect order
+    pub fn changes(&self) -> &[PackageChange] {
+        self.engine.changes()
+    }
+
+    /// Package changes still in play, in bisect order
+    pub fn candidates(&self) -> &[PackageChange] {
+        self.engine.candidates()
+    }
+
     pub fn total_packages(&self) -> usize {
-        self.package_changes.len()
+        self.engine.changes().len()
+    }
+
+    /// Offer to continue an interrupted bisect between the same two snapshots
+    pub fn resume_saved(&mut self) -> Result<bool> {
+        let saved = match load_saved()? {
+            Some(s) if s.good == self.good_snapshot.id && s.bad == self.bad_snapshot.id => s,
+            _ => return Ok(false),
+        };
+
+        let resume = Confirm::new()
+            .with_prompt(format!(
+                "Resume the interrupted bisect ({} of {} candidates left)?",
+                saved.high - saved.low,
+                saved.package_changes.len()
+            ))
+            .default(true)
+            .interact()?;
+
+        if !resume {
+            clear_saved()?;
+            return Ok(false);
+        }
+
+        self.adopt(saved);
+        Ok(true)
+    }
+
+    /// Continue an interrupted bisect between the same two snapshots without asking
+    pub fn resume_matching(&mut self) -> Result<bool> {
+        match load_saved()? {
+            Some(s) if s.good == self.good_snapshot.id && s.bad == self.bad_snapshot.id => {
+                self.adopt(s);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn adopt(&mut self, saved: SavedSession) {
+        self.engine = BisectEngine::resume(saved.package_changes, saved.low, saved.high, saved.skipped);
+        self.engine.set_weights(saved.weights);
+        self.history = saved.history;
+    }
+
+    /// Narrow the range around the pending split and persist the step
+    pub fn record_verdict(&mut self, issue: bool) -> Result<()> {
+        self.record(Verdict::from_issue(issue))
+    }
+
+    pub fn record(&mut self, verdict: Verdict) -> Result<()> {
+        let Some(applied) = self.engine.record_verdict(verdict) else {
+            anyhow::bail!("No bisect step is waiting for a verdict");
+        };
+
+        self.history.push(BisectStep {
+            applied,
+            issue: verdict == Verdict::Bad,
+            skipped: verdict == Verdict::Skip,
+            at: chrono::Local::now().to_rfc3339(),
+        });
+        self.save()?;
+
+        let mut env = self.hook_env(self.history.len(), applied);
+        let label = match verdict {
+            Verdict::Bad => "issue",
+            Verdict::Good => "ok",
+            Verdict::Skip => "skip",
+        };
+        env.push(("ESHU_TRACE_VERDICT", label.to_string()));
+        hooks::run(Hook::PostStep, &env)
+    }
+
+    /// ESHU_TRACE_* variables describing step `step` (from 1), which applies the first
+    /// `applied` changes, for hooks
+    fn hook_env(&self, step: usize, applied: usize) -> Vec<(&'static str, String)> {
+        let names: Vec<&str> = self.engine.changes()[..applied].iter().map(|c| c.name()).collect();
+        let (low, high) = self.engine.range();
+        vec![
+            ("ESHU_TRACE_GOOD", self.good_snapshot.id.clone()),
+            ("ESHU_TRACE_BAD", self.bad_snapshot.id.clone()),
+            ("ESHU_TRACE_STEP", step.to_string()),
+            ("ESHU_TRACE_TOTAL", self.total_packages().to_string()),
+            ("ESHU_TRACE_LOW", low.to_string()),
+            ("ESHU_TRACE_HIGH", high.to_string()),
+            ("ESHU_TRACE_APPLIED", names.join(" ")),
+        ]
+    }
+
+    fn save(&self) -> Result<()> {
+        let (low, high) = self.engine.range();
+        let saved = SavedSession {
+            good: self.good_snapshot.id.clone(),
+            bad: self.bad_snapshot.id.clone(),
+            package_changes: self.engine.changes().to_vec(),
+            low,
+            high,
+            skipped: self.engine.skipped().to_vec(),
+            weights: self.engine.weights().to_vec(),
+            history: self.history.clone(),
+        };
+
+        let path = saved_session_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(&saved)?)?;
+        Ok(())
+    }
+
+    /// Keep the outcome around for `eshu-trace serve` after the session file is gone
+    fn save_report(&self) -> Result<()> {
+        let report = BisectReport {
+            good: self.good_snapshot.id.clone(),
+            bad: self.bad_snapshot.id.clone(),
+            culprit: self.found_culprit.clone(),
+            group: self.get_culprit_group(),
+            history: self.history.clone(),
+            finished_at: chrono::Local::now().to_rfc3339(),
+            assessment: Some(self.assess()),
+        };
+
+        let path = report_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+
+        if let Some(culprit) = &report.culprit {
+            let mut history = culprit_history();
+            history.push(CulpritRecord {
+                culprit: culprit.clone(),
+                good: report.good.clone(),
+                bad: report.bad.clone(),
+                finished_at: report.finished_at.clone(),
+            });
+            let excess = history.len().saturating_sub(HISTORY_LIMIT);
+            history.drain(..excess);
+            fs::write(history_path(), serde_json::to_string_pretty(&history)?)?;
+        }
+        Ok(())
     }
 
     pub fn run_manual(&mut self) -> Result<()> {
-        let total_steps = (self.total_packages() as f64).log2().ceil() as usize;
+        let total_steps = self.engine.steps_left();
 
         println!(
             "{} Binary search will take approximately {} steps",
@@ -57,7 +460,7 @@ impl BisectSession {
 
         let mut step = 1;
 
-        while self.current_low < self.current_high - 1 {
+        while let Some(mid) = self.engine.next_candidate() {
             println!(
                 "{} {} ({}/{})",
                 "Step".cyan().bold(),
@@ -67,9 +470,7 @@ impl BisectSession {
             );
             println!();
 
-            self.current_mid = (self.current_low + self.current_high) / 2;
-
-            let test_packages: Vec<_> = self.package_changes[..self.current_mid]
+            let test_packages: Vec<_> = self.engine.changes()[..mid]
                 .iter()
                 .collect();
 
@@ -81,42 +482,77 @@ impl BisectSession {
             println!();
 
             println!("{}", "Packages in this test:".dimmed());
-            for pkg in test_packages.iter().take(10) {
+            let groups = presets::large_groups(test_packages.iter().map(|c| c.name()), 1);
+            for (label, count) in &groups {
+                println!("  • {} ({} packages)", label.dimmed(), count);
+            }
+            let single: Vec<_> = test_packages
+                .iter()
+                .filter(|c| !presets::ecosystem(c.name()).is_some_and(|l| groups.iter().any(|(g, _)| *g == l)))
+                .collect();
+            for pkg in single.iter().take(10) {
                 println!("  • {}", pkg.name().dimmed());
             }
-            if test_packages.len() > 10 {
-                println!("  ... and {} more", test_packages.len() - 10);
+            if single.len() > 10 {
+                println!("  ... and {} more", single.len() - 10);
             }
             println!();
 
+            hooks::run(Hook::PreTest, &self.hook_env(self.history.len() + 1, mid))?;
+
             println!("{}", "Please test your system now.".yellow().bold());
             println!("Boot into the snapshot and check if the issue occurs.");
             println!();
 
-            let issue_occurs = Confirm::new()
-                .with_prompt("Does the issue still occur?")
-                .interact()?;
+            let issue_occurs = ask_verdict(self.test_runner.as_ref())?;
 
             println!();
 
             if issue_occurs {
                 // Issue is in first half
                 println!("{} Issue found in first half", "➡️".yellow());
-                self.current_high = self.current_mid;
             } else {
                 // Issue is in second half
                 println!("{} Issue found in second half", "➡️".yellow());
-                self.current_low = self.current_mid;
             }
+            self.record_verdict(issue_occurs)?;
 
             println!();
             step += 1;
         }
 
-        // Found the culprit
-        if self.current_low < self.package_changes.len() {
-            let culprit = &self.package_changes[self.current_low];
-            self.found_culprit = Some(culprit.clone());
+        self.report_culprit();
+
+        Ok(())
+    }
+
+    /// Changes to apply for the next step, or None once the range is down to one candidate.
+    /// For callers that drive the bisect themselves (the RPC API).
+    pub fn next_step(&mut self) -> Option<&[PackageChange]> {
+        let mid = self.engine.next_candidate()?;
+        Some(&self.engine.changes()[..mid])
+    }
+
+    /// Remaining candidate range as (low, high)
+    pub fn range(&self) -> (usize, usize) {
+        self.engine.range()
+    }
+
+    /// Conclude the bisect: pick the culprit, drop the resumable state and keep the report
+    pub fn finish(&mut self) -> Option<&PackageChange> {
+        // Finished; nothing left to resume
+        let _ = clear_saved();
+
+        self.found_culprit = self.engine.culprit().cloned();
+        let _ = self.save_report();
+        self.found_culprit.as_ref()
+    }
+
+    fn report_culprit(&mut self) {
+        self.finish();
+        self.notify_finished();
+
+        if let Some(culprit) = &self.found_culprit {
 
             println!("{}", "🎯 FOUND THE CULPRIT!".green().bold());
             println!();
@@ -129,7 +565,7 @@ impl BisectSession {
                 PackageChange::Removed(pkg) => {
                     println!("{} Removed (was version {})", "Change:".cyan(), pkg.version);
                 }
-                PackageChange::Upgraded(pkg, old_ver, new_ver) => {
+                PackageChange::Upgraded(_, old_ver, new_ver) => {
                     println!(
                         "{} Upgraded from {} to {}",
                         "Change:".cyan(),
@@ -137,7 +573,7 @@ impl BisectSession {
                         new_ver
                     );
                 }
-                PackageChange::Downgraded(pkg, old_ver, new_ver) => {
+                PackageChange::Downgraded(_, old_ver, new_ver) => {
                     println!(
                         "{} Downgraded from {} to {}",
                         "Change:".cyan(),
@@ -147,28 +583,407 @@ impl BisectSession {
                 }
             }
 
+            let group = self.get_culprit_group();
+            if group.len() > 1 {
+                println!();
+                println!("{}", "Moves together with:".cyan());
+                for change in group.iter().filter(|c| c.name() != culprit.name()) {
+                    println!("  • {}", change.name());
+                }
+            }
+
+            let assessment = self.assess();
+            println!();
+            println!("{} {}", "Confidence:".cyan(), confidence_label(assessment.confidence));
+            for caveat in &assessment.caveats {
+                println!("  {} {}", "⚠".yellow(), caveat);
+            }
+            // A clean run still lists alternatives in the saved report; only show them when in doubt
+            if assessment.confidence < 0.8 && !assessment.alternatives.is_empty() {
+                println!();
+                println!("{}", "Other candidates, most likely first:".yellow());
+                for alternative in &assessment.alternatives {
+                    println!("  • {}", describe(&alternative.change));
+                    println!("    {}", alternative.evidence.dimmed());
+                }
+            }
+
             println!();
             println!("{}", "Recommended actions:".yellow());
             println!("  1. Downgrade just this package");
             println!("  2. Report issue to package maintainers");
             println!("  3. Check if others reported this issue");
             println!();
+        } else if !self.engine.skipped().is_empty() {
+            // Skipped steps left the culprit somewhere in the remaining range
+            let assessment = self.assess();
+            println!("{}", "Could not narrow it down further: untestable steps were skipped".yellow().bold());
+            println!("The culprit is most likely one of ({} each):", confidence_label(assessment.confidence));
+            for alternative in assessment.alternatives.iter().filter(|a| a.verdicts_against == 0) {
+                println!("  • {}", describe(&alternative.change));
+            }
+            let flaky: Vec<_> = assessment.alternatives.iter().filter(|a| a.verdicts_against > 0).collect();
+            if !flaky.is_empty() {
+                println!("Less likely:");
+                for alternative in flaky {
+                    println!("  • {}", describe(&alternative.change));
+                    println!("    {}", alternative.evidence.dimmed());
+                }
+            }
+            println!();
         }
+    }
 
-        Ok(())
+    fn notify_finished(&self) {
+        let mut report = format!(
+            "Good snapshot: {}\nBad snapshot: {}\nSteps: {}\n",
+            self.good_snapshot.id,
+            self.bad_snapshot.id,
+            self.history.len()
+        );
+
+        let culprit = match &self.found_culprit {
+            Some(c) => c,
+            None => {
+                notify::finished("Bisect finished without a culprit", &report);
+                return;
+            }
+        };
+
+        report.push_str(&format!("Culprit: {}\n", describe(culprit)));
+        report.push_str(&format!("Confidence: {:.0}%\n", self.assess().confidence * 100.0));
+        for change in self.get_culprit_group().iter().filter(|c| c.name() != culprit.name()) {
+            report.push_str(&format!("Moves together with: {}\n", describe(change)));
+        }
+        notify::finished(&format!("Culprit found: {}", culprit.name()), &report);
     }
 
-    pub fn run_automated(&mut self) -> Result<()> {
-        // Premium feature - automated testing with VMs
+    pub fn run_automated(&mut self, driver: &mut dyn TestDriver) -> Result<()> {
+        // Premium feature - automated testing without manual reboots
         println!("{}", "🤖 Automated Bisect (Premium)".cyan().bold());
+        println!("{} {}", "Driver:".cyan(), driver.name());
         println!();
 
-        println!("{}", "This feature will:".dimmed());
-        println!("  • Boot test VMs for each bisect step");
-        println!("  • Run your test suite automatically");
-        println!("  • Find the culprit without manual intervention");
-        println!();
+        let runner = match self.test_runner.take() {
+            Some(r) if r.has_test() => r,
+            _ => anyhow::bail!(
+                "Automated bisect needs a test: use --test-command, --preset, --probe or --bench"
+            ),
+        };
+
+        let mut tester = DriverTester { driver, runner: &runner };
+        let mut step = 1;
+
+        while let Some(mid) = self.engine.next_candidate() {
+            println!(
+                "{} {}: testing with {}/{} packages applied...",
+                "Step".cyan().bold(),
+                step,
+                mid,
+                self.total_packages()
+            );
+
+            hooks::run(Hook::PreTest, &self.hook_env(self.history.len() + 1, mid))?;
+            let verdict = tester.test(&self.engine.changes()[..mid])?;
+
+            let passed = verdict == Verdict::Good;
+            if passed {
+                println!("{} Test passed - issue is in second half", "➡️".yellow());
+            } else {
+                println!("{} Test failed - issue is in first half", "➡️".yellow());
+            }
+            self.record(verdict)?;
+            let (low, high) = self.engine.range();
+            notify::send(
+                &format!("Bisect step {} {}", step, if passed { "passed" } else { "failed" }),
+                &format!("{} candidate changes left", high - low),
+            );
+
+            println!();
+            step += 1;
+        }
+
+        self.test_runner = Some(runner);
+        self.report_culprit();
+
+        Ok(())
+    }
+
+    /// Drive the bisect over a line protocol: JSON events on `output`, one verdict per
+    /// line on `input` (see `eshu-trace bisect --machine`). Returns true once a culprit
+    /// is found; false if the input ended or asked to abort (the session stays resumable).
+    pub fn run_machine(&mut self, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<bool> {
+        let mut emit = |event: serde_json::Value| -> Result<()> {
+            writeln!(output, "{}", event)?;
+            output.flush()?;
+            Ok(())
+        };
+
+        emit(json!({
+            "event": "start",
+            "good": self.good_snapshot.id,
+            "bad": self.bad_snapshot.id,
+            "total": self.total_packages(),
+            "resumed_steps": self.history.len(),
+        }))?;
+
+        let mut line = String::new();
+        while let Some(mid) = self.engine.next_candidate() {
+            let step = self.history.len() + 1;
+            let (low, high) = self.engine.range();
+            emit(json!({
+                "event": "step",
+                "step": step,
+                "low": low,
+                "high": high,
+                "candidates": self.candidates(),
+                "apply": &self.engine.changes()[..mid],
+            }))?;
+            hooks::run(Hook::PreTest, &self.hook_env(self.history.len() + 1, mid))?;
+
+            let verdict = loop {
+                emit(json!({ "event": "verdict_request", "step": step }))?;
+                line.clear();
+                if input.read_line(&mut line)? == 0 {
+                    emit(json!({ "event": "aborted", "reason": "end of input" }))?;
+                    return Ok(false);
+                }
+                match parse_verdict(&line) {
+                    Some(Some(verdict)) => break verdict,
+                    Some(None) => {
+                        emit(json!({ "event": "aborted", "reason": "abort requested" }))?;
+                        return Ok(false);
+                    }
+                    None => emit(json!({
+                        "event": "invalid_verdict",
+                        "input": line.trim(),
+                        "expected": "issue | ok | skip | abort, or {\"issue\": true|false}",
+                    }))?,
+                }
+            };
+
+            self.record(verdict)?;
+            let (low, high) = self.engine.range();
+            emit(json!({
+                "event": "verdict",
+                "step": step,
+                "issue": verdict == Verdict::Bad,
+                "skipped": verdict == Verdict::Skip,
+                "remaining": high - low,
+            }))?;
+        }
+
+        let culprit = self.finish().cloned();
+        emit(json!({
+            "event": "finished",
+            "culprit": culprit,
+            "group": self.get_culprit_group(),
+            // Without a culprit (skipped steps), it is one of these
+            "remaining": if culprit.is_none() { self.candidates() } else { &[] },
+            "assessment": self.assess(),
+            "steps": self.history.len(),
+        }))?;
+        Ok(culprit.is_some())
+    }
+}
+
+/// Some(Some(verdict)) for a verdict, Some(None) for abort, None if unreadable
+fn parse_verdict(line: &str) -> Option<Option<Verdict>> {
+    let line = line.trim();
+    if let Ok(value) = serde_json::from_str::<serde_json::Value>(line) {
+        if let Some(issue) = value.get("issue").and_then(|v| v.as_bool()) {
+            return Some(Some(Verdict::from_issue(issue)));
+        }
+        return value.get("verdict").and_then(|v| v.as_str()).and_then(parse_verdict);
+    }
+
+    match line.to_lowercase().as_str() {
+        "issue" | "bad" | "fail" | "failed" => Some(Some(Verdict::Bad)),
+        "ok" | "good" | "pass" | "passed" => Some(Some(Verdict::Good)),
+        "skip" | "untestable" => Some(Some(Verdict::Skip)),
+        "abort" | "quit" => Some(None),
+        _ => None,
+    }
+}
 
-        anyhow::bail!("Automated bisect requires Premium license");
+/// A VM/chroot/container driver running the session's test command
+struct DriverTester<'a> {
+    driver: &'a mut dyn TestDriver,
+    runner: &'a TestRunner,
+}
+
+impl Tester for DriverTester<'_> {
+    fn test(&mut self, apply: &[PackageChange]) -> Result<Verdict> {
+        let passed = self.driver.test(apply, self.runner)?;
+        Ok(Verdict::from_issue(!passed))
+    }
+}
+
+/// Ask whether the issue occurs, running the test command first if one is configured
+pub fn ask_verdict(runner: Option<&TestRunner>) -> Result<bool> {
+    let runner = match runner {
+        Some(r) if r.has_test() => r,
+        _ => {
+            return Ok(Confirm::new()
+                .with_prompt("Does the issue still occur?")
+                .interact()?);
+        }
+    };
+
+    if let Some(user) = runner.test_user() {
+        println!("{} Running test as user {}...", "🧪".bold(), user.yellow());
+    } else {
+        println!("{} Running test...", "🧪".bold());
+    }
+
+    let passed = runner.run_test()?;
+
+    if passed {
+        println!("{} Test passed", "✓".green());
+    } else {
+        println!("{} Test failed", "✗".red());
+    }
+    println!();
+
+    Ok(Confirm::new()
+        .with_prompt("Does the issue still occur?")
+        .default(!passed)
+        .interact()?)
+}
+
+fn saved_session_path() -> PathBuf {
+    paths::state_file("bisect-session.json")
+}
+
+fn report_path() -> PathBuf {
+    paths::state_file("bisect-report.json")
+}
+
+fn history_path() -> PathBuf {
+    paths::state_file("culprit-history.json")
+}
+
+/// Culprits of earlier bisects, oldest first
+pub fn culprit_history() -> Vec<CulpritRecord> {
+    fs::read_to_string(history_path())
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// The last finished bisect, if any
+pub fn load_report() -> Result<Option<BisectReport>> {
+    let path = report_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let data = fs::read_to_string(&path).context("Failed to read bisect report")?;
+    Ok(Some(serde_json::from_str(&data).context("Failed to parse bisect report")?))
+}
+
+/// Confidence as a colored percentage
+fn confidence_label(confidence: f64) -> ColoredString {
+    let label = format!("{:.0}%", confidence * 100.0);
+    if confidence >= 0.8 {
+        label.green()
+    } else if confidence >= 0.5 {
+        label.yellow()
+    } else {
+        label.red()
+    }
+}
+
+/// One-line description of a change for reports
+pub fn describe(change: &PackageChange) -> String {
+    match change {
+        PackageChange::Added(pkg) => format!("{} {} (added)", pkg.name, pkg.version),
+        PackageChange::Removed(pkg) => format!("{} {} (removed)", pkg.name, pkg.version),
+        PackageChange::Upgraded(pkg, old, new) | PackageChange::Downgraded(pkg, old, new) => {
+            let rebuild = if change.is_rebuild() { " (rebuild)" } else { "" };
+            format!("{} {} → {}{}", pkg.name, old, new, rebuild)
+        }
+    }
+}
+
+pub fn load_saved() -> Result<Option<SavedSession>> {
+    let path = saved_session_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let data = fs::read_to_string(&path).context("Failed to read bisect session")?;
+    Ok(Some(serde_json::from_str(&data).context("Failed to parse bisect session")?))
+}
+
+fn clear_saved() -> Result<()> {
+    let path = saved_session_path();
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Order explicitly installed packages and their direct dependencies before
+/// packages that only came in as dependencies of something else, and rebuilds of the
+/// same upstream version after all real version changes; within a rank the canonical
+/// order is kept
+fn prefer_explicit(diff: &PackageDiff, root: Option<&str>) -> Vec<PackageChange> {
+    let mut changes = diff.all_changes();
+    sort_canonical(&mut changes);
+    if diff.reasons.is_empty() {
+        changes.sort_by_key(|c| c.is_rebuild());
+        return changes;
+    }
+
+    let explicit: Vec<&str> = changes
+        .iter()
+        .map(|c| c.name())
+        .filter(|n| diff.reason(n) == Some(InstallReason::Explicit))
+        .collect();
+
+    let mut direct_deps: Vec<String> = Vec::new();
+    if let Some(root) = root {
+        for name in &explicit {
+            direct_deps.extend(ownership::dependencies_in(root, name));
+        }
+    }
+
+    let rank = |c: &PackageChange| {
+        if diff.reason(c.name()) == Some(InstallReason::Explicit) {
+            0
+        } else if direct_deps.iter().any(|d| d == c.name()) {
+            1
+        } else {
+            2
+        }
+    };
+
+    let ranks: std::collections::HashMap<String, u8> =
+        changes.iter().map(|c| (c.name().to_string(), rank(c))).collect();
+    changes.sort_by_key(|c| (c.is_rebuild(), ranks[c.name()]));
+    changes
+}
+
+/// Reorder changes so packages that must move together, and desktop groups, are
+/// adjacent, keeping the position of each set's first member
+fn group_coupled(changes: Vec<PackageChange>) -> Vec<PackageChange> {
+    let mut units: Vec<Vec<PackageChange>> = Vec::new();
+    let mut unit_for_key: std::collections::HashMap<&'static str, usize> = std::collections::HashMap::new();
+
+    for change in changes {
+        match presets::ecosystem(change.name()).or_else(|| coupling_key(change.name())) {
+            Some(key) => {
+                if let Some(&idx) = unit_for_key.get(key) {
+                    units[idx].push(change);
+                } else {
+                    unit_for_key.insert(key, units.len());
+                    units.push(vec![change]);
+                }
+            }
+            None => units.push(vec![change]),
+        }
     }
+
+    units.into_iter().flatten().collect()
 }
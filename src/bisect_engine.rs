@@ -0,0 +1,383 @@
+// The package bisect search as a pure state machine: no prompts, output or files
+//
+// Callers ask for the next split with `next_candidate`, test the first N changes in
+// bisect order however they like (the user, a VM driver, the machine protocol, the RPC
+// API) and report back with `record_verdict`. Persistence, hooks and printing stay in
+// BisectSession.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::package_diff::PackageChange;
+use crate::presets::{self, coupling_key};
+
+/// Outcome of testing one split
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    /// The issue does not occur with these changes applied
+    Good,
+    /// The issue occurs
+    Bad,
+    /// This split can't be tested (e.g. it doesn't boot for an unrelated reason)
+    Skip,
+}
+
+impl Verdict {
+    pub fn from_issue(issue: bool) -> Self {
+        if issue {
+            Verdict::Bad
+        } else {
+            Verdict::Good
+        }
+    }
+}
+
+/// Decides a verdict for the good snapshot with `apply` (a prefix of the bisect order) on top
+pub trait Tester {
+    fn test(&mut self, apply: &[PackageChange]) -> Result<Verdict>;
+}
+
+#[derive(Debug, Clone)]
+pub struct BisectEngine {
+    changes: Vec<PackageChange>,
+    /// Applying the first `low` changes is known good
+    low: usize,
+    /// Applying the first `high` changes is known bad
+    high: usize,
+    /// Split handed out by `next_candidate` and not yet answered
+    pending: Option<usize>,
+    /// Splits answered with Skip; never offered again
+    skipped: Vec<usize>,
+    /// Prior culprit likelihood of each change; splits halve the remaining weight
+    weights: Vec<f64>,
+}
+
+impl BisectEngine {
+    pub fn new(changes: Vec<PackageChange>) -> Self {
+        let high = changes.len();
+        Self::resume(changes, 0, high, Vec::new())
+    }
+
+    /// Continue from a saved range
+    pub fn resume(changes: Vec<PackageChange>, low: usize, high: usize, skipped: Vec<usize>) -> Self {
+        let high = high.min(changes.len());
+        Self {
+            weights: vec![1.0; changes.len()],
+            changes,
+            low: low.min(high),
+            high,
+            pending: None,
+            skipped,
+        }
+    }
+
+    /// Weigh the changes by how likely each is to be the culprit, so likely ones are
+    /// isolated in fewer steps. Ignored unless there is one positive weight per change.
+    pub fn set_weights(&mut self, weights: Vec<f64>) {
+        if weights.len() == self.changes.len() && weights.iter().all(|w| w.is_finite() && *w > 0.0) {
+            self.weights = weights;
+            self.pending = None;
+        }
+    }
+
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+
+    /// Every change, in bisect order
+    pub fn changes(&self) -> &[PackageChange] {
+        &self.changes
+    }
+
+    /// Changes still in play
+    pub fn candidates(&self) -> &[PackageChange] {
+        &self.changes[self.low..self.high]
+    }
+
+    /// Remaining candidate range as (low, high)
+    pub fn range(&self) -> (usize, usize) {
+        (self.low, self.high)
+    }
+
+    pub fn skipped(&self) -> &[usize] {
+        &self.skipped
+    }
+
+    /// Keep only the changes `keep` accepts and start over; false if none are left
+    pub fn restrict(&mut self, keep: impl Fn(&PackageChange) -> bool) -> bool {
+        let (changes, weights): (Vec<_>, Vec<_>) = std::mem::take(&mut self.changes)
+            .into_iter()
+            .zip(std::mem::take(&mut self.weights))
+            .filter(|(c, _)| keep(c))
+            .unzip();
+        *self = Self::new(changes);
+        self.set_weights(weights);
+        !self.changes.is_empty()
+    }
+
+    /// Number of changes to apply for the next test, or None once the search is over.
+    /// Asking again before `record_verdict` returns the same split.
+    pub fn next_candidate(&mut self) -> Option<usize> {
+        if self.pending.is_none() {
+            self.pending = self.split_point(true);
+        }
+        self.pending
+    }
+
+    /// Narrow the range with the verdict for the pending split. Returns the split it applied
+    /// to, or None if no split was pending.
+    pub fn record_verdict(&mut self, verdict: Verdict) -> Option<usize> {
+        let mid = self.pending.take()?;
+        match verdict {
+            Verdict::Good => self.low = mid,
+            Verdict::Bad => self.high = mid,
+            Verdict::Skip => self.skipped.push(mid),
+        }
+        Some(mid)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.split_point(true).is_none()
+    }
+
+    /// Rough number of verdicts still needed: the entropy of the remaining weights, which
+    /// is log2 of the range when all changes are equally likely
+    pub fn steps_left(&self) -> usize {
+        let range = &self.weights[self.low..self.high];
+        let total: f64 = range.iter().sum();
+        let entropy: f64 = range
+            .iter()
+            .map(|w| w / total)
+            .filter(|p| *p > 0.0)
+            .map(|p| -p * p.log2())
+            .sum();
+        entropy.ceil() as usize
+    }
+
+    /// The first change of the final range, once the search is over. None while it runs,
+    /// and when skipped splits left more than one coupled set of changes in the range.
+    pub fn culprit(&self) -> Option<&PackageChange> {
+        if !self.is_finished() || self.split_point(false).is_some() {
+            return None;
+        }
+        self.changes.get(self.low)
+    }
+
+    /// Split that comes closest to halving the remaining weight (the most informative test
+    /// when every verdict is equally trusted) without cutting through a coupled package
+    /// set. Desktop groups (KDE Plasma, GNOME, ...) are only split once they are all that
+    /// is left.
+    fn split_point(&self, honour_skips: bool) -> Option<usize> {
+        if self.low + 1 >= self.high {
+            return None;
+        }
+
+        let is_boundary = |key: fn(&str) -> Option<&'static str>, i: usize| {
+            let current = key(self.changes[i].name());
+            current.is_none() || current != key(self.changes[i - 1].name())
+        };
+
+        // below[i - low] = weight of the changes applied in addition to the known-good ones at split i
+        let mut below = Vec::with_capacity(self.high - self.low + 1);
+        let mut sum = 0.0;
+        below.push(sum);
+        for w in &self.weights[self.low..self.high] {
+            sum += w;
+            below.push(sum);
+        }
+        let half = sum / 2.0;
+
+        let closest = |key: fn(&str) -> Option<&'static str>| {
+            ((self.low + 1)..self.high)
+                .filter(|i| !honour_skips || !self.skipped.contains(i))
+                .filter(|&i| is_boundary(coupling_key, i) && is_boundary(key, i))
+                .min_by(|&a, &b| {
+                    let distance = |i: usize| (below[i - self.low] - half).abs();
+                    distance(a).total_cmp(&distance(b)).then(a.cmp(&b))
+                })
+        };
+
+        closest(presets::ecosystem).or_else(|| closest(coupling_key))
+    }
+}
+
+/// Positions in `verdicts` (split, verdict) that disagree with change `index` being the
+/// culprit: an issue with it not applied, or no issue with it applied. Skips never do.
+pub fn contradicting(index: usize, verdicts: &[(usize, Verdict)]) -> Vec<usize> {
+    verdicts
+        .iter()
+        .enumerate()
+        .filter(|(_, (split, verdict))| match verdict {
+            Verdict::Bad => index >= *split,
+            Verdict::Good => index < *split,
+            Verdict::Skip => false,
+        })
+        .map(|(pos, _)| pos)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_diff::Package;
+
+    fn upgrade(name: &str) -> PackageChange {
+        PackageChange::Upgraded(
+            Package {
+                name: name.to_string(),
+                version: "2".to_string(),
+            },
+            "1".to_string(),
+            "2".to_string(),
+        )
+    }
+
+    fn changes(names: &[&str]) -> Vec<PackageChange> {
+        names.iter().map(|n| upgrade(n)).collect()
+    }
+
+    /// Bad whenever `culprit` is applied; Skip for the splits in `skip`. Records every split it saw.
+    struct Scripted {
+        culprit: &'static str,
+        skip: Vec<usize>,
+        splits: Vec<usize>,
+    }
+
+    impl Scripted {
+        fn new(culprit: &'static str) -> Self {
+            Self {
+                culprit,
+                skip: Vec::new(),
+                splits: Vec::new(),
+            }
+        }
+    }
+
+    impl Tester for Scripted {
+        fn test(&mut self, apply: &[PackageChange]) -> Result<Verdict> {
+            self.splits.push(apply.len());
+            if self.skip.contains(&apply.len()) {
+                return Ok(Verdict::Skip);
+            }
+            Ok(Verdict::from_issue(apply.iter().any(|c| c.name() == self.culprit)))
+        }
+    }
+
+    fn run(engine: &mut BisectEngine, tester: &mut Scripted) -> Option<String> {
+        while let Some(mid) = engine.next_candidate() {
+            let verdict = tester.test(&engine.changes()[..mid]).unwrap();
+            assert_eq!(engine.record_verdict(verdict), Some(mid));
+        }
+        engine.culprit().map(|c| c.name().to_string())
+    }
+
+    const EIGHT: [&str; 8] = ["a", "b", "c", "d", "e", "f", "g", "h"];
+
+    #[test]
+    fn finds_the_culprit_at_every_position() {
+        for culprit in EIGHT {
+            let mut engine = BisectEngine::new(changes(&EIGHT));
+            let mut tester = Scripted::new(culprit);
+            assert_eq!(run(&mut engine, &mut tester).as_deref(), Some(culprit));
+            assert_eq!(tester.splits.len(), 3, "culprit {}", culprit);
+        }
+    }
+
+    #[test]
+    fn candidate_is_stable_until_answered() {
+        let mut engine = BisectEngine::new(changes(&EIGHT));
+        assert_eq!(engine.record_verdict(Verdict::Bad), None);
+        assert_eq!(engine.steps_left(), 3);
+
+        let mid = engine.next_candidate();
+        assert_eq!(mid, Some(4));
+        assert_eq!(engine.next_candidate(), mid);
+        assert_eq!(engine.record_verdict(Verdict::Good), mid);
+        assert_eq!(engine.range(), (4, 8));
+        assert_eq!(engine.record_verdict(Verdict::Good), None);
+        assert_eq!(engine.candidates().len(), 4);
+    }
+
+    #[test]
+    fn skipped_splits_are_not_offered_again() {
+        let mut engine = BisectEngine::new(changes(&EIGHT));
+        let mut tester = Scripted::new("f");
+        tester.skip = vec![4];
+
+        assert_eq!(run(&mut engine, &mut tester).as_deref(), Some("f"));
+        assert_eq!(tester.splits.iter().filter(|&&s| s == 4).count(), 1);
+        assert_eq!(engine.skipped(), &[4]);
+    }
+
+    #[test]
+    fn skips_that_leave_two_candidates_name_no_culprit() {
+        let mut engine = BisectEngine::new(changes(&["a", "b"]));
+        let mut tester = Scripted::new("b");
+        tester.skip = vec![1];
+
+        assert_eq!(run(&mut engine, &mut tester), None);
+        assert!(engine.is_finished());
+        assert_eq!(engine.range(), (0, 2));
+    }
+
+    #[test]
+    fn coupled_packages_are_never_split() {
+        let names = ["foo", "mesa", "lib32-mesa", "vulkan-radeon", "bar"];
+        let mut engine = BisectEngine::new(changes(&names));
+        let mut tester = Scripted::new("lib32-mesa");
+
+        // The whole mesa set is the culprit, reported by its first member
+        assert_eq!(run(&mut engine, &mut tester).as_deref(), Some("mesa"));
+        assert_eq!(engine.range(), (1, 4));
+        assert!(tester.splits.iter().all(|s| !(2..=3).contains(s)), "splits {:?}", tester.splits);
+    }
+
+    #[test]
+    fn ecosystem_groups_split_only_when_all_that_is_left() {
+        let names = ["a", "b", "qt6-base", "qt6-svg", "qt6-wayland", "qt6-tools", "c", "d"];
+        let mut engine = BisectEngine::new(changes(&names));
+        let first = engine.next_candidate().unwrap();
+        assert!(!(3..=5).contains(&first), "split {} cuts through Qt", first);
+
+        let mut tester = Scripted::new("qt6-wayland");
+        assert_eq!(run(&mut engine, &mut tester).as_deref(), Some("qt6-wayland"));
+    }
+
+    #[test]
+    fn weights_isolate_likely_culprits_first() {
+        let mut engine = BisectEngine::new(changes(&EIGHT));
+        engine.set_weights(vec![8.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(engine.next_candidate(), Some(1));
+
+        let mut tester = Scripted::new("a");
+        assert_eq!(run(&mut engine, &mut tester).as_deref(), Some("a"));
+        assert_eq!(tester.splits, vec![1]);
+    }
+
+    #[test]
+    fn invalid_weights_are_ignored() {
+        let mut engine = BisectEngine::new(changes(&EIGHT));
+        engine.set_weights(vec![1.0, 2.0]);
+        engine.set_weights(vec![0.0; 8]);
+        assert!(engine.weights().iter().all(|w| *w == 1.0));
+        assert_eq!(engine.next_candidate(), Some(4));
+    }
+
+    #[test]
+    fn single_change_needs_no_test() {
+        let mut engine = BisectEngine::new(changes(&["only"]));
+        assert_eq!(engine.next_candidate(), None);
+        assert!(engine.is_finished());
+        assert_eq!(engine.steps_left(), 0);
+        assert_eq!(engine.culprit().map(|c| c.name()), Some("only"));
+    }
+
+    #[test]
+    fn contradicting_verdicts() {
+        let verdicts = [(4, Verdict::Good), (6, Verdict::Bad), (5, Verdict::Skip)];
+        assert!(contradicting(4, &verdicts).is_empty());
+        assert_eq!(contradicting(2, &verdicts), vec![0]);
+        assert_eq!(contradicting(7, &verdicts), vec![1]);
+    }
+}
@@ -0,0 +1,122 @@
+// Fedora Bodhi: finding the update a culprit build shipped in, and sending karma
+//
+// Updates in updates-testing wait for karma before going stable, and recently pushed
+// stable updates can still be unpushed or superseded quickly, so negative feedback with
+// the bisect's evidence is most useful there. Bodhi requires a Fedora account login, so
+// the comment goes through the official `bodhi` client, which handles the OIDC flow.
+
+use anyhow::{Context, Result};
+use chrono::{NaiveDateTime, Utc};
+use serde::Deserialize;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::audit;
+use crate::bisect;
+use crate::http;
+use crate::package_diff::PackageChange;
+use crate::pins::RecordedTest;
+
+const BODHI_URL: &str = "https://bodhi.fedoraproject.org";
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Stable updates younger than this still get feedback
+const RECENT_STABLE_DAYS: i64 = 30;
+
+/// A Bodhi update
+#[derive(Debug, Deserialize)]
+pub struct Update {
+    /// e.g. FEDORA-2024-1a2b3c4d5e
+    pub alias: String,
+    /// "pending", "testing", "stable", "obsolete", ...
+    pub status: String,
+    /// "2024-05-20 10:00:00" (UTC)
+    #[serde(default)]
+    pub date_stable: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UpdateList {
+    updates: Vec<Update>,
+}
+
+impl Update {
+    pub fn url(&self) -> String {
+        format!("{}/updates/{}", BODHI_URL, self.alias)
+    }
+
+    /// Still in testing, or pushed to stable recently enough to act on feedback
+    pub fn wants_feedback(&self) -> bool {
+        match self.status.as_str() {
+            "pending" | "testing" => true,
+            "stable" => self
+                .date_stable
+                .as_deref()
+                .and_then(|date| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").ok())
+                .is_some_and(|date| (Utc::now().naive_utc() - date).num_days() <= RECENT_STABLE_DAYS),
+            _ => false,
+        }
+    }
+
+    /// "testing" or "stable for 3 days"
+    pub fn describe(&self) -> String {
+        let age = self
+            .date_stable
+            .as_deref()
+            .and_then(|date| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").ok())
+            .map(|date| (Utc::now().naive_utc() - date).num_days());
+        match (self.status.as_str(), age) {
+            ("stable", Some(days)) => format!("stable for {} days", days),
+            (status, _) => status.to_string(),
+        }
+    }
+}
+
+/// The update that shipped build `nvr` (name-version-release)
+pub fn find_update(nvr: &str) -> Result<Option<Update>> {
+    let url = format!("{}/updates/?builds={}", BODHI_URL, http::encode(nvr));
+    let response = http::get(&url, TIMEOUT)?;
+    if !response.is_success() {
+        anyhow::bail!("Bodhi answered HTTP {}", response.status);
+    }
+    let list: UpdateList = response.json().context("Unexpected answer from Bodhi")?;
+    Ok(list.updates.into_iter().next())
+}
+
+/// Feedback text: what broke, how it was isolated, and how to reproduce it
+pub fn evidence(culprit: &PackageChange, test: Option<&RecordedTest>) -> String {
+    let mut text = format!(
+        "This update causes a regression on my system. eshu-trace bisected the package changes between a working and a broken state and isolated {}.",
+        bisect::describe(culprit)
+    );
+
+    if let Ok(Some(report)) = bisect::load_report() {
+        if report.culprit.as_ref().is_some_and(|c| c.name() == culprit.name()) {
+            let tested = report.history.iter().filter(|s| !s.skipped).count();
+            text.push_str(&format!(" {} package combinations were tested", tested));
+            match &report.assessment {
+                Some(assessment) => text.push_str(&format!(" (confidence {:.0}%).", assessment.confidence * 100.0)),
+                None => text.push('.'),
+            }
+            let coupled: Vec<&str> = report.group.iter().map(|c| c.name()).filter(|n| *n != culprit.name()).collect();
+            if !coupled.is_empty() {
+                text.push_str(&format!(" It moves together with {}.", coupled.join(", ")));
+            }
+        }
+    }
+
+    if let Some(test) = test {
+        text.push_str(&format!(" Reproducer (fails with this update, passes without it): {}", test.command));
+    }
+    text
+}
+
+/// Post the comment with -1 karma through the bodhi client
+pub fn send_negative_karma(update: &Update, text: &str) -> Result<bool> {
+    let status = audit::run(
+        &format!("bodhi: negative karma for {}", update.alias),
+        Command::new("bodhi")
+            .args(["updates", "comment", &update.alias, text, "--karma", "-1"]),
+    )?;
+    Ok(status.success())
+}
@@ -0,0 +1,268 @@
+// Compare kernel command line and sysctl settings between snapshots
+//
+// Neither is a package, but both come from files packages ship or rewrite: a
+// bootloader update can change the default cmdline, a new sysctl.d drop-in can
+// override a tuned value. The bisect then blames the package without saying why.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ownership;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingKind {
+    Cmdline,
+    Sysctl,
+}
+
+impl SettingKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SettingKind::Cmdline => "cmdline",
+            SettingKind::Sysctl => "sysctl",
+        }
+    }
+}
+
+/// A kernel parameter or sysctl key whose value differs between two snapshots
+#[derive(Debug, Clone)]
+pub struct SettingChange {
+    pub kind: SettingKind,
+    pub key: String,
+    /// None if the setting was not present
+    pub old: Option<String>,
+    pub new: Option<String>,
+    /// File that set the new value (or held the old one, if it was removed)
+    pub source: String,
+    /// Packages owning `source`
+    pub owners: Vec<String>,
+}
+
+/// Cmdline and sysctl differences between the good and bad snapshot roots
+pub fn detect(good_root: &str, bad_root: &str) -> Vec<SettingChange> {
+    let mut changes = cmdline_changes(good_root, bad_root);
+    changes.extend(sysctl_changes(good_root, bad_root));
+
+    for change in &mut changes {
+        let root = if change.new.is_some() { bad_root } else { good_root };
+        change.owners = ownership::owning_packages_in(root, &change.source).unwrap_or_default();
+    }
+    changes
+}
+
+/// Parameter name -> the parameter(s) as written, per cmdline source file
+type Params = BTreeMap<String, String>;
+
+fn cmdline_changes(good_root: &str, bad_root: &str) -> Vec<SettingChange> {
+    let before = cmdline_sources(Path::new(good_root));
+    let after = cmdline_sources(Path::new(bad_root));
+
+    let mut sources: Vec<&String> = before.keys().chain(after.keys()).collect();
+    sources.sort();
+    sources.dedup();
+
+    let empty = Params::new();
+    let mut changes = Vec::new();
+    for source in sources {
+        let old = before.get(source).unwrap_or(&empty);
+        let new = after.get(source).unwrap_or(&empty);
+        changes.extend(compare(SettingKind::Cmdline, source, old, new));
+    }
+    changes
+}
+
+/// Where distributions keep the kernel command line, keyed by file
+fn cmdline_sources(root: &Path) -> BTreeMap<String, Params> {
+    let mut sources = BTreeMap::new();
+
+    // systemd-boot/UKI (kernel-install) and dracut
+    if let Some(text) = read_in(root, "/etc/kernel/cmdline") {
+        let text: Vec<&str> = text.lines().filter(|l| !l.trim_start().starts_with('#')).collect();
+        sources.insert("/etc/kernel/cmdline".to_string(), params(&text.join(" ")));
+    }
+
+    // GRUB defaults; grub.d drop-ins (Ubuntu) override or extend them
+    let mut grub_files = vec!["/etc/default/grub".to_string()];
+    grub_files.extend(files_in(root, "/etc/default/grub.d", ".cfg"));
+    let mut grub: BTreeMap<&str, String> = BTreeMap::new();
+    let mut grub_source = None;
+    for file in &grub_files {
+        let Some(text) = read_in(root, file) else { continue };
+        for var in ["GRUB_CMDLINE_LINUX", "GRUB_CMDLINE_LINUX_DEFAULT"] {
+            if let Some(value) = shell_assignment(&text, var) {
+                let previous = grub.get(var).cloned().unwrap_or_default();
+                let value = value.replace(&format!("${{{}}}", var), &previous).replace(&format!("${}", var), &previous);
+                grub.insert(var, value);
+                grub_source = Some(file.clone());
+            }
+        }
+    }
+    if let Some(file) = grub_source {
+        let joined: Vec<&str> = grub.values().map(|v| v.as_str()).collect();
+        sources.insert(file, params(&joined.join(" ")));
+    }
+
+    // What grub-mkconfig actually generated, for the default (first) entry
+    for cfg in ["/boot/grub/grub.cfg", "/boot/grub2/grub.cfg"] {
+        let Some(text) = read_in(root, cfg) else { continue };
+        let linux = text
+            .lines()
+            .map(str::trim)
+            .find(|l| l.starts_with("linux ") || l.starts_with("linuxefi "));
+        if let Some(line) = linux {
+            // Skip the keyword and kernel image path
+            let args: Vec<&str> = line.split_whitespace().skip(2).collect();
+            let mut found = params(&args.join(" "));
+            found.remove("BOOT_IMAGE");
+            sources.insert(cfg.to_string(), found);
+        }
+    }
+
+    // Boot loader spec entries, when /boot is part of the snapshot
+    for entry in files_in(root, "/boot/loader/entries", ".conf") {
+        if entry.contains("fallback") {
+            continue;
+        }
+        let Some(text) = read_in(root, &entry) else { continue };
+        let options: Vec<&str> = text
+            .lines()
+            .filter_map(|l| l.trim().strip_prefix("options"))
+            .collect();
+        if !options.is_empty() {
+            sources.insert(entry, params(&options.join(" ")));
+        }
+    }
+
+    sources
+}
+
+/// Split a command line into parameters; repeated ones (console=) keep every value
+fn params(cmdline: &str) -> Params {
+    let mut found = Params::new();
+    for token in cmdline.split_whitespace() {
+        let key = token.split_once('=').map(|(k, _)| k).unwrap_or(token);
+        let entry = found.entry(key.to_string()).or_default();
+        if !entry.is_empty() {
+            entry.push(' ');
+        }
+        entry.push_str(token);
+    }
+    found
+}
+
+/// Value of `VAR="..."` in a shell-style config file (last assignment wins)
+fn shell_assignment(text: &str, var: &str) -> Option<String> {
+    text.lines()
+        .rev()
+        .find_map(|l| l.trim().strip_prefix(var)?.strip_prefix('='))
+        .map(|v| v.trim().trim_matches(|c| c == '"' || c == '\'').to_string())
+}
+
+fn sysctl_changes(good_root: &str, bad_root: &str) -> Vec<SettingChange> {
+    let before = sysctl_settings(Path::new(good_root));
+    let after = sysctl_settings(Path::new(bad_root));
+
+    let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let old = before.get(key);
+            let new = after.get(key);
+            if old.map(|(v, _)| v) == new.map(|(v, _)| v) {
+                return None;
+            }
+            let source = new.or(old).map(|(_, file)| file.clone()).unwrap_or_default();
+            Some(SettingChange {
+                kind: SettingKind::Sysctl,
+                key: key.clone(),
+                old: old.map(|(v, _)| v.clone()),
+                new: new.map(|(v, _)| v.clone()),
+                source,
+                owners: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// Effective sysctl values and the file setting each, in sysctl.d(5) order:
+/// a file name in an earlier directory masks the same name in later ones,
+/// files apply sorted by name, /etc/sysctl.conf last
+fn sysctl_settings(root: &Path) -> BTreeMap<String, (String, String)> {
+    const DIRS: [&str; 4] = ["/etc/sysctl.d", "/usr/local/lib/sysctl.d", "/usr/lib/sysctl.d", "/lib/sysctl.d"];
+
+    let mut by_name: BTreeMap<String, String> = BTreeMap::new();
+    for dir in DIRS {
+        for file in files_in(root, dir, ".conf") {
+            let name = file.rsplit('/').next().unwrap_or(&file).to_string();
+            by_name.entry(name).or_insert(file);
+        }
+    }
+    let mut files: Vec<String> = by_name.into_values().collect();
+    files.push("/etc/sysctl.conf".to_string());
+
+    let mut settings = BTreeMap::new();
+    for file in files {
+        let Some(text) = read_in(root, &file) else { continue };
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else { continue };
+            // "-key = value" only means errors are ignored
+            let key = key.trim().trim_start_matches('-').replace('/', ".");
+            settings.insert(key, (value.trim().to_string(), file.clone()));
+        }
+    }
+    settings
+}
+
+fn compare(kind: SettingKind, source: &str, old: &Params, new: &Params) -> Vec<SettingChange> {
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|key| SettingChange {
+            kind,
+            key: key.clone(),
+            old: old.get(key).cloned(),
+            new: new.get(key).cloned(),
+            source: source.to_string(),
+            owners: Vec::new(),
+        })
+        .collect()
+}
+
+/// Absolute paths of `dir/*<suffix>` inside `root`, sorted
+fn files_in(root: &Path, dir: &str, suffix: &str) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(in_root(root, dir))
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .filter(|name| name.ends_with(suffix))
+                .map(|name| format!("{}/{}", dir, name))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+/// Read `path` inside `root`, following an absolute symlink (99-sysctl.conf -> /etc/sysctl.conf)
+/// within the root rather than on the host
+fn read_in(root: &Path, path: &str) -> Option<String> {
+    let full = in_root(root, path);
+    match fs::read_link(&full) {
+        Ok(target) if target.is_absolute() => fs::read_to_string(in_root(root, &target.to_string_lossy())).ok(),
+        _ => fs::read_to_string(full).ok(),
+    }
+}
+
+fn in_root(root: &Path, path: &str) -> PathBuf {
+    root.join(path.trim_start_matches('/'))
+}
@@ -0,0 +1,364 @@
+// Managed cache of previous package versions, kept independently of the distro's cache
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::package_diff::version_compare;
+
+/// System-wide, so the package manager hook (running as root) and eshu-trace see the same files
+const CACHE_DIR: &str = "/var/cache/eshu-trace";
+/// Versions kept per package when no limit has been configured
+const DEFAULT_VERSIONS: usize = 3;
+
+/// EnvironmentFile of paccache.service (pacman-contrib)
+const PACCACHE_CONF: &str = "/etc/conf.d/pacman-contrib";
+
+/// Distro caches that package files are collected from
+const DISTRO_CACHES: &[&str] = &["/var/cache/pacman/pkg", "/var/cache/apt/archives"];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CacheIndex {
+    /// Packages whose old versions are preserved
+    #[serde(default)]
+    pub kept: Vec<String>,
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    #[serde(default)]
+    pub versions: Option<usize>,
+}
+
+/// A package file in the managed cache
+#[derive(Debug, Clone)]
+pub struct CachedPackage {
+    pub name: String,
+    pub version: String,
+    pub path: PathBuf,
+    pub size: u64,
+    /// When the file was written into the cache
+    pub modified: Option<SystemTime>,
+}
+
+impl CacheIndex {
+    pub fn load() -> Result<Self> {
+        let path = index_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = fs::read_to_string(&path).context("Failed to read cache index")?;
+        serde_json::from_str(&data).context("Failed to parse cache index")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs::create_dir_all(packages_dir()).context("Failed to create cache directory (run as root)")?;
+        fs::write(index_path(), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn versions(&self) -> usize {
+        self.versions.unwrap_or(DEFAULT_VERSIONS)
+    }
+}
+
+pub fn packages_dir() -> PathBuf {
+    Path::new(CACHE_DIR).join("packages")
+}
+
+fn index_path() -> PathBuf {
+    Path::new(CACHE_DIR).join("cache.json")
+}
+
+/// Every package file in the managed cache
+pub fn list() -> Vec<CachedPackage> {
+    let entries = match fs::read_dir(packages_dir()) {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let file = entry.file_name().to_string_lossy().to_string();
+            let (name, version) = parse_package_file(&file)?;
+            let metadata = entry.metadata().ok();
+            Some(CachedPackage {
+                name,
+                version,
+                size: metadata.as_ref().map(|m| m.len()).unwrap_or(0),
+                modified: metadata.and_then(|m| m.modified().ok()),
+                path: entry.path(),
+            })
+        })
+        .collect()
+}
+
+/// Managed copy of `name` at `version`, if one was preserved
+pub fn find(name: &str, version: &str) -> Option<PathBuf> {
+    list()
+        .into_iter()
+        .find(|p| p.name == name && p.version == version)
+        .map(|p| p.path)
+}
+
+/// Copy distro-cache files of kept packages into the managed cache. Returns how many were added.
+pub fn sync(index: &CacheIndex) -> Result<usize> {
+    let target = packages_dir();
+    fs::create_dir_all(&target).context("Failed to create cache directory (run as root)")?;
+
+    let mut added = 0;
+    for dir in DISTRO_CACHES {
+        let entries = match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            let file = entry.file_name().to_string_lossy().to_string();
+            let name = match parse_package_file(&file) {
+                Some((name, _)) => name,
+                None => continue,
+            };
+            if !index.kept.contains(&name) || target.join(&file).exists() {
+                continue;
+            }
+
+            // Hard links cost nothing when both caches share a filesystem
+            if fs::hard_link(entry.path(), target.join(&file)).is_err() {
+                fs::copy(entry.path(), target.join(&file))?;
+            }
+            let sig = entry.path().with_file_name(format!("{}.sig", file));
+            if sig.exists() {
+                let _ = fs::copy(&sig, target.join(format!("{}.sig", file)));
+            }
+            added += 1;
+        }
+    }
+
+    Ok(added)
+}
+
+/// Drop forgotten packages, versions beyond the per-package limit, then the oldest files
+/// until the cache fits `max_bytes`. Returns the removed files.
+pub fn prune(index: &CacheIndex) -> Result<Vec<CachedPackage>> {
+    let mut by_name: HashMap<String, Vec<CachedPackage>> = HashMap::new();
+    for package in list() {
+        by_name.entry(package.name.clone()).or_default().push(package);
+    }
+
+    let mut remove = Vec::new();
+    let mut keep = Vec::new();
+    for (name, mut versions) in by_name {
+        if !index.kept.contains(&name) {
+            remove.extend(versions);
+            continue;
+        }
+
+        versions.sort_by(|a, b| newest_first(&a.version, &b.version));
+        let old = versions.split_off(index.versions().min(versions.len()));
+        remove.extend(old);
+        keep.extend(versions);
+    }
+
+    if let Some(max) = index.max_bytes {
+        // Versions of different packages don't compare, so the oldest files go first
+        keep.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| a.path.cmp(&b.path)));
+        let mut total: u64 = keep.iter().map(|p| p.size).sum();
+        while total > max {
+            match keep.pop() {
+                Some(p) => {
+                    total -= p.size;
+                    remove.push(p);
+                }
+                None => break,
+            }
+        }
+    }
+
+    for package in &remove {
+        fs::remove_file(&package.path)?;
+        let _ = fs::remove_file(package.path.with_file_name(format!(
+            "{}.sig",
+            package.path.file_name().unwrap_or_default().to_string_lossy()
+        )));
+    }
+
+    Ok(remove)
+}
+
+/// A total order for sorting: versions that compare equal fall back to the text
+fn newest_first(a: &str, b: &str) -> Ordering {
+    if version_compare(a, b) {
+        Ordering::Less
+    } else if version_compare(b, a) {
+        Ordering::Greater
+    } else {
+        a.cmp(b)
+    }
+}
+
+/// Name and version from a pacman, deb or rpm file name
+pub fn parse_package_file(file: &str) -> Option<(String, String)> {
+    if file.ends_with(".sig") || file.ends_with(".part") {
+        return None;
+    }
+
+    // mesa-1:24.0.1-1-x86_64.pkg.tar.zst
+    if let Some((stem, _)) = file.split_once(".pkg.tar") {
+        let mut parts = stem.rsplitn(4, '-');
+        let (_arch, rel, ver, name) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        return Some((name.to_string(), format!("{}-{}", ver, rel)));
+    }
+
+    // libc6_1%3a2.36-9_amd64.deb
+    if let Some(stem) = file.strip_suffix(".deb") {
+        let mut parts = stem.split('_');
+        let (name, ver) = (parts.next()?, parts.next()?);
+        return Some((name.to_string(), ver.replace("%3a", ":")));
+    }
+
+    // mesa-libGL-24.0.1-1.fc40.x86_64.rpm
+    if let Some(stem) = file.strip_suffix(".rpm") {
+        let (stem, _arch) = stem.rsplit_once('.')?;
+        let mut parts = stem.rsplitn(3, '-');
+        let (rel, ver, name) = (parts.next()?, parts.next()?, parts.next()?);
+        return Some((name.to_string(), format!("{}-{}", ver, rel)));
+    }
+
+    None
+}
+
+/// "5G", "500M", "1.5GiB" or plain bytes
+pub fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let split = text.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.trim().parse().with_context(|| format!("Invalid size: {}", text))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => anyhow::bail!("Invalid size unit in {} (use K, M, G or T)", text),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Preserve `packages` in the managed cache and exclude them from the distro's cache cleaner
+pub fn protect(distro: &str, packages: &[String]) -> Result<usize> {
+    let mut index = CacheIndex::load()?;
+    for package in packages {
+        if !index.kept.contains(package) {
+            index.kept.push(package.clone());
+        }
+    }
+    index.save()?;
+
+    if matches!(distro, "arch" | "manjaro" | "endeavouros") {
+        exclude_from_paccache(packages)?;
+    }
+
+    sync(&index)
+}
+
+/// Add `-i <packages>` to the paccache.service arguments in /etc/conf.d/pacman-contrib
+fn exclude_from_paccache(packages: &[String]) -> Result<()> {
+    let path = Path::new(PACCACHE_CONF);
+    if !path.exists() && !Path::new("/usr/bin/paccache").exists() {
+        return Ok(());
+    }
+
+    let conf = fs::read_to_string(path).unwrap_or_default();
+    // paccache.service defaults to keeping three versions
+    let current = conf
+        .lines()
+        .find_map(|l| l.strip_prefix("PACCACHE_ARGS="))
+        .map(|v| v.trim().trim_matches(|c| c == '\'' || c == '"').to_string())
+        .unwrap_or_else(|| "-k3".to_string());
+
+    let mut ignored: Vec<String> = current
+        .split_whitespace()
+        .skip_while(|a| *a != "-i" && *a != "--ignore")
+        .nth(1)
+        .map(|list| list.split(',').map(String::from).collect())
+        .unwrap_or_default();
+    for package in packages {
+        if !ignored.contains(package) {
+            ignored.push(package.clone());
+        }
+    }
+
+    let mut args: Vec<&str> = Vec::new();
+    let mut words = current.split_whitespace();
+    while let Some(word) = words.next() {
+        if word == "-i" || word == "--ignore" {
+            words.next();
+        } else {
+            args.push(word);
+        }
+    }
+    let line = format!("PACCACHE_ARGS='{} -i {}'", args.join(" "), ignored.join(","));
+
+    let mut lines: Vec<String> = conf
+        .lines()
+        .filter(|l| !l.starts_with("PACCACHE_ARGS="))
+        .map(String::from)
+        .collect();
+    lines.push(line);
+
+    fs::write(path, lines.join("\n") + "\n").with_context(|| format!("Failed to update {}", PACCACHE_CONF))?;
+    Ok(())
+}
+
+/// Package manager hook that runs `eshu-trace cache sync` around every transaction
+pub fn install_hook() -> Result<PathBuf> {
+    let exe = std::env::current_exe()?.to_string_lossy().to_string();
+
+    if Path::new("/etc/pacman.d").is_dir() {
+        let dir = Path::new("/etc/pacman.d/hooks");
+        fs::create_dir_all(dir)?;
+        let path = dir.join("eshu-trace-cache.hook");
+        fs::write(
+            &path,
+            format!(
+                "[Trigger]\nOperation = Upgrade\nOperation = Remove\nType = Package\nTarget = *\n\n\
+                 [Action]\nDescription = Preserving previous package versions (eshu-trace)...\n\
+                 When = PostTransaction\nExec = {} cache sync --quiet\n",
+                exe
+            ),
+        )?;
+        return Ok(path);
+    }
+
+    if Path::new("/etc/apt/apt.conf.d").is_dir() {
+        // apt deletes downloaded debs after installing, so collect them before dpkg runs
+        let path = Path::new("/etc/apt/apt.conf.d/80eshu-trace-cache");
+        fs::write(path, format!("DPkg::Pre-Invoke {{ \"{} cache sync --quiet || true\"; }};\n", exe))?;
+        return Ok(path.to_path_buf());
+    }
+
+    anyhow::bail!("No supported package manager hook directory (pacman or apt) found")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newest_first_is_a_total_order() {
+        // Equal as versions, so only the text orders them
+        assert_eq!(newest_first("1.0", "1.0.0a"), Ordering::Less);
+        assert_eq!(newest_first("1.0.0a", "1.0"), Ordering::Greater);
+
+        let mut versions = vec!["1.2-1", "1.10-1", "1.2-1", "1.9-2", "1.9-10"];
+        versions.sort_by(|a, b| newest_first(a, b));
+        assert_eq!(versions, vec!["1.10-1", "1.9-10", "1.9-2", "1.2-1", "1.2-1"]);
+    }
+}
@@ -0,0 +1,180 @@
+// Sharing culprit findings with the community database, and asking it about upgrades
+//
+// A finding carries only what helps others recognise the same breakage: the package and
+// versions, distro, architecture, symptom tags and the bisect's confidence. Snapshot ids,
+// host names, paths, the free-text symptom and the license stay on this machine. The
+// database answers with a page where others hit by the same update can confirm it.
+// `oracle` sends the pending upgrade list and gets back the regression reports filed
+// against those versions.
+
+use anyhow::{Context, Result};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config;
+use crate::http;
+use crate::package_diff::PackageChange;
+use crate::priors;
+use crate::upgrades::PendingUpgrade;
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// An anonymized culprit finding
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub package: String,
+    /// "upgraded", "downgraded", "added" or "removed"
+    pub change: &'static str,
+    pub from_version: Option<String>,
+    pub to_version: Option<String>,
+    pub distro: String,
+    pub distro_version: Option<String>,
+    pub arch: &'static str,
+    pub symptom_tags: Vec<&'static str>,
+    pub confidence: f64,
+    /// Packages that had to change together with the culprit
+    pub moves_with: Vec<String>,
+    pub eshu_trace_version: &'static str,
+}
+
+#[derive(Debug, Deserialize)]
+struct Submitted {
+    url: String,
+}
+
+/// A regression report in the community database
+#[derive(Debug, Deserialize)]
+pub struct KnownIssue {
+    pub package: String,
+    /// Version the regression was found in
+    pub version: String,
+    pub title: String,
+    /// "open", "confirmed" or "fixed"
+    pub status: String,
+    /// Other users who confirmed it
+    #[serde(default)]
+    pub confirmations: u32,
+    #[serde(default)]
+    pub symptom_tags: Vec<String>,
+    #[serde(default)]
+    pub fixed_in: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+impl KnownIssue {
+    pub fn is_open(&self) -> bool {
+        self.status != "fixed"
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct IssueList {
+    issues: Vec<KnownIssue>,
+}
+
+impl Finding {
+    pub fn new(culprit: &PackageChange, group: &[PackageChange], confidence: f64, root: &str, symptom: Option<&str>) -> Self {
+        let (change, from_version, to_version) = match culprit {
+            PackageChange::Upgraded(_, old, new) => ("upgraded", Some(old.clone()), Some(new.clone())),
+            PackageChange::Downgraded(_, old, new) => ("downgraded", Some(old.clone()), Some(new.clone())),
+            PackageChange::Added(pkg) => ("added", None, Some(pkg.version.clone())),
+            PackageChange::Removed(pkg) => ("removed", Some(pkg.version.clone()), None),
+        };
+
+        Self {
+            package: culprit.name().to_string(),
+            change,
+            from_version,
+            to_version,
+            distro: os_release_field(root, "ID").unwrap_or_else(|| "unknown".to_string()),
+            distro_version: os_release_field(root, "VERSION_ID"),
+            arch: std::env::consts::ARCH,
+            symptom_tags: symptom.map(priors::symptom_tags).unwrap_or_default(),
+            // Two decimals are plenty and don't fingerprint the exact step count
+            confidence: (confidence * 100.0).round() / 100.0,
+            moves_with: group
+                .iter()
+                .filter(|c| c.name() != culprit.name())
+                .map(|c| c.name().to_string())
+                .collect(),
+            eshu_trace_version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+/// Show the finding, ask, and submit it; failures only warn
+pub fn offer_share(finding: &Finding) {
+    if let Err(e) = try_offer_share(finding) {
+        println!("{} Could not share the finding: {:#}", "⚠".yellow(), e);
+    }
+}
+
+fn try_offer_share(finding: &Finding) -> Result<()> {
+    let Some(base) = config::community_url()? else {
+        println!(
+            "{} No community database configured (community_url in config.json or ESHU_TRACE_COMMUNITY_URL)",
+            "⚠".yellow()
+        );
+        return Ok(());
+    };
+
+    println!();
+    println!("{}", "Sharing this finding (nothing else leaves the machine):".cyan());
+    println!("{}", serde_json::to_string_pretty(finding)?);
+    let share = dialoguer::Confirm::new()
+        .with_prompt(format!("Submit it to {}?", base))
+        .default(true)
+        .interact()?;
+    if !share {
+        return Ok(());
+    }
+
+    let url = submit(&base, finding)?;
+    println!("{} Shared. Others affected by this update can confirm it at:", "✓".green());
+    println!("  {}", url.cyan());
+    Ok(())
+}
+
+/// POST the finding to `<base>/v1/findings`; returns the page where others can confirm it.
+/// Sent once: a retry after a lost answer would file the finding twice.
+pub fn submit(base: &str, finding: &Finding) -> Result<String> {
+    let url = format!("{}/v1/findings", base.trim_end_matches('/'));
+    let response = http::post_json_once(&url, finding, None, TIMEOUT)?;
+    if !response.is_success() {
+        anyhow::bail!("{} answered HTTP {}", url, response.status);
+    }
+
+    let submitted: Submitted = response.json().context("Community database answered without a url")?;
+    Ok(submitted.url)
+}
+
+/// Reports filed against the versions `upgrades` would install, via `<base>/v1/issues`
+pub fn known_issues(base: &str, upgrades: &[PendingUpgrade]) -> Result<Vec<KnownIssue>> {
+    let url = format!("{}/v1/issues", base.trim_end_matches('/'));
+    let query = serde_json::json!({
+        "distro": os_release_field("/", "ID"),
+        "distro_version": os_release_field("/", "VERSION_ID"),
+        "arch": std::env::consts::ARCH,
+        "packages": upgrades
+            .iter()
+            .map(|u| serde_json::json!({ "name": u.name, "from": u.from, "to": u.to }))
+            .collect::<Vec<_>>(),
+    });
+
+    let response = http::post_json(&url, &query, TIMEOUT)?;
+    if !response.is_success() {
+        anyhow::bail!("{} answered HTTP {}", url, response.status);
+    }
+    let list: IssueList = response.json().context("Community database answered without an issue list")?;
+    Ok(list.issues)
+}
+
+fn os_release_field(root: &str, key: &str) -> Option<String> {
+    let text = std::fs::read_to_string(Path::new(root).join("etc/os-release")).ok()?;
+    text.lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+        .map(|value| value.trim_matches('"').to_string())
+}
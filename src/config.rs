@@ -0,0 +1,94 @@
+// User configuration (config.json in the XDG config directory)
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+
+use crate::paths;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// Base URL of a self-hosted license server used instead of Gumroad
+    #[serde(default)]
+    pub license_server: Option<String>,
+
+    /// Crash report consent: true = always create, false = never ask, unset = ask on panic
+    #[serde(default)]
+    pub crash_reports: Option<bool>,
+
+    /// Endpoint that receives crash reports; without it reports are only saved locally
+    #[serde(default)]
+    pub crash_report_url: Option<String>,
+
+    /// Reuse snapshot listings across invocations for this many seconds (off when unset)
+    #[serde(default)]
+    pub snapshot_cache_seconds: Option<u64>,
+
+    /// Directories holding btrfs snapshots, e.g. ["/.snapshots", "/mnt/btr_pool/@snapshots", "/home/.snapshots"]
+    /// (default just /.snapshots)
+    #[serde(default)]
+    pub btrfs_snapshot_dirs: Vec<String>,
+
+    /// strftime format for dates in all output, e.g. "%d.%m.%Y %H:%M" (default "%Y-%m-%d %H:%M")
+    #[serde(default)]
+    pub date_format: Option<String>,
+
+    /// OpenAI-compatible chat completions URL for culprit analysis, e.g.
+    /// "http://localhost:11434/v1/chat/completions" for a local Ollama
+    #[serde(default)]
+    pub ai_endpoint: Option<String>,
+
+    /// Model name sent to the AI endpoint (default "llama3.1")
+    #[serde(default)]
+    pub ai_model: Option<String>,
+
+    /// Environment variable holding the endpoint's API key (default ESHU_TRACE_AI_KEY)
+    #[serde(default)]
+    pub ai_api_key_env: Option<String>,
+
+    /// AI culprit analysis: true = always send, false = never (offline), unset = ask each time
+    #[serde(default)]
+    pub ai_analysis: Option<bool>,
+
+    /// Base URL of the community culprit database (see README)
+    #[serde(default)]
+    pub community_url: Option<String>,
+
+    /// Shell commands per hook point ("pre-test", "post-step", "pre-fix", "post-fix")
+    #[serde(default)]
+    pub hooks: HashMap<String, Vec<String>>,
+}
+
+/// Load config.json, or defaults if it does not exist
+pub fn load() -> Result<Config> {
+    let path = paths::config_file("config.json");
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let data = fs::read_to_string(&path).context("Failed to read config file")?;
+    serde_json::from_str(&data).context("Failed to parse config file")
+}
+
+/// License server from ESHU_LICENSE_SERVER, falling back to the config file
+pub fn license_server() -> Result<Option<String>> {
+    if let Ok(url) = std::env::var("ESHU_LICENSE_SERVER") {
+        if !url.is_empty() {
+            return Ok(Some(url));
+        }
+    }
+
+    Ok(load()?.license_server)
+}
+
+/// Community database from ESHU_TRACE_COMMUNITY_URL, falling back to the config file
+pub fn community_url() -> Result<Option<String>> {
+    if let Ok(url) = std::env::var("ESHU_TRACE_COMMUNITY_URL") {
+        if !url.is_empty() {
+            return Ok(Some(url));
+        }
+    }
+
+    Ok(load()?.community_url)
+}
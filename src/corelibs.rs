@@ -0,0 +1,86 @@
+// Core system libraries whose changes need extra care (glibc, OpenSSL, systemd, D-Bus, PAM)
+
+use colored::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Medium => "MEDIUM",
+            Severity::High => "HIGH",
+            Severity::Critical => "CRITICAL",
+        }
+    }
+
+    /// "[CRITICAL]" etc., coloured by severity
+    pub fn tag(&self) -> ColoredString {
+        let tag = format!("[{}]", self.label());
+        match self {
+            Severity::Medium => tag.cyan(),
+            Severity::High => tag.yellow(),
+            Severity::Critical => tag.red().bold(),
+        }
+    }
+}
+
+/// A changed package that belongs to a core library
+#[derive(Debug, Clone, Copy)]
+pub struct CoreLibrary {
+    pub component: &'static str,
+    pub severity: Severity,
+    pub advice: &'static str,
+}
+
+/// (component, package name prefixes across distros, severity, advice)
+const CORE_LIBRARIES: &[(&str, &[&str], Severity, &str)] = &[
+    (
+        "glibc",
+        &["glibc", "lib32-glibc", "libc6", "libc-bin", "libc-dev-bin", "libc-l10n", "locales"],
+        Severity::Critical,
+        "never downgrade glibc on its own: everything built against the newer version stops starting. Roll back the whole snapshot instead",
+    ),
+    (
+        "openssl",
+        &["openssl", "libssl", "lib32-openssl"],
+        Severity::High,
+        "programs built for the newer OpenSSL may refuse to start; restart sshd and other TLS services afterwards",
+    ),
+    (
+        "systemd",
+        &["systemd", "libsystemd", "lib32-systemd", "libudev", "udev", "libnss-systemd", "libpam-systemd"],
+        Severity::High,
+        "keep systemd, its libraries and udev at one version (downgrade them together) and reboot",
+    ),
+    (
+        "pam",
+        &["pam", "libpam", "lib32-pam"],
+        Severity::High,
+        "a broken PAM stack locks every user out: keep a root shell open and test su/login before closing it",
+    ),
+    (
+        "dbus",
+        &["dbus", "libdbus", "lib32-dbus"],
+        Severity::Medium,
+        "restarting the bus ends the desktop session; reboot after changing it",
+    ),
+];
+
+/// The core library `package` belongs to, if any
+pub fn classify(package: &str) -> Option<CoreLibrary> {
+    CORE_LIBRARIES.iter().find_map(|(component, prefixes, severity, advice)| {
+        let matches = prefixes.iter().any(|p| {
+            package == *p || package.strip_prefix(p).is_some_and(|rest| rest.starts_with(['-', '0', '1', '2', '3']))
+        });
+        matches.then_some(CoreLibrary {
+            component,
+            severity: *severity,
+            advice,
+        })
+    })
+}
@@ -0,0 +1,165 @@
+// Opt-in crash reports: nothing is written or sent without consent
+
+use colored::*;
+use serde::Serialize;
+use std::backtrace::Backtrace;
+use std::io::IsTerminal;
+
+use crate::config;
+use crate::http;
+use crate::paths;
+
+/// Options whose values must not leave the machine: license key, email, webhook URL
+const SECRET_OPTIONS: &[&str] = &["--key", "--key-file", "--email", "--notify", "-k", "-e"];
+
+#[derive(Debug, Serialize)]
+struct CrashReport {
+    version: &'static str,
+    kind: &'static str,
+    message: String,
+    backtrace: String,
+    command: Vec<String>,
+    distro: String,
+    kernel: String,
+    time: String,
+}
+
+/// Offer a report when eshu-trace panics
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        let message = match info.location() {
+            Some(loc) => format!("{} at {}:{}", panic_message(info), loc.file(), loc.line()),
+            None => panic_message(info),
+        };
+        offer_report("panic", message, Backtrace::force_capture().to_string());
+    }));
+}
+
+/// Errors are usually the user's environment, so only report them when opted in via config
+pub fn report_error(err: &anyhow::Error) {
+    if consent_from_config() == Some(true) {
+        offer_report("error", format!("{:#}", err), format!("{:?}", err));
+    }
+}
+
+fn panic_message(info: &std::panic::PanicHookInfo) -> String {
+    if let Some(s) = info.payload().downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = info.payload().downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// `crash_reports` in config.json: true = always report, false = never ask
+fn consent_from_config() -> Option<bool> {
+    config::load().ok().and_then(|c| c.crash_reports)
+}
+
+fn offer_report(kind: &'static str, message: String, backtrace: String) {
+    let consent = match consent_from_config() {
+        Some(c) => c,
+        None if std::io::stdin().is_terminal() => {
+            eprintln!();
+            eprintln!("{}", "eshu-trace crashed. A report would include the backtrace, command line (license".yellow());
+            eprintln!("{}", "key, email and webhook removed), distro, kernel and eshu-trace version - nothing else.".yellow());
+            dialoguer::Confirm::new()
+                .with_prompt("Create a crash report?")
+                .default(false)
+                .interact()
+                .unwrap_or(false)
+        }
+        None => false,
+    };
+
+    if !consent {
+        return;
+    }
+
+    let report = CrashReport {
+        version: env!("CARGO_PKG_VERSION"),
+        kind,
+        message,
+        backtrace,
+        command: redacted_args(std::env::args()),
+        distro: distro_name(),
+        kernel: std::fs::read_to_string("/proc/sys/kernel/osrelease")
+            .map(|k| k.trim().to_string())
+            .unwrap_or_default(),
+        time: chrono::Utc::now().to_rfc3339(),
+    };
+
+    let submitted = submit(&report);
+    match save(&report) {
+        Ok(path) if !submitted => {
+            eprintln!("{} Crash report saved to {}", "✓".green(), path.display());
+            eprintln!("   Please attach it to an issue: https://github.com/eshu-apps/eshu-trace/issues");
+        }
+        Ok(_) => eprintln!("{} Crash report sent - thank you!", "✓".green()),
+        Err(e) => eprintln!("{} Could not save crash report: {}", "✗".red(), e),
+    }
+}
+
+/// POST the report to `crash_report_url` if one is configured
+fn submit(report: &CrashReport) -> bool {
+    let url = match config::load().ok().and_then(|c| c.crash_report_url) {
+        Some(u) => u,
+        None => return false,
+    };
+
+    http::post_json(&url, report, std::time::Duration::from_secs(10))
+        .map(|r| r.is_success())
+        .unwrap_or(false)
+}
+
+/// The command line with the values of [`SECRET_OPTIONS`] replaced
+fn redacted_args(args: impl Iterator<Item = String>) -> Vec<String> {
+    let mut redacted = Vec::new();
+    let mut hide_next = false;
+    for arg in args {
+        if std::mem::take(&mut hide_next) {
+            redacted.push("<redacted>".to_string());
+            continue;
+        }
+        if SECRET_OPTIONS.contains(&arg.as_str()) {
+            hide_next = true;
+            redacted.push(arg);
+            continue;
+        }
+        // --key=VALUE, or -kVALUE for the short forms
+        let attached = SECRET_OPTIONS.iter().find(|opt| match arg.strip_prefix(**opt) {
+            Some(rest) if opt.starts_with("--") => rest.starts_with('='),
+            Some(rest) => !rest.is_empty(),
+            None => false,
+        });
+        match attached {
+            Some(opt) => redacted.push(format!("{}=<redacted>", opt)),
+            None => redacted.push(arg),
+        }
+    }
+    redacted
+}
+
+fn save(report: &CrashReport) -> anyhow::Result<std::path::PathBuf> {
+    let name = format!("crash-{}.json", chrono::Utc::now().format("%Y%m%d-%H%M%S"));
+    let path = paths::state_file(&name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, serde_json::to_string_pretty(report)?)?;
+    Ok(path)
+}
+
+fn distro_name() -> String {
+    let os_release = std::fs::read_to_string("/etc/os-release").unwrap_or_default();
+    os_release
+        .lines()
+        .find_map(|l| l.strip_prefix("PRETTY_NAME="))
+        .map(|v| v.trim_matches('"').to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -0,0 +1,156 @@
+// Re-run a culprit's test in containers of other distros to tell distro bugs from upstream ones
+
+use anyhow::Result;
+use std::process::Command;
+
+use crate::exec::CommandExt;
+use crate::test_runner::which;
+
+/// Images tried when none are given, one per package manager family
+pub const DEFAULT_IMAGES: &[&str] = &[
+    "docker.io/library/archlinux:latest",
+    "docker.io/library/debian:testing",
+    "docker.io/library/ubuntu:rolling",
+    "registry.fedoraproject.org/fedora:latest",
+    "registry.opensuse.org/opensuse/tumbleweed:latest",
+];
+
+/// Output markers printed by the in-container script
+const VERSION_MARKER: &str = "ESHU_TRACE_VERSION=";
+const EXIT_MARKER: &str = "ESHU_TRACE_EXIT=";
+
+#[derive(Debug)]
+pub enum Outcome {
+    /// The test failed, so the regression shows up there too
+    Reproduced,
+    NotReproduced,
+    /// The package could not be installed or the container did not start
+    Unavailable(String),
+}
+
+#[derive(Debug)]
+pub struct CheckResult {
+    pub image: String,
+    /// Version that ended up installed in the container
+    pub installed: Option<String>,
+    /// Whether the installed version has the same upstream version as the broken one
+    pub same_upstream: bool,
+    pub outcome: Outcome,
+}
+
+/// podman or docker, whichever is installed
+pub fn container_runtime() -> Option<&'static str> {
+    ["podman", "docker"].into_iter().find(|r| which(r))
+}
+
+/// Install `package` in each image (at the upstream release of `version` when the
+/// distro still ships it) and run `test`; a failing test means the bug reproduces.
+pub fn check(images: &[String], package: &str, version: &str, test: &str) -> Result<Vec<CheckResult>> {
+    let runtime = container_runtime()
+        .ok_or_else(|| anyhow::anyhow!("Neither podman nor docker is installed; one is needed to run containers"))?;
+    let upstream = upstream_version(version);
+
+    let mut results = Vec::new();
+    for image in images {
+        let output = Command::new(runtime)
+            .args(["run", "--rm", "-e"])
+            .arg(format!("ESHU_TRACE_TEST={}", test))
+            .arg(image)
+            .args(["sh", "-c", &script(image, package, &upstream)])
+            .run_output();
+
+        let output = match output {
+            Ok(o) => o,
+            Err(e) => {
+                results.push(CheckResult {
+                    image: image.clone(),
+                    installed: None,
+                    same_upstream: false,
+                    outcome: Outcome::Unavailable(e.to_string()),
+                });
+                continue;
+            }
+        };
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let installed = stdout
+            .lines()
+            .find_map(|l| l.strip_prefix(VERSION_MARKER))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+        let exit = stdout
+            .lines()
+            .find_map(|l| l.strip_prefix(EXIT_MARKER))
+            .and_then(|c| c.trim().parse::<i32>().ok());
+
+        let outcome = match (&installed, exit) {
+            (None, _) => Outcome::Unavailable(last_line(&output.stderr).unwrap_or_else(|| "package not available".into())),
+            (Some(_), None) => Outcome::Unavailable("test did not run".into()),
+            (Some(_), Some(0)) => Outcome::NotReproduced,
+            (Some(_), Some(_)) => Outcome::Reproduced,
+        };
+
+        results.push(CheckResult {
+            image: image.clone(),
+            same_upstream: installed.as_deref().map(upstream_version).as_deref() == Some(upstream.as_str()),
+            installed,
+            outcome,
+        });
+    }
+
+    Ok(results)
+}
+
+/// "1:24.1.0-2ubuntu1" -> "24.1.0": drop the epoch and the distro release
+pub fn upstream_version(version: &str) -> String {
+    let without_epoch = version.split_once(':').map(|(_, v)| v).unwrap_or(version);
+    match without_epoch.rsplit_once('-') {
+        Some((upstream, _)) => upstream.to_string(),
+        None => without_epoch.to_string(),
+    }
+}
+
+/// Shell script run inside the container: install, report the version, run the test
+fn script(image: &str, package: &str, upstream: &str) -> String {
+    let install = if image.contains("archlinux") {
+        format!("pacman -Syu --noconfirm --needed {0} >/dev/null", package)
+    } else if image.contains("debian") || image.contains("ubuntu") {
+        // Prefer a repo version with the same upstream release as the broken one
+        format!(
+            "export DEBIAN_FRONTEND=noninteractive; apt-get update -qq >/dev/null; \
+             v=$(apt-cache madison {0} | awk -F'|' '{{gsub(/ /, \"\", $2); print $2}}' | grep -E '^([0-9]+:)?{1}([-+~]|$)' | head -n1); \
+             apt-get install -y -qq {0}${{v:+=$v}} >/dev/null",
+            package,
+            upstream.replace('.', "\\.")
+        )
+    } else if image.contains("fedora") {
+        format!("dnf install -y -q {0}-{1} >/dev/null 2>&1 || dnf install -y -q {0} >/dev/null", package, upstream)
+    } else if image.contains("opensuse") {
+        format!("zypper -n -q in {0}={1} >/dev/null 2>&1 || zypper -n -q in {0} >/dev/null", package, upstream)
+    } else {
+        format!("echo 'unknown package manager in {}' >&2; exit 1", image)
+    };
+
+    let query = format!(
+        "pacman -Q {0} 2>/dev/null | cut -d' ' -f2 || true; \
+         dpkg-query -W -f='${{Version}}' {0} 2>/dev/null || true; \
+         rpm -q --qf '%{{VERSION}}-%{{RELEASE}}' {0} 2>/dev/null | grep -v 'not installed' || true",
+        package
+    );
+
+    format!(
+        "{install} || exit 1; echo \"{version}$({query})\"; sh -c \"$ESHU_TRACE_TEST\"; echo \"{exit}$?\"",
+        install = install,
+        version = VERSION_MARKER,
+        query = query,
+        exit = EXIT_MARKER
+    )
+}
+
+fn last_line(bytes: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .rev()
+        .find(|l| !l.trim().is_empty())
+        .map(|l| l.trim().to_string())
+}
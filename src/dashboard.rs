@@ -0,0 +1,141 @@
+// Local web dashboard (`eshu-trace serve`) for watching a bisect from another machine
+
+use anyhow::Result;
+use colored::*;
+use serde_json::json;
+use tiny_http::{Header, Response, Server};
+
+use crate::bisect;
+use crate::lock;
+
+/// Single page that polls /api/status; no external assets so it works offline
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>eshu-trace</title>
+<style>
+  body { font-family: sans-serif; margin: 2em; background: #111; color: #ddd; }
+  h1 { font-size: 1.4em; }
+  .muted { color: #888; }
+  .bar { position: relative; height: 1.2em; background: #333; margin: .5em 0; }
+  .range { position: absolute; height: 100%; background: #c90; }
+  table { border-collapse: collapse; }
+  td, th { padding: .2em .8em; text-align: left; }
+  .bad { color: #e55; } .good { color: #5c5; }
+</style>
+</head>
+<body>
+<h1>eshu-trace</h1>
+<p id="state" class="muted">Loading...</p>
+<div id="session" hidden>
+  <p>Good <b id="good"></b> &rarr; Bad <b id="bad"></b></p>
+  <p><span id="left"></span> of <span id="total"></span> candidate changes left</p>
+  <div class="bar"><div class="range" id="range"></div></div>
+  <h2>Candidates</h2>
+  <ul id="candidates"></ul>
+</div>
+<div id="history-box" hidden>
+  <h2>Steps</h2>
+  <table><thead><tr><th>#</th><th>Applied</th><th>Verdict</th><th>Time</th></tr></thead><tbody id="history"></tbody></table>
+</div>
+<div id="report" hidden>
+  <h2>Result</h2>
+  <p id="culprit"></p>
+  <p id="group" class="muted"></p>
+</div>
+<script>
+function text(id, value) { document.getElementById(id).textContent = value; }
+function show(id, visible) { document.getElementById(id).hidden = !visible; }
+function describe(change) {
+  const kind = Object.keys(change)[0], v = change[kind];
+  if (kind === "Upgraded" || kind === "Downgraded") return v[0].name + " " + v[1] + " → " + v[2];
+  return v.name + " " + v.version + " (" + kind.toLowerCase() + ")";
+}
+function rows(history) {
+  const body = document.getElementById("history");
+  body.replaceChildren();
+  history.forEach((step, i) => {
+    const tr = body.insertRow();
+    tr.insertCell().textContent = i + 1;
+    tr.insertCell().textContent = step.applied;
+    const verdict = tr.insertCell();
+    verdict.textContent = step.issue ? "issue" : "ok";
+    verdict.className = step.issue ? "bad" : "good";
+    tr.insertCell().textContent = step.at;
+  });
+  show("history-box", history.length > 0);
+}
+async function refresh() {
+  try {
+    const status = await (await fetch("/api/status")).json();
+    const s = status.session, r = status.report;
+    text("state", status.running ? "Bisect running (PID " + status.running + ")"
+      : s ? "Bisect interrupted; resume it on the machine" : r ? "Last bisect finished " + r.finished_at : "No bisect yet");
+    show("session", !!s);
+    if (s) {
+      const total = s.package_changes.length;
+      text("good", s.good); text("bad", s.bad);
+      text("left", s.high - s.low); text("total", total);
+      const range = document.getElementById("range");
+      range.style.left = (100 * s.low / total) + "%";
+      range.style.width = (100 * (s.high - s.low) / total) + "%";
+      const list = document.getElementById("candidates");
+      list.replaceChildren(...s.package_changes.slice(s.low, s.high).slice(0, 50).map(c => {
+        const li = document.createElement("li"); li.textContent = describe(c); return li;
+      }));
+    }
+    rows(s ? s.history : r ? r.history : []);
+    show("report", !s && !!r);
+    if (!s && r) {
+      text("culprit", r.culprit ? "Culprit: " + describe(r.culprit) : "No culprit found");
+      const others = r.group.filter(c => !r.culprit || describe(c) !== describe(r.culprit));
+      text("group", others.length ? "Moves together with: " + others.map(describe).join(", ") : "");
+    }
+  } catch (e) {
+    text("state", "eshu-trace serve is not reachable");
+  }
+}
+refresh();
+setInterval(refresh, 3000);
+</script>
+</body>
+</html>
+"#;
+
+/// Serve the dashboard on `listen` until interrupted
+pub fn serve(listen: &str) -> Result<()> {
+    let server = Server::http(listen).map_err(|e| anyhow::anyhow!("Cannot listen on {}: {}", listen, e))?;
+
+    println!("{} Dashboard at {}", "🌐".bold(), format!("http://{}/", listen).cyan());
+    if listen.starts_with("127.") || listen.starts_with("localhost") {
+        println!("{}", "   Only reachable from this machine; use --listen 0.0.0.0:<port> to watch remotely".dimmed());
+    } else {
+        println!("{}", "   No authentication: anyone who can reach this port sees the bisect".yellow());
+    }
+    println!("{}", "   Press Ctrl-C to stop".dimmed());
+
+    for request in server.incoming_requests() {
+        let response = match request.url() {
+            "/" | "/index.html" => Response::from_string(PAGE).with_header(header("text/html; charset=utf-8")),
+            "/api/status" => Response::from_string(status().to_string()).with_header(header("application/json")),
+            _ => Response::from_string("Not found").with_status_code(404),
+        };
+        let _ = request.respond(response);
+    }
+
+    Ok(())
+}
+
+/// {"running": pid|null, "session": saved session|null, "report": last report|null}
+fn status() -> serde_json::Value {
+    json!({
+        "running": lock::holder(),
+        "session": bisect::load_saved().ok().flatten(),
+        "report": bisect::load_report().ok().flatten(),
+    })
+}
+
+fn header(content_type: &str) -> Header {
+    Header::from_bytes("Content-Type", content_type).expect("static header is valid")
+}
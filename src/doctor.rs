@@ -0,0 +1,262 @@
+// Environment diagnostics for `eshu-trace doctor`
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::config;
+use crate::exec::CommandExt;
+use crate::fixer::detect_distro_at;
+use crate::paths;
+use crate::probe::{self, Probe};
+use crate::snapshot;
+use crate::test_runner::{is_root, which};
+
+/// Free space below which snapshot mounts and package downloads start failing
+const MIN_FREE_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about a warning or failure
+    pub fix: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Warning,
+    Failed,
+}
+
+impl Check {
+    fn ok(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Warning,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(name: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Failed,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Run every check
+pub fn run_checks() -> Vec<Check> {
+    let mut checks = Vec::new();
+    checks.extend(snapshot_backends());
+    checks.push(privileges());
+    checks.push(package_manager());
+    checks.push(mount_support());
+    checks.extend(network());
+    checks.extend(disk_space());
+    checks
+}
+
+fn snapshot_backends() -> Vec<Check> {
+    let timeshift = which("timeshift");
+    let snapper = which("snapper");
+    let btrfs_dirs: Vec<String> = snapshot::btrfs_snapshot_dirs()
+        .iter()
+        .filter(|d| d.is_dir())
+        .map(|d| d.display().to_string())
+        .collect();
+    let btrfs = !btrfs_dirs.is_empty();
+
+    let mut checks = vec![
+        if timeshift {
+            Check::ok("Timeshift", "installed")
+        } else {
+            Check::warn("Timeshift", "not installed", "Install timeshift for rsync or btrfs snapshots")
+        },
+        if snapper {
+            Check::ok("Snapper", "installed")
+        } else {
+            Check::warn("Snapper", "not installed", "Install snapper if you use btrfs")
+        },
+        if btrfs {
+            Check::ok("BTRFS snapshots", format!("{} found", btrfs_dirs.join(", ")))
+        } else {
+            Check::warn(
+                "BTRFS snapshots",
+                "no snapshot directory",
+                "Create snapshots with snapper or btrfs subvolume snapshot, or list their directories in btrfs_snapshot_dirs in config.json",
+            )
+        },
+    ];
+
+    if !timeshift && !snapper && !btrfs {
+        checks.push(Check::fail(
+            "Snapshot backend",
+            "none available",
+            "Install Timeshift or Snapper and take a snapshot before the next update",
+        ));
+    }
+
+    checks
+}
+
+fn privileges() -> Check {
+    if is_root() {
+        return Check::ok("Privileges", "running as root");
+    }
+
+    // sudo -n succeeds only if no password prompt is needed
+    let passwordless = Command::new("sudo")
+        .args(["-n", "true"])
+        .run_output()
+        .map(|o| o.status.success())
+        .unwrap_or(false);
+
+    if passwordless {
+        Check::ok("Privileges", "sudo available without password")
+    } else if which("sudo") {
+        Check::warn("Privileges", "sudo will prompt for a password", "Run eshu-trace with sudo, or expect password prompts")
+    } else {
+        Check::fail("Privileges", "not root and sudo not installed", "Run eshu-trace as root")
+    }
+}
+
+fn package_manager() -> Check {
+    for (tool, name) in [("pacman", "pacman"), ("apt-get", "apt"), ("dnf", "dnf"), ("zypper", "zypper")] {
+        if which(tool) {
+            return Check::ok("Package manager", name);
+        }
+    }
+
+    Check::fail(
+        "Package manager",
+        "no supported package manager found",
+        "eshu-trace supports pacman, apt, dnf and zypper systems",
+    )
+}
+
+fn mount_support() -> Check {
+    if !which("mount") {
+        return Check::fail("Mounting", "mount not found", "Install util-linux");
+    }
+    if !is_root() {
+        return Check::warn(
+            "Mounting",
+            "needs root to mount snapshots and overlays",
+            "Run automated bisects with sudo",
+        );
+    }
+
+    let filesystems = std::fs::read_to_string("/proc/filesystems").unwrap_or_default();
+    if !filesystems.contains("overlay") {
+        return Check::warn(
+            "Mounting",
+            "overlayfs not available",
+            "Load it with: modprobe overlay (needed for --auto with the chroot driver)",
+        );
+    }
+
+    Check::ok("Mounting", "root with overlayfs")
+}
+
+fn network() -> Vec<Check> {
+    let license_url = config::license_server()
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| "https://api.gumroad.com".to_string());
+
+    // Where older package versions come from on this distro
+    let archive = match detect_distro_at("/").unwrap_or_default().as_str() {
+        "arch" | "manjaro" | "endeavouros" => Some("https://archive.archlinux.org"),
+        "debian" => Some("https://snapshot.debian.org"),
+        "ubuntu" => Some("https://launchpad.net"),
+        "fedora" => Some("https://kojipkgs.fedoraproject.org"),
+        _ => None,
+    };
+
+    let mut targets = vec![(
+        "License server",
+        license_url,
+        "Check your connection or proxy; offline keys work without network",
+    )];
+    if let Some(archive) = archive {
+        targets.push((
+            "Package archive",
+            archive.to_string(),
+            "Older versions cannot be downloaded; only cached packages can be used",
+        ));
+    }
+
+    targets
+        .into_iter()
+        .map(|(name, url, fix)| {
+            if reachable(&url) {
+                Check::ok(name, format!("{} reachable", url))
+            } else {
+                Check::warn(name, format!("{} unreachable", url), fix)
+            }
+        })
+        .collect()
+}
+
+/// TCP reachability of a URL's host, so 404s on the bare host don't count as failures
+fn reachable(url: &str) -> bool {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("https", url));
+    let authority = rest.split('/').next().unwrap_or(rest);
+    let default_port = if scheme == "http" { 80 } else { 443 };
+
+    let (host, port) = probe::split_host_port(authority);
+    let port = port.and_then(|p| p.parse().ok()).unwrap_or(default_port);
+
+    Probe::Tcp(host.to_string(), port).run()
+}
+
+fn disk_space() -> Vec<Check> {
+    let state_dir = paths::state_file("state")
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let locations = [("Disk space (state)", state_dir), ("Disk space (temp)", std::env::temp_dir())];
+
+    locations
+        .into_iter()
+        .map(|(name, dir)| {
+            // Check the nearest existing ancestor; the state dir may not exist yet
+            let existing = dir.ancestors().find(|p| p.exists()).unwrap_or(Path::new("/"));
+            match free_bytes(existing) {
+                Some(free) if free >= MIN_FREE_BYTES => {
+                    Check::ok(name, format!("{} free on {}", crate::snapshot::human_size(free), existing.display()))
+                }
+                Some(free) => Check::fail(
+                    name,
+                    format!("only {} free on {}", crate::snapshot::human_size(free), existing.display()),
+                    "Free at least 2 GiB (e.g. clean the package cache)",
+                ),
+                None => Check::warn(name, "could not determine free space", "Check with: df -h"),
+            }
+        })
+        .collect()
+}
+
+fn free_bytes(path: &Path) -> Option<u64> {
+    let output = Command::new("df").args(["-B1", "--output=avail"]).arg(path).run_output().ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)?
+        .trim()
+        .parse()
+        .ok()
+}
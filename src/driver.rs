@@ -0,0 +1,521 @@
+// Test drivers for automated bisect (Premium feature)
+//
+// A driver applies a candidate package set on top of the good state in some
+// isolated environment and runs the configured test there.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use colored::*;
+use std::fs;
+#[cfg(feature = "vm")]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+#[cfg(feature = "vm")]
+use std::process::Stdio;
+#[cfg(feature = "vm")]
+use std::time::{Duration, Instant};
+
+use crate::audit;
+use crate::fixer::{detect_distro_at, is_package_token};
+use crate::interrupt;
+use crate::package_diff::PackageChange;
+use crate::snapshot::Snapshot;
+use crate::test_runner::TestRunner;
+#[cfg(feature = "vm")]
+use crate::test_runner::which;
+
+/// How long a QEMU guest may take to boot and report its marker
+#[cfg(feature = "vm")]
+const QEMU_BOOT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Markers written by the guest over virtio-serial
+#[cfg(feature = "vm")]
+const MARKER_PASS: &str = "ESHU_TRACE_PASS";
+#[cfg(feature = "vm")]
+const MARKER_FAIL: &str = "ESHU_TRACE_FAIL";
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum DriverKind {
+    /// Throwaway overlayfs + chroot of the good snapshot (no reboot needed)
+    Chroot,
+    /// Boot the snapshot under QEMU with the candidate kernel (-kernel/-initrd)
+    #[cfg(feature = "vm")]
+    Qemu,
+}
+
+pub trait TestDriver {
+    fn name(&self) -> &str;
+
+    /// Apply `changes` on top of the good state and run the test.
+    /// Returns true if the test passed (issue not present).
+    fn test(&mut self, changes: &[PackageChange], runner: &TestRunner) -> Result<bool>;
+}
+
+/// Build the driver of the given kind, using `good` as the base state
+pub fn create(kind: DriverKind, good: &Snapshot) -> Result<Box<dyn TestDriver>> {
+    let root = good.path.as_deref().context(format!(
+        "Snapshot {} has no accessible filesystem path for automated testing",
+        good.id
+    ))?;
+
+    match kind {
+        DriverKind::Chroot => Ok(Box::new(ChrootDriver::new(root)?)),
+        #[cfg(feature = "vm")]
+        DriverKind::Qemu => Ok(Box::new(QemuDriver::new(root)?)),
+    }
+}
+
+/// Runs each step inside an overlayfs of the good snapshot, discarded afterwards
+pub struct ChrootDriver {
+    lower: PathBuf,
+    distro: String,
+}
+
+impl ChrootDriver {
+    pub fn new(snapshot_root: &str) -> Result<Self> {
+        let (lower, distro) = snapshot_base(snapshot_root)?;
+        Ok(Self { lower, distro })
+    }
+}
+
+impl TestDriver for ChrootDriver {
+    fn name(&self) -> &str {
+        "chroot"
+    }
+
+    fn test(&mut self, changes: &[PackageChange], runner: &TestRunner) -> Result<bool> {
+        let overlay = OverlayRoot::mount(&self.lower)?;
+        apply_changes(overlay.root(), &self.distro, changes)?;
+
+        runner.run_test_in(Some(overlay.root()))
+    }
+}
+
+/// Boots the candidate state under QEMU, skipping the bootloader entirely.
+///
+/// The candidate packages are applied in an overlay as for the chroot driver,
+/// then the overlay's newest kernel/initramfs are passed to QEMU directly and
+/// the overlay is shared with the guest as its root filesystem over 9p. A
+/// one-shot unit in the guest runs the test command and reports the result
+/// on a virtio-serial port before powering off.
+#[cfg(feature = "vm")]
+pub struct QemuDriver {
+    lower: PathBuf,
+    distro: String,
+}
+
+/// Built without the `vm` feature: no value exists, so kernel bisects stay manual
+#[cfg(not(feature = "vm"))]
+pub enum QemuDriver {}
+
+#[cfg(not(feature = "vm"))]
+impl QemuDriver {
+    pub fn test_kernel(&self, _kernel: &Path, _initrd: &Path, _runner: &TestRunner) -> Result<bool> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "vm")]
+impl QemuDriver {
+    pub fn new(snapshot_root: &str) -> Result<Self> {
+        if !which("qemu-system-x86_64") {
+            anyhow::bail!("qemu-system-x86_64 not found. Install QEMU to use the qemu driver");
+        }
+
+        let (lower, distro) = snapshot_base(snapshot_root)?;
+        Ok(Self { lower, distro })
+    }
+
+    /// Boot the base state with an explicit kernel/initramfs taken from the host
+    pub fn test_kernel(&self, kernel: &Path, initrd: &Path, runner: &TestRunner) -> Result<bool> {
+        let overlay = OverlayRoot::mount(&self.lower)?;
+        Self::install_marker_unit(overlay.root(), runner)?;
+
+        overlay.release_binds();
+        self.boot(overlay.root(), kernel, initrd)
+    }
+
+    /// The guest evaluates the test command and benchmark itself, as the test user if one is set
+    fn install_marker_unit(root: &Path, runner: &TestRunner) -> Result<()> {
+        // Probes run on the host, which can't reach the guest's services
+        if runner.has_probes() {
+            anyhow::bail!("The qemu driver cannot evaluate --probe checks; use a test command instead");
+        }
+        if runner.command().is_none() && runner.benchmark().is_none() {
+            anyhow::bail!("The qemu driver needs a test command or --bench to decide pass or fail");
+        }
+
+        let quote = |s: &str| format!("'{}'", s.replace('\'', "'\\''"));
+        let mut script = format!(
+            "#!/bin/sh\n\
+             port=/dev/virtio-ports/eshu.trace\n\
+             user={}\n\
+             run() {{ if [ -n \"$user\" ]; then runuser -u \"$user\" -- sh -c \"$1\"; else sh -c \"$1\"; fi; }}\n\
+             ok=1\n",
+            quote(runner.test_user().unwrap_or_default())
+        );
+        if let Some(test) = runner.command() {
+            script.push_str(&format!("run {} || ok=0\n", quote(test)));
+        }
+        if let Some(bench) = runner.benchmark() {
+            script.push_str(&format!(
+                "start=$(date +%s%N)\n\
+                 run {} || ok=0\n\
+                 [ $(( ($(date +%s%N) - start) / 1000000 )) -le {} ] || ok=0\n",
+                quote(&bench.command),
+                (bench.max_seconds * 1000.0) as u64
+            ));
+        }
+        script.push_str(&format!(
+            "if [ $ok = 1 ]; then echo {} > $port; else echo {} > $port; fi\n\
+             systemctl poweroff\n",
+            MARKER_PASS, MARKER_FAIL
+        ));
+        let unit = "[Unit]\n\
+             Description=Eshu-Trace boot marker\n\
+             After=multi-user.target graphical.target\n\n\
+             [Service]\n\
+             Type=oneshot\n\
+             ExecStart=/usr/local/sbin/eshu-trace-marker\n\n\
+             [Install]\n\
+             WantedBy=multi-user.target\n";
+
+        let script_path = root.join("usr/local/sbin/eshu-trace-marker");
+        fs::create_dir_all(root.join("usr/local/sbin"))?;
+        fs::write(&script_path, script)?;
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755))?;
+
+        let unit_dir = root.join("etc/systemd/system");
+        fs::create_dir_all(unit_dir.join("multi-user.target.wants"))?;
+        fs::write(unit_dir.join("eshu-trace-marker.service"), unit)?;
+        let _ = std::os::unix::fs::symlink(
+            "/etc/systemd/system/eshu-trace-marker.service",
+            unit_dir.join("multi-user.target.wants/eshu-trace-marker.service"),
+        );
+
+        Ok(())
+    }
+
+    fn boot(&self, root: &Path, kernel: &Path, initrd: &Path) -> Result<bool> {
+        let marker = root.join("..").join("marker.log");
+
+        println!(
+            "{} Booting {} under QEMU...",
+            "→".dimmed(),
+            kernel.file_name().unwrap_or_default().to_string_lossy()
+        );
+
+        let kvm = if Path::new("/dev/kvm").exists() {
+            vec!["-enable-kvm", "-cpu", "host"]
+        } else {
+            Vec::new()
+        };
+
+        let mut child = Command::new("qemu-system-x86_64")
+            .args(["-m", "2048", "-smp", "2", "-nographic", "-no-reboot"])
+            .args(kvm)
+            .arg("-kernel")
+            .arg(kernel)
+            .arg("-initrd")
+            .arg(initrd)
+            .args([
+                "-append",
+                "root=eshuroot rootfstype=9p rootflags=trans=virtio,version=9p2000.L rw console=ttyS0",
+            ])
+            .arg("-virtfs")
+            .arg(format!(
+                "local,path={},mount_tag=eshuroot,security_model=passthrough",
+                root.display()
+            ))
+            .args(["-device", "virtio-serial"])
+            .arg("-chardev")
+            .arg(format!("file,id=eshumarker,path={}", marker.display()))
+            .args(["-device", "virtserialport,chardev=eshumarker,name=eshu.trace"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Failed to start QEMU")?;
+
+        let start = Instant::now();
+        loop {
+            if child.try_wait()?.is_some() {
+                break;
+            }
+            if start.elapsed() > QEMU_BOOT_TIMEOUT {
+                let _ = child.kill();
+                let _ = child.wait();
+                println!(
+                    "{} Guest did not finish within {}s",
+                    "⚠".yellow(),
+                    QEMU_BOOT_TIMEOUT.as_secs()
+                );
+                break;
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+
+        // No marker at all means the boot itself failed
+        let output = fs::read_to_string(&marker).unwrap_or_default();
+        Ok(output.contains(MARKER_PASS))
+    }
+}
+
+#[cfg(feature = "vm")]
+impl TestDriver for QemuDriver {
+    fn name(&self) -> &str {
+        "qemu"
+    }
+
+    fn test(&mut self, changes: &[PackageChange], runner: &TestRunner) -> Result<bool> {
+        let overlay = OverlayRoot::mount(&self.lower)?;
+        apply_changes(overlay.root(), &self.distro, changes)?;
+
+        let (kernel, initrd) = find_kernel(overlay.root())?;
+        Self::install_marker_unit(overlay.root(), runner)?;
+
+        // The guest mounts the overlay itself; release the host-side bind mounts first
+        overlay.release_binds();
+        self.boot(overlay.root(), &kernel, &initrd)
+    }
+}
+
+/// Newest kernel image in the root's /boot together with its initramfs
+#[cfg(feature = "vm")]
+pub fn find_kernel(root: &Path) -> Result<(PathBuf, PathBuf)> {
+    let boot = root.join("boot");
+    let mut kernels: Vec<PathBuf> = fs::read_dir(&boot)
+        .context(format!("Failed to read {}", boot.display()))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .map(|n| n.to_string_lossy().starts_with("vmlinuz"))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    kernels.sort_by_key(|p| p.metadata().and_then(|m| m.modified()).ok());
+
+    for kernel in kernels.iter().rev() {
+        let name = kernel.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let suffix = name.trim_start_matches("vmlinuz").trim_start_matches('-');
+
+        // Arch: initramfs-linux.img, Fedora: initramfs-<ver>.img, Debian: initrd.img-<ver>
+        let candidates = [
+            boot.join(format!("initramfs-{}.img", suffix)),
+            boot.join(format!("initrd.img-{}", suffix)),
+            boot.join(format!("initrd-{}", suffix)),
+        ];
+
+        if let Some(initrd) = candidates.iter().find(|p| p.exists()) {
+            return Ok((kernel.clone(), initrd.clone()));
+        }
+    }
+
+    anyhow::bail!("No kernel with matching initramfs found in {}", boot.display())
+}
+
+fn snapshot_base(snapshot_root: &str) -> Result<(PathBuf, String)> {
+    let lower = PathBuf::from(snapshot_root);
+
+    if !lower.join("etc").is_dir() {
+        anyhow::bail!("Snapshot root {} does not look like a root filesystem", snapshot_root);
+    }
+
+    let distro = detect_distro_at(snapshot_root)?;
+    Ok((lower, distro))
+}
+
+/// Install/remove the candidate package set inside `root` with the distro's package manager
+fn apply_changes(root: &Path, distro: &str, changes: &[PackageChange]) -> Result<()> {
+    for cmd in install_commands(distro, changes)? {
+        println!("{} {}", "→".dimmed(), cmd.dimmed());
+
+        let status = audit::run(
+            "bisect: apply candidate",
+            Command::new("chroot").arg(root).args(["sh", "-c", &cmd]),
+        )?;
+
+        if !status.success() {
+            anyhow::bail!("Failed to apply candidate packages in chroot: {}", cmd);
+        }
+    }
+
+    Ok(())
+}
+
+fn install_commands(distro: &str, changes: &[PackageChange]) -> Result<Vec<String>> {
+    let mut install = Vec::new();
+    let mut remove = Vec::new();
+
+    for change in changes {
+        match change {
+            PackageChange::Added(pkg)
+            | PackageChange::Upgraded(pkg, _, _)
+            | PackageChange::Downgraded(pkg, _, _) => install.push((&pkg.name, &pkg.version)),
+            PackageChange::Removed(pkg) => remove.push(&pkg.name),
+        }
+    }
+
+    // Names and versions come from snapshots, manifests and received streams, and end up in a root shell
+    let unsafe_token = install
+        .iter()
+        .flat_map(|(name, ver)| [*name, *ver])
+        .chain(remove.iter().copied())
+        .find(|t| !is_package_token(t));
+    if let Some(token) = unsafe_token {
+        anyhow::bail!("Refusing to install {:?}: not a package name or version", token);
+    }
+
+    let mut commands = Vec::new();
+
+    match distro {
+        "arch" | "manjaro" | "endeavouros" => {
+            if !install.is_empty() {
+                let prefixes: Vec<String> = install.iter().map(|(name, ver)| format!("{}-{}-", name, ver)).collect();
+                // Only the packages: `.pkg.tar.*` would also hand pacman the .sig files
+                commands.push(format!(
+                    "set --; for p in {}; do \
+                     f=$(ls -d /var/cache/pacman/pkg/$p*.pkg.tar.zst /var/cache/pacman/pkg/$p*.pkg.tar.xz 2>/dev/null | head -n 1); \
+                     [ -n \"$f\" ] || {{ echo \"no cached package $p*\" >&2; exit 1; }}; set -- \"$@\" \"$f\"; done; \
+                     pacman -U --noconfirm --needed \"$@\"",
+                    prefixes.join(" ")
+                ));
+            }
+            if !remove.is_empty() {
+                commands.push(format!("pacman -Rdd --noconfirm {}", join(&remove)));
+            }
+        }
+        "ubuntu" | "debian" | "linuxmint" | "pop" => {
+            if !install.is_empty() {
+                let specs: Vec<String> = install.iter().map(|(n, v)| format!("{}={}", n, v)).collect();
+                commands.push(format!(
+                    "DEBIAN_FRONTEND=noninteractive apt-get install -y --allow-downgrades {}",
+                    specs.join(" ")
+                ));
+            }
+            if !remove.is_empty() {
+                commands.push(format!("apt-get remove -y {}", join(&remove)));
+            }
+        }
+        "fedora" | "rhel" | "centos" => {
+            if !install.is_empty() {
+                let specs: Vec<String> = install.iter().map(|(n, v)| format!("{}-{}", n, v)).collect();
+                commands.push(format!("dnf install -y --allowerasing {}", specs.join(" ")));
+            }
+            if !remove.is_empty() {
+                commands.push(format!("dnf remove -y --noautoremove {}", join(&remove)));
+            }
+        }
+        other => anyhow::bail!("Test drivers do not support distro: {}", other),
+    }
+
+    Ok(commands)
+}
+
+/// Writable overlay on top of a snapshot, unmounted and deleted on drop
+struct OverlayRoot {
+    /// Taken on drop: kept on disk instead of deleted when something stays mounted
+    dir: Option<tempfile::TempDir>,
+    merged: PathBuf,
+    mounts: Vec<PathBuf>,
+}
+
+impl OverlayRoot {
+    fn mount(lower: &Path) -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("eshu-trace-overlay")
+            .tempdir()
+            .context("Failed to create overlay directory")?;
+
+        for sub in ["upper", "work", "merged"] {
+            fs::create_dir(dir.path().join(sub))?;
+        }
+
+        let merged = dir.path().join("merged");
+        interrupt::track_dir(dir.path());
+        let options = format!(
+            "lowerdir={},upperdir={},workdir={}",
+            lower.display(),
+            dir.path().join("upper").display(),
+            dir.path().join("work").display()
+        );
+        let mut overlay = Self { dir: Some(dir), merged: merged.clone(), mounts: Vec::new() };
+        overlay.run_mount(&["-t", "overlay", "overlay", "-o", &options], &merged)?;
+
+        for (fstype, target) in [("proc", "proc"), ("sysfs", "sys")] {
+            overlay.run_mount(&["-t", fstype, fstype], &merged.join(target))?;
+        }
+        overlay.run_mount(&["--rbind", "/dev"], &merged.join("dev"))?;
+
+        // Share the host's package cache and DNS so candidate versions can be installed
+        for cache in ["var/cache/pacman/pkg", "var/cache/apt/archives"] {
+            let host = Path::new("/").join(cache);
+            let target = merged.join(cache);
+            if host.is_dir() && target.is_dir() {
+                overlay.run_mount(&["--bind", &host.to_string_lossy()], &target)?;
+            }
+        }
+        let _ = fs::copy("/etc/resolv.conf", merged.join("etc/resolv.conf"));
+
+        Ok(overlay)
+    }
+
+    fn root(&self) -> &Path {
+        &self.merged
+    }
+
+    /// Unmount everything except the overlay itself
+    #[cfg(feature = "vm")]
+    fn release_binds(&self) {
+        for target in self.mounts.iter().skip(1).rev() {
+            let _ = audit::run("bisect: unmount", Command::new("umount").arg("-R").arg("-l").arg(target));
+            interrupt::untrack_mount(target);
+        }
+    }
+
+    fn run_mount(&mut self, args: &[&str], target: &Path) -> Result<()> {
+        let status = audit::run("bisect: mount", Command::new("mount").args(args).arg(target))?;
+
+        if !status.success() {
+            anyhow::bail!("mount {} {} failed", args.join(" "), target.display());
+        }
+
+        self.mounts.push(target.to_path_buf());
+        interrupt::track_mount(target);
+        Ok(())
+    }
+}
+
+impl Drop for OverlayRoot {
+    fn drop(&mut self) {
+        let mut unmounted = true;
+        for target in self.mounts.iter().rev() {
+            let status = audit::run("bisect: unmount", Command::new("umount").arg("-R").arg("-l").arg(target));
+            if status.is_ok_and(|s| s.success()) {
+                interrupt::untrack_mount(target);
+            } else {
+                unmounted = false;
+            }
+        }
+
+        let Some(dir) = self.dir.take() else {
+            return;
+        };
+        interrupt::untrack_dir(dir.path());
+        // Deleting through a live bind of /dev or the package caches would delete host files
+        if !unmounted || interrupt::mounted_under(dir.path()) {
+            let kept = dir.keep();
+            eprintln!(
+                "{} Still mounted, left behind: {} (umount -R -l it, then delete it)",
+                "⚠".yellow(),
+                kept.display()
+            );
+        }
+    }
+}
+
+fn join(names: &[&String]) -> String {
+    names.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,105 @@
+// Typed errors with stable codes and a suggested fix
+
+use serde_json::json;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TraceError {
+    #[error("No snapshot backend detected")]
+    NoBackend,
+
+    #[error("Failed to run {tool}")]
+    BackendFailed {
+        tool: &'static str,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Snapshot not found: {0}")]
+    SnapshotNotFound(String),
+
+    #[error("No snapshots available")]
+    NoSnapshots,
+
+    #[error("No package database in snapshot {0}")]
+    NoPackageDatabase(String),
+
+    #[error("Cannot read {0}/etc/os-release")]
+    NoOsRelease(String),
+
+    #[error("Unsupported distro: {0}")]
+    UnsupportedDistro(String),
+
+    #[error("Refusing to install {file}: signature verification failed ({reason})")]
+    VerificationFailed { file: String, reason: String },
+
+    #[error("Bisect state is locked by another process{}", pid.map(|p| format!(" (PID {})", p)).unwrap_or_default())]
+    SessionLocked { pid: Option<u32>, stale: bool },
+}
+
+impl TraceError {
+    /// Stable identifier for scripts and bug reports
+    pub fn code(&self) -> &'static str {
+        match self {
+            TraceError::NoBackend => "E_NO_BACKEND",
+            TraceError::BackendFailed { .. } => "E_BACKEND_FAILED",
+            TraceError::SnapshotNotFound(_) => "E_SNAPSHOT_NOT_FOUND",
+            TraceError::NoSnapshots => "E_NO_SNAPSHOTS",
+            TraceError::NoPackageDatabase(_) => "E_NO_PACKAGE_DB",
+            TraceError::NoOsRelease(_) => "E_NO_OS_RELEASE",
+            TraceError::UnsupportedDistro(_) => "E_UNSUPPORTED_DISTRO",
+            TraceError::VerificationFailed { .. } => "E_VERIFICATION_FAILED",
+            TraceError::SessionLocked { .. } => "E_SESSION_LOCKED",
+        }
+    }
+
+    pub fn hint(&self) -> String {
+        match self {
+            TraceError::NoBackend => {
+                "Install Timeshift or Snapper, or use BTRFS snapshots; `eshu-trace doctor` shows what is missing".into()
+            }
+            TraceError::BackendFailed { tool, .. } => format!("Check that {} works: sudo {} --help", tool, tool),
+            TraceError::SnapshotNotFound(_) => "List valid IDs with `eshu-trace snapshots`".into(),
+            TraceError::NoSnapshots => "Take a snapshot before the next update so there is a good state to compare".into(),
+            TraceError::NoPackageDatabase(_) => {
+                "A snapshot needs a readable (mounted) root with a pacman, dpkg or rpm database, a package list file one \"name version\" line per package".into()
+            }
+            TraceError::NoOsRelease(root) => {
+                format!("Make sure {} is the root of a Linux system (mount it first in recovery)", root)
+            }
+            TraceError::UnsupportedDistro(_) => {
+                "Automatic fixes support Arch, Debian/Ubuntu and Fedora; apply the fix with your package manager".into()
+            }
+            // Package manifests carry a content hash
+            TraceError::VerificationFailed { file, .. } if !file.contains(".pkg.") && !file.ends_with(".deb") => {
+                "It was changed after it was exported; export it again on the source machine".into()
+            }
+            TraceError::VerificationFailed { .. } => {
+                "The file was deleted. Refresh the keyring (pacman -Sy archlinux-keyring) and retry; if it still fails, do not install it".into()
+            }
+            TraceError::SessionLocked { stale: true, .. } => {
+                "That process is gone; the lock is stale. Re-run with --force to take it over".into()
+            }
+            TraceError::SessionLocked { stale: false, .. } => {
+                "Wait for it to finish; use --force only if you are sure it is no longer running".into()
+            }
+        }
+    }
+}
+
+/// The typed error behind an anyhow chain, if any
+pub fn find(err: &anyhow::Error) -> Option<&TraceError> {
+    err.chain().find_map(|e| e.downcast_ref::<TraceError>())
+}
+
+/// {"error": {"code", "message", "hint"}}; untyped errors get code E_OTHER
+pub fn to_json(err: &anyhow::Error) -> serde_json::Value {
+    let typed = find(err);
+    json!({
+        "error": {
+            "code": typed.map(|t| t.code()).unwrap_or("E_OTHER"),
+            "message": format!("{:#}", err),
+            "hint": typed.map(|t| t.hint()),
+        }
+    })
+}
@@ -0,0 +1,282 @@
+// Every external program eshu-trace starts goes through one CommandRunner
+//
+// The real runner executes commands. `--dry-run` still runs read-only queries but only
+// prints commands that would change the system (those that go through audit::run).
+// ESHU_TRACE_COMMAND_FIXTURES points at a JSON file of canned outputs; with it nothing
+// is executed and every command line is recorded, so whole commands can be exercised
+// without root, snapshot tools or package managers.
+
+use anyhow::{Context, Result};
+use colored::*;
+use serde::Deserialize;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+static RUNNER: OnceLock<Box<dyn CommandRunner>> = OnceLock::new();
+
+pub trait CommandRunner: Send + Sync {
+    /// Run to completion and capture stdout/stderr
+    fn output(&self, cmd: &mut Command) -> io::Result<Output>;
+
+    /// Run with the terminal attached
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus>;
+
+    /// Run a command that changes the system, passing its output through to the terminal.
+    /// Returns the exit status and the captured stdout followed by stderr.
+    fn modify(&self, cmd: &mut Command) -> io::Result<(ExitStatus, Vec<u8>)>;
+
+    /// Whether commands that change the system actually run
+    fn applies_changes(&self) -> bool {
+        true
+    }
+}
+
+/// Runs everything
+pub struct RealRunner;
+
+impl CommandRunner for RealRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        cmd.output()
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        cmd.status()
+    }
+
+    fn modify(&self, cmd: &mut Command) -> io::Result<(ExitStatus, Vec<u8>)> {
+        let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+        let stdout = child.stdout.take().map(|s| tee(s, io::stdout()));
+        let stderr = child.stderr.take().map(|s| tee(s, io::stderr()));
+        let status = child.wait()?;
+
+        let mut captured = Vec::new();
+        for handle in [stdout, stderr].into_iter().flatten() {
+            captured.extend(handle.join().unwrap_or_default());
+        }
+        Ok((status, captured))
+    }
+}
+
+/// Runs queries, prints what it would change
+pub struct DryRunner;
+
+impl CommandRunner for DryRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        cmd.output()
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        cmd.status()
+    }
+
+    fn modify(&self, cmd: &mut Command) -> io::Result<(ExitStatus, Vec<u8>)> {
+        println!("{} {}", "[dry-run] would run:".yellow(), command_line(cmd));
+        Ok((ExitStatus::from_raw(0), Vec::new()))
+    }
+
+    fn applies_changes(&self) -> bool {
+        false
+    }
+}
+
+/// One canned answer: the first fixture whose `command` is a prefix of the command line wins
+#[derive(Debug, Deserialize)]
+struct Fixture {
+    command: String,
+    #[serde(default)]
+    stdout: String,
+    #[serde(default)]
+    stderr: String,
+    #[serde(default)]
+    status: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct FixtureFile {
+    commands: Vec<Fixture>,
+    /// Append every command line here
+    #[serde(default)]
+    log: Option<String>,
+}
+
+/// Never executes anything: answers from fixtures and records every command line.
+/// Commands without a fixture fail with status 127, like a missing program.
+pub struct RecordingRunner {
+    fixtures: Vec<Fixture>,
+    log: Option<String>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl RecordingRunner {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read command fixtures {}", path))?;
+        let file: FixtureFile = serde_json::from_str(&text).with_context(|| format!("Invalid command fixtures {}", path))?;
+        Ok(Self {
+            fixtures: file.commands,
+            log: file.log,
+            calls: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Command lines seen so far, in order
+    #[cfg(test)]
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    fn answer(&self, cmd: &Command) -> Output {
+        let line = command_line(cmd);
+        if let Some(path) = &self.log {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+
+        let output = match self.fixtures.iter().find(|f| line.starts_with(&f.command)) {
+            Some(f) => Output {
+                status: ExitStatus::from_raw(f.status << 8),
+                stdout: f.stdout.clone().into_bytes(),
+                stderr: f.stderr.clone().into_bytes(),
+            },
+            None => Output {
+                status: ExitStatus::from_raw(127 << 8),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            },
+        };
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(line);
+        }
+        output
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        Ok(self.answer(cmd))
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        Ok(self.answer(cmd).status)
+    }
+
+    fn modify(&self, cmd: &mut Command) -> io::Result<(ExitStatus, Vec<u8>)> {
+        let output = self.answer(cmd);
+        let mut captured = output.stdout;
+        captured.extend(output.stderr);
+        Ok((output.status, captured))
+    }
+}
+
+/// Pick the runner for this process; only the first call has an effect
+pub fn install(runner: Box<dyn CommandRunner>) {
+    let _ = RUNNER.set(runner);
+}
+
+/// The runner chosen with `install`, else fixtures from ESHU_TRACE_COMMAND_FIXTURES, else the real one
+pub fn runner() -> &'static dyn CommandRunner {
+    RUNNER
+        .get_or_init(|| match std::env::var("ESHU_TRACE_COMMAND_FIXTURES") {
+            Ok(path) if !path.is_empty() => match RecordingRunner::from_file(&path) {
+                Ok(runner) => Box::new(runner),
+                Err(e) => {
+                    eprintln!("{} {:#}", "✗".red(), e);
+                    std::process::exit(1);
+                }
+            },
+            _ => Box::new(RealRunner),
+        })
+        .as_ref()
+}
+
+/// `cmd.run_output()` / `cmd.run_status()` in place of `output()` / `status()`
+pub trait CommandExt {
+    fn run_output(&mut self) -> io::Result<Output>;
+    fn run_status(&mut self) -> io::Result<ExitStatus>;
+}
+
+impl CommandExt for Command {
+    fn run_output(&mut self) -> io::Result<Output> {
+        runner().output(self)
+    }
+
+    fn run_status(&mut self) -> io::Result<ExitStatus> {
+        runner().status(self)
+    }
+}
+
+/// Copy a child's stream to `out` as it arrives (so prompts still show) and keep a copy
+fn tee<R, W>(mut from: R, mut out: W) -> thread::JoinHandle<Vec<u8>>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut captured = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = from.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let _ = out.write_all(&buf[..n]);
+            let _ = out.flush();
+            captured.extend_from_slice(&buf[..n]);
+        }
+        captured
+    })
+}
+
+pub fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if arg.contains(char::is_whitespace) {
+                format!("'{}'", arg)
+            } else {
+                arg.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runner(fixtures: &str) -> RecordingRunner {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), fixtures).unwrap();
+        RecordingRunner::from_file(&file.path().to_string_lossy()).unwrap()
+    }
+
+    #[test]
+    fn recording_runner_answers_from_fixtures() {
+        let runner = runner(
+            r#"{"commands": [
+                {"command": "pacman -Q", "stdout": "mesa 24.1.1-1\n"},
+                {"command": "umount", "status": 32, "stderr": "target is busy"}
+            ]}"#,
+        );
+
+        let output = runner.output(Command::new("pacman").arg("-Q")).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"mesa 24.1.1-1\n");
+
+        let (status, captured) = runner.modify(Command::new("umount").args(["-R", "/tmp/a b"])).unwrap();
+        assert_eq!(status.code(), Some(32));
+        assert_eq!(captured, b"target is busy");
+
+        // Anything without a fixture is a missing program, and nothing is executed
+        let status = runner.status(Command::new("rm").args(["-rf", "/"])).unwrap();
+        assert_eq!(status.code(), Some(127));
+
+        assert_eq!(runner.calls(), vec!["pacman -Q", "umount -R '/tmp/a b'", "rm -rf /"]);
+    }
+}
@@ -0,0 +1,77 @@
+// Firmware and CPU microcode: changes that a package downgrade alone does not undo
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::exec::CommandExt;
+
+/// linux-firmware, sof-firmware, intel-ucode/amd-ucode, intel-microcode, microcode_ctl, ...
+pub fn is_firmware_package(name: &str) -> bool {
+    name.contains("firmware") || is_microcode_package(name)
+}
+
+pub fn is_microcode_package(name: &str) -> bool {
+    name.ends_with("-ucode") || name.contains("microcode")
+}
+
+/// What else rolling back this package takes, beyond installing the old version
+pub fn downgrade_note(name: &str) -> Option<&'static str> {
+    if is_microcode_package(name) {
+        Some("microcode loads during early boot: the initramfs/boot entries must be rebuilt, then a full reboot")
+    } else if is_firmware_package(name) {
+        Some("devices keep the firmware they loaded: power off or reboot, reloading the driver is not enough")
+    } else {
+        None
+    }
+}
+
+/// A device firmware update applied by fwupd between two snapshots
+#[derive(Debug, Clone)]
+pub struct FirmwareFlash {
+    pub device: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// Entries in the bad snapshot's fwupd history that the good snapshot does not have.
+/// Flashed firmware lives on the device, so no package downgrade reverts it.
+pub fn fwupd_flashes(good_root: &str, bad_root: &str) -> Vec<FirmwareFlash> {
+    let before = fwupd_history(good_root);
+    fwupd_history(bad_root)
+        .into_iter()
+        .filter(|(id, flash)| !before.iter().any(|(old_id, old)| old_id == id && old.new == flash.new))
+        .map(|(_, flash)| flash)
+        .collect()
+}
+
+/// (device id, flash) rows of <root>/var/lib/fwupd/history.db, read with the sqlite3 CLI
+fn fwupd_history(root: &str) -> Vec<(String, FirmwareFlash)> {
+    let db = Path::new(root).join("var/lib/fwupd/history.db");
+    if !db.exists() {
+        return Vec::new();
+    }
+
+    // immutable=1: snapshots are usually read-only, so no journal can be created
+    let output = Command::new("sqlite3")
+        .args(["-readonly", "-separator", "\t"])
+        .arg(format!("file:{}?immutable=1", db.display()))
+        .arg("SELECT device_id, name, version_old, version_new FROM history")
+        .run_output();
+
+    let Ok(output) = output else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let id = fields.next()?.to_string();
+            let flash = FirmwareFlash {
+                device: fields.next()?.to_string(),
+                old: fields.next()?.to_string(),
+                new: fields.next()?.to_string(),
+            };
+            Some((id, flash))
+        })
+        .collect()
+}
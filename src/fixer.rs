@@ -3,27 +3,91 @@
 use anyhow::Result;
 use colored::*;
 use dialoguer::{Confirm, Select};
+use std::cell::OnceCell;
+use std::path::Path;
 use std::process::Command;
 
-use crate::package_diff::PackageChange;
+use crate::audit;
+use crate::aur;
+use crate::bodhi;
+use crate::cache;
+use crate::corelibs::{self, Severity};
+use crate::error::TraceError;
+use crate::firmware;
+use crate::hooks::{self, Hook};
+use crate::http;
+use crate::initramfs;
+use crate::kernel;
+use crate::keyring;
+use crate::ownership;
+use crate::package_diff::{self, PackageChange};
+use crate::pins::{self, PinMethod};
+use crate::presets;
+use crate::reboot;
 use crate::recovery::RecoveryContext;
+use crate::remediation::{self, Remedy};
+use crate::restart;
+use crate::test_runner;
+use crate::tracker::{self, BugReport};
+use crate::upgrades;
 
 pub struct PackageFixer {
     recovery_ctx: RecoveryContext,
+    coupled: Vec<PackageChange>,
+    /// Every change between the good and bad state, for versions to move dependents back to
+    changes: Vec<PackageChange>,
+    test: Option<pins::RecordedTest>,
+    assume_yes: bool,
+    /// Open tracker reports about the culprit, searched on first use
+    known_bugs: OnceCell<Vec<BugReport>>,
 }
 
 #[derive(Debug)]
 pub enum FixAction {
     Downgrade(String, String),      // package, target_version
+    Update(String, String),         // package, release with the fix
     Remove(String),                  // package
     Pin(String, String),            // package, version
+    KeepKernel(String, String),     // kernel package, version to keep as a fallback
     ReportBug(String),              // package
     DoNothing,
 }
 
 impl PackageFixer {
     pub fn new(recovery_ctx: RecoveryContext) -> Self {
-        Self { recovery_ctx }
+        Self {
+            recovery_ctx,
+            coupled: Vec::new(),
+            changes: Vec::new(),
+            test: None,
+            assume_yes: false,
+            known_bugs: OnceCell::new(),
+        }
+    }
+
+    /// Answer every prompt with its default and pass -y/--noconfirm to the package manager
+    pub fn assume_yes(mut self) -> Self {
+        self.assume_yes = true;
+        self
+    }
+
+    /// Test that reproduced the regression, stored with pins so new releases can be re-tested
+    pub fn with_test(mut self, test: Option<pins::RecordedTest>) -> Self {
+        self.test = test;
+        self
+    }
+
+    /// Packages that must be downgraded in the same transaction as the culprit
+    pub fn with_coupled(mut self, coupled: Vec<PackageChange>) -> Self {
+        self.coupled = coupled;
+        self
+    }
+
+    /// All changes between the snapshots, so packages pinned to the culprit's exact
+    /// version can be moved back with it
+    pub fn with_changes(mut self, changes: Vec<PackageChange>) -> Self {
+        self.changes = changes;
+        self
     }
 
     pub fn offer_fix(&self, culprit: &PackageChange) -> Result<()> {
@@ -51,13 +115,24 @@ impl PackageFixer {
             }
         }
 
+        let known = self.known_bugs(culprit);
+        if !known.is_empty() {
+            println!();
+            println!("{}", "Already reported:".cyan());
+            print_reports(known);
+        }
+
         println!();
         println!("{}", "What would you like to do?".cyan().bold());
         println!();
 
         // Present fix options
         let options = self.get_fix_options(culprit);
-        let option_labels: Vec<String> = options.iter().map(|o| self.format_option(o)).collect();
+        let option_labels: Vec<String> = options
+            .iter()
+            .enumerate()
+            .map(|(i, o)| self.format_option(o, i == 0))
+            .collect();
 
         let selection = Select::new()
             .with_prompt("Choose action")
@@ -74,6 +149,11 @@ impl PackageFixer {
     fn get_fix_options(&self, culprit: &PackageChange) -> Vec<FixAction> {
         let mut options = Vec::new();
 
+        // A released fix beats going back
+        if let Some(version) = self.fixed_release(culprit) {
+            options.push(FixAction::Update(culprit.name().to_string(), version));
+        }
+
         match culprit {
             PackageChange::Added(pkg) => {
                 options.push(FixAction::Remove(pkg.name.clone()));
@@ -85,6 +165,10 @@ impl PackageFixer {
             }
             PackageChange::Upgraded(pkg, old_ver, _new_ver) => {
                 options.push(FixAction::Downgrade(pkg.name.clone(), old_ver.clone()));
+                // Boot entries and /proc/cmdline belong to the running system, not a chroot
+                if presets::is_kernel(&pkg.name) && !pkg.name.contains("headers") && !self.recovery_ctx.is_chroot {
+                    options.push(FixAction::KeepKernel(pkg.name.clone(), old_ver.clone()));
+                }
                 options.push(FixAction::Pin(pkg.name.clone(), old_ver.clone()));
                 options.push(FixAction::Remove(pkg.name.clone()));
                 options.push(FixAction::ReportBug(pkg.name.clone()));
@@ -99,10 +183,14 @@ impl PackageFixer {
         options
     }
 
-    fn format_option(&self, action: &FixAction) -> String {
+    fn format_option(&self, action: &FixAction, first: bool) -> String {
+        let recommended = if first { " (Recommended)" } else { "" };
         match action {
             FixAction::Downgrade(pkg, ver) => {
-                format!("⏪ Downgrade {} to {} (Recommended)", pkg, ver)
+                format!("⏪ Downgrade {} to {}{}", pkg, ver, recommended)
+            }
+            FixAction::Update(pkg, ver) => {
+                format!("⬆️  Update {} to {}, which has the fix{}", pkg, ver, recommended)
             }
             FixAction::Remove(pkg) => {
                 format!("🗑️  Remove {} completely", pkg)
@@ -110,6 +198,9 @@ impl PackageFixer {
             FixAction::Pin(pkg, ver) => {
                 format!("📌 Keep {} at {} and prevent future updates", pkg, ver)
             }
+            FixAction::KeepKernel(pkg, ver) => {
+                format!("🐧 Keep {} {} as a fallback boot entry next to the new kernel", pkg, ver)
+            }
             FixAction::ReportBug(pkg) => {
                 format!("🐛 Report bug for {} (opens issue)", pkg)
             }
@@ -120,15 +211,99 @@ impl PackageFixer {
     }
 
     fn execute_fix(&self, action: &FixAction, culprit: &PackageChange) -> Result<()> {
+        let hook_env = |kind: &str, pkg: &str, version: Option<&str>| {
+            vec![
+                ("ESHU_TRACE_FIX_ACTION", kind.to_string()),
+                ("ESHU_TRACE_PACKAGE", pkg.to_string()),
+                ("ESHU_TRACE_VERSION", version.unwrap_or_default().to_string()),
+            ]
+        };
+        let env = match action {
+            FixAction::Downgrade(pkg, version) => Some(hook_env("downgrade", pkg, Some(version))),
+            FixAction::Update(pkg, version) => Some(hook_env("update", pkg, Some(version))),
+            FixAction::Remove(pkg) => Some(hook_env("remove", pkg, None)),
+            FixAction::Pin(pkg, version) => Some(hook_env("pin", pkg, Some(version))),
+            FixAction::KeepKernel(pkg, version) => Some(hook_env("keep-kernel", pkg, Some(version))),
+            FixAction::ReportBug(_) | FixAction::DoNothing => None,
+        };
+        if let Some(env) = &env {
+            hooks::run(Hook::PreFix, env)?;
+        }
+
+        let applied = self.apply_action(action, culprit)?;
+
+        if let Some(mut env) = env {
+            env.push(("ESHU_TRACE_FIX_RESULT", if applied { "success" } else { "failed" }.to_string()));
+            hooks::run(Hook::PostFix, &env)?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the package manager succeeded
+    fn apply_action(&self, action: &FixAction, culprit: &PackageChange) -> Result<bool> {
+        let mut applied = true;
         match action {
             FixAction::Downgrade(pkg, version) => {
-                self.downgrade_package(pkg, version)?;
+                let Some(targets) = self.confirm_downgrade_set(pkg, version)? else {
+                    return Ok(false);
+                };
+                let names: Vec<String> = targets.iter().map(|(p, _)| p.clone()).collect();
+                if !self.confirm_core_changes(&names, "Downgrade")? {
+                    return Ok(false);
+                }
+                let early_boot = initramfs::affects_early_boot(self.target_root(), &names);
+                let rebuild = self.aur_rebuild_candidates(&names)?;
+                applied = self.downgrade_packages(&targets)?;
+                if applied {
+                    if early_boot {
+                        self.rebuild_early_boot()?;
+                    }
+                    self.offer_aur_rebuild(&targets, &rebuild)?;
+                    self.offer_script(Remedy::Downgrade(targets))?;
+                    self.offer_reboot(action, culprit, early_boot, None)?;
+                }
+            }
+            FixAction::Update(pkg, version) => {
+                let early_boot = initramfs::affects_early_boot(self.target_root(), std::slice::from_ref(pkg));
+                applied = self.update_package(pkg, version)?;
+                if applied {
+                    self.offer_reboot(action, culprit, early_boot, None)?;
+                }
             }
             FixAction::Remove(pkg) => {
-                self.remove_package(pkg)?;
+                if !self.confirm_core_changes(std::slice::from_ref(pkg), "Remove")? {
+                    return Ok(false);
+                }
+                // Checked first: the file list is gone once the package is
+                let early_boot = initramfs::affects_early_boot(self.target_root(), std::slice::from_ref(pkg));
+                applied = self.remove_package(pkg)?;
+                if applied {
+                    if early_boot {
+                        self.rebuild_early_boot()?;
+                    }
+                    self.offer_script(Remedy::Remove(pkg.clone()))?;
+                    self.offer_reboot(action, culprit, early_boot, None)?;
+                }
+            }
+            FixAction::KeepKernel(pkg, version) => {
+                let distro = self.detect_distro()?;
+                let fallback = kernel::keep_fallback(&distro, pkg, version)?;
+                println!();
+                println!(
+                    "{} The new kernel stays the default; pick {} at boot to use the old one",
+                    "✓".green().bold(),
+                    fallback.entry.bold()
+                );
+                println!("   Once a fixed kernel ships, remove the entry (and on Arch its /usr/lib/modules tree)");
+                self.offer_reboot(action, culprit, true, Some(&fallback.release))?;
             }
             FixAction::Pin(pkg, version) => {
-                self.pin_package(pkg, version)?;
+                let bad_version = match culprit {
+                    PackageChange::Upgraded(_, _, new_ver) => Some(new_ver.as_str()),
+                    _ => None,
+                };
+                self.pin_package(pkg, version, bad_version)?;
+                self.offer_script(Remedy::Pin(pkg.clone(), version.clone()))?;
             }
             FixAction::ReportBug(pkg) => {
                 self.report_bug(pkg, culprit)?;
@@ -143,14 +318,216 @@ impl PackageFixer {
             }
         }
 
+        Ok(applied)
+    }
+
+    /// On Arch, the AUR packages built against the releases about to be downgraded
+    fn aur_rebuild_candidates(&self, libraries: &[String]) -> Result<Vec<String>> {
+        if !matches!(self.detect_distro()?.as_str(), "arch" | "manjaro" | "endeavouros") {
+            return Ok(Vec::new());
+        }
+        Ok(aur::affected(self.target_root(), libraries))
+    }
+
+    /// After a library downgrade on Arch, rebuild the AUR packages (`affected`, from
+    /// [`Self::aur_rebuild_candidates`]) built against the broken release
+    fn offer_aur_rebuild(&self, targets: &[(String, String)], affected: &[String]) -> Result<()> {
+        if affected.is_empty() {
+            return Ok(());
+        }
+        let libraries: Vec<String> = targets.iter().map(|(p, _)| p.clone()).collect();
+
+        println!();
+        println!(
+            "{} These AUR/local packages link against {} and were built after its broken release arrived:",
+            "🔧".cyan(),
+            libraries.join(", ").bold()
+        );
+        for package in affected {
+            println!("   • {}", package);
+        }
+        println!("{}", "   They may need a rebuild to work with the restored release".dimmed());
+
+        // AUR builds can't run inside the recovery chroot, and never unattended
+        if self.recovery_ctx.is_chroot || self.assume_yes {
+            println!("   Rebuild them from the fixed system, e.g. paru -S --rebuild {}", affected.join(" "));
+            return Ok(());
+        }
+        let Some(builder) = aur::Builder::detect() else {
+            println!("   No AUR helper (paru, yay) or devtools found; rebuild them with makepkg -si from their PKGBUILDs");
+            return Ok(());
+        };
+
+        let rebuild = Confirm::new()
+            .with_prompt(format!("Rebuild them now with {}?", builder.describe()))
+            .default(true)
+            .interact()?;
+        if !rebuild {
+            return Ok(());
+        }
+
+        let failed = aur::rebuild(&builder, affected, targets)?;
+        if failed.is_empty() {
+            println!("{} Rebuilt {}", "✓".green(), affected.join(", "));
+        } else {
+            println!("{} Could not rebuild: {}", "⚠".yellow(), failed.join(", "));
+        }
         Ok(())
     }
 
-    fn downgrade_package(&self, package: &str, version: &str) -> Result<()> {
+    /// The smallest set that can go back together with `package`: its coupled family, then
+    /// repeatedly every changed package tied to a member by an exact-version dependency.
+    /// Shown with the reason for each; None if the user declines.
+    fn confirm_downgrade_set(&self, package: &str, version: &str) -> Result<Option<Vec<(String, String)>>> {
+        let old_version = |name: &str| {
+            self.coupled.iter().chain(&self.changes).find_map(|c| match c {
+                PackageChange::Upgraded(p, old, _) | PackageChange::Downgraded(p, old, _) if p.name == name => {
+                    Some(old.clone())
+                }
+                _ => None,
+            })
+        };
+
+        let mut set: Vec<(String, String, String)> = vec![(package.to_string(), version.to_string(), String::new())];
+        for change in &self.coupled {
+            if let PackageChange::Upgraded(p, old, _) | PackageChange::Downgraded(p, old, _) = change {
+                if p.name != package {
+                    set.push((p.name.clone(), old.clone(), format!("released together with {}", package)));
+                }
+            }
+        }
+
+        let pairs = ownership::exact_dependencies_in(self.target_root());
+        let mut stranded: Vec<String> = Vec::new();
+        let mut i = 0;
+        while i < set.len() {
+            let name = set[i].0.clone();
+            for (dependent, dependency) in &pairs {
+                let (other, reason, other_depends) = if *dependency == name {
+                    (dependent, format!("requires exactly the installed {}", name), true)
+                } else if *dependent == name {
+                    (dependency, format!("{} requires exactly this version", name), false)
+                } else {
+                    continue;
+                };
+                if set.iter().any(|(p, _, _)| p == other) {
+                    continue;
+                }
+                match old_version(other) {
+                    Some(old) => set.push((other.clone(), old, reason)),
+                    // Nothing to go back to; only a dependent left behind breaks
+                    None if other_depends && !stranded.contains(other) => stranded.push(other.clone()),
+                    None => {}
+                }
+            }
+            i += 1;
+        }
+
+        if set.len() > 1 || !stranded.is_empty() {
+            println!();
+            println!("{} {} cannot go back alone. Downgrading in one transaction:", "🔗".cyan(), package.bold());
+            for (p, v, reason) in &set {
+                if reason.is_empty() {
+                    println!("   {} {}", p.bold(), v);
+                } else {
+                    println!("   {} {}  {}", p.bold(), v, reason.dimmed());
+                }
+            }
+            for p in &stranded {
+                println!(
+                    "   {} {} requires exactly the current version and has no older version to return to; the package manager may remove it",
+                    "⚠".yellow(),
+                    p
+                );
+            }
+            if !self.assume_yes {
+                let proceed = Confirm::new()
+                    .with_prompt(format!("Downgrade all {} together?", set.len()))
+                    .default(true)
+                    .interact()?;
+                if !proceed {
+                    println!("{} No changes made; {} alone would leave them mismatched", "ℹ".cyan(), package);
+                    return Ok(None);
+                }
+            }
+        }
+
+        Ok(Some(set.into_iter().map(|(p, v, _)| (p, v)).collect()))
+    }
+
+    /// Warn before a fix touches glibc, OpenSSL, systemd, PAM or D-Bus and ask to go ahead.
+    /// With --yes, critical changes are refused rather than applied unattended.
+    fn confirm_core_changes(&self, packages: &[String], verb: &str) -> Result<bool> {
+        let core: Vec<(&String, corelibs::CoreLibrary)> = packages
+            .iter()
+            .filter_map(|p| corelibs::classify(p).map(|lib| (p, lib)))
+            .collect();
+        let Some(worst) = core.iter().map(|(_, lib)| lib.severity).max() else {
+            return Ok(true);
+        };
+
         println!();
-        println!("{} Downgrading {} to {}...", "⏪".yellow(), package, version);
+        println!("{} This fix changes core system libraries:", "⚠".yellow().bold());
+        for (package, lib) in &core {
+            println!("   {} {}: {}", lib.severity.tag(), package.bold(), lib.advice);
+        }
 
-        let distro = self.detect_distro()?;
+        if self.assume_yes {
+            if worst == Severity::Critical {
+                println!("{} Not applied unattended; run the fix interactively to confirm", "✗".red());
+                return Ok(false);
+            }
+            return Ok(true);
+        }
+
+        println!();
+        let proceed = Confirm::new()
+            .with_prompt(format!("{} anyway?", verb))
+            .default(false)
+            .interact()?;
+        if !proceed {
+            println!("{} No changes made", "ℹ".cyan());
+        }
+        Ok(proceed)
+    }
+
+    /// Offer to reboot when the fix needs one, and verify it on the first run afterwards.
+    /// `early_boot` fixes need a real reboot; otherwise only if programs still run replaced files.
+    fn offer_reboot(&self, action: &FixAction, culprit: &PackageChange, early_boot: bool, fallback: Option<&str>) -> Result<()> {
+        // From a rescue system the reboot is the next step anyway, and the state lives there
+        if self.recovery_ctx.is_chroot {
+            return Ok(());
+        }
+        let needed = if early_boot {
+            reboot::Needed::Boot
+        } else {
+            let stale = restart::detect();
+            if stale.is_empty() {
+                return Ok(());
+            }
+            match stale.kernel {
+                true => reboot::Needed::Boot,
+                false => reboot::Needed::Userspace,
+            }
+        };
+
+        let fix = self.format_option(action, false);
+        let pending = reboot::PendingVerify::new(fix, culprit.name().to_string(), self.test.clone());
+        reboot::offer(pending, needed, fallback, self.assume_yes)
+    }
+
+    /// Regenerate the initramfs and boot menu so a kernel, driver or firmware fix takes effect
+    fn rebuild_early_boot(&self) -> Result<()> {
+        let commands = initramfs::rebuild_commands(self.target_root());
+        println!();
+        if commands.is_empty() {
+            println!(
+                "{} This fix affects early boot, but no initramfs tool was found; rebuild the initramfs before rebooting",
+                "⚠".yellow()
+            );
+            return Ok(());
+        }
+        println!("{} This fix affects early boot; rebuilding the initramfs and boot menu...", "🔧".bold());
 
         let chroot_prefix = if self.recovery_ctx.is_chroot {
             format!("arch-chroot {} ", self.recovery_ctx.system_root)
@@ -158,59 +535,134 @@ impl PackageFixer {
             String::new()
         };
 
-        let success = match distro.as_str() {
-            "arch" | "manjaro" => {
-                // Try pacman cache first
-                let cmd = format!("{}sudo pacman -U /var/cache/pacman/pkg/{}-{}*.pkg.tar.*",
-                                 chroot_prefix, package, version);
+        for (what, command) in commands {
+            let cmd = format!("{}sudo {}", chroot_prefix, command);
+            println!("{} Running: {}", "→".dimmed(), cmd.dimmed());
+            if audit::run(&format!("fix: rebuild {}", what), Command::new("sh").arg("-c").arg(&cmd))?.success() {
+                println!("{} Rebuilt {}", "✓".green(), what);
+            } else {
+                println!("{} Rebuilding the {} failed; run `{}` before rebooting", "⚠".yellow(), what, command);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remember the applied fix and offer it as a shell script for other identical machines
+    fn offer_script(&self, remedy: Remedy) -> Result<()> {
+        let distro = self.detect_distro()?;
+        remediation::record(&distro, &remedy)?;
+        if self.assume_yes {
+            return Ok(());
+        }
 
-                println!("{} Running: {}", "→".dimmed(), cmd.dimmed());
+        println!();
+        if !Confirm::new()
+            .with_prompt("Save a script that applies this fix to other machines?")
+            .default(false)
+            .interact()?
+        {
+            return Ok(());
+        }
 
-                let result = Command::new("sh")
-                    .arg("-c")
-                    .arg(&cmd)
-                    .status()?;
+        let path = std::path::PathBuf::from(remediation::script_name(&remedy));
+        remediation::save(&path, &distro, &remedy, false)?;
+        println!("{} Saved {}", "✓".green(), path.display());
+        println!("   Run it as root on a machine with the same distro release");
+        println!("   For many hosts: eshu-trace fix --export-ansible fix.yml");
+        Ok(())
+    }
 
-                result.success()
-            }
-            "ubuntu" | "debian" => {
-                let cmd = format!("{}sudo apt-get install {}={}", chroot_prefix, package, version);
+    /// Downgrade one package, or a coupled set of packages in a single transaction
+    /// Returns whether the package manager succeeded
+    pub fn downgrade_packages(&self, targets: &[(String, String)]) -> Result<bool> {
+        let (package, version) = (&targets[0].0, &targets[0].1);
+        // The command goes through sh for the cache globs
+        if let Some((p, v)) = targets.iter().find(|(p, v)| !is_package_token(p) || !is_package_token(v)) {
+            anyhow::bail!("Refusing to downgrade {:?} to {:?}: not a package name and version", p, v);
+        }
 
-                println!("{} Running: {}", "→".dimmed(), cmd.dimmed());
+        println!();
+        println!("{} Downgrading {} to {}...", "⏪".yellow(), package, version);
+        for (pkg, ver) in &targets[1..] {
+            println!("   {} together with {} {}", "+".dimmed(), pkg, ver);
+        }
 
-                let result = Command::new("sh")
-                    .arg("-c")
-                    .arg(&cmd)
-                    .status()?;
+        let distro = self.detect_distro()?;
 
-                result.success()
-            }
-            "fedora" | "rhel" => {
-                let cmd = format!("{}sudo dnf downgrade {}-{}", chroot_prefix, package, version);
+        let chroot_prefix = if self.recovery_ctx.is_chroot {
+            format!("arch-chroot {} ", self.recovery_ctx.system_root)
+        } else {
+            String::new()
+        };
 
-                println!("{} Running: {}", "→".dimmed(), cmd.dimmed());
+        self.ensure_keys(&distro, targets, &chroot_prefix)?;
 
-                let result = Command::new("sh")
-                    .arg("-c")
-                    .arg(&cmd)
-                    .status()?;
+        // The managed cache lives on the host, so it can't be used from inside a chroot
+        let preserved = |p: &str, v: &str| {
+            if self.recovery_ctx.is_chroot {
+                None
+            } else {
+                cache::find(p, v).map(|path| path.to_string_lossy().to_string())
+            }
+        };
 
-                result.success()
+        let cmd = match distro.as_str() {
+            "arch" | "manjaro" => {
+                // Try pacman cache first, then eshu-trace's own cache
+                let files: Vec<String> = targets
+                    .iter()
+                    .map(|(p, v)| {
+                        let glob = format!("/var/cache/pacman/pkg/{}-{}*.pkg.tar.*", p, v);
+                        match preserved(p, v) {
+                            Some(file) if !has_match(&glob) => file,
+                            _ => glob,
+                        }
+                    })
+                    .collect();
+                format!("{}sudo pacman -U{} {}", chroot_prefix, self.yes_flag(&distro), files.join(" "))
+            }
+            "ubuntu" | "debian" => {
+                let specs: Vec<String> = targets
+                    .iter()
+                    .map(|(p, v)| preserved(p, v).unwrap_or_else(|| format!("{}={}", p, v)))
+                    .collect();
+                // Keep the downloaded debs so they can be protected from cleaning afterwards
+                format!(
+                    "{}sudo apt-get install{} -o APT::Keep-Downloaded-Packages=true {}",
+                    chroot_prefix,
+                    self.yes_flag(&distro),
+                    specs.join(" ")
+                )
+            }
+            "fedora" | "rhel" => {
+                let specs: Vec<String> = targets.iter().map(|(p, v)| format!("{}-{}", p, v)).collect();
+                format!("{}sudo dnf downgrade{} {}", chroot_prefix, self.yes_flag(&distro), specs.join(" "))
             }
             _ => {
-                println!("{} Unsupported distro for auto-downgrade", "⚠".yellow());
-                return Ok(());
+                return Err(TraceError::UnsupportedDistro(distro).into());
             }
         };
 
+        println!("{} Running: {}", "→".dimmed(), cmd.dimmed());
+
+        let success = audit::run(&format!("fix: downgrade {}", package), Command::new("sh").arg("-c").arg(&cmd))?
+            .success();
+
         if success {
             println!();
             println!("{} Successfully downgraded {}!", "✓".green().bold(), package);
+            self.protect_versions(&distro, targets);
             println!();
             println!("Next steps:");
             println!("  1. Reboot your system");
             println!("  2. Verify the issue is fixed");
             println!("  3. Consider pinning this version (see below)");
+            for (pkg, _) in targets {
+                if let Some(note) = firmware::downgrade_note(pkg) {
+                    println!("  {} {}: {}", "⚠".yellow(), pkg, note);
+                }
+            }
         } else {
             println!();
             println!("{} Downgrade failed", "✗".red());
@@ -220,53 +672,158 @@ impl PackageFixer {
             println!("  • Check if version {} exists", version);
         }
 
-        Ok(())
+        Ok(success)
     }
 
-    fn remove_package(&self, package: &str) -> Result<()> {
-        println!();
+    /// Keep the working package files out of reach of paccache/apt clean
+    fn protect_versions(&self, distro: &str, targets: &[(String, String)]) {
+        if self.recovery_ctx.is_chroot {
+            println!(
+                "{} Run {} after rebooting to protect these versions from cache cleaning",
+                "ℹ".cyan(),
+                format!("eshu-trace cache keep {}", targets.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>().join(" ")).white()
+            );
+            return;
+        }
 
-        if !Confirm::new()
-            .with_prompt(format!("Really remove {}? This may break dependencies", package))
-            .interact()? {
+        let packages: Vec<String> = targets.iter().map(|(p, _)| p.clone()).collect();
+        match cache::protect(distro, &packages) {
+            Ok(copied) => println!(
+                "{} Protected from cache cleaning ({} file(s) preserved in {})",
+                "🔒".bold(),
+                copied,
+                cache::packages_dir().display()
+            ),
+            Err(e) => println!("{} Could not protect the working versions: {}", "⚠".yellow(), e),
+        }
+    }
+
+    /// Warn about expired or missing signing keys and offer a keyring refresh before installing
+    fn ensure_keys(&self, distro: &str, targets: &[(String, String)], chroot_prefix: &str) -> Result<()> {
+        let root = if self.recovery_ctx.is_chroot {
+            self.recovery_ctx.system_root.as_str()
+        } else {
+            "/"
+        };
+
+        let problems = keyring::preflight(root, distro, targets);
+        if problems.is_empty() {
             return Ok(());
         }
 
-        println!("{} Removing {}...", "🗑️".red(), package);
+        println!();
+        println!("{} This fix is likely to fail signature checks:", "⚠".yellow());
+        for problem in &problems {
+            println!("   • {}", problem);
+        }
 
-        let distro = self.detect_distro()?;
-        let chroot_prefix = if self.recovery_ctx.is_chroot {
-            format!("arch-chroot {} ", self.recovery_ctx.system_root)
-        } else {
-            String::new()
+        let refresh = match keyring::refresh_command(distro) {
+            Some(r) => r,
+            None => return Ok(()),
         };
 
-        let cmd = match distro.as_str() {
-            "arch" | "manjaro" => format!("{}sudo pacman -R {}", chroot_prefix, package),
-            "ubuntu" | "debian" => format!("{}sudo apt-get remove {}", chroot_prefix, package),
-            "fedora" | "rhel" => format!("{}sudo dnf remove {}", chroot_prefix, package),
+        if self.assume_yes
+            || Confirm::new()
+                .with_prompt("Refresh the keyring first?")
+                .default(true)
+                .interact()?
+        {
+            let cmd = format!("{}sudo sh -c '{}'", chroot_prefix, refresh);
+            println!("{} Running: {}", "→".dimmed(), cmd.dimmed());
+            if !audit::run("fix: refresh keyring", Command::new("sh").arg("-c").arg(&cmd))?.success() {
+                println!("{} Keyring refresh failed; continuing anyway", "⚠".yellow());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Upgrade to the release that fixes the regression, the package manager's normal way.
+    /// Returns whether the package was updated.
+    pub fn update_package(&self, package: &str, version: &str) -> Result<bool> {
+        if !is_package_token(package) || !is_package_token(version) {
+            anyhow::bail!("Refusing to update {:?}: not a package name and version", package);
+        }
+        println!();
+        println!("{} Updating {} to {}...", "⬆️".green(), package, version);
+
+        let distro = self.detect_distro()?;
+        let (command, target): (&[&str], String) = match distro.as_str() {
+            // Partial upgrades are unsupported on Arch; the fix comes with the rest of the system
+            "arch" | "manjaro" | "endeavouros" => (&["pacman", "-Syu"], package.to_string()),
+            "ubuntu" | "debian" | "linuxmint" | "pop" => {
+                (&["apt-get", "install", "--only-upgrade"], format!("{}={}", package, version))
+            }
+            "fedora" | "rhel" | "centos" => (&["dnf", "upgrade"], format!("{}-{}", package, version)),
+            other if pins::is_zypper(other) => (&["zypper", "update"], package.to_string()),
             _ => {
-                println!("{} Unsupported distro", "⚠".yellow());
-                return Ok(());
+                return Err(TraceError::UnsupportedDistro(distro).into());
             }
         };
+        let mut argv: Vec<&str> = vec!["sudo"];
+        argv.extend(command);
+        argv.extend(self.yes_flag(&distro).split_whitespace());
+        argv.extend(["--", &target]);
 
-        println!("{} Running: {}", "→".dimmed(), cmd.dimmed());
+        println!("{} Running: {}", "→".dimmed(), argv.join(" ").dimmed());
+
+        let result = audit::run(&format!("fix: update {} to {}", package, version), Command::new(argv[0]).args(&argv[1..]))?;
+
+        if result.success() {
+            println!();
+            println!("{} Successfully updated {} to {}!", "✓".green().bold(), package, version);
+        }
+
+        Ok(result.success())
+    }
+
+    /// Returns whether the package was removed
+    pub fn remove_package(&self, package: &str) -> Result<bool> {
+        println!();
+
+        if !self.assume_yes
+            && !Confirm::new()
+                .with_prompt(format!("Really remove {}? This may break dependencies", package))
+                .interact()?
+        {
+            return Ok(false);
+        }
 
-        let result = Command::new("sh")
-            .arg("-c")
-            .arg(&cmd)
-            .status()?;
+        println!("{} Removing {}...", "🗑️".red(), package);
+
+        let distro = self.detect_distro()?;
+        let mut argv: Vec<&str> = Vec::new();
+        if self.recovery_ctx.is_chroot {
+            argv.extend(["arch-chroot", self.recovery_ctx.system_root.as_str()]);
+        }
+        match distro.as_str() {
+            "arch" | "manjaro" => argv.extend(["sudo", "pacman", "-R"]),
+            "ubuntu" | "debian" => argv.extend(["sudo", "apt-get", "remove"]),
+            "fedora" | "rhel" => argv.extend(["sudo", "dnf", "remove"]),
+            _ => {
+                return Err(TraceError::UnsupportedDistro(distro).into());
+            }
+        }
+        argv.extend(self.yes_flag(&distro).split_whitespace());
+        argv.extend(["--", package]);
+
+        println!("{} Running: {}", "→".dimmed(), argv.join(" ").dimmed());
+
+        let result = audit::run(&format!("fix: remove {}", package), Command::new(argv[0]).args(&argv[1..]))?;
 
         if result.success() {
             println!();
             println!("{} Successfully removed {}!", "✓".green().bold(), package);
         }
 
-        Ok(())
+        Ok(result.success())
     }
 
-    fn pin_package(&self, package: &str, version: &str) -> Result<()> {
+    pub fn pin_package(&self, package: &str, version: &str, bad_version: Option<&str>) -> Result<()> {
+        // Both end up in /etc/apt/preferences.d and the pin registry
+        if ![package, version].into_iter().chain(bad_version).all(is_package_token) {
+            anyhow::bail!("Refusing to pin {:?}: not a package name and version", package);
+        }
         println!();
         println!("{} Pinning {} at version {}...", "📌".yellow(), package, version);
 
@@ -274,19 +831,45 @@ impl PackageFixer {
 
         match distro.as_str() {
             "arch" | "manjaro" => {
-                println!("Add to /etc/pacman.conf:");
+                println!("Add to {}:", Path::new(self.target_root()).join("etc/pacman.conf").display());
                 println!("  {}", format!("IgnorePkg = {}", package).yellow());
             }
             "ubuntu" | "debian" => {
-                let cmd = format!("sudo apt-mark hold {}", package);
-                println!("{} Running: {}", "→".dimmed(), cmd.dimmed());
-                Command::new("sh").arg("-c").arg(&cmd).status()?;
-                println!("{} Package pinned", "✓".green());
+                let mut methods = vec![PinMethod::Preferences, PinMethod::Hold];
+                if bad_version.is_some() {
+                    methods.insert(1, PinMethod::Ceiling);
+                }
+                let labels: Vec<&str> = methods.iter().map(|m| m.description()).collect();
+                let choice = if self.assume_yes {
+                    0
+                } else {
+                    Select::new()
+                        .with_prompt("How should it be pinned?")
+                        .items(&labels)
+                        .default(0)
+                        .interact()?
+                };
+
+                let pin = pins::apply(self.target_root(), package, version, bad_version, methods[choice], self.test.clone())?;
+                println!("{} Package pinned ({})", "✓".green(), pin.method.description());
+                if let Some(file) = &pin.file {
+                    println!("   {}", file.display().to_string().dimmed());
+                }
+                println!();
+                println!("Lift it later with: {}", format!("eshu-trace pin remove {}", package).white());
+                return Ok(());
             }
             "fedora" | "rhel" => {
-                println!("Add to /etc/dnf/dnf.conf:");
+                println!("Add to {}:", Path::new(self.target_root()).join("etc/dnf/dnf.conf").display());
                 println!("  {}", format!("exclude={}", package).yellow());
             }
+            other if pins::is_zypper(other) => {
+                let pin = pins::apply(self.target_root(), package, version, bad_version, PinMethod::Lock, self.test.clone())?;
+                println!("{} Package locked ({})", "✓".green(), pin.method.description());
+                println!();
+                println!("Lift it later with: {}", format!("eshu-trace pin remove {}", package).white());
+                return Ok(());
+            }
             _ => {}
         }
 
@@ -297,11 +880,41 @@ impl PackageFixer {
         Ok(())
     }
 
-    fn report_bug(&self, package: &str, _culprit: &PackageChange) -> Result<()> {
+    fn report_bug(&self, package: &str, culprit: &PackageChange) -> Result<()> {
         println!();
         println!("{} Generating bug report for {}...", "🐛".cyan(), package);
         println!();
 
+        // Karma on a Fedora update still in testing can keep it from reaching stable
+        if self.detect_distro()? == "fedora" {
+            self.offer_karma(culprit)?;
+        }
+
+        // Add to an existing report rather than filing a duplicate
+        let known = self.known_bugs(culprit);
+        if !known.is_empty() {
+            println!("{}", "These reports may already cover it:".yellow());
+            print_reports(known);
+            println!();
+
+            let mut labels: Vec<String> = known.iter().map(|r| format!("Add to {} {}", r.tracker, r.id)).collect();
+            labels.push("File a new report".to_string());
+            let choice = if self.assume_yes {
+                labels.len() - 1
+            } else {
+                Select::new()
+                    .with_prompt("Where should your findings go?")
+                    .items(&labels)
+                    .default(0)
+                    .interact()?
+            };
+            if let Some(report) = known.get(choice) {
+                println!("Opening {}...", report.url.cyan());
+                let _ = Command::new("xdg-open").arg(&report.url).spawn();
+                return Ok(());
+            }
+        }
+
         // Try to find package homepage/bug tracker
         let distro = self.detect_distro()?;
 
@@ -328,20 +941,183 @@ impl PackageFixer {
         Ok(())
     }
 
-    fn detect_distro(&self) -> Result<String> {
-        let os_release = if self.recovery_ctx.is_chroot {
-            std::fs::read_to_string(format!("{}/etc/os-release", self.recovery_ctx.system_root))?
-        } else {
-            std::fs::read_to_string("/etc/os-release")?
+    /// Offer negative karma with the bisect's evidence on the Bodhi update that shipped the
+    /// culprit, when it is in testing or recently stable; a failed lookup only warns
+    fn offer_karma(&self, culprit: &PackageChange) -> Result<()> {
+        let version = match culprit {
+            PackageChange::Upgraded(_, _, new) | PackageChange::Downgraded(_, _, new) => new,
+            PackageChange::Added(pkg) => &pkg.version,
+            PackageChange::Removed(_) => return Ok(()),
+        };
+        if !http::available() {
+            return Ok(());
+        }
+        // Bodhi knows builds by name-version-release, without the epoch
+        let version = version.split_once(':').map_or(version.as_str(), |(_, v)| v);
+        let nvr = format!("{}-{}", culprit.name(), version);
+
+        let update = match bodhi::find_update(&nvr) {
+            Ok(Some(update)) if update.wants_feedback() => update,
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                println!("{} Could not look up {} in Bodhi: {:#}", "⚠".yellow(), nvr, e);
+                return Ok(());
+            }
         };
 
-        for line in os_release.lines() {
-            if line.starts_with("ID=") {
-                let distro = line.trim_start_matches("ID=").trim_matches('"');
-                return Ok(distro.to_string());
+        println!("{} {} shipped in {} ({})", "ℹ".cyan(), nvr, update.alias.yellow(), update.describe());
+        println!("  {}", update.url().cyan());
+        let text = bodhi::evidence(culprit, self.test.as_ref());
+        println!();
+        println!("{}", "Feedback:".bold());
+        println!("  {}", text);
+        println!();
+
+        // Karma is public and tied to the user's account, so never send it unasked
+        if self.assume_yes {
+            return Ok(());
+        }
+        let send = Confirm::new()
+            .with_prompt(format!("Post this with -1 karma on {}?", update.alias))
+            .default(false)
+            .interact()?;
+        if !send {
+            return Ok(());
+        }
+
+        if !test_runner::which("bodhi") {
+            println!("{} The bodhi client is not installed (dnf install bodhi-client)", "⚠".yellow());
+            println!("  Paste the feedback above at {}", update.url().cyan());
+            return Ok(());
+        }
+        if bodhi::send_negative_karma(&update, &text)? {
+            println!("{} Feedback posted on {}", "✓".green(), update.alias);
+        } else {
+            println!("{} bodhi could not post the comment; paste it at {}", "⚠".yellow(), update.url().cyan());
+        }
+        Ok(())
+    }
+
+    /// Open reports about the culprit in the distro's tracker; a failed search only warns
+    fn known_bugs(&self, culprit: &PackageChange) -> &[BugReport] {
+        self.known_bugs.get_or_init(|| {
+            if !http::available() {
+                return Vec::new();
             }
+            let version = match culprit {
+                PackageChange::Added(pkg) | PackageChange::Removed(pkg) => &pkg.version,
+                PackageChange::Upgraded(_, _, new) | PackageChange::Downgraded(_, _, new) => new,
+            };
+            let distro = self.detect_distro().unwrap_or_default();
+            tracker::search(&distro, culprit.name(), version).unwrap_or_else(|e| {
+                println!("{} Could not search the bug tracker: {:#}", "⚠".yellow(), e);
+                Vec::new()
+            })
+        })
+    }
+
+    /// The pending upgrade of the culprit, when a tracker says a released version fixes it
+    /// and the package manager offers that version (or a newer one)
+    fn fixed_release(&self, culprit: &PackageChange) -> Option<String> {
+        // Pending upgrades are only known for the running system
+        if self.recovery_ctx.is_chroot || matches!(culprit, PackageChange::Removed(_)) {
+            return None;
+        }
+        let fixes: Vec<&BugReport> = self
+            .known_bugs(culprit)
+            .iter()
+            .filter(|r| r.fix_released && r.mentions_version)
+            .collect();
+        if fixes.is_empty() {
+            return None;
+        }
+
+        let distro = self.detect_distro().ok()?;
+        let available = upgrades::pending(&distro)
+            .ok()?
+            .into_iter()
+            .find(|u| u.name == culprit.name())?;
+        let includes_fix = fixes.iter().any(|r| match &r.fixed_in {
+            Some(fixed) => available.to == *fixed || package_diff::version_compare(&available.to, fixed),
+            None => true,
+        });
+        includes_fix.then_some(available.to)
+    }
+
+    fn yes_flag(&self, distro: &str) -> &'static str {
+        match (self.assume_yes, distro) {
+            (false, _) => "",
+            (true, "arch" | "manjaro" | "endeavouros") => " --noconfirm",
+            (true, _) => " -y",
+        }
+    }
+
+    /// Root of the system being fixed
+    fn target_root(&self) -> &str {
+        if self.recovery_ctx.is_chroot {
+            &self.recovery_ctx.system_root
+        } else {
+            "/"
+        }
+    }
+
+    fn detect_distro(&self) -> Result<String> {
+        if self.recovery_ctx.is_chroot {
+            detect_distro_at(&self.recovery_ctx.system_root)
+        } else {
+            detect_distro_at("/")
         }
+    }
+}
+
+fn print_reports(reports: &[BugReport]) {
+    for report in reports {
+        let marker = if report.mentions_version { "•".yellow() } else { "•".dimmed() };
+        let status = match (&report.fixed_in, report.fix_released) {
+            (Some(version), _) => format!("{}, fixed in {}", report.status, version).green(),
+            (None, true) => format!("{}, fix released", report.status).green(),
+            (None, false) => report.status.normal(),
+        };
+        println!("  {} {} {}: {} [{}]", marker, report.tracker, report.id, report.title, status);
+        println!("    {}", report.url.dimmed());
+    }
+}
 
-        Ok("unknown".to_string())
+/// Whether `s` is a plausible package name or version: the characters package managers
+/// allow (apt epochs and tildes included), and not something that reads as an option
+pub fn is_package_token(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with('-')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || "@._+:~-".contains(c))
+}
+
+/// Whether a shell glob of the form dir/prefix*suffix matches an existing file
+fn has_match(glob: &str) -> bool {
+    let (dir, pattern) = glob.rsplit_once('/').unwrap_or((".", glob));
+    let (prefix, rest) = pattern.split_once('*').unwrap_or((pattern, ""));
+    let suffix_parts: Vec<&str> = rest.split('*').filter(|s| !s.is_empty()).collect();
+
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries.flatten().any(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                name.starts_with(prefix) && suffix_parts.iter().all(|part| name.contains(part))
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// Read the distro ID from os-release under the given root
+pub fn detect_distro_at(root: &str) -> Result<String> {
+    let os_release = std::fs::read_to_string(Path::new(root).join("etc/os-release"))
+        .map_err(|_| TraceError::NoOsRelease(root.trim_end_matches('/').to_string()))?;
+
+    for line in os_release.lines() {
+        if line.starts_with("ID=") {
+            let distro = line.trim_start_matches("ID=").trim_matches('"');
+            return Ok(distro.to_string());
+        }
     }
+
+    Ok("unknown".to_string())
 }
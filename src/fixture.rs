@@ -0,0 +1,76 @@
+// Fake snapshot roots for exercising diff and bisect without real systems (feature "fixtures")
+//
+// ESHU_TRACE_FIXTURE_ROOT names a directory whose subdirectories are snapshots: each is a
+// root filesystem holding just a package database (var/lib/pacman/local, var/lib/dpkg/status,
+// var/lib/rpm or usr/lib/sysimage/rpm/rpmdb.sqlite). An optional `fixture.json` in a snapshot sets its date and description.
+// ESHU_TRACE_FIXTURE_CURRENT names the root that stands in for the running system.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::package_diff;
+use crate::snapshot::Snapshot;
+
+#[derive(Debug, Default, Deserialize)]
+struct FixtureMeta {
+    created_at: Option<DateTime<Local>>,
+    description: Option<String>,
+}
+
+/// The fixture directory, when the harness is in use
+pub fn root() -> Option<PathBuf> {
+    std::env::var_os("ESHU_TRACE_FIXTURE_ROOT").filter(|r| !r.is_empty()).map(PathBuf::from)
+}
+
+/// Each subdirectory of the fixture root as a snapshot, newest first
+pub fn snapshots() -> Result<Vec<Snapshot>> {
+    let Some(root) = root() else {
+        return Ok(Vec::new());
+    };
+
+    let mut snapshots = Vec::new();
+    let entries = fs::read_dir(&root).with_context(|| format!("Failed to read fixture root {}", root.display()))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+
+        let meta: FixtureMeta = fs::read_to_string(path.join("fixture.json"))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        let created_at = meta
+            .created_at
+            .or_else(|| path.metadata().and_then(|m| m.modified()).ok().map(Into::into));
+
+        snapshots.push(Snapshot {
+            id: entry.file_name().to_string_lossy().to_string(),
+            created_at,
+            description: meta.description,
+            kind: None,
+            cleanup: None,
+            packages: None,
+            package_count: None,
+            path: Some(path.to_string_lossy().to_string()),
+        });
+    }
+
+    // Same date (e.g. no fixture.json in a fresh checkout): fall back to the name
+    snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| b.id.cmp(&a.id)));
+    Ok(snapshots)
+}
+
+/// Root of the fake running system, if ESHU_TRACE_FIXTURE_CURRENT is set
+pub fn current_root() -> Option<String> {
+    std::env::var("ESHU_TRACE_FIXTURE_CURRENT").ok().filter(|c| !c.is_empty())
+}
+
+/// Packages of the fake running system
+pub fn current_packages() -> Option<HashMap<String, String>> {
+    Some(package_diff::packages_in(&current_root()?))
+}
@@ -0,0 +1,552 @@
+// Guix System generations as snapshots (feature "guix")
+//
+// Every `guix system reconfigure` creates a generation: /var/guix/profiles/system-<N>-link
+// points at the generation's directory in /gnu/store, and `system` points at the current
+// one. The root filesystem is shared, so a generation has no root of its own to read a
+// package database from; its package set comes from the manifest of its profile (an
+// S-expression listing name, version and output of each package) plus the kernel named in
+// its boot parameters, read only when a diff needs them. Going back to a generation is
+// `guix system switch-generation N`. Guix installs a generation as a whole, so `bisect`
+// between two generations searches the generations in between instead of packages: each
+// step switches to one and waits for the reboot into it before asking for a verdict.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use colored::*;
+use dialoguer::Confirm;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::audit;
+use crate::bisect;
+use crate::exec;
+use crate::package_diff::compute_diff;
+use crate::paths;
+use crate::snapshot::Snapshot;
+use crate::test_runner::TestRunner;
+
+const PROFILES_DIR: &str = "var/guix/profiles";
+
+/// `kind` of the snapshots this backend lists
+pub const GENERATION: &str = "generation";
+
+/// Whether `root` is a Guix System
+pub fn is_guix(root: &str) -> bool {
+    Path::new(root).join(PROFILES_DIR).join("system").exists()
+}
+
+/// System generations, newest first. Their packages are left for [`packages_of`].
+pub fn generations() -> Result<Vec<Snapshot>> {
+    let dir = Path::new("/").join(PROFILES_DIR);
+    let current = fs::read_link(dir.join("system")).ok();
+
+    let mut snapshots = Vec::new();
+    let entries = fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(number) = name.strip_prefix("system-").and_then(|n| n.strip_suffix("-link")) else {
+            continue;
+        };
+        if number.parse::<u64>().is_err() {
+            continue;
+        }
+
+        // Guix never touches a generation link after creating it
+        let created_at: Option<DateTime<Local>> =
+            entry.path().symlink_metadata().and_then(|m| m.modified()).ok().map(Into::into);
+        let is_current = current.as_deref().is_some_and(|c| c.file_name() == Some(entry.file_name().as_os_str()));
+
+        snapshots.push(Snapshot {
+            id: number.to_string(),
+            created_at,
+            description: Some(if is_current {
+                format!("generation {} (current)", number)
+            } else {
+                format!("generation {}", number)
+            }),
+            kind: Some(GENERATION.to_string()),
+            cleanup: None,
+            package_count: None,
+            packages: None,
+            path: None,
+        });
+    }
+
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.id.parse::<u64>().unwrap_or(0)));
+    Ok(snapshots)
+}
+
+/// Progress of a generation bisect, kept across the reboots between steps
+#[derive(Debug, Serialize, Deserialize)]
+struct GenerationBisect {
+    good: String,
+    bad: String,
+    /// Generation numbers from good to bad, oldest first
+    generations: Vec<u64>,
+    /// `generations[low]` is known good and `generations[high]` known bad
+    low: usize,
+    high: usize,
+    /// Step switched to and waiting for a verdict after the reboot
+    pending: Option<usize>,
+}
+
+impl GenerationBisect {
+    fn new(good: &str, bad: &str, available: &[u64]) -> Result<Self> {
+        let (Ok(first), Ok(last)) = (good.parse::<u64>(), bad.parse::<u64>()) else {
+            anyhow::bail!("Generations are numbered; got {} and {}", good, bad);
+        };
+        if first >= last {
+            anyhow::bail!("The good generation ({}) must be older than the bad one ({})", first, last);
+        }
+
+        let mut generations: Vec<u64> = available.iter().copied().filter(|n| (first..=last).contains(n)).collect();
+        generations.sort_unstable();
+        if generations.first() != Some(&first) || generations.last() != Some(&last) {
+            anyhow::bail!("Generation {} or {} no longer exists", first, last);
+        }
+        Ok(Self {
+            good: good.to_string(),
+            bad: bad.to_string(),
+            high: generations.len() - 1,
+            generations,
+            low: 0,
+            pending: None,
+        })
+    }
+
+    /// Step to test next, or None once good and bad are neighbours
+    fn next(&self) -> Option<usize> {
+        (self.high - self.low > 1).then(|| (self.low + self.high) / 2)
+    }
+
+    fn record(&mut self, step: usize, issue: bool) {
+        if issue {
+            self.high = step;
+        } else {
+            self.low = step;
+        }
+        self.pending = None;
+    }
+
+    fn steps_left(&self) -> u32 {
+        (self.high - self.low).next_power_of_two().trailing_zeros()
+    }
+}
+
+/// Bisect the generations between `good` and `bad`, one reboot per step. Each run gives
+/// the verdict for the generation booted into and switches to the next one; returns
+/// whether the bisect finished.
+pub fn bisect_generations(good: &Snapshot, bad: &Snapshot, runner: &TestRunner) -> Result<bool> {
+    let available = generations()?;
+    let numbers: Vec<u64> = available.iter().filter_map(|s| s.id.parse().ok()).collect();
+    let mut state = match load_bisect()? {
+        Some(s) if s.good == good.id && s.bad == bad.id => s,
+        _ => GenerationBisect::new(&good.id, &bad.id, &numbers)?,
+    };
+
+    if let Some(step) = state.pending {
+        let number = state.generations[step];
+        if !booted(number) {
+            println!(
+                "{} Generation {} is the default boot entry; reboot into it, then run this again",
+                "ℹ️".cyan(),
+                number
+            );
+            return Ok(false);
+        }
+
+        println!("{} Testing generation {}", "🔍".bold(), number);
+        let issue = bisect::ask_verdict(Some(runner))?;
+        state.record(step, issue);
+        save_bisect(&state)?;
+        println!();
+    }
+
+    let Some(step) = state.next() else {
+        report(&state, &available)?;
+        return Ok(true);
+    };
+
+    let number = state.generations[step];
+    println!(
+        "{} {} generations left, about {} reboots",
+        "ℹ️".cyan(),
+        state.high - state.low - 1,
+        state.steps_left()
+    );
+    if !Confirm::new()
+        .with_prompt(format!("Switch to generation {} and reboot into it?", number))
+        .default(true)
+        .interact()?
+    {
+        return Ok(false);
+    }
+
+    switch_to(number)?;
+    if !exec::runner().applies_changes() {
+        return Ok(false);
+    }
+    state.pending = Some(step);
+    save_bisect(&state)?;
+
+    println!();
+    println!("Reboot, then run {} to test it", format!("eshu-trace bisect -g {} -b {}", good.id, bad.id).cyan());
+    Ok(false)
+}
+
+/// Name the first bad generation and what changed in it, and offer to go back
+fn report(state: &GenerationBisect, available: &[Snapshot]) -> Result<()> {
+    let last_good = state.generations[state.low];
+    let first_bad = state.generations[state.high];
+    clear_bisect()?;
+
+    println!("{} First bad generation: {}", "🎯".bold(), first_bad.to_string().red().bold());
+    let find = |number: u64| available.iter().find(|s| s.id == number.to_string());
+    if let (Some(before), Some(after)) = (find(last_good), find(first_bad)) {
+        let diff = compute_diff(before, after)?;
+        println!("Changes since generation {}:", last_good);
+        for change in diff.all_changes() {
+            println!("  {}", bisect::describe(&change));
+        }
+    }
+    println!();
+
+    if Confirm::new()
+        .with_prompt(format!("Switch back to generation {}?", last_good))
+        .default(true)
+        .interact()?
+    {
+        switch_to(last_good)?;
+        println!("{} Generation {} boots next", "✓".green(), last_good);
+    }
+    Ok(())
+}
+
+fn switch_to(number: u64) -> Result<()> {
+    let status = audit::run(
+        &format!("Switch to Guix generation {}", number),
+        Command::new("sudo").args(["guix", "system", "switch-generation", &number.to_string()]),
+    )?;
+    if !status.success() {
+        anyhow::bail!("guix system switch-generation {} failed", number);
+    }
+    Ok(())
+}
+
+/// Whether the running system is generation `number`
+fn booted(number: u64) -> bool {
+    let link = Path::new("/").join(PROFILES_DIR).join(format!("system-{}-link", number));
+    match (fs::canonicalize("/run/current-system"), fs::canonicalize(link)) {
+        (Ok(running), Ok(generation)) => running == generation,
+        _ => false,
+    }
+}
+
+fn bisect_path() -> PathBuf {
+    paths::state_file("guix-bisect.json")
+}
+
+fn load_bisect() -> Result<Option<GenerationBisect>> {
+    let path = bisect_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read_to_string(&path).context("Failed to read the generation bisect")?;
+    Ok(Some(serde_json::from_str(&data).context("Failed to parse the generation bisect")?))
+}
+
+fn save_bisect(state: &GenerationBisect) -> Result<()> {
+    let path = bisect_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(state)?)?;
+    Ok(())
+}
+
+fn clear_bisect() -> Result<()> {
+    let path = bisect_path();
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Packages of a generation listed by [`generations`]
+pub fn packages_of(generation: &Snapshot) -> HashMap<String, String> {
+    let link = Path::new("/").join(PROFILES_DIR).join(format!("system-{}-link", generation.id));
+    generation_packages("/", &link)
+}
+
+/// Packages of the current system generation of the Guix System under `root`
+pub fn current_packages(root: &str) -> HashMap<String, String> {
+    generation_packages(root, &Path::new(root).join(PROFILES_DIR).join("system"))
+}
+
+/// Packages of the generation `link` points to, resolving store links inside `root`
+fn generation_packages(root: &str, link: &Path) -> HashMap<String, String> {
+    let Some(system) = resolve(root, link) else {
+        return HashMap::new();
+    };
+
+    let mut packages = resolve(root, &system.join("profile"))
+        .and_then(|profile| fs::read_to_string(profile.join("manifest")).ok())
+        .map(|text| manifest_packages(&text))
+        .unwrap_or_default();
+
+    // The kernel is part of the operating system, not the profile
+    if let Some((name, version)) = fs::read_to_string(system.join("parameters"))
+        .ok()
+        .and_then(|text| kernel_from_parameters(&text))
+    {
+        packages.insert(name, version);
+    }
+    packages
+}
+
+/// Follow symlinks to /gnu/store/... as seen from `root` (a mounted system in recovery)
+fn resolve(root: &str, path: &Path) -> Option<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..8 {
+        let Ok(target) = fs::read_link(&path) else {
+            return path.exists().then_some(path);
+        };
+        path = match target.strip_prefix("/") {
+            Ok(relative) => Path::new(root).join(relative),
+            Err(_) => path.parent()?.join(target),
+        };
+    }
+    None
+}
+
+/// name → version for each top-level entry of a profile manifest. Outputs other than
+/// "out" are listed as `name:output`, the way Guix names them on the command line.
+fn manifest_packages(text: &str) -> HashMap<String, String> {
+    let mut packages = HashMap::new();
+    let Some(Sexp::List(manifest)) = parse(text) else {
+        return packages;
+    };
+
+    let entries = manifest.iter().find_map(|item| match item {
+        Sexp::List(items) if matches!(items.first(), Some(Sexp::Symbol(s)) if s == "packages") => items.get(1),
+        _ => None,
+    });
+    let Some(Sexp::List(entries)) = entries else {
+        return packages;
+    };
+
+    for entry in entries {
+        let Sexp::List(fields) = entry else {
+            continue;
+        };
+        if let [Sexp::Str(name), Sexp::Str(version), Sexp::Str(output), ..] = fields.as_slice() {
+            let name = if output == "out" { name.clone() } else { format!("{}:{}", name, output) };
+            packages.insert(name, version.clone());
+        }
+    }
+    packages
+}
+
+/// The kernel's (name, version) from a generation's `parameters` file, which names its
+/// store item, e.g. (kernel "/gnu/store/<hash>-linux-libre-6.9.1/bzImage")
+fn kernel_from_parameters(text: &str) -> Option<(String, String)> {
+    let start = text.find("(kernel \"")? + "(kernel \"".len();
+    let path = &text[start..start + text[start..].find('"')?];
+    let item = path.strip_prefix("/gnu/store/")?.split('/').next()?;
+    // <32-character hash>-<name>-<version>
+    let (_, name_version) = item.split_once('-')?;
+    let split = name_version
+        .match_indices('-')
+        .map(|(i, _)| i)
+        .find(|&i| name_version[i + 1..].starts_with(|c: char| c.is_ascii_digit()))?;
+    Some((name_version[..split].to_string(), name_version[split + 1..].to_string()))
+}
+
+/// Just enough of an S-expression reader for manifests
+enum Sexp {
+    Str(String),
+    Symbol(String),
+    List(Vec<Sexp>),
+}
+
+fn parse(text: &str) -> Option<Sexp> {
+    let mut chars = text.chars().peekable();
+    parse_item(&mut chars)
+}
+
+/// Skip whitespace and `;` comments (version 4 manifests start with a comment header)
+fn skip_blank(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next_if_eq(&';').is_none() {
+            return;
+        }
+        while chars.next_if(|c| *c != '\n').is_some() {}
+    }
+}
+
+fn parse_item(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Sexp> {
+    skip_blank(chars);
+    match chars.next()? {
+        '(' => {
+            let mut items = Vec::new();
+            loop {
+                skip_blank(chars);
+                if chars.next_if_eq(&')').is_some() {
+                    return Some(Sexp::List(items));
+                }
+                items.push(parse_item(chars)?);
+            }
+        }
+        '"' => {
+            let mut s = String::new();
+            loop {
+                match chars.next()? {
+                    '"' => return Some(Sexp::Str(s)),
+                    '\\' => s.push(chars.next()?),
+                    c => s.push(c),
+                }
+            }
+        }
+        c => {
+            let mut s = c.to_string();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '(' && *c != ')') {
+                s.push(c);
+            }
+            Some(Sexp::Symbol(s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A system profile manifest as written by Guix 1.4 and later
+    const MANIFEST_V4: &str = r#";; This file was automatically generated and is for internal use only.
+;; It cannot be converted back to the manifest file it was generated from.
+;; Editing it may lead to inconsistent profiles or other problems.
+(manifest
+  (version 4)
+  (packages
+    (("glibc-utf8-locales"
+      "2.35"
+      "out"
+      "/gnu/store/8m6b3wbb4dgh4brkhqlkimzg4k7l5ll5-glibc-utf8-locales-2.35"
+      (propagated-inputs ())
+      (search-paths
+        (("GUIX_LOCPATH" ("lib/locale") ":" directory #f))))
+     ("gcc"
+      "11.3.0"
+      "lib"
+      "/gnu/store/2lczkxbdbzh4gk7wh91bzrqrk7h5g1dl-gcc-11.3.0-lib"
+      (propagated-inputs ())
+      (search-paths ())
+      (properties ((description . "GNU \"Compiler\" Collection"))))
+     ("openssh"
+      "9.3p1"
+      "out"
+      "/gnu/store/rb3w9cwl6s8rywa4k8ngsv3iiqa5vnz6-openssh-9.3p1"
+      (propagated-inputs ())
+      (search-paths ())))))
+"#;
+
+    /// The same manifest as written by Guix 1.3
+    const MANIFEST_V3: &str = r#"(manifest
+  (version 3)
+  (packages
+    (("glibc-utf8-locales"
+      "2.31"
+      "out"
+      "/gnu/store/rgydar9dfvflqqz2irgh7njj34amaxc6-glibc-utf8-locales-2.31"
+      (propagated-inputs ())
+      (search-paths
+        (("GUIX_LOCPATH" ("lib/locale") ":" directory #f)))
+      (properties))
+     ("openssh"
+      "8.8p1"
+      "out"
+      "/gnu/store/0x0y8wz8q0g0w1pyhvd8a2v1f7hh8kr4-openssh-8.8p1"
+      (propagated-inputs ())
+      (search-paths ())
+      (properties)))))
+"#;
+
+    const PARAMETERS: &str = r#"(boot-parameters
+  (version 0)
+  (label "GNU with Linux-Libre 6.9.1")
+  (root-device "/dev/sda2")
+  (kernel "/gnu/store/z0c3vp5y1ymlmf0zgzw9cgbhhzyxx9kw-linux-libre-6.9.1/bzImage")
+  (kernel-arguments ("quiet"))
+  (initrd "/gnu/store/pl3pijx3zdd8cbkkgsz8w8cp4r6dsfyb-raw-initrd/initrd.cpio.gz")
+  (bootloader-name grub)
+  (store (device #f) (mount-point "/")))
+"#;
+
+    #[test]
+    fn manifest_v4_with_comment_header() {
+        let packages = manifest_packages(MANIFEST_V4);
+        assert_eq!(packages.len(), 3);
+        assert_eq!(packages["glibc-utf8-locales"], "2.35");
+        assert_eq!(packages["openssh"], "9.3p1");
+        assert_eq!(packages["gcc:lib"], "11.3.0");
+    }
+
+    #[test]
+    fn manifest_v3() {
+        let packages = manifest_packages(MANIFEST_V3);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages["openssh"], "8.8p1");
+    }
+
+    #[test]
+    fn parse_escapes_and_comments() {
+        let Some(Sexp::List(items)) = parse("; header\n(a \"b \\\"c\\\"\" ; trailing\n (d))") else {
+            panic!("not a list");
+        };
+        assert_eq!(items.len(), 3);
+        assert!(matches!(&items[0], Sexp::Symbol(s) if s == "a"));
+        assert!(matches!(&items[1], Sexp::Str(s) if s == "b \"c\""));
+        assert!(matches!(&items[2], Sexp::List(d) if d.len() == 1));
+    }
+
+    #[test]
+    fn unbalanced_manifest_is_empty() {
+        assert!(manifest_packages("(manifest (version 4) (packages ((\"a\" \"1\" \"out\"").is_empty());
+    }
+
+    #[test]
+    fn kernel_from_boot_parameters() {
+        assert_eq!(
+            kernel_from_parameters(PARAMETERS),
+            Some(("linux-libre".to_string(), "6.9.1".to_string()))
+        );
+        assert_eq!(kernel_from_parameters("(boot-parameters (version 0))"), None);
+    }
+
+    #[test]
+    fn generation_bisect_narrows_to_neighbours() {
+        // 12 was deleted
+        let mut state = GenerationBisect::new("10", "15", &[15, 14, 13, 11, 10, 9]).unwrap();
+        assert_eq!(state.generations, vec![10, 11, 13, 14, 15]);
+        assert_eq!(state.steps_left(), 2);
+
+        let step = state.next().unwrap();
+        assert_eq!(state.generations[step], 13);
+        state.record(step, false);
+        let step = state.next().unwrap();
+        assert_eq!(state.generations[step], 14);
+        state.record(step, true);
+        assert_eq!(state.next(), None);
+        assert_eq!((state.generations[state.low], state.generations[state.high]), (13, 14));
+    }
+
+    #[test]
+    fn generation_bisect_needs_good_before_bad() {
+        assert!(GenerationBisect::new("15", "10", &[10, 15]).is_err());
+        assert!(GenerationBisect::new("10", "16", &[10, 15]).is_err());
+    }
+}
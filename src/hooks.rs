@@ -0,0 +1,140 @@
+// User hooks: executables in <config>/hooks/ and commands in config.json, run at lifecycle points
+//
+//   hooks/pre-test, hooks/pre-test.d/*     before each bisect step is tested
+//   hooks/post-step, hooks/post-step.d/*   after each verdict
+//   hooks/pre-fix, hooks/pre-fix.d/*       before a fix changes packages
+//   hooks/post-fix, hooks/post-fix.d/*     after it
+//
+// Session details arrive as ESHU_TRACE_* environment variables. A failing
+// pre-* hook cancels the step or fix; failing post-* hooks are only reported.
+// Hook output goes to stderr.
+
+use anyhow::Result;
+use colored::*;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config;
+use crate::exec::CommandExt;
+use crate::paths;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Hook {
+    PreTest,
+    PostStep,
+    PreFix,
+    PostFix,
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreTest => "pre-test",
+            Hook::PostStep => "post-step",
+            Hook::PreFix => "pre-fix",
+            Hook::PostFix => "post-fix",
+        }
+    }
+
+    fn can_veto(&self) -> bool {
+        matches!(self, Hook::PreTest | Hook::PreFix)
+    }
+}
+
+/// Run every hook registered for `hook` with `env` added to the environment
+pub fn run(hook: Hook, env: &[(&str, String)]) -> Result<()> {
+    let mut commands: Vec<(String, Command)> = scripts(hook)
+        .into_iter()
+        .map(|path| (path.display().to_string(), Command::new(path)))
+        .collect();
+
+    if let Some(configured) = config::load().ok().and_then(|c| c.hooks.get(hook.name()).cloned()) {
+        for line in configured {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(&line);
+            commands.push((line, cmd));
+        }
+    }
+
+    for (label, mut cmd) in commands {
+        // stdout may be carrying --json or --machine output
+        cmd.stdout(std::io::stderr());
+        cmd.env("ESHU_TRACE_HOOK", hook.name());
+        for (key, value) in env {
+            cmd.env(key, value);
+        }
+
+        let ok = match cmd.run_status() {
+            Ok(status) => status.success(),
+            Err(e) => {
+                eprintln!("{} Hook {} could not run: {}", "⚠".yellow(), label, e);
+                false
+            }
+        };
+        if ok {
+            continue;
+        }
+
+        if hook.can_veto() {
+            anyhow::bail!("{} hook {} failed; stopping (remove or fix the hook to continue)", hook.name(), label);
+        }
+        eprintln!("{} {} hook {} failed", "⚠".yellow(), hook.name(), label);
+    }
+
+    Ok(())
+}
+
+/// Each hook with the number of scripts and configured commands registered for it
+pub fn registered() -> Vec<(Hook, usize)> {
+    let configured = config::load().map(|c| c.hooks).unwrap_or_default();
+    [Hook::PreTest, Hook::PostStep, Hook::PreFix, Hook::PostFix]
+        .into_iter()
+        .map(|hook| {
+            let scripts = scripts(hook).len();
+            let commands = configured.get(hook.name()).map_or(0, |lines| lines.len());
+            (hook, scripts + commands)
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect()
+}
+
+pub fn hooks_dir() -> PathBuf {
+    paths::config_file("hooks")
+}
+
+/// hooks/<name> followed by hooks/<name>.d/* in name order
+fn scripts(hook: Hook) -> Vec<PathBuf> {
+    let dir = hooks_dir();
+    let mut found = vec![dir.join(hook.name())];
+
+    let mut drop_ins: Vec<PathBuf> = fs::read_dir(dir.join(format!("{}.d", hook.name())))
+        .map(|entries| entries.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    drop_ins.sort();
+    found.extend(drop_ins);
+
+    found.retain(|p| is_trusted_executable(p));
+    found
+}
+
+/// Executable files owned by us or root; eshu-trace often runs as root, so a
+/// hook someone else could have written must not run
+fn is_trusted_executable(path: &Path) -> bool {
+    let Ok(meta) = fs::metadata(path) else {
+        return false;
+    };
+    let uid = effective_uid();
+    meta.is_file()
+        && meta.permissions().mode() & 0o111 != 0
+        && meta.mode() & 0o022 == 0
+        && (meta.uid() == 0 || Some(meta.uid()) == uid)
+}
+
+/// Effective UID, from /proc (no libc dependency)
+fn effective_uid() -> Option<u32> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("Uid:"))?;
+    line.split_whitespace().nth(2)?.parse().ok()
+}
@@ -0,0 +1,295 @@
+// Shared HTTP client; compiled out without the `http` feature (e.g. the static musl build)
+//
+// Every request is retried with exponential backoff on connection errors,
+// timeouts, 429 and 502-504, honouring Retry-After. Downloads resume from a
+// `.part` file, so a dropped tethered connection does not restart them.
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Tries per request, including the first
+const ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Longest wait between tries, also the cap on a server's Retry-After
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A fully read response
+pub struct Response {
+    pub status: u16,
+    body: Vec<u8>,
+    retry_after: Option<Duration>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.status)
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+/// Whether this build can reach the network at all
+pub fn available() -> bool {
+    cfg!(feature = "http")
+}
+
+pub fn get(url: &str, timeout: Duration) -> Result<Response> {
+    with_retry(url, || imp::send(&imp::Method::Get, url, timeout))
+}
+
+/// A single try, for callers where a failure is itself the answer (probes)
+pub fn get_once(url: &str, timeout: Duration) -> Result<Response> {
+    imp::send(&imp::Method::Get, url, timeout)
+}
+
+pub fn post_json<T: Serialize>(url: &str, body: &T, timeout: Duration) -> Result<Response> {
+    let method = imp::Method::Json(serde_json::to_vec(body)?, None);
+    with_retry(url, || imp::send(&method, url, timeout))
+}
+
+/// A single `post_json` try, optionally with an `Authorization: Bearer` header, for requests
+/// that must not be repeated (submissions, slow paid calls)
+pub fn post_json_once<T: Serialize>(url: &str, body: &T, token: Option<&str>, timeout: Duration) -> Result<Response> {
+    let method = imp::Method::Json(serde_json::to_vec(body)?, token.map(str::to_string));
+    imp::send(&method, url, timeout)
+}
+
+/// POST a prepared body; `headers` must include its Content-Type
+pub fn post_body(url: &str, headers: &[(&str, &str)], body: Vec<u8>, timeout: Duration) -> Result<Response> {
+    let method = imp::Method::Body(headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(), body);
+    with_retry(url, || imp::send(&method, url, timeout))
+}
+
+pub fn post_form(url: &str, fields: &[(&str, &str)], timeout: Duration) -> Result<Response> {
+    let method = imp::Method::Form(fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect());
+    with_retry(url, || imp::send(&method, url, timeout))
+}
+
+/// Percent-encode a path segment or query value
+pub fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Download `url` to `dest`, resuming a partial `.part` file left by an earlier try.
+/// `timeout` applies to each try. Returns false if the server does not have the file.
+pub fn download(url: &str, dest: &Path, timeout: Duration) -> Result<bool> {
+    let part = part_path(dest);
+
+    let response = with_retry(url, || {
+        let offset = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+        imp::fetch_to(url, &part, offset, timeout)
+    })?;
+
+    match response.status {
+        // 416: the part file already holds everything
+        200..=299 | 416 => {
+            fs::rename(&part, dest)?;
+            Ok(true)
+        }
+        404 | 410 => {
+            let _ = fs::remove_file(&part);
+            Ok(false)
+        }
+        status => anyhow::bail!("{} answered HTTP {}", url, status),
+    }
+}
+
+fn part_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    dest.with_file_name(name)
+}
+
+fn retryable(status: u16) -> bool {
+    matches!(status, 429 | 502 | 503 | 504)
+}
+
+/// Run `attempt` until it succeeds, fails for good, or the tries run out
+fn with_retry(url: &str, mut attempt: impl FnMut() -> Result<Response>) -> Result<Response> {
+    let mut backoff = INITIAL_BACKOFF;
+
+    for n in 1..=ATTEMPTS {
+        let wait = match attempt() {
+            Ok(response) if !retryable(response.status) || n == ATTEMPTS => return Ok(response),
+            Ok(response) => response.retry_after.unwrap_or(backoff).min(MAX_BACKOFF),
+            Err(e) if !imp::is_transient(&e) || n == ATTEMPTS => return Err(e),
+            Err(_) => backoff,
+        };
+
+        eprintln!("  ↻ {} did not answer, retry {}/{} in {}s", host(url), n, ATTEMPTS - 1, wait.as_secs());
+        std::thread::sleep(wait);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+
+    unreachable!("the last attempt always returns")
+}
+
+fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map(|(_, r)| r).unwrap_or(url);
+    rest.split('/').next().unwrap_or(rest)
+}
+
+#[cfg(feature = "http")]
+mod imp {
+    use super::Response;
+    use anyhow::Result;
+    use std::fs::OpenOptions;
+    use std::path::Path;
+    use std::time::Duration;
+
+    pub enum Method {
+        Get,
+        /// Body and optional bearer token
+        Json(Vec<u8>, Option<String>),
+        Form(Vec<(String, String)>),
+        /// Headers and body
+        Body(Vec<(String, String)>, Vec<u8>),
+    }
+
+    fn client(timeout: Duration) -> Result<reqwest::blocking::Client> {
+        Ok(reqwest::blocking::Client::builder().timeout(timeout).build()?)
+    }
+
+    /// Seconds form of Retry-After; HTTP dates fall back to the normal backoff
+    fn retry_after(response: &reqwest::blocking::Response) -> Option<Duration> {
+        let value = response.headers().get(reqwest::header::RETRY_AFTER)?;
+        value.to_str().ok()?.trim().parse().ok().map(Duration::from_secs)
+    }
+
+    pub fn send(method: &Method, url: &str, timeout: Duration) -> Result<Response> {
+        let client = client(timeout)?;
+        let request = match method {
+            Method::Get => client.get(url),
+            Method::Json(body, token) => {
+                let request = client
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body.clone());
+                match token {
+                    Some(token) => request.bearer_auth(token),
+                    None => request,
+                }
+            }
+            Method::Form(fields) => client.post(url).form(fields),
+            Method::Body(headers, body) => headers
+                .iter()
+                .fold(client.post(url), |request, (name, value)| request.header(name.as_str(), value.as_str()))
+                .body(body.clone()),
+        };
+
+        let response = request.send()?;
+        let status = response.status().as_u16();
+        let retry_after = retry_after(&response);
+        let body = response.bytes()?.to_vec();
+        Ok(Response { status, body, retry_after })
+    }
+
+    /// GET `url` into `part`, asking for the bytes after `offset` when some are already there
+    pub fn fetch_to(url: &str, part: &Path, offset: u64, timeout: Duration) -> Result<Response> {
+        let mut request = client(timeout)?.get(url);
+        if offset > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+        }
+
+        let mut response = request.send()?;
+        let status = response.status().as_u16();
+        let retry_after = retry_after(&response);
+        if (200..300).contains(&status) {
+            // 206 continues the part file; a plain 200 means the server ignored the range
+            let mut file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(status == 206)
+                .truncate(status != 206)
+                .open(part)?;
+            response.copy_to(&mut file)?;
+        }
+        Ok(Response { status, body: Vec::new(), retry_after })
+    }
+
+    /// Connection problems worth another try, as opposed to bad URLs or local errors
+    pub fn is_transient(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| {
+            cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(|e| e.is_connect() || e.is_timeout() || e.is_body() || e.is_request())
+                || cause.downcast_ref::<std::io::Error>().is_some_and(|e| {
+                    matches!(
+                        e.kind(),
+                        std::io::ErrorKind::ConnectionReset
+                            | std::io::ErrorKind::ConnectionAborted
+                            | std::io::ErrorKind::UnexpectedEof
+                            | std::io::ErrorKind::TimedOut
+                    )
+                })
+        })
+    }
+}
+
+#[cfg(not(feature = "http"))]
+mod imp {
+    use super::Response;
+    use anyhow::Result;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[allow(dead_code)]
+    pub enum Method {
+        Get,
+        Json(Vec<u8>, Option<String>),
+        Form(Vec<(String, String)>),
+        Body(Vec<(String, String)>, Vec<u8>),
+    }
+
+    fn unavailable(url: &str) -> Result<Response> {
+        anyhow::bail!("Cannot reach {}: this eshu-trace was built without network support (feature `http`)", url)
+    }
+
+    pub fn send(_method: &Method, url: &str, _timeout: Duration) -> Result<Response> {
+        unavailable(url)
+    }
+
+    pub fn fetch_to(url: &str, _part: &Path, _offset: u64, _timeout: Duration) -> Result<Response> {
+        unavailable(url)
+    }
+
+    pub fn is_transient(_error: &anyhow::Error) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_query_values() {
+        assert_eq!(encode("libstdc++-14.1.1-5.fc40"), "libstdc%2B%2B-14.1.1-5.fc40");
+        assert_eq!(encode("In Progress"), "In%20Progress");
+        assert_eq!(encode("1:2.3~rc1"), "1%3A2.3~rc1");
+    }
+}
@@ -0,0 +1,72 @@
+// Rebuild the initramfs and bootloader config after a fix that touches early boot
+//
+// Downgrading a kernel, a kernel-module driver or firmware leaves the old
+// initramfs and boot menu behind; the fix only takes effect after both are
+// regenerated with the distro's own tools.
+
+use std::path::Path;
+
+use crate::ownership;
+
+/// Package names that end up in the initramfs or boot menu even when their file lists are unavailable
+const EARLY_BOOT_PACKAGES: &[&str] = &[
+    "linux", "kernel", "nvidia", "firmware", "microcode", "mkinitcpio", "dracut", "initramfs-tools",
+    "systemd", "cryptsetup", "lvm2", "mdadm", "btrfs-progs", "zfs", "plymouth", "grub",
+];
+
+/// Where files that go into the initramfs or affect the boot entries live
+const EARLY_BOOT_PATHS: &[&str] = &[
+    "/usr/lib/modules/",
+    "/lib/modules/",
+    "/usr/lib/firmware/",
+    "/lib/firmware/",
+    "/usr/lib/initcpio/",
+    "/usr/lib/dracut/",
+    "/usr/share/initramfs-tools/",
+    "/etc/mkinitcpio",
+    "/etc/dracut.conf",
+    "/etc/modprobe.d/",
+    "/usr/lib/modprobe.d/",
+    "/boot/",
+];
+
+/// Whether any of `packages` installs kernel modules, firmware or initramfs configuration in `root`
+pub fn affects_early_boot(root: &str, packages: &[String]) -> bool {
+    packages.iter().any(|package| {
+        let files = ownership::package_files_in(root, package);
+        if files.is_empty() {
+            return EARLY_BOOT_PACKAGES.iter().any(|p| package == p || package.starts_with(&format!("{}-", p)));
+        }
+        files.iter().any(|f| EARLY_BOOT_PATHS.iter().any(|p| f.starts_with(p)))
+    })
+}
+
+/// Commands that regenerate the initramfs and the bootloader menu, for the tools installed in `root`.
+/// Each is (description, shell command) and runs inside the target system.
+pub fn rebuild_commands(root: &str) -> Vec<(&'static str, &'static str)> {
+    let has = |tool: &str| {
+        ["usr/bin", "usr/sbin", "bin", "sbin"]
+            .iter()
+            .any(|dir| Path::new(root).join(dir).join(tool).exists())
+    };
+    let mut commands = Vec::new();
+
+    // Arch uses mkinitcpio, Fedora/openSUSE dracut, Debian/Ubuntu initramfs-tools
+    if has("mkinitcpio") {
+        commands.push(("initramfs", "mkinitcpio -P"));
+    } else if has("dracut") {
+        commands.push(("initramfs", "dracut --regenerate-all --force"));
+    } else if has("update-initramfs") {
+        commands.push(("initramfs", "update-initramfs -u -k all"));
+    }
+
+    if has("update-grub") {
+        commands.push(("GRUB menu", "update-grub"));
+    } else if has("grub2-mkconfig") {
+        commands.push(("GRUB menu", "grub2-mkconfig -o /boot/grub2/grub.cfg"));
+    } else if has("grub-mkconfig") && Path::new(root).join("boot/grub/grub.cfg").exists() {
+        commands.push(("GRUB menu", "grub-mkconfig -o /boot/grub/grub.cfg"));
+    }
+
+    commands
+}
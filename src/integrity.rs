@@ -0,0 +1,211 @@
+// Content hashes and signatures on exported manifests and fix scripts
+//
+// An export carries the sha256 of its content, so the receiving side (support, another
+// admin) can tell it arrived intact, and with `--sign` an ed25519 signature by this
+// machine's signing key, so they can tell who made it. JSON manifests get an "integrity"
+// object hashed over the rest of the document with keys in sorted order; scripts and
+// playbooks get a trailing comment line hashed over everything above it. The signing key
+// is created on first use in the config directory; only its public half leaves it.
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::paths;
+
+/// Prefix of the integrity line appended to text exports
+const TEXT_MARKER: &str = "# eshu-trace-integrity: ";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Integrity {
+    /// Hex sha256 of the content
+    pub sha256: String,
+    /// Signer's ed25519 public key (base64), when signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Signature over the sha256 digest (base64)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Outcome of a successful check
+pub struct Verified {
+    pub sha256: String,
+    /// Public key of the signer, for signed exports
+    pub signer: Option<String>,
+}
+
+/// Hash `content` and, with `sign`, sign the digest with this machine's key
+pub fn seal(content: &[u8], sign: bool) -> Result<Integrity> {
+    let digest = Sha256::digest(content);
+    let mut integrity = Integrity {
+        sha256: format!("{:x}", digest),
+        public_key: None,
+        signature: None,
+    };
+    if sign {
+        let key = signing_key()?;
+        integrity.public_key = Some(STANDARD.encode(key.verifying_key().to_bytes()));
+        integrity.signature = Some(STANDARD.encode(key.sign(&digest).to_bytes()));
+    }
+    Ok(integrity)
+}
+
+/// Check `content` against its recorded hash and signature
+pub fn check(content: &[u8], integrity: &Integrity) -> Result<Verified> {
+    let digest = Sha256::digest(content);
+    let sha256 = format!("{:x}", digest);
+    if sha256 != integrity.sha256 {
+        anyhow::bail!("content hash does not match: the file was modified after export");
+    }
+
+    let signer = match (&integrity.public_key, &integrity.signature) {
+        (Some(public_key), Some(signature)) => {
+            let key_bytes: [u8; 32] = STANDARD
+                .decode(public_key)
+                .ok()
+                .and_then(|b| b.try_into().ok())
+                .context("malformed public key")?;
+            let key = VerifyingKey::from_bytes(&key_bytes).context("malformed public key")?;
+            let signature = STANDARD.decode(signature).context("malformed signature")?;
+            let signature = Signature::from_slice(&signature).context("malformed signature")?;
+            key.verify(&digest, &signature)
+                .map_err(|_| anyhow::anyhow!("signature does not match the content"))?;
+            Some(public_key.clone())
+        }
+        (None, None) => None,
+        _ => anyhow::bail!("incomplete signature"),
+    };
+
+    Ok(Verified { sha256, signer })
+}
+
+/// Seal a JSON document: the "integrity" object covers every other field
+pub fn seal_json(mut document: serde_json::Value, sign: bool) -> Result<(String, Integrity)> {
+    let content = json_content(&mut document)?;
+    let integrity = seal(&content, sign)?;
+    document["integrity"] = serde_json::to_value(&integrity)?;
+    Ok((serde_json::to_string_pretty(&document)?, integrity))
+}
+
+/// The integrity object of a JSON document and the content it covers, if it has one
+pub fn split_json(text: &str) -> Result<Option<(Vec<u8>, Integrity)>> {
+    let mut document: serde_json::Value = serde_json::from_str(text).context("not a JSON document")?;
+    let Some(integrity) = document.as_object_mut().and_then(|o| o.remove("integrity")) else {
+        return Ok(None);
+    };
+    let integrity: Integrity = serde_json::from_value(integrity).context("malformed integrity object")?;
+    Ok(Some((json_content(&mut document)?, integrity)))
+}
+
+/// Canonical bytes of a document without its integrity object (serde_json sorts keys)
+fn json_content(document: &mut serde_json::Value) -> Result<Vec<u8>> {
+    let object = document.as_object_mut().context("not a JSON object")?;
+    object.remove("integrity");
+    Ok(serde_json::to_vec(object)?)
+}
+
+/// Append the integrity line to a script or playbook
+pub fn seal_text(text: &str, sign: bool) -> Result<(String, Integrity)> {
+    let integrity = seal(text.as_bytes(), sign)?;
+    let sealed = format!("{}{}{}\n", text, TEXT_MARKER, serde_json::to_string(&integrity)?);
+    Ok((sealed, integrity))
+}
+
+/// The integrity line of a text export and the content above it, if it has one
+pub fn split_text(text: &str) -> Result<Option<(&str, Integrity)>> {
+    let body = text.strip_suffix('\n').unwrap_or(text);
+    let (content, last) = match body.rfind('\n') {
+        Some(i) => (&text[..=i], &body[i + 1..]),
+        None => ("", body),
+    };
+    let Some(json) = last.strip_prefix(TEXT_MARKER) else {
+        return Ok(None);
+    };
+    let integrity = serde_json::from_str(json).context("malformed integrity line")?;
+    Ok(Some((content, integrity)))
+}
+
+fn signing_key() -> Result<SigningKey> {
+    let path = paths::config_file("signing.key");
+    if let Ok(text) = fs::read_to_string(&path) {
+        let bytes: [u8; 32] = STANDARD
+            .decode(text.trim())
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .with_context(|| format!("{} is not a signing key", path.display()))?;
+        return Ok(SigningKey::from_bytes(&bytes));
+    }
+
+    let mut secret = [0u8; 32];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut secret))
+        .context("Failed to read /dev/urandom")?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Readable only by the owner from the moment it exists
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut f| f.write_all(STANDARD.encode(secret).as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trip() {
+        let (sealed, _) = seal_text("#!/bin/sh\necho fixed\n", false).unwrap();
+        let (content, integrity) = split_text(&sealed).unwrap().unwrap();
+        assert_eq!(content, "#!/bin/sh\necho fixed\n");
+        assert!(check(content.as_bytes(), &integrity).unwrap().signer.is_none());
+
+        let tampered = sealed.replace("fixed", "broken");
+        let (content, integrity) = split_text(&tampered).unwrap().unwrap();
+        assert!(check(content.as_bytes(), &integrity).is_err());
+        assert!(split_text("#!/bin/sh\n").unwrap().is_none());
+    }
+
+    #[test]
+    fn json_round_trip_ignores_key_order() {
+        let (sealed, integrity) = seal_json(serde_json::json!({"b": 1, "a": [2, 3]}), false).unwrap();
+        let reordered = format!(r#"{{"integrity": {}, "b": 1, "a": [2, 3]}}"#, serde_json::to_string(&integrity).unwrap());
+        let (content, integrity) = split_json(&reordered).unwrap().unwrap();
+        check(&content, &integrity).unwrap();
+
+        let (content, integrity) = split_json(&sealed.replace("\"b\": 1", "\"b\": 2")).unwrap().unwrap();
+        assert!(check(&content, &integrity).is_err());
+    }
+
+    #[test]
+    fn checks_signatures() {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let content = b"manifest";
+        let digest = Sha256::digest(content);
+        let mut integrity = Integrity {
+            sha256: format!("{:x}", digest),
+            public_key: Some(STANDARD.encode(key.verifying_key().to_bytes())),
+            signature: Some(STANDARD.encode(key.sign(&digest).to_bytes())),
+        };
+        assert_eq!(check(content, &integrity).unwrap().signer, integrity.public_key);
+
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        integrity.public_key = Some(STANDARD.encode(other.verifying_key().to_bytes()));
+        assert!(check(content, &integrity).is_err());
+        integrity.signature = None;
+        assert!(check(content, &integrity).is_err());
+    }
+}
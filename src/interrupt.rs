@@ -0,0 +1,117 @@
+// Ctrl-C handling: undo temporary mounts and tell the user how to resume
+
+use colored::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use crate::audit;
+use crate::lock;
+
+/// Mount points to detach on interrupt, in mount order
+static MOUNTS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+/// Scratch directories to delete once their mounts are gone
+static DIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+/// Command that picks the interrupted work back up
+static RESUME_HINT: Mutex<Option<String>> = Mutex::new(None);
+
+/// Install the SIGINT/SIGTERM handler
+pub fn install() {
+    let _ = ctrlc::set_handler(|| {
+        eprintln!();
+        eprintln!("{} Interrupted, cleaning up...", "⚠".yellow());
+
+        let clean = unmount_all();
+        remove_dirs(clean);
+        lock::release_held();
+
+        if let Some(hint) = RESUME_HINT.lock().ok().and_then(|h| h.clone()) {
+            eprintln!("{} Progress was saved. Resume with: {}", "↻".cyan(), hint.white());
+        }
+
+        std::process::exit(130);
+    });
+}
+
+pub fn track_mount(target: &Path) {
+    if let Ok(mut mounts) = MOUNTS.lock() {
+        mounts.push(target.to_path_buf());
+    }
+}
+
+pub fn untrack_mount(target: &Path) {
+    if let Ok(mut mounts) = MOUNTS.lock() {
+        mounts.retain(|m| m != target);
+    }
+}
+
+pub fn track_dir(dir: &Path) {
+    if let Ok(mut dirs) = DIRS.lock() {
+        dirs.push(dir.to_path_buf());
+    }
+}
+
+pub fn untrack_dir(dir: &Path) {
+    if let Ok(mut dirs) = DIRS.lock() {
+        dirs.retain(|d| d != dir);
+    }
+}
+
+pub fn set_resume_hint(hint: Option<String>) {
+    if let Ok(mut current) = RESUME_HINT.lock() {
+        *current = hint;
+    }
+}
+
+/// Returns false if anything is still mounted afterwards
+fn unmount_all() -> bool {
+    let mounts = match MOUNTS.lock() {
+        Ok(m) => m.clone(),
+        Err(_) => return false,
+    };
+
+    for target in mounts.iter().rev() {
+        let _ = audit::run("interrupt: unmount", Command::new("umount").arg("-R").arg("-l").arg(target));
+    }
+
+    let leftover: Vec<&PathBuf> = mounts.iter().filter(|m| mounted_under(m)).collect();
+
+    for target in &leftover {
+        eprintln!("{} Still mounted: {} (umount -R -l it manually)", "✗".red(), target.display());
+    }
+
+    leftover.is_empty()
+}
+
+/// Whether anything is mounted at or below `dir`, per /proc/self/mounts
+pub fn mounted_under(dir: &Path) -> bool {
+    mount_targets().iter().any(|target| target.starts_with(dir))
+}
+
+/// Whether something is mounted exactly at `path`
+pub fn is_mountpoint(path: &Path) -> bool {
+    mount_targets().iter().any(|target| target == path)
+}
+
+fn mount_targets() -> Vec<PathBuf> {
+    let table = fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+    table
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn remove_dirs(unmounted: bool) {
+    let dirs = DIRS.lock().map(|d| d.clone()).unwrap_or_default();
+
+    for dir in dirs {
+        // Deleting through a live bind mount would delete host files
+        if unmounted {
+            let _ = fs::remove_dir_all(&dir);
+        } else {
+            eprintln!("{} Left behind: {}", "⚠".yellow(), dir.display());
+        }
+    }
+}
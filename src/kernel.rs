@@ -0,0 +1,712 @@
+// Kernel bisect mode: binary search over kernel releases instead of the package diff
+
+use anyhow::{Context, Result};
+use colored::*;
+use dialoguer::{Confirm, Select};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::audit;
+use crate::driver::QemuDriver;
+use crate::exec::CommandExt;
+use crate::fixer::detect_distro_at;
+use crate::http;
+use crate::interrupt;
+use crate::notify;
+use crate::package_diff::version_compare;
+use crate::paths;
+use crate::test_runner::{which, TestRunner};
+use crate::verify;
+
+const ARCH_ARCHIVE_URL: &str = "https://archive.archlinux.org/packages/l/linux/";
+/// Per try; interrupted downloads resume where they stopped
+const KERNEL_DOWNLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(600);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum KernelSource {
+    Installed,
+    Cached,
+    Archive,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KernelCandidate {
+    /// Release as reported by `uname -r`
+    pub release: String,
+    pub source: KernelSource,
+    /// Package file or URL for candidates that are not installed yet
+    pub location: Option<String>,
+}
+
+/// Fallback boot entry added next to a new kernel
+pub struct Fallback {
+    pub release: String,
+    /// Menu entry to pick at boot
+    pub entry: String,
+}
+
+/// Progress of a kernel bisect, persisted across the reboots it needs
+#[derive(Debug, Serialize, Deserialize)]
+struct KernelBisectState {
+    candidates: Vec<KernelCandidate>,
+    low: usize,  // known good
+    high: usize, // known bad
+    testing: Option<usize>,
+}
+
+impl KernelSource {
+    fn label(&self) -> &'static str {
+        match self {
+            KernelSource::Installed => "installed",
+            KernelSource::Cached => "cached",
+            KernelSource::Archive => "archive",
+        }
+    }
+}
+
+/// Run (or resume) a kernel bisect. Returns true once the breaking release is identified.
+pub fn run_kernel_bisect(runner: &TestRunner, vm: Option<&QemuDriver>) -> Result<bool> {
+    let distro = detect_distro_at("/")?;
+    interrupt::set_resume_hint(Some("eshu-trace bisect --kernel".to_string()));
+
+    let mut state = match load_state()? {
+        Some(state) => {
+            println!("{} Resuming kernel bisect", "↻".cyan());
+            state
+        }
+        None => start_state(&distro)?,
+    };
+
+    if let Some(idx) = state.testing.take() {
+        let candidate = state.candidates[idx].release.clone();
+        let running = running_kernel();
+
+        if running != candidate {
+            println!(
+                "{} Expected to be running {} but found {}",
+                "⚠".yellow(),
+                candidate,
+                running
+            );
+            println!("   If the candidate failed to boot, answer yes below.");
+        }
+
+        let issue_occurs = ask_issue(&candidate, runner)?;
+        record_verdict(&mut state, idx, issue_occurs);
+    }
+
+    while state.high - state.low > 1 {
+        let mid = (state.low + state.high) / 2;
+        let candidate = state.candidates[mid].clone();
+
+        println!(
+            "{} Testing kernel {} ({} candidates left)",
+            "Step".cyan().bold(),
+            candidate.release.yellow(),
+            state.high - state.low - 1
+        );
+
+        install_candidate(&distro, &candidate)?;
+        let (kernel, initrd) = kernel_files(&candidate.release)?;
+
+        if let Some(driver) = vm {
+            let passed = driver.test_kernel(&kernel, &initrd, runner)?;
+            record_verdict(&mut state, mid, !passed);
+            save_state(&state)?;
+            notify::send(
+                &format!("Kernel {} {}", candidate.release, if passed { "passed" } else { "failed" }),
+                &format!("{} candidate kernels left", (state.high - state.low).saturating_sub(1)),
+            );
+            continue;
+        }
+
+        set_oneshot_boot(&candidate.release, &kernel, &initrd)?;
+        state.testing = Some(mid);
+        save_state(&state)?;
+
+        println!();
+        println!("{}", "Reboot into the candidate kernel to test it.".yellow().bold());
+        println!("The next boot (only) will use {}.", candidate.release);
+        println!("After testing, run {} again.", "eshu-trace bisect --kernel".white());
+        println!();
+
+        if Confirm::new().with_prompt("Reboot now?").default(false).interact()? {
+            audit::run("kernel bisect: reboot", Command::new("systemctl").arg("reboot"))?;
+        }
+
+        return Ok(false);
+    }
+
+    clear_state()?;
+    notify::finished(
+        "Breaking kernel found",
+        &format!(
+            "Last good: {}\nFirst bad: {}",
+            state.candidates[state.low].release, state.candidates[state.high].release
+        ),
+    );
+
+    println!();
+    println!("{}", "🎯 FOUND THE BREAKING KERNEL!".green().bold());
+    println!();
+    println!("{} {}", "Last good:".green(), state.candidates[state.low].release);
+    println!("{} {}", "First bad:".red(), state.candidates[state.high].release);
+    println!();
+    println!("{}", "Recommended actions:".yellow());
+    println!("  1. Keep booting {} (or an LTS kernel) for now", state.candidates[state.low].release);
+    println!("  2. Report the regression upstream with both versions");
+    println!();
+
+    Ok(true)
+}
+
+fn start_state(distro: &str) -> Result<KernelBisectState> {
+    let candidates = list_kernel_versions(distro)?;
+
+    if candidates.len() < 2 {
+        anyhow::bail!("Need at least two kernel versions to bisect (found {})", candidates.len());
+    }
+
+    let items: Vec<String> = candidates
+        .iter()
+        .map(|c| format!("{} ({})", c.release, c.source.label()))
+        .collect();
+
+    let running = running_kernel();
+    let bad_default = candidates
+        .iter()
+        .position(|c| c.release == running)
+        .unwrap_or(candidates.len() - 1);
+
+    let good = Select::new()
+        .with_prompt("Select a kernel that WORKED")
+        .items(&items)
+        .default(0)
+        .interact()?;
+
+    let bad = Select::new()
+        .with_prompt("Select a kernel that is BROKEN")
+        .items(&items)
+        .default(bad_default)
+        .interact()?;
+
+    if good >= bad {
+        anyhow::bail!("The working kernel must be older than the broken one");
+    }
+
+    println!(
+        "{} {} kernel releases between good and bad",
+        "📦".bold(),
+        bad - good - 1
+    );
+    println!();
+
+    Ok(KernelBisectState {
+        candidates: candidates[good..=bad].to_vec(),
+        low: 0,
+        high: bad - good,
+        testing: None,
+    })
+}
+
+fn record_verdict(state: &mut KernelBisectState, idx: usize, issue_occurs: bool) {
+    if issue_occurs {
+        println!("{} {} is broken", "✗".red(), state.candidates[idx].release);
+        state.high = idx;
+    } else {
+        println!("{} {} works", "✓".green(), state.candidates[idx].release);
+        state.low = idx;
+    }
+    println!();
+}
+
+fn ask_issue(release: &str, runner: &TestRunner) -> Result<bool> {
+    let mut prompt = Confirm::new().with_prompt(format!("Does the issue occur with kernel {}?", release));
+
+    if runner.has_test() {
+        let passed = runner.run_test()?;
+        prompt = prompt.default(!passed);
+    }
+
+    Ok(prompt.interact()?)
+}
+
+/// Kernel releases available locally, in the package cache, or in the distro archive
+pub fn list_kernel_versions(distro: &str) -> Result<Vec<KernelCandidate>> {
+    let mut candidates: Vec<KernelCandidate> = Vec::new();
+
+    for dir in ["/usr/lib/modules", "/lib/modules"] {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let release = entry.file_name().to_string_lossy().to_string();
+                if entry.path().join("modules.dep").exists() || entry.path().join("vmlinuz").exists() {
+                    push_unique(&mut candidates, release, KernelSource::Installed, None);
+                }
+            }
+        }
+    }
+
+    for (release, path) in cached_kernel_packages(distro) {
+        push_unique(&mut candidates, release, KernelSource::Cached, Some(path));
+    }
+
+    if matches!(distro, "arch" | "endeavouros") {
+        if let Ok(archived) = arch_archive_kernels() {
+            for (release, url) in archived {
+                push_unique(&mut candidates, release, KernelSource::Archive, Some(url));
+            }
+        }
+    }
+
+    candidates.sort_by(|a, b| {
+        if a.release == b.release {
+            std::cmp::Ordering::Equal
+        } else if version_compare(&a.release, &b.release) {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Less
+        }
+    });
+
+    Ok(candidates)
+}
+
+fn push_unique(list: &mut Vec<KernelCandidate>, release: String, source: KernelSource, location: Option<String>) {
+    if !list.iter().any(|c| c.release == release) {
+        list.push(KernelCandidate { release, source, location });
+    }
+}
+
+fn cached_kernel_packages(distro: &str) -> Vec<(String, String)> {
+    let mut found = Vec::new();
+
+    let (dir, matcher): (&str, fn(&str) -> Option<String>) = match distro {
+        "arch" | "endeavouros" => ("/var/cache/pacman/pkg", arch_release_from_file),
+        "ubuntu" | "debian" | "linuxmint" | "pop" => ("/var/cache/apt/archives", debian_release_from_file),
+        "fedora" | "rhel" | "centos" => ("/var/cache/dnf", fedora_release_from_file),
+        _ => return found,
+    };
+
+    for entry in walkdir::WalkDir::new(dir).max_depth(4).into_iter().flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(release) = matcher(&name) {
+            found.push((release, entry.path().to_string_lossy().to_string()));
+        }
+    }
+
+    found
+}
+
+/// linux-6.9.7.arch1-1-x86_64.pkg.tar.zst -> 6.9.7-arch1-1
+fn arch_release_from_file(name: &str) -> Option<String> {
+    let stem = name.split("-x86_64.pkg.tar").next().filter(|v| *v != name)?;
+    let mut parts = stem.rsplitn(3, '-');
+    let (pkgrel, pkgver, package) = (parts.next()?, parts.next()?, parts.next()?);
+    if package.ends_with("-headers") || package.ends_with("-docs") || name.ends_with(".sig") {
+        return None;
+    }
+    arch_release(package, &format!("{}-{}", pkgver, pkgrel))
+}
+
+/// `uname -r` of an Arch kernel package: linux 6.8.9.arch1-1 is 6.8.9-arch1-1, linux-zen
+/// 6.8.9.zen1-1 is 6.8.9-zen1-1-zen and linux-lts 6.6.30-1 is 6.6.30-1-lts
+fn arch_release(package: &str, pkgver: &str) -> Option<String> {
+    let flavour = match package.strip_prefix("linux")? {
+        "" => None,
+        rest => Some(rest.strip_prefix('-')?),
+    };
+    if !pkgver.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    // The first dot before a letter separates the upstream version from the local one
+    let split = pkgver
+        .char_indices()
+        .find(|&(i, c)| c == '.' && pkgver[i + 1..].starts_with(|c: char| c.is_ascii_alphabetic()));
+    let release = match split {
+        Some((i, _)) => format!("{}-{}", &pkgver[..i], &pkgver[i + 1..]),
+        None => pkgver.to_string(),
+    };
+    Some(match flavour {
+        Some(flavour) => format!("{}-{}", release, flavour),
+        None => release,
+    })
+}
+
+/// linux-image-6.1.0-18-amd64_6.1.76-1_amd64.deb -> 6.1.0-18-amd64
+fn debian_release_from_file(name: &str) -> Option<String> {
+    let rest = name.strip_prefix("linux-image-")?;
+    if !name.ends_with(".deb") || rest.starts_with("unsigned") {
+        return None;
+    }
+    rest.split('_').next().map(|s| s.to_string())
+}
+
+/// kernel-core-6.8.9-300.fc40.x86_64.rpm -> 6.8.9-300.fc40.x86_64
+fn fedora_release_from_file(name: &str) -> Option<String> {
+    name.strip_prefix("kernel-core-")?
+        .strip_suffix(".rpm")
+        .map(|s| s.to_string())
+}
+
+fn arch_archive_kernels() -> Result<Vec<(String, String)>> {
+    let body = http::get(ARCH_ARCHIVE_URL, std::time::Duration::from_secs(10))?.text();
+
+    let re = regex::Regex::new(r#"href="(linux-[0-9][^"]*-x86_64\.pkg\.tar\.(?:zst|xz))""#)?;
+
+    Ok(re
+        .captures_iter(&body)
+        .filter_map(|c| {
+            let file = c[1].to_string();
+            arch_release_from_file(&file).map(|r| (r, format!("{}{}", ARCH_ARCHIVE_URL, file)))
+        })
+        .collect())
+}
+
+/// Install a candidate kernel next to the running one without replacing it
+fn install_candidate(distro: &str, candidate: &KernelCandidate) -> Result<()> {
+    if candidate.source == KernelSource::Installed && kernel_files(&candidate.release).is_ok() {
+        return Ok(());
+    }
+
+    let location = candidate
+        .location
+        .as_deref()
+        .context(format!("No package available for kernel {}", candidate.release))?;
+
+    let commands = match distro {
+        // Arch ships one `linux` package; unpack only the versioned module tree
+        // so the candidate coexists with the installed kernel
+        "arch" | "endeavouros" => {
+            let mut cmds = Vec::new();
+            let file = if location.starts_with("http") {
+                let file = format!(
+                    "/var/cache/pacman/pkg/{}",
+                    location.rsplit('/').next().unwrap_or_default()
+                );
+                println!("{} {}", "↓".cyan(), location.dimmed());
+                if !http::download(location, Path::new(&file), KERNEL_DOWNLOAD_TIMEOUT)? {
+                    anyhow::bail!("{} is no longer in the archive", location);
+                }
+                // Unpacked without pacman, so nothing else would check the signature
+                let sig = http::get(&format!("{}.sig", location), KERNEL_DOWNLOAD_TIMEOUT)?;
+                if sig.is_success() {
+                    fs::write(verify::signature_path(Path::new(&file)), sig.bytes())?;
+                }
+                verify::pacman_package(Path::new(&file))?;
+                file
+            } else {
+                location.to_string()
+            };
+            cmds.push(format!("bsdtar -xf {} -C / usr/lib/modules/{}", file, candidate.release));
+            cmds.push(format!(
+                "cp /usr/lib/modules/{0}/vmlinuz /boot/vmlinuz-{0}",
+                candidate.release
+            ));
+            cmds.push(format!(
+                "mkinitcpio -k {0} -g /boot/initramfs-{0}.img",
+                candidate.release
+            ));
+            cmds
+        }
+        "ubuntu" | "debian" | "linuxmint" | "pop" => vec![format!("apt-get install -y {}", location)],
+        "fedora" | "rhel" | "centos" => vec![format!("dnf install -y {}", location)],
+        other => anyhow::bail!("Kernel bisect does not support distro: {}", other),
+    };
+
+    for cmd in commands {
+        println!("{} {}", "→".dimmed(), cmd.dimmed());
+        let status = audit::run(
+            &format!("kernel bisect: install {}", candidate.release),
+            Command::new("sh").arg("-c").arg(&cmd),
+        )?;
+        if !status.success() {
+            anyhow::bail!("Failed to install kernel {}: {}", candidate.release, cmd);
+        }
+    }
+
+    Ok(())
+}
+
+/// Kernel image and initramfs for an installed release
+fn kernel_files(release: &str) -> Result<(PathBuf, PathBuf)> {
+    let boot = Path::new("/boot");
+    let kernel = [
+        boot.join(format!("vmlinuz-{}", release)),
+        PathBuf::from(format!("/usr/lib/modules/{}/vmlinuz", release)),
+    ]
+    .into_iter()
+    .find(|p| p.exists())
+    .context(format!("Kernel image for {} not found", release))?;
+
+    let initrd = [
+        boot.join(format!("initramfs-{}.img", release)),
+        boot.join(format!("initrd.img-{}", release)),
+    ]
+    .into_iter()
+    .find(|p| p.exists())
+    .context(format!("Initramfs for {} not found", release))?;
+
+    Ok((kernel, initrd))
+}
+
+/// Make only the next boot use the candidate kernel
+fn set_oneshot_boot(release: &str, kernel: &Path, initrd: &Path) -> Result<()> {
+    let is_systemd_boot = Command::new("bootctl")
+        .arg("is-installed")
+        .run_output()
+        .map(|o| o.status.success())
+        .unwrap_or(false);
+
+    if is_systemd_boot {
+        return set_systemd_boot_oneshot(release, kernel, initrd);
+    }
+
+    for (mkconfig, reboot, cfg) in [
+        ("grub-mkconfig", "grub-reboot", "/boot/grub/grub.cfg"),
+        ("grub2-mkconfig", "grub2-reboot", "/boot/grub2/grub.cfg"),
+    ] {
+        if which(reboot) {
+            audit::run("kernel bisect: regenerate grub config", Command::new(mkconfig).args(["-o", cfg]))?;
+            let entry = find_grub_entry(cfg, release)?;
+            println!("{} {} {}", "→".dimmed(), reboot.dimmed(), entry.dimmed());
+            let status = audit::run("kernel bisect: one-shot boot entry", Command::new(reboot).arg(&entry))?;
+            if !status.success() {
+                anyhow::bail!("{} failed", reboot);
+            }
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!("No supported bootloader found (systemd-boot or GRUB) to set a one-shot entry")
+}
+
+fn set_systemd_boot_oneshot(release: &str, kernel: &Path, initrd: &Path) -> Result<()> {
+    let entry_id = format!("eshu-trace-{}", release);
+    write_systemd_boot_entry(&entry_id, &format!("Eshu-Trace candidate {}", release), release, kernel, initrd)?;
+
+    let status = audit::run(
+        "kernel bisect: one-shot boot entry",
+        Command::new("bootctl").arg("set-oneshot").arg(format!("{}.conf", entry_id)),
+    )?;
+    if !status.success() {
+        anyhow::bail!("bootctl set-oneshot failed");
+    }
+
+    Ok(())
+}
+
+/// Copy the kernel and initrd to the ESP and add a loader entry `<entry_id>.conf` booting
+/// them with the running kernel's command line
+fn write_systemd_boot_entry(entry_id: &str, title: &str, release: &str, kernel: &Path, initrd: &Path) -> Result<PathBuf> {
+    let output = Command::new("bootctl").arg("--print-boot-path").run_output()?;
+    let esp = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    let dir = esp.join("eshu-trace");
+    fs::create_dir_all(&dir)?;
+    fs::copy(kernel, dir.join(format!("vmlinuz-{}", release)))?;
+    fs::copy(initrd, dir.join(format!("initrd-{}", release)))?;
+
+    let cmdline: String = fs::read_to_string("/proc/cmdline")?
+        .split_whitespace()
+        .filter(|arg| !arg.starts_with("BOOT_IMAGE=") && !arg.starts_with("initrd="))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let entry = format!(
+        "title   {0}\nlinux   /eshu-trace/vmlinuz-{1}\ninitrd  /eshu-trace/initrd-{1}\noptions {2}\n",
+        title, release, cmdline
+    );
+    let path = esp.join("loader/entries").join(format!("{}.conf", entry_id));
+    fs::write(&path, entry)?;
+    Ok(path)
+}
+
+/// Install the kernel the culprit replaced next to the new one and give it a permanent
+/// boot entry, so the new kernel stays installed and the old one is a menu choice away.
+/// `package` is the culprit and `old_version` its previous version. Fails rather than keep
+/// some other kernel when that exact one can't be found.
+pub fn keep_fallback(distro: &str, package: &str, old_version: &str) -> Result<Fallback> {
+    // Fedora 6.8.9-300.fc40 is 6.8.9-300.fc40.x86_64 without the arch. Debian's versioned
+    // packages name the release (linux-image-6.1.0-18-amd64), its meta packages don't.
+    let wanted = match distro {
+        "arch" | "endeavouros" => arch_release(package, old_version),
+        "ubuntu" | "debian" | "linuxmint" | "pop" => package
+            .strip_prefix("linux-image-")
+            .filter(|release| release.starts_with(|c: char| c.is_ascii_digit()))
+            .map(|release| release.to_string()),
+        _ => Some(old_version.to_string()),
+    }
+    .with_context(|| format!("{} {} doesn't say which kernel release it installed", package, old_version))?;
+
+    let candidate = list_kernel_versions(distro)?
+        .into_iter()
+        .find(|c| c.release == wanted || c.release.strip_prefix(&wanted).is_some_and(|arch| arch.starts_with('.')))
+        .with_context(|| format!("No kernel package found for {} {} (release {})", package, old_version, wanted))?;
+
+    println!("{} Keeping kernel {} ({})", "🐧".cyan(), candidate.release.yellow(), candidate.source.label());
+    install_candidate(distro, &candidate)?;
+    let (kernel, initrd) = kernel_files(&candidate.release)?;
+
+    // Debian's autoremove would otherwise take the older kernel on the next cleanup
+    if matches!(distro, "ubuntu" | "debian" | "linuxmint" | "pop") {
+        audit::run(
+            &format!("fix: keep kernel {}", candidate.release),
+            Command::new("apt-mark").args(["manual", &format!("linux-image-{}", candidate.release)]),
+        )?;
+    }
+
+    let is_systemd_boot = Command::new("bootctl")
+        .arg("is-installed")
+        .run_output()
+        .map(|o| o.status.success())
+        .unwrap_or(false);
+    if is_systemd_boot {
+        let entry_id = format!("eshu-trace-fallback-{}", candidate.release);
+        let title = format!("Fallback kernel {} (eshu-trace)", candidate.release);
+        let path = write_systemd_boot_entry(&entry_id, &title, &candidate.release, &kernel, &initrd)?;
+        println!("{} Boot entry {}", "✓".green(), path.display());
+        return Ok(Fallback { release: candidate.release, entry: title });
+    }
+
+    // GRUB lists every kernel in /boot once the config is regenerated
+    for (mkconfig, cfg) in [("grub-mkconfig", "/boot/grub/grub.cfg"), ("grub2-mkconfig", "/boot/grub2/grub.cfg")] {
+        if which(mkconfig) {
+            audit::run("fix: regenerate grub config", Command::new(mkconfig).args(["-o", cfg]))?;
+            let entry = find_grub_entry(cfg, &candidate.release)?;
+            return Ok(Fallback {
+                release: candidate.release,
+                entry: format!("{} (GRUB, under Advanced options)", entry),
+            });
+        }
+    }
+
+    anyhow::bail!("No supported bootloader found (systemd-boot or GRUB) to add a boot entry")
+}
+
+/// Boot the fallback kernel `release` kept by [`keep_fallback`] on the next boot only
+pub fn boot_fallback_once(release: &str) -> Result<()> {
+    let is_systemd_boot = Command::new("bootctl")
+        .arg("is-installed")
+        .run_output()
+        .map(|o| o.status.success())
+        .unwrap_or(false);
+    if is_systemd_boot {
+        let status = audit::run(
+            "fix: one-shot boot entry",
+            Command::new("bootctl").arg("set-oneshot").arg(format!("eshu-trace-fallback-{}.conf", release)),
+        )?;
+        if !status.success() {
+            anyhow::bail!("bootctl set-oneshot failed");
+        }
+        return Ok(());
+    }
+
+    for (reboot, cfg) in [("grub-reboot", "/boot/grub/grub.cfg"), ("grub2-reboot", "/boot/grub2/grub.cfg")] {
+        if which(reboot) {
+            let entry = find_grub_entry(cfg, release)?;
+            let status = audit::run("fix: one-shot boot entry", Command::new(reboot).arg(&entry))?;
+            if !status.success() {
+                anyhow::bail!("{} failed", reboot);
+            }
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!("No supported bootloader found (systemd-boot or GRUB) to set a one-shot entry")
+}
+
+/// grub-reboot target (`submenu>entry` id) for the menu entry booting `release`
+fn find_grub_entry(cfg: &str, release: &str) -> Result<String> {
+    let content = fs::read_to_string(cfg).context(format!("Failed to read {}", cfg))?;
+    let id_re = regex::Regex::new(r"\$menuentry_id_option '([^']+)'")?;
+    let mut submenu: Option<String> = None;
+
+    for line in content.lines() {
+        let line = line.trim_start();
+        if line.starts_with("submenu ") {
+            submenu = id_re.captures(line).map(|c| c[1].to_string());
+        } else if line.starts_with("menuentry ") && line.contains(release) && !line.contains("recovery") {
+            if let Some(id) = id_re.captures(line).map(|c| c[1].to_string()) {
+                return Ok(match &submenu {
+                    Some(sub) => format!("{}>{}", sub, id),
+                    None => id,
+                });
+            }
+        }
+    }
+
+    anyhow::bail!("No GRUB entry found for kernel {}", release)
+}
+
+fn running_kernel() -> String {
+    Command::new("uname")
+        .arg("-r")
+        .run_output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Range of an in-progress kernel bisect: (last good, first bad, candidates left)
+pub fn bisect_progress() -> Option<(String, String, usize)> {
+    let state = load_state().ok()??;
+    Some((
+        state.candidates[state.low].release.clone(),
+        state.candidates[state.high].release.clone(),
+        (state.high - state.low).saturating_sub(1),
+    ))
+}
+
+fn get_state_path() -> PathBuf {
+    paths::state_file("kernel-bisect.json")
+}
+
+fn load_state() -> Result<Option<KernelBisectState>> {
+    let path = get_state_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let data = fs::read_to_string(&path).context("Failed to read kernel bisect state")?;
+    Ok(Some(serde_json::from_str(&data).context("Failed to parse kernel bisect state")?))
+}
+
+fn save_state(state: &KernelBisectState) -> Result<()> {
+    let path = get_state_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(state)?)?;
+    Ok(())
+}
+
+fn clear_state() -> Result<()> {
+    let path = get_state_path();
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arch_release_per_flavour() {
+        assert_eq!(arch_release("linux", "6.8.9.arch1-1").as_deref(), Some("6.8.9-arch1-1"));
+        assert_eq!(arch_release("linux-zen", "6.8.9.zen1-1").as_deref(), Some("6.8.9-zen1-1-zen"));
+        assert_eq!(arch_release("linux-hardened", "6.8.9.hardened1-1").as_deref(), Some("6.8.9-hardened1-1-hardened"));
+        assert_eq!(arch_release("linux-lts", "6.6.30-1").as_deref(), Some("6.6.30-1-lts"));
+        assert_eq!(arch_release("mesa", "24.0.5-1"), None);
+    }
+
+    #[test]
+    fn arch_release_from_package_file() {
+        assert_eq!(arch_release_from_file("linux-6.8.9.arch1-1-x86_64.pkg.tar.zst").as_deref(), Some("6.8.9-arch1-1"));
+        assert_eq!(arch_release_from_file("linux-zen-6.8.9.zen1-1-x86_64.pkg.tar.zst").as_deref(), Some("6.8.9-zen1-1-zen"));
+        assert_eq!(arch_release_from_file("linux-headers-6.8.9.arch1-1-x86_64.pkg.tar.zst"), None);
+        assert_eq!(arch_release_from_file("linux-6.8.9.arch1-1-x86_64.pkg.tar.zst.sig"), None);
+    }
+}
@@ -0,0 +1,271 @@
+// Package signing keyrings: trust changes in diffs and key problems before fixes
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::exec::CommandExt;
+
+/// Packages that change which signing keys the package manager trusts
+pub fn is_keyring_package(name: &str) -> bool {
+    name.ends_with("-keyring")
+        || name.ends_with("-gpg-keys")
+        || name == "gpg-pubkey"
+}
+
+#[derive(Debug, Clone)]
+pub enum KeyProblem {
+    /// A trusted key has expired or was revoked
+    Expired(String),
+    /// A package to be installed is signed by a key that is not in the keyring
+    Missing(String),
+}
+
+impl fmt::Display for KeyProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyProblem::Expired(key) => write!(f, "expired or revoked key: {}", key),
+            KeyProblem::Missing(key) => write!(f, "missing signing key: {}", key),
+        }
+    }
+}
+
+/// Check whether installing `targets` (package, version) under `root` will fail on signatures
+pub fn preflight(root: &str, distro: &str, targets: &[(String, String)]) -> Vec<KeyProblem> {
+    match distro {
+        "arch" | "manjaro" => pacman_problems(root, targets),
+        "ubuntu" | "debian" => apt_problems(root, targets),
+        _ => Vec::new(),
+    }
+}
+
+/// Command that refreshes the distro keyring, if we know one
+pub fn refresh_command(distro: &str) -> Option<&'static str> {
+    match distro {
+        "arch" => Some("pacman -Sy --needed --noconfirm archlinux-keyring && pacman-key --populate archlinux"),
+        "manjaro" => Some(
+            "pacman -Sy --needed --noconfirm archlinux-keyring manjaro-keyring && pacman-key --populate archlinux manjaro",
+        ),
+        "debian" => Some("apt-get install --reinstall -y debian-archive-keyring"),
+        "ubuntu" => Some("apt-get install --reinstall -y ubuntu-keyring"),
+        "fedora" => Some("dnf upgrade --refresh -y fedora-gpg-keys"),
+        _ => None,
+    }
+}
+
+fn pacman_problems(root: &str, targets: &[(String, String)]) -> Vec<KeyProblem> {
+    let homedir = Path::new(root).join("etc/pacman.d/gnupg");
+    if !homedir.exists() {
+        return Vec::new();
+    }
+
+    // The Arch keyring legitimately keeps revoked keys of former packagers, so only the
+    // signatures of the packages we are about to install matter
+    let mut problems = Vec::new();
+
+    // Verify the detached signatures of the cached packages the fix will install
+    let cache = Path::new(root).join("var/cache/pacman/pkg");
+    if let Ok(entries) = fs::read_dir(&cache) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let wanted = targets
+                .iter()
+                .any(|(p, v)| name.starts_with(&format!("{}-{}", p, v)) && name.ends_with(".sig"));
+            if !wanted {
+                continue;
+            }
+
+            let package = entry.path().with_extension("");
+            let output = Command::new("gpg")
+                .arg("--homedir")
+                .arg(&homedir)
+                .args(["--batch", "--status-fd", "1", "--verify"])
+                .arg(entry.path())
+                .arg(&package)
+                .run_output();
+
+            if let Ok(output) = output {
+                for line in String::from_utf8_lossy(&output.stdout).lines() {
+                    let mut fields = line.split_whitespace().skip(1);
+                    match (fields.next(), fields.next()) {
+                        (Some("NO_PUBKEY"), Some(key)) => problems.push(KeyProblem::Missing(key.to_string())),
+                        (Some("EXPKEYSIG"), Some(key)) | (Some("REVKEYSIG"), Some(key)) => {
+                            problems.push(KeyProblem::Expired(key.to_string()))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+/// Expired keys in the keyrings apt checks the targets' repositories against: the
+/// `signed-by` keyrings of the sources that offer the target versions (of every source,
+/// if apt can't tell which). Sources without `signed-by` trust trusted.gpg.d, which keeps
+/// retired release keys around, so an expired key there says nothing about this install.
+fn apt_problems(root: &str, targets: &[(String, String)]) -> Vec<KeyProblem> {
+    let uris = target_uris(root, targets);
+
+    let mut keyrings: Vec<String> = Vec::new();
+    for source in apt_sources(root) {
+        if !uris.is_empty() && !source.uris.iter().any(|u| uris.contains(&u.trim_end_matches('/').to_string())) {
+            continue;
+        }
+        for keyring in source.signed_by {
+            if !keyrings.contains(&keyring) {
+                keyrings.push(keyring);
+            }
+        }
+    }
+
+    let mut problems = Vec::new();
+    for keyring in keyrings {
+        let mut show = Command::new("gpg");
+        show.args(["--batch", "--with-colons", "--show-keys"])
+            .arg(Path::new(root).join(keyring.trim_start_matches('/')));
+        problems.extend(expired_keys(show));
+    }
+    problems
+}
+
+/// Repository URIs that offer the target versions, per `apt-cache madison`
+fn target_uris(root: &str, targets: &[(String, String)]) -> Vec<String> {
+    let mut cmd = Command::new("apt-cache");
+    if root != "/" {
+        cmd.arg("-o").arg(format!("Dir={}", root));
+    }
+    cmd.arg("madison").args(targets.iter().map(|(p, _)| p));
+    let Ok(output) = cmd.run_output() else {
+        return Vec::new();
+    };
+
+    //  mesa | 24.0.5-1 | http://deb.debian.org/debian bookworm/main amd64 Packages
+    let mut uris = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let columns: Vec<&str> = line.split('|').map(str::trim).collect();
+        let [package, version, source, ..] = columns.as_slice() else {
+            continue;
+        };
+        if !targets.iter().any(|(p, v)| p == package && v == version) {
+            continue;
+        }
+        if let Some(uri) = source.split_whitespace().next() {
+            let uri = uri.trim_end_matches('/').to_string();
+            if !uris.contains(&uri) {
+                uris.push(uri);
+            }
+        }
+    }
+    uris
+}
+
+/// One `deb` entry of sources.list or a deb822 .sources stanza
+struct AptSource {
+    uris: Vec<String>,
+    /// Keyring files; fingerprints and inline keys are left out
+    signed_by: Vec<String>,
+}
+
+fn apt_sources(root: &str) -> Vec<AptSource> {
+    let etc = Path::new(root).join("etc/apt");
+    let mut files = vec![etc.join("sources.list")];
+    if let Ok(entries) = fs::read_dir(etc.join("sources.list.d")) {
+        files.extend(entries.flatten().map(|e| e.path()));
+    }
+
+    let keyring_paths = |value: &str| -> Vec<String> {
+        value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|v| v.starts_with('/'))
+            .map(str::to_string)
+            .collect()
+    };
+
+    let mut sources = Vec::new();
+    for file in files {
+        let Ok(text) = fs::read_to_string(&file) else {
+            continue;
+        };
+        match file.extension().and_then(|e| e.to_str()) {
+            Some("sources") => {
+                for stanza in text.split("\n\n") {
+                    let field = |key: &str| {
+                        stanza.lines().find_map(|l| {
+                            let (k, v) = l.split_once(':')?;
+                            k.trim().eq_ignore_ascii_case(key).then(|| v.trim().to_string())
+                        })
+                    };
+                    if field("Enabled").is_some_and(|e| e.eq_ignore_ascii_case("no")) {
+                        continue;
+                    }
+                    if let Some(uris) = field("URIs") {
+                        sources.push(AptSource {
+                            uris: uris.split_whitespace().map(str::to_string).collect(),
+                            signed_by: field("Signed-By").map(|v| keyring_paths(&v)).unwrap_or_default(),
+                        });
+                    }
+                }
+            }
+            Some("list") => {
+                for line in text.lines().map(str::trim) {
+                    let Some(rest) = line.strip_prefix("deb ").or_else(|| line.strip_prefix("deb\t")) else {
+                        continue;
+                    };
+                    // deb [arch=amd64 signed-by=/usr/share/keyrings/x.gpg] URI suite components
+                    let rest = rest.trim_start();
+                    let (options, rest) = match rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+                        Some((options, rest)) => (options, rest),
+                        None => ("", rest),
+                    };
+                    let signed_by = options
+                        .split_whitespace()
+                        .filter_map(|o| o.strip_prefix("signed-by="))
+                        .flat_map(keyring_paths)
+                        .collect();
+                    if let Some(uri) = rest.split_whitespace().next() {
+                        sources.push(AptSource {
+                            uris: vec![uri.to_string()],
+                            signed_by,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    sources
+}
+
+/// Expired or revoked primary keys in the colon-format listing printed by `cmd`
+fn expired_keys(mut cmd: Command) -> Vec<KeyProblem> {
+    let output = match cmd.run_output() {
+        Ok(o) if o.status.success() => o,
+        _ => return Vec::new(),
+    };
+
+    // pub:<validity>:...  then uid:...:<user id>
+    let mut problems = Vec::new();
+    let mut pending: Option<String> = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.first() {
+            Some(&"pub") => {
+                pending = matches!(fields.get(1), Some(&"e") | Some(&"r"))
+                    .then(|| fields.get(4).unwrap_or(&"").to_string());
+            }
+            Some(&"uid") => {
+                if let Some(key) = pending.take() {
+                    let uid = fields.get(9).unwrap_or(&"");
+                    problems.push(KeyProblem::Expired(format!("{} ({})", key, uid)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    problems
+}
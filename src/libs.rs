@@ -0,0 +1,108 @@
+// Map missing shared libraries (sonames) to the package changes behind them
+
+use anyhow::Result;
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+use crate::ownership::owning_packages_in;
+
+/// Library directories searched inside a root filesystem
+const LIB_DIRS: &[&str] = &[
+    "usr/lib",
+    "usr/lib64",
+    "usr/lib32",
+    "usr/lib/x86_64-linux-gnu",
+    "usr/lib/aarch64-linux-gnu",
+    "lib",
+    "lib64",
+    "lib/x86_64-linux-gnu",
+];
+
+/// Where a soname lives in one root and which package owns it
+#[derive(Debug, Clone)]
+pub struct SonameLocation {
+    pub path: String,
+    pub packages: Vec<String>,
+}
+
+/// Extract the soname from a loader error such as
+/// `foo: error while loading shared libraries: libfoo.so.5: cannot open shared object file`,
+/// or accept a bare soname
+pub fn parse_soname(input: &str) -> Option<String> {
+    let re = Regex::new(r"(lib[A-Za-z0-9_+\-.]*\.so(?:\.[0-9]+)*)").ok()?;
+    re.captures(input).map(|c| c[1].to_string())
+}
+
+/// The program named in a loader error, if any
+pub fn failing_program(input: &str) -> Option<String> {
+    input
+        .split_once(": error while loading shared libraries")
+        .map(|(prog, _)| prog.trim().to_string())
+}
+
+/// `libfoo.so.5` -> `libfoo.so`
+pub fn soname_family(soname: &str) -> &str {
+    match soname.find(".so") {
+        Some(idx) => &soname[..idx + 3],
+        None => soname,
+    }
+}
+
+/// Locate `soname` under `root` and resolve its owning packages
+pub fn locate(root: &str, soname: &str) -> Result<Option<SonameLocation>> {
+    match find_in(root, soname) {
+        Some(path) => {
+            let packages = owning_packages_in(root, &path)?;
+            Ok(Some(SonameLocation { path, packages }))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Absolute path (relative to `root`) of `soname` in the library directories
+pub fn find_in(root: &str, soname: &str) -> Option<String> {
+    LIB_DIRS.iter().find_map(|dir| {
+        let candidate = Path::new(root).join(dir).join(soname);
+        candidate
+            .symlink_metadata()
+            .is_ok()
+            .then(|| format!("/{}/{}", dir, soname))
+    })
+}
+
+/// True for versioned soname file names like `libicuuc.so.74`
+pub fn is_soname(file_name: &str) -> bool {
+    let family = soname_family(file_name);
+    family.starts_with("lib")
+        && family.ends_with(".so")
+        && file_name
+            .strip_prefix(family)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .is_some_and(|major| !major.is_empty() && major.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Other versions of the same library present under `root` (e.g. libfoo.so.6 when .5 is missing)
+pub fn sibling_sonames(root: &str, soname: &str) -> Vec<String> {
+    let family = soname_family(soname);
+    let mut found = Vec::new();
+
+    for dir in LIB_DIRS {
+        if let Ok(entries) = fs::read_dir(Path::new(root).join(dir)) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                // Only versioned sonames (libfoo.so.N), not the dev symlink or full versions
+                if name != soname
+                    && soname_family(&name) == family
+                    && is_soname(&name)
+                    && !found.contains(&name)
+                {
+                    found.push(name);
+                }
+            }
+        }
+    }
+
+    found.sort();
+    found
+}
@@ -0,0 +1,113 @@
+// Lockfile that keeps two bisects from changing the system at the same time
+//
+// Bisects by different users (or one under sudo and one without) have separate state
+// directories but share the system they downgrade, so the lock lives in /run/lock where the
+// user can write there. That is only root on distributions that keep it 0755 (Arch, Fedora);
+// other users lock in their state directory, and still respect a lock root holds.
+
+use anyhow::Result;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::TraceError;
+use crate::paths;
+
+const LOCK_DIR: &str = "/run/lock";
+
+/// Lock currently held by this process, so the Ctrl-C handler can release it
+static HELD: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Held for the lifetime of a bisect; removed on drop
+pub struct SessionLock {
+    path: PathBuf,
+}
+
+impl SessionLock {
+    /// Take the lock, or replace an existing one when `force` is set
+    pub fn acquire(force: bool) -> Result<Self> {
+        let path = lock_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if force {
+            let _ = fs::remove_file(&path);
+        } else if path != shared_path() {
+            // Can't take the shared lock, but a bisect running as root still excludes this one
+            if let Some(pid) = lock_pid(&shared_path()).filter(|p| is_alive(*p)) {
+                return Err(TraceError::SessionLocked { pid: Some(pid), stale: false }.into());
+            }
+        }
+
+        // create_new fails if another process got there first
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                writeln!(file, "{}", std::process::id())?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                let pid = lock_pid(&path);
+                let stale = pid.map(|p| !is_alive(p)).unwrap_or(true);
+                return Err(TraceError::SessionLocked { pid, stale }.into());
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        if let Ok(mut held) = HELD.lock() {
+            *held = Some(path.clone());
+        }
+        Ok(Self { path })
+    }
+}
+
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        if let Ok(mut held) = HELD.lock() {
+            *held = None;
+        }
+    }
+}
+
+/// Remove the lock held by this process (for exits that skip destructors)
+pub fn release_held() {
+    if let Some(path) = HELD.lock().ok().and_then(|mut h| h.take()) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// PID of the live process holding the bisect lock, if any
+#[cfg_attr(not(feature = "dashboard"), allow(dead_code))]
+pub fn holder() -> Option<u32> {
+    [lock_path(), shared_path()]
+        .iter()
+        .find_map(|path| lock_pid(path).filter(|p| is_alive(*p)))
+}
+
+/// The system-wide lock when this user can create files in /run/lock; otherwise (and for
+/// fixture runs, which change nothing) `bisect.lock` in the state directory
+fn lock_path() -> PathBuf {
+    #[cfg(feature = "fixtures")]
+    if crate::fixture::root().is_some() {
+        return paths::state_file("bisect.lock");
+    }
+    // Permission bits don't tell the whole story (ACLs, read-only mounts), so try it
+    if tempfile::tempfile_in(LOCK_DIR).is_ok() {
+        shared_path()
+    } else {
+        paths::state_file("bisect.lock")
+    }
+}
+
+fn shared_path() -> PathBuf {
+    Path::new(LOCK_DIR).join("eshu-trace-bisect.lock")
+}
+
+fn lock_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok().and_then(|s| s.trim().parse().ok())
+}
+
+fn is_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
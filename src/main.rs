@@ -18,18 +18,85 @@ Binary search through package update history to find the exact package that brok
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use colored::*;
+use std::io::{self, IsTerminal};
 use std::process;
 
+mod analysis;
+mod audit;
+mod aur;
 mod bisect;
+mod bisect_engine;
+mod bodhi;
+mod bootparams;
+mod cache;
+mod community;
 mod snapshot;
 mod package_diff;
 mod test_runner;
 mod premium;
+mod reboot;
+mod recover;
 mod recovery;
 mod fixer;
+mod presets;
+mod priors;
+mod driver;
+mod kernel;
+mod timeline;
+mod tracker;
+mod upgrades;
+mod verify;
+mod ownership;
+mod libs;
+mod lock;
+mod monitor;
+mod abi;
+mod origin;
+mod keyring;
+mod orphans;
+mod restart;
+mod paths;
+mod pins;
+mod config;
+mod corelibs;
+mod crash;
+mod doctor;
+mod hooks;
+mod initramfs;
+mod integrity;
+mod http;
+mod interrupt;
+mod media;
+mod error;
+mod exec;
+mod firmware;
+#[cfg(feature = "fixtures")]
+mod fixture;
+#[cfg(feature = "guix")]
+mod guix;
+mod prefetch;
+#[cfg(feature = "rpmdb")]
+mod rpmdb;
+mod probe;
+mod sandbox;
+#[cfg(feature = "dashboard")]
+mod dashboard;
+mod rpc;
+mod notify;
+#[cfg(feature = "containers")]
+mod crossdistro;
+mod remediation;
+mod oci;
 
 use crate::bisect::BisectSession;
+use crate::driver::DriverKind;
+use crate::exec::CommandExt;
 use crate::snapshot::SnapshotManager;
+use crate::premium::{Feature, FeatureGate};
+use crate::presets::{BisectScope, TestPreset};
+use crate::probe::Probe;
+use crate::sandbox::{Sandbox, SandboxKind};
+use crate::test_runner::{Benchmark, TestRunner};
 
 #[derive(Parser)]
 #[command(name = "eshu-trace")]
@@ -37,6 +104,18 @@ use crate::snapshot::SnapshotManager;
 #[command(version)]
 #[command(about = "Eshu-Trace: Find which package broke your system", long_about = "No More Rollbacks. Trace and Target the Exact Offending Package. Build On.")]
 struct Cli {
+    /// Print errors as JSON ({"error": {"code", "message", "hint"}}) for scripts
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Snapshot source to use instead of auto-detection: timeshift, snapper, btrfs, guix or manifest
+    #[arg(long, global = true, value_parser = snapshot::parse_backend)]
+    backend: Option<String>,
+
+    /// Print the commands that would change the system (fixes, mounts, hooks) instead of running them
+    #[arg(long, global = true)]
+    dry_run: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -49,13 +128,98 @@ enum Commands {
         #[arg(short, long)]
         good: Option<String>,
 
-        /// Snapshot ID when system was broken
+        /// Snapshot ID when system was broken ("current" for the system as it is now)
         #[arg(short, long)]
         bad: Option<String>,
 
         /// Automated testing (Premium)
         #[arg(long)]
         auto: bool,
+
+        /// Line protocol for external orchestrators: JSON events on stdout, verdicts on stdin
+        #[arg(long, requires_all = ["good", "bad"], conflicts_with_all = ["auto", "kernel", "test_command", "preset", "probes", "bench"])]
+        machine: bool,
+
+        /// Bisect over kernel releases instead of the package diff
+        #[arg(long, conflicts_with_all = ["good", "bad"])]
+        kernel: bool,
+
+        /// Only bisect a preset package scope (e.g. graphics: NVIDIA/Mesa, kernel, Xorg/Wayland)
+        #[arg(long, value_enum)]
+        scope: Option<BisectScope>,
+
+        /// Only bisect these packages (comma-separated), e.g. suspects from `blame`
+        #[arg(long, value_delimiter = ',')]
+        suspects: Vec<String>,
+
+        /// What goes wrong, in a few words (e.g. "no sound after resume"); packages that
+        /// fit the symptom are tested first
+        #[arg(long)]
+        symptom: Option<String>,
+
+        /// Plain binary search: treat every changed package as equally likely
+        #[arg(long, conflicts_with = "symptom")]
+        uniform: bool,
+
+        /// How automated bisect applies each candidate package set
+        #[arg(long, value_enum, default_value = "chroot")]
+        driver: DriverKind,
+
+        /// Test command to run at each step
+        #[arg(short = 'c', long)]
+        test_command: Option<String>,
+
+        /// Use a built-in test instead of a test command
+        #[arg(long, value_enum, conflicts_with = "test_command")]
+        preset: Option<TestPreset>,
+
+        /// Service probe to evaluate (http://host/path, tcp://host:port, dns://name); repeatable
+        #[arg(long = "probe")]
+        probes: Vec<Probe>,
+
+        /// Benchmark command; the test fails if it is slower than --max-seconds
+        #[arg(long, requires = "max_seconds")]
+        bench: Option<String>,
+
+        /// Time limit for --bench in seconds
+        #[arg(long, requires = "bench")]
+        max_seconds: Option<f64>,
+
+        /// Run the test command as this (desktop) user instead of root
+        #[arg(long)]
+        test_user: Option<String>,
+
+        /// Confine the test command (read-only filesystem, private /tmp, no network)
+        #[arg(long, value_enum)]
+        sandbox: Option<SandboxKind>,
+
+        /// Keep network access inside the --sandbox
+        #[arg(long, requires = "sandbox")]
+        sandbox_network: bool,
+
+        /// Take over the session lock of another (or crashed) bisect
+        #[arg(long)]
+        force: bool,
+
+        /// Don't download candidate package versions before an automated bisect
+        #[arg(long)]
+        no_prefetch: bool,
+
+        /// Don't send desktop notifications during an automated bisect
+        #[arg(long)]
+        no_notify: bool,
+
+        /// Don't offer the AI analysis of the culprit (Premium); nothing is sent anywhere
+        #[arg(long)]
+        no_ai: bool,
+
+        /// Offer to share the culprit (anonymized, with symptom tags) with the community database
+        #[arg(long)]
+        share: bool,
+
+        /// Send the final report to a webhook (Slack/Matrix-compatible) or mailto:address
+        #[arg(long, value_name = "URL|mailto:ADDRESS", value_parser = notify::parse_target)]
+        notify: Option<notify::Target>,
     },
 
     /// List available snapshots
@@ -63,15 +227,65 @@ enum Commands {
         /// Show detailed information
         #[arg(short, long)]
         verbose: bool,
+
+        /// Show per-snapshot disk usage and retention warnings
+        #[arg(long)]
+        usage: bool,
+
+        /// Only snapshots newer than this age or date (2d, 12h, 2024-05-01)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Only snapshots older than this age or date
+        #[arg(long)]
+        until: Option<String>,
+
+        /// Show at most this many snapshots (after sorting)
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Sort order, newest first
+        #[arg(long, value_enum, default_value = "date")]
+        sort: snapshot::SnapshotSort,
     },
 
     /// Show package differences between snapshots
     Diff {
-        /// First snapshot ID
+        /// First snapshot ID ("current" for the running system, or a package list file)
         snapshot1: String,
 
-        /// Second snapshot ID
-        snapshot2: String,
+        /// Second snapshot ID ("current" for the running system, or a package list file)
+        #[arg(required_unless_present = "receive")]
+        snapshot2: Option<String>,
+
+        /// Only show explicitly installed packages
+        #[arg(long)]
+        explicit: bool,
+
+        /// List every package of large desktop groups (KDE Plasma, GNOME, Qt, ...) instead of one line each
+        #[arg(long)]
+        expand: bool,
+
+        /// Only summarize: counts, groups, largest version jumps and risk flags
+        #[arg(long, conflicts_with_all = ["explicit", "expand"])]
+        stat: bool,
+
+        /// Compare against another machine's state instead: a received subvolume, or a
+        /// `btrfs send` stream file ("-" reads it from stdin)
+        #[arg(long, value_name = "STREAM|DIR", conflicts_with = "snapshot2")]
+        receive: Option<String>,
+    },
+
+    /// Show which good → bad changes are still present now, after a partial rollback
+    Diff3 {
+        /// Snapshot ID when system was working
+        good: String,
+
+        /// Snapshot ID when system was broken
+        bad: String,
+
+        /// State to check (default: "current", the running system)
+        current: Option<String>,
     },
 
     /// Test if issue occurs with current packages
@@ -79,70 +293,572 @@ enum Commands {
         /// Test command to run
         #[arg(short, long)]
         command: Option<String>,
+
+        /// Use a built-in test instead of a test command
+        #[arg(long, value_enum, conflicts_with = "command")]
+        preset: Option<TestPreset>,
+
+        /// Service probe to evaluate (http://host/path, tcp://host:port, dns://name); repeatable
+        #[arg(long = "probe")]
+        probes: Vec<Probe>,
+
+        /// Benchmark command; the test fails if it is slower than --max-seconds
+        #[arg(long, requires = "max_seconds")]
+        bench: Option<String>,
+
+        /// Time limit for --bench in seconds
+        #[arg(long, requires = "bench")]
+        max_seconds: Option<f64>,
+
+        /// Run the test command as this (desktop) user instead of root
+        #[arg(long)]
+        test_user: Option<String>,
+
+        /// Confine the test command (read-only filesystem, private /tmp, no network)
+        #[arg(long, value_enum)]
+        sandbox: Option<SandboxKind>,
+
+        /// Keep network access inside the --sandbox
+        #[arg(long, requires = "sandbox")]
+        sandbox_network: bool,
+    },
+
+    /// Find which package changes likely affected a file, binary, or service
+    Blame {
+        /// File or command to blame (e.g. /usr/bin/kwin_wayland)
+        #[arg(required_unless_present = "unit")]
+        target: Option<String>,
+
+        /// Blame a systemd unit instead (e.g. sddm.service)
+        #[arg(long, conflicts_with = "target")]
+        unit: Option<String>,
+
+        /// Snapshot ID when system was working
+        #[arg(short, long)]
+        good: Option<String>,
+
+        /// Snapshot ID when system was broken
+        #[arg(short, long)]
+        bad: Option<String>,
+    },
+
+    /// Show which package owned a file in a snapshot, and at what version
+    Owns {
+        /// File path as seen from the snapshot's root (e.g. /usr/lib/libEGL.so.1)
+        path: String,
+
+        /// Snapshot to look in
+        #[arg(short, long)]
+        snapshot: String,
+    },
+
+    /// Map a missing shared library to the package change that removed it
+    Soname {
+        /// Soname or the full loader error (e.g. "error while loading shared libraries: libfoo.so.5")
+        library: String,
+
+        /// Snapshot ID when system was working
+        #[arg(short, long)]
+        good: Option<String>,
+
+        /// Snapshot ID when system was broken (defaults to the running system)
+        #[arg(short, long)]
+        bad: Option<String>,
+    },
+
+    /// What changed since yesterday: diff the running system against the newest older snapshot
+    Recent {
+        /// How far back to look: an age (2d, 12h, 1w) or a date (YYYY-MM-DD [HH:MM])
+        #[arg(long, default_value = "1d")]
+        since: String,
+
+        /// Only show explicitly installed packages
+        #[arg(long)]
+        explicit: bool,
+
+        /// List every package of large desktop groups instead of one line each
+        #[arg(long)]
+        expand: bool,
+    },
+
+    /// Show snapshots, package transactions, reboots and kernel changes in one timeline
+    Timeline {
+        /// Only show events after this date (YYYY-MM-DD or YYYY-MM-DD HH:MM) or age (2d, 12h)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// List every package in each transaction
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// Check pending upgrades for open regression reports before upgrading (Premium)
+    Oracle {
+        /// Upgrade list file ("name old -> new" per line, as checkupdates prints; "-" for
+        /// stdin) instead of asking the package manager
+        list: Option<String>,
     },
 
     /// Show premium features and upgrade info
     Premium,
 
-    /// Activate license key
+    /// Activate license key (without prompts: ESHU_TRACE_LICENSE_KEY and ESHU_TRACE_EMAIL)
     Activate {
-        /// License key from Gumroad
+        /// License key from Gumroad, or an offline key (ESHU1...) or key file
         #[arg(short, long)]
         key: Option<String>,
 
+        /// Read the license key from a file, or from stdin with `-`
+        #[arg(long, conflicts_with = "key")]
+        key_file: Option<String>,
+
         /// Email address
         #[arg(short, long)]
         email: Option<String>,
+
+        /// One line of output; exit status 1 if rejected, 2 if queued until the server answers
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
+    /// Record a package manifest after every transaction and keep a health log
+    Monitor {
+        #[command(subcommand)]
+        action: MonitorAction,
+    },
+
+    /// Preserve previous versions of critical packages for later downgrades
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+
+    /// Manage package pins created by eshu-trace
+    Pin {
+        #[command(subcommand)]
+        action: PinAction,
     },
 
     /// Show status and configuration
     Status,
 
+    /// Check the environment and explain how to fix problems
+    Doctor,
+
+    /// Export a snapshot (the good state) for use outside this system
+    Export {
+        /// Write an OCI image archive (load with podman load -i / docker load -i)
+        #[arg(long, value_name = "FILE", required_unless_present = "manifest")]
+        oci: Option<std::path::PathBuf>,
+
+        /// Write the package manifest as JSON with a content hash, for support or another admin
+        #[arg(long, value_name = "FILE")]
+        manifest: Option<std::path::PathBuf>,
+
+        /// Also sign the manifest with this machine's key
+        #[arg(long, requires = "manifest")]
+        sign: bool,
+
+        /// Snapshot to export (prompted for when omitted)
+        #[arg(short, long)]
+        snapshot: Option<String>,
+    },
+
+    /// Show the last applied fix and export it for other machines
+    Fix {
+        /// Write an Ansible playbook applying the fix across hosts
+        #[arg(long, value_name = "FILE")]
+        export_ansible: Option<std::path::PathBuf>,
+
+        /// Write a standalone shell script applying the fix
+        #[arg(long, value_name = "FILE")]
+        export_script: Option<std::path::PathBuf>,
+
+        /// Sign the exported files with this machine's key
+        #[arg(long)]
+        sign: bool,
+    },
+
+    /// Check the content hash and signature of an exported manifest, fix script or playbook
+    Verify {
+        file: std::path::PathBuf,
+
+        /// Require a signature by this public key (base64, as printed on export)
+        #[arg(long)]
+        key: Option<String>,
+    },
+
+    /// Check whether a culprit's regression also shows up on other distros (Premium)
+    #[cfg(feature = "containers")]
+    Crosscheck {
+        /// Culprit package
+        package: String,
+
+        /// Broken version found by the bisect
+        version: String,
+
+        /// Command that fails while the regression is present
+        #[arg(short, long)]
+        test_command: String,
+
+        /// Container images to try (repeatable; default: Arch, Debian, Ubuntu, Fedora, openSUSE)
+        #[arg(long = "image")]
+        images: Vec<String>,
+    },
+
+    /// Answer JSON-RPC requests on a Unix socket (for eshu-installer and other tools)
+    Rpc {
+        /// Socket path (default: $XDG_RUNTIME_DIR/eshu-trace/rpc.sock)
+        #[arg(long)]
+        socket: Option<std::path::PathBuf>,
+    },
+
+    /// Serve a small web dashboard showing bisect progress
+    #[cfg(feature = "dashboard")]
+    Serve {
+        /// Address to listen on (0.0.0.0:<port> to watch from another machine)
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+
+    /// Review the log of commands that modified the system
+    Audit {
+        /// Only show the most recent entries
+        #[arg(short = 'n', long)]
+        last: Option<usize>,
+    },
+
     /// Show recovery mode instructions (for broken systems)
     Recovery,
+
+    /// Guided recovery from a live USB: unlock, mount, pick snapshots and bisect
+    Recover,
+}
+
+#[derive(Subcommand)]
+enum PinAction {
+    /// Keep a package at a version
+    Add {
+        package: String,
+
+        /// Working version to stay on
+        version: String,
+
+        /// Defaults to preferences on Debian/Ubuntu and lock on openSUSE
+        #[arg(long, value_enum)]
+        method: Option<pins::PinMethod>,
+
+        /// Broken version (required for --method ceiling)
+        #[arg(long)]
+        bad: Option<String>,
+
+        /// Command that reproduces the regression, used to re-test new releases
+        #[arg(long, requires = "snapshot")]
+        test: Option<String>,
+
+        /// Known-good snapshot to re-test new releases on top of
+        #[arg(short, long, requires = "test")]
+        snapshot: Option<String>,
+    },
+
+    /// List active pins
+    List,
+
+    /// Lift a pin and delete the files it wrote
+    Remove { package: String },
+
+    /// Look for releases newer than the broken version of each pinned package
+    Check {
+        /// Only print when a fixed version is available (for timers and cron)
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Re-test each new release in a throwaway overlay before reporting it
+        #[arg(long)]
+        retest: bool,
+    },
+
+    /// Install the new release in a throwaway overlay and run the recorded test (Premium)
+    Retest {
+        /// Only this package (default: every pin with a new release)
+        package: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MonitorAction {
+    /// Add the package manager hook and hourly systemd timer
+    Install,
+
+    /// Remove the hook and timer (recorded manifests are kept)
+    Uninstall,
+
+    /// Record a manifest if packages changed, and a health entry (run by the hook and timer)
+    Record {
+        /// Only print errors
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Skip the health check (package hooks leave it to the timer)
+        #[arg(long)]
+        no_check: bool,
+    },
+
+    /// Register a test to run with every timer record; when it starts failing, offer to bisect
+    Check {
+        /// Test command; exits 0 when the system is healthy (omit to show and run the current one)
+        command: Option<String>,
+
+        /// Use a built-in test instead of a command
+        #[arg(long, value_enum, conflicts_with = "command")]
+        preset: Option<TestPreset>,
+
+        /// Run the test as this (desktop) user; the timer itself runs as root
+        #[arg(long)]
+        test_user: Option<String>,
+
+        /// Stop running the health check
+        #[arg(long, conflicts_with_all = ["command", "preset"])]
+        remove: bool,
+    },
+
+    /// Show recorded manifests and the health log
+    Status {
+        /// How many manifests and health entries to show
+        #[arg(short = 'n', long, default_value = "10")]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Start preserving old versions of these packages
+    Keep {
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+
+    /// Stop preserving these packages (their files go on the next prune)
+    Forget {
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+
+    /// Show preserved packages and versions
+    List,
+
+    /// Copy new files of kept packages from the distro cache
+    Sync {
+        /// Only print errors (for package manager hooks)
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
+    /// Remove versions beyond the configured limits
+    Prune,
+
+    /// Set the size limit and how many versions to keep per package
+    Limit {
+        /// Total size, e.g. 5G or 500M
+        #[arg(long)]
+        max_size: Option<String>,
+
+        /// Versions to keep per package
+        #[arg(long)]
+        versions: Option<usize>,
+    },
+
+    /// Run `cache sync` automatically on every pacman/apt transaction
+    InstallHook,
 }
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("{} {}", "✗ Error:".red().bold(), e);
+    crash::install_panic_hook();
+    interrupt::install();
+
+    let cli = Cli::parse();
+    if cli.dry_run {
+        exec::install(Box::new(exec::DryRunner));
+    }
+    // --machine owns stdout, so its errors have to be JSON as well
+    let json = cli.json || matches!(cli.command, Commands::Bisect { machine: true, .. });
+
+    // JSON output and dry runs must not wait on (or print about) the license server
+    if !json && !cli.dry_run {
+        premium::start_pending_retry();
+    }
+    let result = run(cli);
+    media::release_all();
+    premium::finish_pending_retry();
+
+    if let Err(e) = result {
+        print_error(&e, json);
+        crash::report_error(&e);
         process::exit(1);
     }
 }
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
+fn print_error(e: &anyhow::Error, json: bool) {
+    if json {
+        println!("{}", error::to_json(e));
+        return;
+    }
+
+    match error::find(e) {
+        Some(typed) => {
+            eprintln!("{} {}", format!("✗ Error [{}]:", typed.code()).red().bold(), e);
+            eprintln!("  {} {}", "→".dimmed(), typed.hint());
+        }
+        None => eprintln!("{} {}", "✗ Error:".red().bold(), e),
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    if let Some(backend) = &cli.backend {
+        snapshot::force_backend(backend);
+    }
+
+    // A fix applied before a reboot is checked by the first interactive bisect afterwards
+    if matches!(cli.command, Commands::Bisect { machine: false, .. }) && !cli.json && io::stdin().is_terminal() {
+        match reboot::resume() {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) => println!("{} Could not verify the pending fix: {}", "⚠".yellow(), e),
+        }
+    }
 
     match cli.command {
-        Commands::Bisect { good, bad, auto } => {
-            bisect_command(good, bad, auto)?;
+        Commands::Bisect { good, bad, auto, machine, kernel, scope, suspects, symptom, uniform, driver, test_command, preset, probes, bench, max_seconds, test_user, sandbox, sandbox_network, force, no_prefetch, no_notify, no_ai, share, notify } => {
+            let _lock = lock::SessionLock::acquire(force)?;
+            if auto && !no_notify {
+                notify::enable();
+            }
+            notify::set_target(notify);
+            let hardware_symptom = preset.is_some_and(|p| p.is_hardware_level());
+            // The preset names the symptom too ("audio", "wifi", ...)
+            let symptom = (!uniform).then(|| format!("{} {}", symptom.unwrap_or_default(), preset.map(|p| p.name()).unwrap_or_default()));
+            let test_command = test_command.or_else(|| preset.map(|p| p.command()));
+            let runner = TestRunner::new(test_command)
+                .with_user(test_user)
+                .with_sandbox(sandbox.map(|kind| Sandbox::new(kind, sandbox_network)).transpose()?)
+                .with_probes(probes)
+                .with_benchmark(benchmark(bench, max_seconds));
+            let mut gate = premium::gate()?;
+            if let (true, Some(good), Some(bad)) = (machine, &good, &bad) {
+                machine_bisect_command(good, bad, scope, suspects, symptom.as_deref(), gate.as_mut())?;
+            } else if kernel {
+                kernel_bisect_command(auto, driver, runner, gate.as_mut())?;
+            } else {
+                bisect_command(good, bad, auto, scope, hardware_symptom, suspects, symptom.as_deref(), driver, runner, !no_prefetch, !no_ai, share, gate.as_mut())?;
+            }
+        }
+        Commands::Snapshots { verbose, usage, since, until, limit, sort } => {
+            let filter = SnapshotFilter { since, until, limit, sort };
+            list_snapshots(verbose, usage, &filter)?;
+        }
+        Commands::Diff { snapshot1, snapshot2, explicit, expand, stat, receive } => {
+            diff_command(snapshot1, snapshot2, explicit, expand, stat, receive)?;
+        }
+        Commands::Diff3 { good, bad, current } => {
+            diff3_command(&good, &bad, current.as_deref().unwrap_or(snapshot::CURRENT))?;
         }
-        Commands::Snapshots { verbose } => {
-            list_snapshots(verbose)?;
+        Commands::Test { command, preset, probes, bench, max_seconds, test_user, sandbox, sandbox_network } => {
+            if let Some(p) = preset {
+                println!("{} {} - {}", "Preset:".cyan(), p.name(), p.description());
+            }
+            let command = command.or_else(|| preset.map(|p| p.command()));
+            let sandbox = sandbox.map(|kind| Sandbox::new(kind, sandbox_network)).transpose()?;
+            test_command(command, probes, benchmark(bench, max_seconds), test_user, sandbox)?;
+        }
+        Commands::Blame { target, unit, good, bad } => {
+            blame_command(target, unit, good, bad)?;
+        }
+        Commands::Owns { path, snapshot } => {
+            owns_command(path, snapshot)?;
+        }
+        Commands::Soname { library, good, bad } => {
+            soname_command(library, good, bad)?;
         }
-        Commands::Diff { snapshot1, snapshot2 } => {
-            diff_command(snapshot1, snapshot2)?;
+        Commands::Recent { since, explicit, expand } => {
+            recent_command(&since, explicit, expand)?;
         }
-        Commands::Test { command } => {
-            test_command(command)?;
+        Commands::Timeline { since, verbose } => {
+            timeline_command(since, verbose)?;
+        }
+        Commands::Oracle { list } => {
+            oracle_command(list.as_deref())?;
         }
         Commands::Premium => {
             show_premium_info()?;
         }
-        Commands::Activate { key, email } => {
-            activate_command(key, email)?;
+        Commands::Activate { key, key_file, email, quiet } => {
+            activate_command(key, key_file, email, quiet)?;
+        }
+        Commands::Pin { action } => {
+            pin_command(action)?;
+        }
+        Commands::Monitor { action } => {
+            monitor_command(action)?;
+        }
+        Commands::Cache { action } => {
+            cache_command(action)?;
         }
         Commands::Status => {
             show_status()?;
         }
+        Commands::Doctor => {
+            doctor_command()?;
+        }
+        Commands::Export { oci, manifest, sign, snapshot } => {
+            export_command(oci, manifest, sign, snapshot)?;
+        }
+        Commands::Fix { export_ansible, export_script, sign } => {
+            fix_command(export_ansible, export_script, sign)?;
+        }
+        Commands::Verify { file, key } => {
+            verify_command(file, key)?;
+        }
+        #[cfg(feature = "containers")]
+        Commands::Crosscheck { package, version, test_command, images } => {
+            crosscheck_command(package, version, test_command, images)?;
+        }
+        Commands::Rpc { socket } => {
+            rpc::serve(&socket.unwrap_or_else(|| paths::runtime_file("rpc.sock")))?;
+        }
+        #[cfg(feature = "dashboard")]
+        Commands::Serve { listen } => {
+            dashboard::serve(&listen)?;
+        }
+        Commands::Audit { last } => {
+            audit_command(last)?;
+        }
         Commands::Recovery => {
             recovery::show_recovery_instructions();
         }
+        Commands::Recover => {
+            recover::run()?;
+        }
     }
 
     Ok(())
 }
 
-fn bisect_command(good: Option<String>, bad: Option<String>, auto: bool) -> Result<()> {
+#[allow(clippy::too_many_arguments)]
+fn bisect_command(
+    good: Option<String>,
+    bad: Option<String>,
+    auto: bool,
+    scope: Option<BisectScope>,
+    hardware_symptom: bool,
+    suspects: Vec<String>,
+    symptom: Option<&str>,
+    driver_kind: DriverKind,
+    runner: TestRunner,
+    prefetch: bool,
+    ai: bool,
+    share: bool,
+    gate: &mut dyn FeatureGate,
+) -> Result<()> {
     // Detect recovery mode
     let recovery_ctx = recovery::RecoveryContext::detect()?;
     recovery_ctx.show_recovery_banner();
@@ -153,9 +869,7 @@ fn bisect_command(good: Option<String>, bad: Option<String>, auto: bool) -> Resu
     println!();
 
     // Check license and trace limit
-    let license = premium::get_license()?;
-
-    if !license.can_trace() {
+    if !gate.is_enabled(Feature::Trace) {
         println!("{}", "❌ Trial limit reached!".red().bold());
         println!();
         println!("You've used all {} free traces.", 3);
@@ -175,9 +889,9 @@ fn bisect_command(good: Option<String>, bad: Option<String>, auto: bool) -> Resu
     }
 
     // Show trial status
-    match license.license_type {
-        premium::LicenseType::Trial => {
-            if let Some(remaining) = license.remaining_traces() {
+    match gate.license_type() {
+        Some(premium::LicenseType::Trial) => {
+            if let Some(remaining) = gate.remaining_uses(Feature::Trace) {
                 println!(
                     "{} Trial: {}/{} traces remaining",
                     "ℹ️".cyan(),
@@ -188,23 +902,34 @@ fn bisect_command(good: Option<String>, bad: Option<String>, auto: bool) -> Resu
                 println!();
             }
         }
-        premium::LicenseType::Standalone => {
+        Some(premium::LicenseType::Standalone) => {
             println!("{} Eshu Trace Licensed", "✓".green());
             println!();
         }
-        premium::LicenseType::Premium => {
+        Some(premium::LicenseType::Premium) => {
             println!("{} Eshu Premium (includes Trace)", "✓".green());
             println!();
         }
+        None => {}
     }
 
-    if auto && !premium::is_premium()? {
+    let automated = auto && gate.is_enabled(Feature::AutomatedBisect);
+    if auto && !automated {
         println!("{}", "⚠️  Automated bisect is a Premium feature".yellow());
         println!("{}", "   Using manual bisect mode instead...".dimmed());
         println!();
     }
 
-    let snapshot_mgr = SnapshotManager::new()?;
+    // Stale services are the cheapest explanation; rule them out first. In a chroot the
+    // running services belong to the rescue system, not the one being bisected.
+    if !recovery_ctx.is_chroot && offer_service_restart()? {
+        return Ok(());
+    }
+
+    let snapshot_mgr = match (&good, &bad) {
+        (Some(good), Some(bad)) => SnapshotManager::for_ids(&[good, bad])?,
+        _ => SnapshotManager::new()?,
+    };
 
     // Detect snapshots
     let good_snapshot = if let Some(id) = good {
@@ -223,45 +948,179 @@ fn bisect_command(good: Option<String>, bad: Option<String>, auto: bool) -> Resu
 
     println!();
     println!("{} {}", "Good snapshot:".green(), good_snapshot.id);
-    println!("  Date: {}", good_snapshot.created_at);
+    println!("  Date: {}", good_snapshot.date());
     println!();
     println!("{} {}", "Bad snapshot:".red(), bad_snapshot.id);
-    println!("  Date: {}", bad_snapshot.created_at);
+    println!("  Date: {}", bad_snapshot.date());
     println!();
 
-    // Start bisect session
-    let mut session = BisectSession::new(good_snapshot, bad_snapshot)?;
+    // Guix installs generations whole, so the generations in between are bisected instead
+    #[cfg(feature = "guix")]
+    if good_snapshot.kind.as_deref() == Some(guix::GENERATION) && bad_snapshot.kind.as_deref() == Some(guix::GENERATION) {
+        if guix::bisect_generations(&good_snapshot, &bad_snapshot, &runner)? {
+            gate.record_use(Feature::Trace)?;
+        }
+        return Ok(());
+    }
 
-    println!(
-        "{} {} packages changed between snapshots",
-        "📦".bold(),
-        session.total_packages()
-    );
-    println!("{} Starting binary bisect...", "🔍".bold());
-    println!();
+    // Not packages, so the bisect can only name the package that shipped them
+    if let (Some(good_root), Some(bad_root)) = (&good_snapshot.path, &bad_snapshot.path) {
+        let settings = bootparams::detect(good_root, bad_root);
+        if !settings.is_empty() {
+            print_setting_changes(&settings);
+            println!("   A changed kernel parameter or sysctl value can be the culprit on its own.");
+            println!();
+        }
+
+        let flashes = firmware::fwupd_flashes(good_root, bad_root);
+        if !flashes.is_empty() {
+            print_firmware_changes(&[], &flashes);
+            println!("   Device firmware is not part of the bisect.");
+            println!();
+        }
+    }
+
+    // A removal that left requirements dangling explains the breakage without bisecting
+    if let Some(bad_root) = &bad_snapshot.path {
+        let diff = package_diff::compute_diff(&good_snapshot, &bad_snapshot)?;
+        let broken = orphans::broken_requires(bad_root, &diff);
+        if !broken.is_empty() {
+            print_broken_requires(&broken);
+            println!();
+            println!("   Reinstalling the removed packages is likely to fix this.");
+            println!();
+
+            if !dialoguer::Confirm::new()
+                .with_prompt("Run the bisect anyway?")
+                .default(false)
+                .interact()?
+            {
+                return Ok(());
+            }
+            println!();
+        }
+    }
+
+    let mut test_driver = if automated {
+        Some(driver::create(driver_kind, &good_snapshot)?)
+    } else {
+        None
+    };
+    let good_distro = good_snapshot
+        .path
+        .as_deref()
+        .and_then(|root| fixer::detect_distro_at(root).ok());
+
+    let resume_hint = format!(
+        "eshu-trace bisect -g {} -b {}{}",
+        good_snapshot.id,
+        bad_snapshot.id,
+        if automated { " --auto" } else { "" }
+    );
+
+    // Kept with any pin the fix applies, so new releases can be re-tested the same way
+    let recorded_test = runner.command().map(|command| pins::RecordedTest {
+        command: command.to_string(),
+        snapshot: good_snapshot.id.clone(),
+    });
+
+    // Start bisect session
+    let mut session = BisectSession::new(good_snapshot, bad_snapshot)?;
+    session.set_test_runner(runner);
+
+    if let Some(scope) = scope {
+        session.restrict_to(scope)?;
+        println!("{} Limited to {}", "🎯".bold(), scope.description());
+    }
+
+    if !suspects.is_empty() {
+        session.restrict_to_packages(&suspects)?;
+        println!("{} Limited to suspects: {}", "🎯".bold(), suspects.join(", "));
+    }
+
+    // Hardware-level symptoms most often come from firmware or microcode; offer to test those first
+    if hardware_symptom && scope.is_none() && suspects.is_empty() {
+        let changed: Vec<&str> = session
+            .candidates()
+            .iter()
+            .map(|c| c.name())
+            .filter(|name| firmware::is_firmware_package(name))
+            .collect();
+        if !changed.is_empty()
+            && dialoguer::Confirm::new()
+                .with_prompt(format!(
+                    "Firmware changed ({}) and the symptom is hardware-level. Bisect {} first?",
+                    changed.join(", "),
+                    BisectScope::Hardware.description()
+                ))
+                .default(true)
+                .interact()?
+        {
+            session.restrict_to(BisectScope::Hardware)?;
+            println!("{} Limited to {}", "🎯".bold(), BisectScope::Hardware.description());
+        }
+    }
+
+    println!(
+        "{} {} packages changed between snapshots",
+        "📦".bold(),
+        session.total_packages()
+    );
+    if let Some(symptom) = symptom {
+        let likely: Vec<&str> = session.weigh(symptom).iter().take(5).map(|c| c.name()).collect();
+        if !likely.is_empty() {
+            println!("{} Most likely first: {}", "⚖".bold(), likely.join(", "));
+        }
+    }
+    if session.resume_saved()? {
+        println!("{} Resuming where the last bisect stopped", "↻".cyan());
+    } else {
+        println!("{} Starting binary bisect...", "🔍".bold());
+    }
+    println!();
+    interrupt::set_resume_hint(Some(resume_hint));
+
+    // Every step installs packages; fetch them now rather than failing mid-run offline
+    if let (true, true, Some(distro)) = (automated, prefetch, good_distro.as_deref()) {
+        if !prefetch_candidates(distro, session.candidates())? {
+            return Ok(());
+        }
+    }
 
     // Run bisect
-    let result = if auto && premium::is_premium()? {
-        session.run_automated()
+    let result = if let Some(driver) = test_driver.as_deref_mut() {
+        session.run_automated(driver)
     } else {
         session.run_manual()
     };
 
     // Increment usage count after successful trace
     if result.is_ok() {
-        premium::increment_trace_usage()?;
+        gate.record_use(Feature::Trace)?;
 
         // OFFER FIX after finding culprit
         if let Some(culprit) = session.get_culprit() {
-            let fixer = fixer::PackageFixer::new(recovery_ctx);
+            let root = if recovery_ctx.is_chroot { recovery_ctx.system_root.as_str() } else { "/" };
+            if ai {
+                let distro = fixer::detect_distro_at(root).unwrap_or_else(|_| "unknown".to_string());
+                analysis::offer(culprit, root, &distro, symptom, gate);
+            }
+            if share {
+                let finding = community::Finding::new(culprit, &session.get_culprit_group(), session.assess().confidence, root, symptom);
+                community::offer_share(&finding);
+            }
+
+            let fixer = fixer::PackageFixer::new(recovery_ctx)
+                .with_coupled(session.get_culprit_group())
+                .with_changes(session.changes().to_vec())
+                .with_test(recorded_test);
             fixer.offer_fix(culprit)?;
         }
 
         // Show updated trial status
-        let license = premium::get_license()?;
-        if license.license_type == premium::LicenseType::Trial {
+        if gate.license_type() == Some(&premium::LicenseType::Trial) {
             println!();
-            if let Some(remaining) = license.remaining_traces() {
+            if let Some(remaining) = gate.remaining_uses(Feature::Trace) {
                 if remaining > 0 {
                     println!(
                         "{} {} trial traces remaining",
@@ -283,9 +1142,121 @@ fn bisect_command(good: Option<String>, bad: Option<String>, auto: bool) -> Resu
     result
 }
 
-fn list_snapshots(verbose: bool) -> Result<()> {
+/// `bisect --machine`: the bisect without prompts or banners, driven over stdin/stdout
+fn machine_bisect_command(
+    good: &str,
+    bad: &str,
+    scope: Option<BisectScope>,
+    suspects: Vec<String>,
+    symptom: Option<&str>,
+    gate: &mut dyn FeatureGate,
+) -> Result<()> {
+    if !gate.is_enabled(Feature::Trace) {
+        anyhow::bail!("Trial limit reached. Please purchase a license to continue.");
+    }
+
+    let snapshot_mgr = SnapshotManager::for_ids(&[good, bad])?;
+    let good_snapshot = snapshot_mgr.get_snapshot(good)?;
+    let bad_snapshot = snapshot_mgr.get_snapshot(bad)?;
+
+    let mut session = BisectSession::new(good_snapshot, bad_snapshot)?;
+    if let Some(scope) = scope {
+        session.restrict_to(scope)?;
+    }
+    if !suspects.is_empty() {
+        session.restrict_to_packages(&suspects)?;
+    }
+    if let Some(symptom) = symptom {
+        session.weigh(symptom);
+    }
+    session.resume_matching()?;
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let found = session.run_machine(&mut stdin.lock(), &mut stdout.lock())?;
+    if found {
+        gate.record_use(Feature::Trace)?;
+    }
+    Ok(())
+}
+
+fn kernel_bisect_command(
+    auto: bool,
+    driver_kind: DriverKind,
+    runner: TestRunner,
+    gate: &mut dyn FeatureGate,
+) -> Result<()> {
+    println!("{}", "🐧 Eshu-Trace: Find the Breaking Kernel".cyan().bold());
+    println!();
+
+    if !gate.is_enabled(Feature::Trace) {
+        anyhow::bail!("Trial limit reached. Please purchase a license to continue.");
+    }
+
+    // Automated kernel bisect boots the current root under QEMU with each candidate
+    #[cfg(feature = "vm")]
+    let vm = if auto && gate.is_enabled(Feature::AutomatedBisect) {
+        if driver_kind != DriverKind::Qemu {
+            println!("{}", "ℹ️  Kernel bisect automation uses the qemu driver".dimmed());
+        }
+        Some(driver::QemuDriver::new("/")?)
+    } else {
+        None
+    };
+    #[cfg(not(feature = "vm"))]
+    let vm: Option<driver::QemuDriver> = {
+        let _ = driver_kind;
+        if auto {
+            println!("{}", "ℹ️  This build has no QEMU driver (feature `vm`); testing each kernel by reboot".dimmed());
+        }
+        None
+    };
+
+    if kernel::run_kernel_bisect(&runner, vm.as_ref())? {
+        gate.record_use(Feature::Trace)?;
+    }
+
+    Ok(())
+}
+
+/// Narrowing options of the snapshots command
+struct SnapshotFilter {
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<usize>,
+    sort: snapshot::SnapshotSort,
+}
+
+fn list_snapshots(verbose: bool, show_usage: bool, filter: &SnapshotFilter) -> Result<()> {
     let snapshot_mgr = SnapshotManager::new()?;
-    let snapshots = snapshot_mgr.list_snapshots()?;
+    let all = snapshot_mgr.list_snapshots()?;
+    let total = all.len();
+    let mut snapshots = all.clone();
+
+    let parse = |text: &Option<String>| -> Result<Option<chrono::NaiveDateTime>> {
+        text.as_deref()
+            .map(|t| timeline::parse_since(t).ok_or_else(|| anyhow::anyhow!("Invalid age or date: {} (try 2d, 12h or 2024-05-01)", t)))
+            .transpose()
+    };
+    let since = parse(&filter.since)?;
+    let until = parse(&filter.until)?;
+    if since.is_some() || until.is_some() {
+        // Snapshots without a readable date can't be placed in the range
+        snapshots.retain(|s| {
+            s.created_at.map(|c| c.naive_local()).is_some_and(|c| {
+                since.is_none_or(|since| c >= since) && until.is_none_or(|until| c <= until)
+            })
+        });
+    }
+    filter.sort.apply(&mut snapshots);
+    if let Some(limit) = filter.limit {
+        snapshots.truncate(limit);
+    }
+
+    if snapshots.is_empty() && total > 0 {
+        println!("{} None of the {} snapshots match the filters", "ℹ".cyan(), total);
+        return Ok(());
+    }
 
     if snapshots.is_empty() {
         println!("{}", "No snapshots found".yellow());
@@ -298,84 +1269,1274 @@ fn list_snapshots(verbose: bool) -> Result<()> {
         return Ok(());
     }
 
-    println!("{} Available Snapshots:", "📸".bold());
+    let usage = if show_usage {
+        snapshot_mgr.usage(&all)
+    } else {
+        std::collections::HashMap::new()
+    };
+
+    if snapshots.len() < total {
+        println!("{} Available Snapshots ({} of {}):", "📸".bold(), snapshots.len(), total);
+    } else {
+        println!("{} Available Snapshots:", "📸".bold());
+    }
+    println!();
+
+    for snapshot in &snapshots {
+        println!("{} {}", "ID:".cyan(), snapshot.id);
+        println!("   Date: {}", snapshot.date());
+
+        if show_usage {
+            match usage.get(&snapshot.id) {
+                Some(bytes) => println!("   Exclusive: {}", snapshot::human_size(*bytes)),
+                None => println!("   Exclusive: {}", "unknown".dimmed()),
+            }
+        }
+
+        if verbose {
+            println!("   Packages: {}", snapshot.package_count.unwrap_or(0));
+
+            if let Some(desc) = &snapshot.description {
+                println!("   Description: {}", desc);
+            }
+
+            if let Some(kind) = &snapshot.kind {
+                println!("   Type: {}", kind);
+            }
+
+            if let Some(cleanup) = &snapshot.cleanup {
+                println!("   Cleanup: {} {}", cleanup, "(deleted automatically)".dimmed());
+            }
+        }
+
+        println!();
+    }
+
+    if show_usage {
+        // Retention counts every snapshot, not just the listed ones
+        show_retention(&snapshot_mgr, &all, &usage);
+    }
+
+    Ok(())
+}
+
+fn show_retention(
+    snapshot_mgr: &SnapshotManager,
+    snapshots: &[snapshot::Snapshot],
+    usage: &std::collections::HashMap<String, u64>,
+) {
+    if usage.is_empty() {
+        println!("{} Disk usage unavailable (enable btrfs quotas: btrfs quota enable /)", "ℹ".cyan());
+    } else {
+        println!("{} {}", "Total exclusive:".cyan(), snapshot::human_size(usage.values().sum()));
+    }
+
+    let limit = match snapshot_mgr.retention_limit() {
+        Some(l) => l,
+        None => return,
+    };
+    println!("{} {} of {} kept", "Retention:".cyan(), snapshots.len(), limit);
+
+    let expiring = snapshot_mgr.next_to_expire(snapshots);
+    if expiring.is_empty() {
+        return;
+    }
+
+    // The newest snapshot taken before the latest package transaction is the last known-good state
+    let last_transaction = timeline::collect(None)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|e| matches!(e.kind, timeline::EventKind::Transaction(_)))
+        .map(|e| e.time)
+        .max();
+    let last_good = last_transaction.and_then(|t| {
+        snapshots
+            .iter()
+            .filter(|s| s.created_at.is_some_and(|c| c.naive_local() < t))
+            .max_by_key(|s| s.created_at)
+    });
+
+    println!();
+    println!("{} Next cleanup will delete:", "⚠".yellow());
+    for snap in &expiring {
+        println!("   • {} ({})", snap.id, snap.date().dimmed());
+    }
+
+    if let Some(good) = last_good {
+        if expiring.iter().any(|s| s.id == good.id) {
+            println!();
+            println!(
+                "{} Snapshot {} is the last one from before the latest package changes.",
+                "✗".red().bold(),
+                good.id.bold()
+            );
+            println!("   Trace now or protect it, or you will lose your known-good state.");
+        }
+    }
+}
+
+/// Desktop groups with more changes than this are shown as one line by `diff`
+const GROUP_FOLD_THRESHOLD: usize = 5;
+
+fn diff_command(
+    snapshot1: String,
+    snapshot2: Option<String>,
+    explicit_only: bool,
+    expand: bool,
+    stat: bool,
+    receive: Option<String>,
+) -> Result<()> {
+    let ids: Vec<&str> = std::iter::once(snapshot1.as_str()).chain(snapshot2.as_deref()).collect();
+    let snapshot_mgr = SnapshotManager::for_ids(&ids)?;
+
+    let snap1 = snapshot_mgr.get_snapshot(&snapshot1)?;
+    let snap2 = match (snapshot2, receive) {
+        (_, Some(source)) => {
+            let received = snapshot::receive(&source, std::path::Path::new(snapshot::RECEIVE_DIR))?;
+            if let Some(path) = &received.path {
+                println!("{} Received state at {}", "📥".bold(), path);
+                println!();
+            }
+            received
+        }
+        (Some(id), None) => snapshot_mgr.get_snapshot(&id)?,
+        (None, None) => unreachable!("clap requires snapshot2 or --receive"),
+    };
+
+    println!("{} Package Differences", "📊".bold());
+    println!();
+    println!("{} {}", "Snapshot 1:".cyan(), snap1.id);
+    println!("{} {}", "Snapshot 2:".cyan(), snap2.id);
+    println!();
+
+    let diff = package_diff::compute_diff(&snap1, &snap2)?;
+
+    if stat {
+        print_diff_stat(&diff);
+        return Ok(());
+    }
+
+    print_package_changes(&diff, explicit_only, expand);
+
+    let trust: Vec<_> = diff
+        .all_changes()
+        .into_iter()
+        .filter(|c| keyring::is_keyring_package(c.name()))
+        .collect();
+    if !trust.is_empty() {
+        println!();
+        println!("{} Signing trust changes ({}):", "🔑".yellow(), trust.len());
+        for change in &trust {
+            println!("   {}", change.name().bold());
+        }
+        println!("   Fixes that reinstall older packages may fail signature checks; refresh the keyring first.");
+    }
+
+    print_core_library_changes(&diff.all_changes());
+
+    let flashes = match (&snap1.path, &snap2.path) {
+        (Some(root1), Some(root2)) => firmware::fwupd_flashes(root1, root2),
+        _ => Vec::new(),
+    };
+    print_firmware_changes(&diff.all_changes(), &flashes);
+
+    if let Some(root2) = &snap2.path {
+        print_broken_requires(&orphans::broken_requires(root2, &diff));
+    }
+
+    // Soname bumps and origin changes need both snapshot filesystems
+    if let (Some(root1), Some(root2)) = (&snap1.path, &snap2.path) {
+        let moved = origin::origin_changes(root1, root2);
+        let replaced = origin::replacements(root2, &diff);
+        if !moved.is_empty() || !replaced.is_empty() {
+            println!();
+            println!("{} Origin changes ({}):", "🔀".yellow(), moved.len() + replaced.len());
+            for change in &moved {
+                println!("   {} {} → {}", change.package.bold(), change.old.dimmed(), change.new.yellow());
+            }
+            for replacement in &replaced {
+                println!("   {} replaced by {}", replacement.old.bold(), replacement.new.yellow());
+            }
+        }
+
+        print_setting_changes(&bootparams::detect(root1, root2));
+
+        let breaks = abi::detect(root1, root2, &diff.all_changes());
+        if !breaks.is_empty() {
+            println!();
+            println!("{} ABI breaks ({}):", "💥".red(), breaks.len());
+            for abi_break in &breaks {
+                let added = if abi_break.added.is_empty() {
+                    "removed".to_string()
+                } else {
+                    abi_break.added.join(", ")
+                };
+                println!(
+                    "   {} {} → {}",
+                    abi_break.package.bold(),
+                    abi_break.removed.join(", ").dimmed(),
+                    added
+                );
+                if !abi_break.stale_dependents.is_empty() {
+                    println!(
+                        "     {} not rebuilt: {}",
+                        "high-probability culprits".red().bold(),
+                        abi_break.stale_dependents.join(", ")
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Offer restarting services that still run replaced libraries. Returns true if that fixed the issue.
+fn offer_service_restart() -> Result<bool> {
+    let stale = restart::detect();
+    if stale.services.is_empty() {
+        return Ok(false);
+    }
+
+    println!("{} {} service(s) still run files replaced by an upgrade:", "♻".yellow(), stale.services.len());
+    for svc in &stale.services {
+        println!("   • {}", svc);
+    }
+    println!("   {}", "Restarting them is a zero-risk first fix.".dimmed());
+    println!();
+
+    if !dialoguer::Confirm::new()
+        .with_prompt("Restart these services now?")
+        .default(true)
+        .interact()?
+    {
+        println!();
+        return Ok(false);
+    }
+
+    if restart::restart_services(&stale.services)? {
+        println!("{} Services restarted", "✓".green());
+    } else {
+        println!("{} Some services failed to restart", "⚠".yellow());
+    }
+    println!();
+
+    let still_broken = dialoguer::Confirm::new()
+        .with_prompt("Does the issue still occur?")
+        .default(true)
+        .interact()?;
+    println!();
+
+    if !still_broken {
+        println!("{} Fixed by restarting services - no bisect needed", "✓".green().bold());
+    }
+    Ok(!still_broken)
+}
+
+/// Added/removed/upgraded/downgraded sections of a diff, with desktop groups folded unless `expand`
+fn print_package_changes(diff: &package_diff::PackageDiff, explicit_only: bool, expand: bool) {
+    let reason_tag = |name: &str| match diff.reason(name) {
+        Some(package_diff::InstallReason::Explicit) => format!(" {}", "[explicit]".cyan()),
+        Some(package_diff::InstallReason::Dependency) => format!(" {}", "[dep]".dimmed()),
+        None => String::new(),
+    };
+
+    let (mut added, mut removed, mut upgraded, mut downgraded) = (
+        diff.added.clone(),
+        diff.removed.clone(),
+        diff.upgraded.clone(),
+        diff.downgraded.clone(),
+    );
+    if explicit_only {
+        if diff.reasons.is_empty() {
+            println!("{} Install reasons unavailable for these snapshots; showing all packages", "⚠".yellow());
+            println!();
+        } else {
+            let is_explicit = |name: &str| diff.reason(name) == Some(package_diff::InstallReason::Explicit);
+            added.retain(|p| is_explicit(&p.name));
+            removed.retain(|p| is_explicit(&p.name));
+            upgraded.retain(|(p, _, _)| is_explicit(&p.name));
+            downgraded.retain(|(p, _, _)| is_explicit(&p.name));
+        }
+    }
+
+    // A Plasma or GNOME release is hundreds of packages; show each group as one line
+    let fold = |names: Vec<&str>| {
+        if expand {
+            Vec::new()
+        } else {
+            presets::large_groups(names, GROUP_FOLD_THRESHOLD)
+        }
+    };
+    let folded = |groups: &[(&str, usize)], name: &str| {
+        presets::ecosystem(name).is_some_and(|label| groups.iter().any(|(l, _)| *l == label))
+    };
+
+    if !added.is_empty() {
+        println!("{} Added packages ({}):", "➕".green(), added.len());
+        let groups = fold(added.iter().map(|p| p.name.as_str()).collect());
+        print_groups(&groups);
+        for pkg in added.iter().filter(|p| !folded(&groups, &p.name)) {
+            println!("   {} {}{}", "+".green(), pkg, reason_tag(&pkg.name));
+        }
+        println!();
+    }
+
+    if !removed.is_empty() {
+        println!("{} Removed packages ({}):", "➖".red(), removed.len());
+        let groups = fold(removed.iter().map(|p| p.name.as_str()).collect());
+        print_groups(&groups);
+        for pkg in removed.iter().filter(|p| !folded(&groups, &p.name)) {
+            println!("   {} {}{}", "-".red(), pkg, reason_tag(&pkg.name));
+        }
+        println!();
+    }
+
+    if !upgraded.is_empty() {
+        println!("{} Upgraded packages ({}):", "⬆️".yellow(), upgraded.len());
+        let groups = fold(upgraded.iter().map(|(p, _, _)| p.name.as_str()).collect());
+        print_groups(&groups);
+        for (pkg, old_ver, new_ver) in upgraded.iter().filter(|(p, _, _)| !folded(&groups, &p.name)) {
+            println!("   {} {} → {}{}", pkg.name, old_ver.dimmed(), new_ver, reason_tag(&pkg.name));
+        }
+        println!();
+    }
+
+    if !downgraded.is_empty() {
+        println!("{} Downgraded packages ({}):", "⬇️".yellow(), downgraded.len());
+        let groups = fold(downgraded.iter().map(|(p, _, _)| p.name.as_str()).collect());
+        print_groups(&groups);
+        for (pkg, old_ver, new_ver) in downgraded.iter().filter(|(p, _, _)| !folded(&groups, &p.name)) {
+            println!("   {} {} → {}{}", pkg.name, old_ver.dimmed(), new_ver, reason_tag(&pkg.name));
+        }
+        println!();
+    }
+
+    println!("Total changes: {}", diff.total_changes());
+}
+
+fn diff3_command(good: &str, bad: &str, current: &str) -> Result<()> {
+    let snapshot_mgr = SnapshotManager::for_ids(&[good, bad, current])?;
+    let good_snapshot = snapshot_mgr.get_snapshot(good)?;
+    let bad_snapshot = snapshot_mgr.get_snapshot(bad)?;
+    let current_snapshot = snapshot_mgr.get_snapshot(current)?;
+
+    println!("{} Three-way Package Differences", "📊".bold());
+    println!();
+    println!("{} {}", "Good:".green(), good_snapshot.id);
+    println!("{} {}", "Bad:".red(), bad_snapshot.id);
+    println!("{} {}", "Now:".cyan(), current_snapshot.id);
+    println!();
+
+    let diff3 = package_diff::compute_diff3(&good_snapshot, &bad_snapshot, &current_snapshot)?;
+    let total = diff3.persisting.len() + diff3.reverted.len() + diff3.drifted.len();
+    if total == 0 {
+        println!("{} No package changes between {} and {}", "✓".green(), good_snapshot.id, bad_snapshot.id);
+        return Ok(());
+    }
+
+    let print_change = |change: &package_diff::PackageChange| match change {
+        package_diff::PackageChange::Added(pkg) => println!("   {} {}", "+".green(), pkg),
+        package_diff::PackageChange::Removed(pkg) => println!("   {} {}", "-".red(), pkg),
+        package_diff::PackageChange::Upgraded(pkg, old, new) | package_diff::PackageChange::Downgraded(pkg, old, new) => {
+            println!("   {} {} → {}", pkg.name, old.dimmed(), new)
+        }
+    };
+
+    if !diff3.persisting.is_empty() {
+        println!("{} Still as in the bad state ({}):", "⚠".yellow(), diff3.persisting.len());
+        diff3.persisting.iter().for_each(print_change);
+        println!();
+    }
+
+    if !diff3.drifted.is_empty() {
+        println!("{} Changed again since ({}):", "↻".cyan(), diff3.drifted.len());
+        for (change, installed) in &diff3.drifted {
+            let now = installed.as_deref().unwrap_or("not installed");
+            println!("   {} {} {}", change.name(), "now".dimmed(), now.yellow());
+        }
+        println!();
+    }
+
+    if !diff3.reverted.is_empty() {
+        println!("{} Already rolled back ({}):", "✓".green(), diff3.reverted.len());
+        diff3.reverted.iter().for_each(print_change);
+        println!();
+    }
+
+    println!(
+        "{} of {} changes still to act on",
+        diff3.persisting.len().to_string().bold(),
+        total
+    );
+    Ok(())
+}
+
+/// `diff --stat`: a one-screen triage summary
+fn print_diff_stat(diff: &package_diff::PackageDiff) {
+    let changes = diff.all_changes();
+    let names: Vec<&str> = changes.iter().map(|c| c.name()).collect();
+    let preview = |names: &[&str]| {
+        let mut shown = names.iter().take(3).copied().collect::<Vec<_>>().join(", ");
+        if names.len() > 3 {
+            shown.push_str(&format!(" +{} more", names.len() - 3));
+        }
+        shown
+    };
+
+    println!(
+        "{} {} changes: {} added, {} removed, {} upgraded, {} downgraded",
+        "Σ".bold(),
+        diff.total_changes().to_string().bold(),
+        diff.added.len().to_string().green(),
+        diff.removed.len().to_string().red(),
+        diff.upgraded.len().to_string().yellow(),
+        diff.downgraded.len().to_string().yellow()
+    );
+    if !diff.reasons.is_empty() {
+        let explicit = names
+            .iter()
+            .filter(|n| diff.reason(n) == Some(package_diff::InstallReason::Explicit))
+            .count();
+        println!("   {} explicitly installed, {} dependencies", explicit, names.len() - explicit);
+    }
+
+    let mut groups = presets::large_groups(names.iter().copied(), 0);
+    if !groups.is_empty() {
+        groups.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let grouped: usize = groups.iter().map(|(_, count)| count).sum();
+        println!();
+        println!("{} Groups:", "▸".cyan());
+        for (label, count) in groups.iter().take(5) {
+            println!("   {:<24} {}", label.bold(), count);
+        }
+        println!("   {:<24} {}", "other".dimmed(), names.len() - grouped);
+    }
+
+    let mut jumps: Vec<_> = diff
+        .upgraded
+        .iter()
+        .chain(&diff.downgraded)
+        .filter_map(|(pkg, old, new)| package_diff::version_jump(old, new).map(|jump| (pkg, old, new, jump)))
+        .collect();
+    if !jumps.is_empty() {
+        jumps.sort_by(|(a, _, _, ja), (b, _, _, jb)| ja.0.cmp(&jb.0).then(jb.1.cmp(&ja.1)).then(a.name.cmp(&b.name)));
+        println!();
+        println!("{} Largest version jumps:", "↕".cyan());
+        for (pkg, old, new, (position, _)) in jumps.iter().take(5) {
+            let kind = match position {
+                0 => "major".red(),
+                1 => "minor".yellow(),
+                _ => "patch".normal(),
+            };
+            println!("   {} {} → {} {}", pkg.name.bold(), old.dimmed(), new, format!("({})", kind).dimmed());
+        }
+    }
+
+    let matching = |keep: &dyn Fn(&str) -> bool| names.iter().copied().filter(|n| keep(n)).collect::<Vec<_>>();
+    let mut flags: Vec<String> = Vec::new();
+    for (what, hits) in [
+        ("kernel", matching(&presets::is_kernel)),
+        ("core libraries", matching(&|n| corelibs::classify(n).is_some())),
+        ("graphics stack", matching(&|n| presets::BisectScope::Graphics.matches(n))),
+        ("firmware/microcode", matching(&firmware::is_firmware_package)),
+        ("signing keys", matching(&keyring::is_keyring_package)),
+    ] {
+        if !hits.is_empty() {
+            flags.push(format!("{} changed: {}", what, preview(&hits)));
+        }
+    }
+    let majors = jumps.iter().filter(|(_, _, _, (position, _))| *position == 0).count();
+    if majors > 0 {
+        flags.push(format!("{} major version jump(s)", majors));
+    }
+    if !diff.downgraded.is_empty() {
+        flags.push(format!("{} downgrade(s)", diff.downgraded.len()));
+    }
+    let explicit_removed: Vec<&str> = diff
+        .removed
+        .iter()
+        .map(|p| p.name.as_str())
+        .filter(|n| diff.reason(n) == Some(package_diff::InstallReason::Explicit))
+        .collect();
+    if !explicit_removed.is_empty() {
+        flags.push(format!("explicitly installed package(s) removed: {}", preview(&explicit_removed)));
+    }
+
+    println!();
+    if flags.is_empty() {
+        println!("{} No risk flags", "✓".green());
+    } else {
+        println!("{} Risk flags:", "⚠".yellow());
+        for flag in &flags {
+            println!("   • {}", flag);
+        }
+    }
+
+    println!();
+    println!("{}", "Drop --stat for the full list, or run `eshu-trace bisect` to find the culprit.".dimmed());
+}
+
+/// Folded desktop groups in a `diff` section
+fn print_groups(groups: &[(&str, usize)]) {
+    for (label, count) in groups {
+        println!("   {} {} {}", "▸".cyan(), label.bold(), format!("({} packages, --expand to list)", count).dimmed());
+    }
+}
+
+/// glibc, OpenSSL, systemd, PAM and D-Bus changes, most severe first
+fn print_core_library_changes(changes: &[package_diff::PackageChange]) {
+    let mut core: Vec<_> = changes
+        .iter()
+        .filter_map(|c| corelibs::classify(c.name()).map(|lib| (c.name(), lib)))
+        .collect();
+    if core.is_empty() {
+        return;
+    }
+    core.sort_by(|a, b| b.1.severity.cmp(&a.1.severity).then(a.1.component.cmp(b.1.component)));
+
+    println!();
+    println!("{} Core library changes ({}):", "🧬".yellow(), core.len());
+    let mut advised = Vec::new();
+    for (name, lib) in &core {
+        println!("   {} {}", lib.severity.tag(), name.bold());
+        if !advised.contains(&lib.component) {
+            advised.push(lib.component);
+            println!("     {} {}", "→".dimmed(), lib.advice);
+        }
+    }
+}
+
+/// Firmware/microcode packages and fwupd flashes, which need more than a downgrade to undo
+fn print_firmware_changes(changes: &[package_diff::PackageChange], flashes: &[firmware::FirmwareFlash]) {
+    let packages: Vec<_> = changes.iter().filter(|c| firmware::is_firmware_package(c.name())).collect();
+    if packages.is_empty() && flashes.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("{} Firmware and microcode changes ({}):", "🔌".yellow(), packages.len() + flashes.len());
+    let mut notes = Vec::new();
+    for change in &packages {
+        println!("   {}", change.name().bold());
+        if let Some(note) = firmware::downgrade_note(change.name()) {
+            if !notes.contains(&note) {
+                notes.push(note);
+            }
+        }
+    }
+    for flash in flashes {
+        println!("   {} {} → {} {}", flash.device.bold(), flash.old.dimmed(), flash.new, "(flashed by fwupd)".dimmed());
+    }
+    if !flashes.is_empty() {
+        notes.push("device firmware flashed by fwupd is not reverted by any package; use `fwupdmgr downgrade` if the vendor allows it");
+    }
+
+    println!("   {} Downgrading firmware may need extra steps:", "⚠".yellow());
+    for note in notes {
+        println!("     • {}", note);
+    }
+}
+
+/// Kernel cmdline and sysctl changes, with the package that shipped each
+fn print_setting_changes(changes: &[bootparams::SettingChange]) {
+    if changes.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("{} Kernel cmdline and sysctl changes ({}):", "⚙".yellow(), changes.len());
+    for change in changes {
+        let shown = |value: &Option<String>| value.clone().unwrap_or_else(|| "(unset)".to_string());
+        let owner = if change.owners.is_empty() {
+            String::new()
+        } else {
+            format!(", from {}", change.owners.join(", "))
+        };
+        println!(
+            "   {} {}: {} → {}  {}",
+            change.kind.label().dimmed(),
+            change.key.bold(),
+            shown(&change.old).dimmed(),
+            shown(&change.new).yellow(),
+            format!("({}{})", change.source, owner).dimmed()
+        );
+    }
+}
+
+fn print_broken_requires(broken: &[orphans::BrokenRequire]) {
+    if broken.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("{} Broken dependencies ({}):", "⛓".red(), broken.len());
+    for item in broken {
+        println!(
+            "   {} was removed but is still required by {}",
+            item.removed.bold(),
+            item.dependents.join(", ").red()
+        );
+    }
+}
+
+fn benchmark(command: Option<String>, max_seconds: Option<f64>) -> Option<Benchmark> {
+    match (command, max_seconds) {
+        (Some(command), Some(max_seconds)) => Some(Benchmark { command, max_seconds }),
+        _ => None,
+    }
+}
+
+fn blame_command(
+    target: Option<String>,
+    unit: Option<String>,
+    good: Option<String>,
+    bad: Option<String>,
+) -> Result<()> {
+    let files = match (&target, &unit) {
+        (_, Some(unit)) => ownership::unit_files(unit)?,
+        (Some(target), None) => vec![ownership::resolve_path(target)],
+        (None, None) => anyhow::bail!("Specify a file, command, or --unit"),
+    };
+
+    println!("{} Blame", "🔎".bold());
+    println!();
+
+    let mut owners: Vec<String> = Vec::new();
+    for file in &files {
+        let pkgs = ownership::owning_packages(file)?;
+        if pkgs.is_empty() {
+            println!("  {} {} {}", file, "→".dimmed(), "not owned by any package".yellow());
+        } else {
+            println!("  {} {} {}", file, "→".dimmed(), pkgs.join(", ").cyan());
+        }
+        for pkg in pkgs {
+            if !owners.contains(&pkg) {
+                owners.push(pkg);
+            }
+        }
+    }
+    println!();
+
+    if owners.is_empty() {
+        anyhow::bail!("Could not resolve any owning package");
+    }
+
+    let snapshot_mgr = SnapshotManager::new()?;
+    let good_snapshot = match good {
+        Some(id) => snapshot_mgr.get_snapshot(&id)?,
+        None => snapshot_mgr.select_snapshot("Select snapshot when system was WORKING:")?,
+    };
+    let bad_snapshot = match bad {
+        Some(id) => snapshot_mgr.get_snapshot(&id)?,
+        None => snapshot_mgr.select_snapshot("Select snapshot when system was BROKEN:")?,
+    };
+
+    let diff = package_diff::compute_diff(&good_snapshot, &bad_snapshot)?;
+    let suspects: Vec<_> = diff
+        .all_changes()
+        .into_iter()
+        .filter(|c| owners.iter().any(|o| o == c.name()))
+        .collect();
+
+    if suspects.is_empty() {
+        println!(
+            "{} The owning packages did not change between {} and {}",
+            "ℹ".cyan(),
+            good_snapshot.id,
+            bad_snapshot.id
+        );
+        println!("   The cause is likely a dependency; run a full bisect instead.");
+        return Ok(());
+    }
+
+    println!("{} Changed between snapshots:", "⚠".yellow());
+    for change in &suspects {
+        match change {
+            package_diff::PackageChange::Added(pkg) => println!("   {} {} {}", "+".green(), pkg.name, pkg.version),
+            package_diff::PackageChange::Removed(pkg) => println!("   {} {} {}", "-".red(), pkg.name, pkg.version),
+            package_diff::PackageChange::Upgraded(pkg, old, new)
+            | package_diff::PackageChange::Downgraded(pkg, old, new) => {
+                println!("   {} {} → {}", pkg.name, old.dimmed(), new)
+            }
+        }
+    }
+    println!();
+
+    let names: Vec<&str> = suspects.iter().map(|c| c.name()).collect();
+    println!("{}", "Bisect just these suspects:".cyan());
+    println!(
+        "  eshu-trace bisect --good {} --bad {} --suspects {}",
+        good_snapshot.id,
+        bad_snapshot.id,
+        names.join(",")
+    );
+
+    Ok(())
+}
+
+/// Returns false if the user chose to stop because packages are missing
+fn prefetch_candidates(distro: &str, candidates: &[package_diff::PackageChange]) -> Result<bool> {
+    println!("{} Prefetching candidate packages...", "📥".bold());
+
+    let report = match prefetch::prefetch(distro, candidates) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("{} Skipping prefetch: {}", "⚠".yellow(), e);
+            println!();
+            return Ok(true);
+        }
+    };
+
+    println!(
+        "{} {} cached, {} downloaded",
+        "✓".green(),
+        report.cached,
+        report.downloaded
+    );
+
+    if report.missing.is_empty() {
+        println!();
+        return Ok(true);
+    }
+
+    println!("{} Not available from the cache or archive:", "⚠".yellow());
+    for spec in &report.missing {
+        println!("   • {}", spec);
+    }
+    println!("   Steps that need these packages will fail.");
+    println!();
+
+    Ok(dialoguer::Confirm::new()
+        .with_prompt("Start the bisect anyway?")
+        .default(false)
+        .interact()?)
+}
+
+fn owns_command(path: String, snapshot_id: String) -> Result<()> {
+    let snapshot = SnapshotManager::new()?.get_snapshot(&snapshot_id)?;
+    let root = snapshot
+        .path
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("Snapshot {} has no accessible filesystem path", snapshot.id))?;
+
+    // Bare command names are looked up in the snapshot's own bin directories
+    let path = if path.starts_with('/') {
+        path
+    } else {
+        ["/usr/bin", "/usr/sbin", "/bin", "/sbin"]
+            .iter()
+            .map(|dir| format!("{}/{}", dir, path))
+            .find(|p| std::path::Path::new(&root).join(p.trim_start_matches('/')).exists())
+            .unwrap_or_else(|| format!("/usr/bin/{}", path))
+    };
+
+    println!("{} {} in snapshot {}", "🔎".bold(), path.cyan(), snapshot.id);
+    println!("  Date: {}", snapshot.date());
+    println!();
+
+    let owners = ownership::owning_packages_in(&root, &path)?;
+    if owners.is_empty() {
+        let exists = std::path::Path::new(&root).join(path.trim_start_matches('/')).exists();
+        if exists {
+            println!("{} Not owned by any package in that snapshot", "ℹ".cyan());
+        } else {
+            println!("{} The file does not exist in that snapshot", "ℹ".cyan());
+        }
+        return Ok(());
+    }
+
+    for owner in owners {
+        let version = ownership::package_version_in(&root, &owner).unwrap_or_else(|| "unknown version".to_string());
+        println!("  {} {} {}", "→".dimmed(), owner.green().bold(), version);
+    }
+
+    Ok(())
+}
+
+fn soname_command(library: String, good: Option<String>, bad: Option<String>) -> Result<()> {
+    let soname = libs::parse_soname(&library)
+        .ok_or_else(|| anyhow::anyhow!("No shared library name found in: {}", library))?;
+
+    println!("{} Shared library {}", "🔗".bold(), soname.cyan());
+    if let Some(program) = libs::failing_program(&library) {
+        println!("   Needed by: {}", program);
+    }
+    println!();
+
+    let snapshot_mgr = SnapshotManager::new()?;
+    let good_snapshot = match good {
+        Some(id) => snapshot_mgr.get_snapshot(&id)?,
+        None => snapshot_mgr.select_snapshot("Select snapshot when system was WORKING:")?,
+    };
+    let good_root = good_snapshot
+        .path
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("Snapshot {} has no accessible filesystem path", good_snapshot.id))?;
+
+    // Without --bad, compare against the running system
+    let bad_snapshot = match bad {
+        Some(id) => Some(snapshot_mgr.get_snapshot(&id)?),
+        None => None,
+    };
+    let bad_root = match &bad_snapshot {
+        Some(snap) => snap
+            .path
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Snapshot {} has no accessible filesystem path", snap.id))?,
+        None => "/".to_string(),
+    };
+    let bad_label = bad_snapshot.as_ref().map(|s| s.id.clone()).unwrap_or_else(|| "current system".to_string());
+
+    let before = libs::locate(&good_root, &soname)?;
+    let after = libs::locate(&bad_root, &soname)?;
+
+    let describe = |loc: &Option<libs::SonameLocation>| match loc {
+        Some(loc) if loc.packages.is_empty() => format!("{} (unowned)", loc.path),
+        Some(loc) => format!("{} ({})", loc.path, loc.packages.join(", ")),
+        None => "missing".to_string(),
+    };
+    println!("  {:<16} {}", format!("{}:", good_snapshot.id), describe(&before).green());
+    println!("  {:<16} {}", format!("{}:", bad_label), describe(&after).red());
+    println!();
+
+    let owners = before.map(|l| l.packages).unwrap_or_default();
+    if owners.is_empty() {
+        println!("{} {} was not provided by any package in the working snapshot", "ℹ".cyan(), soname);
+        println!("   It may come from a manual install or a package that was never tracked.");
+        return Ok(());
+    }
+
+    // Report the version change of the providing package(s)
+    let diff = match &bad_snapshot {
+        Some(snap) => package_diff::compute_diff(&good_snapshot, snap)?,
+        None => package_diff::compute_diff_to_current(&good_snapshot)?,
+    };
+    let changes: Vec<_> = diff
+        .all_changes()
+        .into_iter()
+        .filter(|c| owners.iter().any(|o| o == c.name()))
+        .collect();
+
+    for change in &changes {
+        match change {
+            package_diff::PackageChange::Removed(pkg) => {
+                println!("{} {} {} was removed", "⚠".yellow(), pkg.name.bold(), pkg.version);
+            }
+            package_diff::PackageChange::Added(pkg) => {
+                println!("{} {} {} was added", "⚠".yellow(), pkg.name.bold(), pkg.version);
+            }
+            package_diff::PackageChange::Upgraded(pkg, old, new)
+            | package_diff::PackageChange::Downgraded(pkg, old, new) => {
+                println!("{} {} changed {} → {}", "⚠".yellow(), pkg.name.bold(), old.dimmed(), new);
+            }
+        }
+    }
+    if changes.is_empty() {
+        println!("{} {} did not change; the library was removed outside the package manager", "ℹ".cyan(), owners.join(", "));
+    }
+
+    if after.is_none() {
+        let replacements = libs::sibling_sonames(&bad_root, &soname);
+        println!();
+        if replacements.is_empty() {
+            println!("{} No other version of {} is installed", "✗".red(), libs::soname_family(&soname));
+            println!("   Reinstall or downgrade {} to restore it.", owners.join(", "));
+        } else {
+            println!(
+                "{} The library moved to {} (soname bump)",
+                "ℹ".cyan(),
+                replacements.join(", ").cyan()
+            );
+            println!("   Programs linked against {} must be rebuilt against the new soname.", soname);
+
+            if let Some(program) = libs::failing_program(&library) {
+                let path = ownership::resolve_path(&program);
+                let needs_rebuild = ownership::owning_packages(&path).unwrap_or_default();
+                if !needs_rebuild.is_empty() {
+                    println!("   Rebuild or update: {}", needs_rebuild.join(", ").bold());
+                }
+            }
+            println!("   Until then, downgrading {} restores the old soname.", owners.join(", "));
+        }
+    }
+
+    Ok(())
+}
+
+fn monitor_command(action: MonitorAction) -> Result<()> {
+    match action {
+        MonitorAction::Install => {
+            for path in monitor::install()? {
+                println!("{} Installed {}", "✓".green(), path.display());
+            }
+            let recorded = monitor::record(true)?;
+            println!();
+            println!("{} Monitoring enabled: a manifest is recorded after every package transaction", "👁".bold());
+            if let Some(manifest) = recorded {
+                println!("   First manifest: {} ({} packages)", manifest.id.cyan(), manifest.packages.len());
+            }
+        }
+        MonitorAction::Uninstall => {
+            monitor::uninstall()?;
+            println!("{} Monitoring disabled; manifests in {} are kept", "✓".green(), monitor::manifests_dir().display());
+        }
+        MonitorAction::Record { quiet, no_check } => {
+            let recorded = monitor::record(!no_check)?;
+            if !quiet {
+                match recorded {
+                    Some(manifest) => println!("{} Recorded {}", "✓".green(), manifest.id),
+                    None => println!("{} Packages unchanged; health entry added", "✓".green()),
+                }
+            }
+        }
+        MonitorAction::Check { command, preset, test_user, remove } => {
+            if remove {
+                monitor::set_health_check(None)?;
+                println!("{} Health check removed", "✓".green());
+                return Ok(());
+            }
+
+            let check = match command.or_else(|| preset.map(|p| p.command())) {
+                Some(command) => {
+                    let check = monitor::HealthCheck {
+                        command,
+                        test_user,
+                        added_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                    };
+                    monitor::set_health_check(Some(check.clone()))?;
+                    println!("{} Health check registered", "✓".green());
+                    check
+                }
+                None => monitor::health_check()
+                    .ok_or_else(|| anyhow::anyhow!("No health check registered; pass a command or --preset"))?,
+            };
+
+            println!("   {} {}", "Command:".cyan(), check.command);
+            if !monitor::is_installed() {
+                println!("   {} Runs automatically once `sudo eshu-trace monitor install` has set up the timer", "ℹ".cyan());
+            }
+            let passed = check.runner().run_test()?;
+            println!(
+                "   {} {}",
+                "Now:".cyan(),
+                if passed { "passing".green() } else { "failing".red() }
+            );
+        }
+        MonitorAction::Status { limit } => {
+            let state = if monitor::is_installed() { "active".green() } else { "not installed".yellow() };
+            println!("{} Monitor: {}", "👁".bold(), state);
+            println!();
+
+            let manifests = monitor::manifests()?;
+            println!("{} ({} recorded):", "Manifests".cyan(), manifests.len());
+            let start = manifests.len().saturating_sub(limit);
+            for (i, manifest) in manifests.iter().enumerate().skip(start) {
+                let change = match i.checked_sub(1).map(|prev| &manifests[prev]) {
+                    Some(prev) => {
+                        let diff = package_diff::compute_diff(&prev.to_snapshot(), &manifest.to_snapshot())?;
+                        format!(
+                            "+{} -{} ↑{} ↓{}",
+                            diff.added.len(),
+                            diff.removed.len(),
+                            diff.upgraded.len(),
+                            diff.downgraded.len()
+                        )
+                    }
+                    None => format!("{} packages", manifest.packages.len()),
+                };
+                println!("   {}  {}  {}", snapshot::format_time(&manifest.created_at).dimmed(), manifest.id, change);
+            }
+            if manifests.is_empty() {
+                println!("   none yet; run `eshu-trace monitor install`");
+            }
+
+            let health = monitor::health_log();
+            println!();
+            println!("{} (last {} of {}):", "Health".cyan(), health.len().min(limit), health.len());
+            for entry in health.iter().skip(health.len().saturating_sub(limit)) {
+                let units = if entry.failed_units.is_empty() {
+                    "no failed units".green()
+                } else {
+                    format!("failed: {}", entry.failed_units.join(", ")).red()
+                };
+                let check = match entry.check_passed {
+                    Some(true) => format!("  check {}", "passed".green()),
+                    Some(false) => format!("  check {}", "FAILED".red().bold()),
+                    None => String::new(),
+                };
+                println!(
+                    "   {}  {}  {}  {} errors this boot{}",
+                    entry.at.dimmed(),
+                    entry.kernel,
+                    units,
+                    entry.boot_errors,
+                    check
+                );
+            }
+
+            if let Some(regression) = monitor::regression() {
+                offer_regression_bisect(&regression)?;
+                return Ok(());
+            }
+
+            if manifests.len() >= 2 {
+                println!();
+                println!("{}", "Compare or bisect any two manifests:".dimmed());
+                println!(
+                    "   eshu-trace diff {} {}",
+                    manifests[manifests.len() - 2].id,
+                    manifests[manifests.len() - 1].id
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The health check broke: propose the last passing manifest as good and the current one as bad
+fn offer_regression_bisect(regression: &monitor::Regression) -> Result<()> {
+    println!();
+    println!("{} The health check fails now but passed at {}", "⚠".yellow().bold(), regression.last_pass);
+
+    if regression.good == regression.bad {
+        println!("   No packages changed since then; the cause is not a package update.");
+        return Ok(());
+    }
+
+    println!("   {} {} (last passing)", "Good:".green(), regression.good);
+    println!("   {} {} (current)", "Bad:".red(), regression.bad);
+    println!();
+    if !dialoguer::Confirm::new()
+        .with_prompt("Start the bisect between them?")
+        .default(true)
+        .interact()?
+    {
+        println!("   Later: eshu-trace bisect --good {} --bad {}", regression.good, regression.bad);
+        return Ok(());
+    }
+
+    let check = monitor::health_check();
+    let runner = check.map(|c| c.runner()).unwrap_or_else(|| TestRunner::new(None));
+    let _lock = lock::SessionLock::acquire(false)?;
+    let mut gate = premium::gate()?;
+    bisect_command(
+        Some(regression.good.clone()),
+        Some(regression.bad.clone()),
+        false,
+        None,
+        false,
+        Vec::new(),
+        Some(""),
+        DriverKind::Chroot,
+        runner,
+        false,
+        true,
+        false,
+        gate.as_mut(),
+    )
+}
+
+fn recent_command(since: &str, explicit_only: bool, expand: bool) -> Result<()> {
+    let cutoff = timeline::parse_since(since)
+        .ok_or_else(|| anyhow::anyhow!("Invalid age or date: {} (try 2d, 12h or 2024-05-01)", since))?;
+
+    // Snapshots and monitor manifests both count; either may be missing
+    let mut snapshots = SnapshotManager::new()
+        .and_then(|mgr| mgr.list_snapshots())
+        .unwrap_or_default();
+    for manifest in monitor::manifests()? {
+        // With --backend manifest the listing already holds them
+        if !snapshots.iter().any(|s| s.id == manifest.id) {
+            snapshots.push(manifest.to_snapshot());
+        }
+    }
+    let baseline = snapshots
+        .iter()
+        .filter_map(|s| s.created_at.map(|time| (time.naive_local(), s)))
+        .filter(|(time, _)| *time <= cutoff)
+        .max_by_key(|(time, _)| *time)
+        .map(|(_, s)| s);
+    let Some(baseline) = baseline else {
+        anyhow::bail!(
+            "No snapshot or manifest older than {} ({}); `eshu-trace monitor install` records one after every upgrade",
+            since,
+            cutoff.format("%Y-%m-%d %H:%M")
+        );
+    };
+
+    println!("{} Changes since {}", "📊".bold(), since);
+    println!();
+    println!("{} {} ({})", "Snapshot:".cyan(), baseline.id, baseline.date());
+    println!("{} running system", "Compared with:".cyan());
+    println!();
+
+    let diff = package_diff::compute_diff_to_current(baseline)?;
+    if diff.total_changes() == 0 {
+        println!("{} No package changes since this snapshot", "✓".green());
+    } else {
+        print_package_changes(&diff, explicit_only, expand);
+        print_core_library_changes(&diff.all_changes());
+        print_firmware_changes(&diff.all_changes(), &[]);
+    }
+
+    if let Some(root) = &baseline.path {
+        print_setting_changes(&bootparams::detect(root, "/"));
+    }
+
+    println!();
+    println!("{}", "Find the breaking package:".cyan());
+    println!("  eshu-trace bisect --good {}", baseline.id);
+
+    Ok(())
+}
+
+/// `oracle`: look up every pending upgrade in the community database
+fn oracle_command(list: Option<&str>) -> Result<()> {
+    let gate = premium::gate()?;
+    if !gate.is_enabled(Feature::Oracle) {
+        println!("{}", "The upgrade oracle is part of Eshu Premium".yellow());
+        println!("  💎 {}", premium::get_eshu_premium_url());
+        return Ok(());
+    }
+    let base = config::community_url()?.ok_or_else(|| {
+        anyhow::anyhow!("No community database configured (community_url in config.json or ESHU_TRACE_COMMUNITY_URL)")
+    })?;
+
+    let pending = match list {
+        Some(path) => upgrades::from_file(path)?,
+        None => upgrades::pending(&fixer::detect_distro_at("/")?)?,
+    };
+    if pending.is_empty() {
+        println!("{} No pending upgrades", "✓".green());
+        return Ok(());
+    }
+
+    println!("{} Checking {} pending upgrades against the community database...", "🔮".bold(), pending.len());
+    let issues = community::known_issues(&base, &pending)?;
     println!();
 
-    for snapshot in snapshots {
-        println!("{} {}", "ID:".cyan(), snapshot.id);
-        println!("   Date: {}", snapshot.created_at);
-
-        if verbose {
-            println!("   Packages: {}", snapshot.package_count.unwrap_or(0));
-
-            if let Some(desc) = snapshot.description {
-                println!("   Description: {}", desc);
+    let mut flagged = 0;
+    for upgrade in &pending {
+        let open: Vec<_> = issues
+            .iter()
+            .filter(|i| i.package == upgrade.name && i.version == upgrade.to && i.is_open())
+            .collect();
+        if open.is_empty() {
+            continue;
+        }
+        flagged += 1;
+
+        let from = upgrade.from.as_deref().unwrap_or("?");
+        println!("{} {} {} → {}", "⚠".yellow(), upgrade.name.bold(), from, upgrade.to.red());
+        for issue in open {
+            let mut details = vec![issue.status.clone()];
+            if issue.confirmations > 0 {
+                details.push(format!("{} confirmations", issue.confirmations));
+            }
+            if !issue.symptom_tags.is_empty() {
+                details.push(issue.symptom_tags.join(", "));
+            }
+            println!("   {} ({})", issue.title, details.join(" · ").dimmed());
+            if let Some(fixed) = &issue.fixed_in {
+                println!("   {} fixed in {}", "→".dimmed(), fixed.green());
+            }
+            if let Some(url) = &issue.url {
+                println!("   {}", url.cyan());
             }
         }
-
+        if let Some(from) = &upgrade.from {
+            println!("   Hold it back: {}", format!("eshu-trace pin add {} {}", upgrade.name, from).white());
+        }
         println!();
     }
 
+    if flagged == 0 {
+        println!("{} None of the {} pending upgrades have open regression reports", "✓".green(), pending.len());
+    } else {
+        println!(
+            "{} of {} pending upgrades have open regression reports; take a snapshot before upgrading",
+            flagged.to_string().yellow().bold(),
+            pending.len()
+        );
+    }
     Ok(())
 }
 
-fn diff_command(snapshot1: String, snapshot2: String) -> Result<()> {
-    let snapshot_mgr = SnapshotManager::new()?;
-
-    let snap1 = snapshot_mgr.get_snapshot(&snapshot1)?;
-    let snap2 = snapshot_mgr.get_snapshot(&snapshot2)?;
-
-    println!("{} Package Differences", "📊".bold());
-    println!();
-    println!("{} {}", "Snapshot 1:".cyan(), snap1.id);
-    println!("{} {}", "Snapshot 2:".cyan(), snap2.id);
-    println!();
+fn timeline_command(since: Option<String>, verbose: bool) -> Result<()> {
+    let since = match since {
+        Some(s) => Some(timeline::parse_since(&s).ok_or_else(|| anyhow::anyhow!("Invalid date: {}", s))?),
+        None => None,
+    };
 
-    let diff = package_diff::compute_diff(&snap1, &snap2)?;
+    let events = timeline::collect(since)?;
 
-    if !diff.added.is_empty() {
-        println!("{} Added packages ({}):", "➕".green(), diff.added.len());
-        for pkg in &diff.added {
-            println!("   {} {}", "+".green(), pkg);
-        }
-        println!();
+    if events.is_empty() {
+        println!("{}", "No snapshots or package transactions found".yellow());
+        return Ok(());
     }
 
-    if !diff.removed.is_empty() {
-        println!("{} Removed packages ({}):", "➖".red(), diff.removed.len());
-        for pkg in &diff.removed {
-            println!("   {} {}", "-".red(), pkg);
-        }
-        println!();
-    }
+    println!("{} System Timeline", "🕒".bold());
+    println!();
 
-    if !diff.upgraded.is_empty() {
-        println!("{} Upgraded packages ({}):", "⬆️".yellow(), diff.upgraded.len());
-        for (pkg, old_ver, new_ver) in &diff.upgraded {
-            println!("   {} {} → {}", pkg, old_ver.dimmed(), new_ver);
-        }
-        println!();
-    }
+    for event in &events {
+        let time = event.time.format("%Y-%m-%d %H:%M").to_string();
 
-    if !diff.downgraded.is_empty() {
-        println!("{} Downgraded packages ({}):", "⬇️".yellow(), diff.downgraded.len());
-        for (pkg, old_ver, new_ver) in &diff.downgraded {
-            println!("   {} {} → {}", pkg, old_ver.dimmed(), new_ver);
+        match &event.kind {
+            timeline::EventKind::Snapshot { backend, id, description } => {
+                print!("{}  {} {} snapshot {}", time.dimmed(), "📸".bold(), backend, id.cyan());
+                if let Some(desc) = description {
+                    print!(" - {}", desc.dimmed());
+                }
+                println!();
+            }
+            timeline::EventKind::Transaction(items) => {
+                let marker = if event.is_kernel_change() {
+                    format!(" {}", "[kernel change]".magenta().bold())
+                } else {
+                    String::new()
+                };
+                println!(
+                    "{}  {} {} package change(s){}",
+                    time.dimmed(),
+                    "📦".bold(),
+                    items.len(),
+                    marker
+                );
+
+                let shown = if verbose { items.len() } else { 5 };
+                for item in items.iter().take(shown) {
+                    println!("                     {} {} ({})", item.action.dimmed(), item.package, item.detail.dimmed());
+                }
+                if items.len() > shown {
+                    println!("                     ... and {} more (use --verbose)", items.len() - shown);
+                }
+            }
+            timeline::EventKind::Reboot => {
+                println!("{}  {} {}", time.dimmed(), "🔄".bold(), "reboot".yellow());
+            }
         }
-        println!();
     }
 
-    println!("Total changes: {}", diff.total_changes());
-
     Ok(())
 }
 
-fn test_command(command: Option<String>) -> Result<()> {
+fn test_command(
+    command: Option<String>,
+    probes: Vec<Probe>,
+    bench: Option<Benchmark>,
+    test_user: Option<String>,
+    sandbox: Option<Sandbox>,
+) -> Result<()> {
     println!("{}", "🧪 Testing for Issue".cyan().bold());
     println!();
 
+    let has_checks = !probes.is_empty() || bench.is_some();
+
     let test_cmd = if let Some(cmd) = command {
         cmd
+    } else if has_checks {
+        String::new()
     } else {
         dialoguer::Input::<String>::new()
             .with_prompt("Enter test command (or press Enter for interactive test)")
@@ -383,28 +2544,34 @@ fn test_command(command: Option<String>) -> Result<()> {
             .interact()?
     };
 
-    if test_cmd.is_empty() {
+    if test_cmd.is_empty() && !has_checks {
         println!("Run your test manually, then answer:");
         println!();
     } else {
-        println!("Running: {}", test_cmd.cyan());
+        if !test_cmd.is_empty() {
+            println!("Running: {}", test_cmd.cyan());
+            if let Some(ref user) = test_user {
+                println!("As user: {}", user.yellow());
+            }
+            if let Some(sandbox) = sandbox {
+                println!("Sandbox: {:?}", sandbox.kind);
+            }
+        }
         println!();
 
-        let result = std::process::Command::new("sh")
-            .arg("-c")
-            .arg(&test_cmd)
-            .status()?;
+        let runner = TestRunner::new(Some(test_cmd))
+            .with_user(test_user)
+            .with_sandbox(sandbox)
+            .with_probes(probes)
+            .with_benchmark(bench);
+        let passed = runner.run_test()?;
 
         println!();
 
-        if result.success() {
-            println!("{} Test passed (exit code 0)", "✓".green());
+        if passed {
+            println!("{} Test passed", "✓".green());
         } else {
-            println!(
-                "{} Test failed (exit code {})",
-                "✗".red(),
-                result.code().unwrap_or(-1)
-            );
+            println!("{} Test failed", "✗".red());
         }
 
         return Ok(());
@@ -448,6 +2615,9 @@ fn show_premium_info() -> Result<()> {
         premium::LicenseType::Premium => {
             println!("{}", "Current Status: Eshu Premium ✓".green());
             println!("Traces used: {} (unlimited via Eshu Premium)", license.traces_used);
+            if let Some(days) = license.days_remaining() {
+                println!("Renews or expires in {} day(s)", days);
+            }
             println!();
             return Ok(());
         }
@@ -506,38 +2676,101 @@ fn show_premium_info() -> Result<()> {
     Ok(())
 }
 
-fn activate_command(key: Option<String>, email: Option<String>) -> Result<()> {
-    println!("{}", "🔑 Activate Eshu Trace License".cyan().bold());
-    println!();
+/// `activate --quiet` exit status while the activation waits for the license server
+const EXIT_QUEUED: i32 = 2;
+
+fn activate_command(key: Option<String>, key_file: Option<String>, email: Option<String>, quiet: bool) -> Result<()> {
+    // Provisioning scripts can't answer prompts: fall back to the environment, and fail
+    // rather than wait for input that will never come
+    let interactive = !quiet && io::stdin().is_terminal();
+    let env = |name: &str| std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+
+    if !quiet {
+        println!("{}", "🔑 Activate Eshu Trace License".cyan().bold());
+        println!();
+    }
+
+    let license_key = match (key, key_file) {
+        (Some(k), _) => k,
+        (None, Some(file)) if file == "-" => {
+            let mut text = String::new();
+            io::Read::read_to_string(&mut io::stdin(), &mut text).context("Failed to read the license key from stdin")?;
+            text.trim().to_string()
+        }
+        (None, Some(file)) => std::fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read {}", file))?
+            .trim()
+            .to_string(),
+        (None, None) => match env("ESHU_TRACE_LICENSE_KEY") {
+            Some(k) => k,
+            None if interactive => dialoguer::Input::<String>::new()
+                .with_prompt("Enter your Gumroad license key")
+                .interact()?,
+            None => anyhow::bail!("No license key: pass --key or --key-file, or set ESHU_TRACE_LICENSE_KEY"),
+        },
+    };
+    if license_key.is_empty() {
+        anyhow::bail!("The license key is empty");
+    }
 
-    let license_key = if let Some(k) = key {
-        k
+    // Offline keys may also be passed as a path to the key file
+    let license_key = if std::path::Path::new(&license_key).is_file() {
+        std::fs::read_to_string(&license_key)?.trim().to_string()
     } else {
-        dialoguer::Input::<String>::new()
-            .with_prompt("Enter your Gumroad license key")
-            .interact()?
+        license_key
     };
 
-    let email_addr = if let Some(e) = email {
+    // Offline keys carry the email in their signed payload
+    let email_addr = if let Some(e) = email.or_else(|| env("ESHU_TRACE_EMAIL")) {
         e
-    } else {
+    } else if premium::is_offline_key(&license_key) {
+        String::new()
+    } else if interactive {
         dialoguer::Input::<String>::new()
             .with_prompt("Enter your email address")
             .interact()?
+    } else {
+        anyhow::bail!("No email address: pass --email or set ESHU_TRACE_EMAIL");
     };
 
+    if quiet {
+        return match premium::activate_license(&license_key, &email_addr)? {
+            premium::Activation::Activated(message) => {
+                println!("activated: {}", message);
+                Ok(())
+            }
+            // Not rejected either: the license switches on by itself once the server answers
+            premium::Activation::Queued(message) => {
+                println!("queued: {}", message);
+                process::exit(EXIT_QUEUED);
+            }
+            premium::Activation::Rejected(message) => anyhow::bail!("License rejected: {}", message),
+        };
+    }
+
     println!();
-    println!("{}", "Validating license...".dimmed());
+    if premium::is_offline_key(&license_key) {
+        println!("{}", "Verifying offline license...".dimmed());
+    } else {
+        println!("{}", "Validating license...".dimmed());
+    }
 
     match premium::activate_license(&license_key, &email_addr) {
-        Ok((true, message)) => {
+        Ok(premium::Activation::Activated(message)) => {
             println!();
             println!("{} {}", "✓".green().bold(), message);
             println!();
             println!("{}", "Thank you for supporting Eshu Trace!".green());
             println!("You now have unlimited traces.");
         }
-        Ok((false, message)) => {
+        Ok(premium::Activation::Queued(message)) => {
+            println!();
+            println!("{} {}", "⏳".yellow(), message);
+            println!();
+            println!("Keep using eshu-trace as usual; the license switches on once the server answers.");
+            println!("Check with: {}", "eshu-trace status".white());
+        }
+        Ok(premium::Activation::Rejected(message)) => {
             println!();
             println!("{} {}", "✗".red().bold(), message);
             println!();
@@ -558,6 +2791,467 @@ fn activate_command(key: Option<String>, email: Option<String>) -> Result<()> {
     Ok(())
 }
 
+fn doctor_command() -> Result<()> {
+    println!("{} Eshu-Trace Doctor", "🩺".bold());
+    println!();
+
+    let checks = doctor::run_checks();
+    for check in &checks {
+        let mark = match check.status {
+            doctor::CheckStatus::Ok => "✓".green(),
+            doctor::CheckStatus::Warning => "⚠".yellow(),
+            doctor::CheckStatus::Failed => "✗".red(),
+        };
+        println!("{} {:<20} {}", mark, check.name, check.detail.dimmed());
+        if let Some(fix) = &check.fix {
+            println!("  {} {}", "→".dimmed(), fix);
+        }
+    }
+
+    let failed = checks.iter().filter(|c| c.status == doctor::CheckStatus::Failed).count();
+    println!();
+    if failed == 0 {
+        println!("{} Ready to trace", "✓".green().bold());
+        Ok(())
+    } else {
+        anyhow::bail!("{} check(s) failed", failed)
+    }
+}
+
+fn pin_command(action: PinAction) -> Result<()> {
+    match action {
+        PinAction::Add { package, version, method, bad, test, snapshot } => {
+            let distro = fixer::detect_distro_at("/")?;
+            let method = match method.or_else(|| pins::PinMethod::default_for(&distro)) {
+                Some(m) if m.supported_on(&distro) => m,
+                Some(m) => anyhow::bail!("{} is not available on {}", m.description(), distro),
+                None => return Err(error::TraceError::UnsupportedDistro(distro).into()),
+            };
+            let test = test.zip(snapshot).map(|(command, snapshot)| pins::RecordedTest { command, snapshot });
+            let pin = pins::apply("/", &package, &version, bad.as_deref(), method, test)?;
+            println!("{} Pinned {} at {} ({})", "📌".bold(), pin.package, pin.version, pin.method.description());
+        }
+        PinAction::List => {
+            let registry = pins::PinRegistry::load()?;
+            if registry.pins.is_empty() {
+                println!("No pins recorded");
+                return Ok(());
+            }
+            for pin in &registry.pins {
+                let bad = pin
+                    .bad_version
+                    .as_ref()
+                    .map(|b| format!(" (broken: {})", b))
+                    .unwrap_or_default();
+                println!("  {} {}{}", pin.package.cyan(), pin.version, bad.dimmed());
+                println!("      {} since {}", pin.method.description().dimmed(), pin.created_at.dimmed());
+                if let Some(fixed) = &pin.fixed_version {
+                    println!("      {} {} is available", "fix:".green(), fixed);
+                }
+                if let Some(retest) = &pin.retest {
+                    let verdict = if retest.passed { "test passed".green() } else { "still broken".red() };
+                    println!("      {} {}: {}", "retest:".cyan(), retest.version, verdict);
+                }
+            }
+        }
+        PinAction::Remove { package } => match pins::remove(&package)? {
+            Some(pin) => println!("{} Lifted pin on {} ({})", "✓".green(), pin.package, pin.method.description()),
+            None => anyhow::bail!("No pin recorded for {}", package),
+        },
+        PinAction::Check { quiet, retest } => {
+            let fixed = pins::check(false)?;
+            if fixed.is_empty() {
+                if !quiet {
+                    println!("{} No release newer than a broken version yet", "✓".green());
+                }
+                return Ok(());
+            }
+            print_fixed_pins(&fixed);
+            if retest {
+                retest_pins(fixed)?;
+            }
+        }
+        PinAction::Retest { package } => {
+            let registry = pins::PinRegistry::load()?;
+            let selected: Vec<pins::Pin> = match package {
+                Some(name) => vec![registry
+                    .find(&name)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("No pin recorded for {}", name))?],
+                None => registry.pins.into_iter().filter(|p| p.fixed_version.is_some()).collect(),
+            };
+            if selected.is_empty() {
+                println!("No pinned package has a new release to test. Look for one with: eshu-trace pin check");
+                return Ok(());
+            }
+            retest_pins(selected)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply each pin's new release on top of its known-good snapshot and run the recorded test
+fn retest_pins(selected: Vec<pins::Pin>) -> Result<()> {
+    let gate = premium::gate()?;
+    if !gate.is_enabled(Feature::AutomatedBisect) {
+        anyhow::bail!("Re-testing new releases is a Premium feature (it uses the automated bisect driver)");
+    }
+
+    let snapshot_mgr = SnapshotManager::new()?;
+    for pin in selected {
+        println!();
+        let (fixed, test) = match (&pin.fixed_version, &pin.test) {
+            (Some(fixed), Some(test)) => (fixed.clone(), test.clone()),
+            (None, _) => {
+                println!("{} {}: no newer release known (run eshu-trace pin check)", "⚠".yellow(), pin.package);
+                continue;
+            }
+            (_, None) => {
+                println!("{} {}: no test recorded with this pin", "⚠".yellow(), pin.package);
+                println!("   Record one with: eshu-trace pin add {} {} --test <command> -s <snapshot>", pin.package, pin.version);
+                continue;
+            }
+        };
+
+        println!("{} Re-testing {} {} on snapshot {}", "🧪".bold(), pin.package.cyan(), fixed, test.snapshot);
+        let snapshot = snapshot_mgr.get_snapshot(&test.snapshot)?;
+        let change = package_diff::PackageChange::Upgraded(
+            package_diff::Package { name: pin.package.clone(), version: fixed.clone() },
+            pin.version.clone(),
+            fixed.clone(),
+        );
+
+        if let Some(distro) = snapshot.path.as_deref().and_then(|root| fixer::detect_distro_at(root).ok()) {
+            if !prefetch_candidates(&distro, std::slice::from_ref(&change))? {
+                continue;
+            }
+        }
+
+        let mut test_driver = driver::create(DriverKind::Chroot, &snapshot)?;
+        let runner = TestRunner::new(Some(test.command));
+        let passed = test_driver.test(std::slice::from_ref(&change), &runner)?;
+        pins::record_retest(&pin.package, &fixed, passed)?;
+
+        if passed {
+            println!("{} The regression appears fixed in {} {}", "✓".green(), pin.package, fixed);
+            println!("   Lift the pin on the real system: eshu-trace pin remove {}", pin.package);
+        } else {
+            println!("{} {} {} still fails the test; keep the pin", "✗".red(), pin.package, fixed);
+        }
+    }
+
+    Ok(())
+}
+
+fn export_command(
+    oci_output: Option<std::path::PathBuf>,
+    manifest_output: Option<std::path::PathBuf>,
+    sign: bool,
+    snapshot_id: Option<String>,
+) -> Result<()> {
+    let snapshot_mgr = SnapshotManager::new()?;
+    let snapshot = match snapshot_id {
+        Some(id) => snapshot_mgr.get_snapshot(&id)?,
+        None => snapshot_mgr.select_snapshot("Select the snapshot to export (the WORKING state):")?,
+    };
+
+    if let Some(output) = manifest_output {
+        let packages = package_diff::get_packages_for_snapshot(&snapshot)?;
+        let document = serde_json::json!({
+            "id": snapshot.id,
+            "created_at": snapshot.created_at,
+            "description": snapshot.description,
+            "packages": packages,
+        });
+        let (text, integrity) = integrity::seal_json(document, sign)?;
+        std::fs::write(&output, text + "\n").with_context(|| format!("Failed to write {}", output.display()))?;
+
+        println!("{} Wrote the manifest of {} ({} packages) to {}", "✓".green(), snapshot.id.cyan(), packages.len(), output.display());
+        print_integrity(&integrity, &output);
+    }
+
+    if let Some(output) = oci_output {
+        println!("{} Exporting snapshot {} as an OCI image...", "📦".bold(), snapshot.id.cyan());
+        println!("{}", "   Home directories, caches and host secrets are left out".dimmed());
+
+        let name = oci::export(&snapshot, &output)?;
+        let size = std::fs::metadata(&output).map(|m| m.len()).unwrap_or(0);
+
+        println!("{} Wrote {} ({})", "✓".green(), output.display(), snapshot::human_size(size));
+        println!("   Load it with: podman load -i {}", output.display());
+        println!("   Then run:     podman run -it {}", name);
+    }
+    Ok(())
+}
+
+fn fix_command(export_ansible: Option<std::path::PathBuf>, export_script: Option<std::path::PathBuf>, sign: bool) -> Result<()> {
+    let last = match remediation::LastFix::load()? {
+        Some(l) => l,
+        None => {
+            println!("No fix applied yet. Run a bisect and choose a fix first.");
+            return Ok(());
+        }
+    };
+
+    println!("{} {} ({}, {})", "🔧 Last fix:".bold(), last.remedy.describe(), last.distro, last.applied_at.dimmed());
+
+    if let Some(path) = export_script {
+        let integrity = remediation::save(&path, &last.distro, &last.remedy, sign)?;
+        println!("{} Script written to {}", "✓".green(), path.display());
+        print_integrity(&integrity, &path);
+    }
+    if let Some(path) = export_ansible {
+        let (text, integrity) = integrity::seal_text(&remediation::playbook(&last.distro, &last.remedy)?, sign)?;
+        std::fs::write(&path, text)?;
+        println!("{} Playbook written to {}", "✓".green(), path.display());
+        println!("   Apply with: ansible-playbook -i <inventory> {}", path.display());
+        print_integrity(&integrity, &path);
+    }
+
+    Ok(())
+}
+
+/// How the receiving side checks an export
+fn print_integrity(integrity: &integrity::Integrity, path: &std::path::Path) {
+    println!("   sha256 {}", integrity.sha256.dimmed());
+    match &integrity.public_key {
+        Some(key) => {
+            println!("   Signed by {}", key.cyan());
+            println!("   Check with: eshu-trace verify {} --key {}", path.display(), key);
+        }
+        None => println!("   Check with: eshu-trace verify {}", path.display()),
+    }
+}
+
+fn verify_command(file: std::path::PathBuf, key: Option<String>) -> Result<()> {
+    let text = std::fs::read_to_string(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+
+    let sealed = if text.trim_start().starts_with('{') {
+        integrity::split_json(&text)?
+    } else {
+        integrity::split_text(&text)?.map(|(content, integrity)| (content.as_bytes().to_vec(), integrity))
+    };
+    let Some((content, integrity)) = sealed else {
+        anyhow::bail!("{} carries no integrity information (exported before it was added, or by hand)", file.display());
+    };
+
+    let verified = integrity::check(&content, &integrity)
+        .map_err(|e| anyhow::anyhow!("{} failed verification ({}); do not act on it", file.display(), e))?;
+    println!("{} Content intact (sha256 {})", "✓".green(), verified.sha256.dimmed());
+
+    match (&verified.signer, &key) {
+        (Some(signer), Some(expected)) if signer == expected.trim() => {
+            println!("{} Signed by the expected key {}", "✓".green(), signer.cyan());
+        }
+        (Some(signer), Some(_)) => {
+            anyhow::bail!("{} is signed by {}, not by the expected key", file.display(), signer);
+        }
+        (Some(signer), None) => {
+            println!("{} Signed by {}", "✓".green(), signer.cyan());
+            println!("   Compare this key with the one the sender gave you, or pass it with --key");
+        }
+        (None, Some(_)) => anyhow::bail!("{} is not signed", file.display()),
+        (None, None) => println!("{} Not signed: intact, but anyone could have made it", "⚠".yellow()),
+    }
+    Ok(())
+}
+
+#[cfg(feature = "containers")]
+fn crosscheck_command(package: String, version: String, test: String, images: Vec<String>) -> Result<()> {
+    let gate = premium::gate()?;
+    if !gate.is_enabled(Feature::AutomatedBisect) {
+        anyhow::bail!("Cross-distro verification is a Premium feature");
+    }
+
+    let images = if images.is_empty() {
+        crossdistro::DEFAULT_IMAGES.iter().map(|i| i.to_string()).collect()
+    } else {
+        images
+    };
+
+    println!(
+        "{} Testing {} {} (upstream {}) in {} container(s)...",
+        "🐳".bold(),
+        package.cyan(),
+        version,
+        crossdistro::upstream_version(&version),
+        images.len()
+    );
+    println!();
+
+    let results = crossdistro::check(&images, &package, &version, &test)?;
+    for result in &results {
+        let installed = match &result.installed {
+            Some(v) if result.same_upstream => v.clone(),
+            Some(v) => format!("{} (different upstream release)", v),
+            None => "-".to_string(),
+        };
+        let outcome = match &result.outcome {
+            crossdistro::Outcome::Reproduced => "reproduced".red().to_string(),
+            crossdistro::Outcome::NotReproduced => "not reproduced".green().to_string(),
+            crossdistro::Outcome::Unavailable(why) => format!("{} ({})", "skipped".yellow(), why),
+        };
+        println!("  {} {}", result.image.cyan(), outcome);
+        println!("      {}", installed.dimmed());
+    }
+
+    // Only runs on the same upstream release say anything about upstream
+    let comparable: Vec<&crossdistro::CheckResult> = results
+        .iter()
+        .filter(|r| r.same_upstream && !matches!(r.outcome, crossdistro::Outcome::Unavailable(_)))
+        .collect();
+    let reproduced = comparable
+        .iter()
+        .filter(|r| matches!(r.outcome, crossdistro::Outcome::Reproduced))
+        .count();
+
+    println!();
+    if comparable.is_empty() {
+        println!("{} No other distro ships upstream {} to compare against", "ℹ".cyan(), crossdistro::upstream_version(&version));
+    } else if reproduced == comparable.len() {
+        println!("{} Reproduced on every distro with the same release: likely an upstream bug", "→".bold());
+        println!("   Report it to the upstream project and mention the distros above.");
+    } else if reproduced == 0 {
+        println!("{} Not reproduced elsewhere: likely specific to your distro's packaging", "→".bold());
+        println!("   Report it to your distro's bug tracker.");
+    } else {
+        println!(
+            "{} Reproduced on {} of {} distros with the same release: check the differing build options",
+            "→".bold(),
+            reproduced,
+            comparable.len()
+        );
+    }
+
+    Ok(())
+}
+
+fn print_fixed_pins(fixed: &[pins::Pin]) {
+    for pin in fixed {
+        println!(
+            "{} {} {} is available (broken: {})",
+            "🔔".bold(),
+            pin.package.cyan(),
+            pin.fixed_version.as_deref().unwrap_or_default(),
+            pin.bad_version.as_deref().unwrap_or_default()
+        );
+        println!("   Lift the pin and retest: eshu-trace pin remove {}", pin.package);
+    }
+}
+
+fn cache_command(action: CacheAction) -> Result<()> {
+    let mut index = cache::CacheIndex::load()?;
+
+    match action {
+        CacheAction::Keep { packages } => {
+            for package in packages {
+                if !index.kept.contains(&package) {
+                    index.kept.push(package);
+                }
+            }
+            index.save()?;
+            let added = cache::sync(&index)?;
+            println!("{} Preserving: {}", "✓".green(), index.kept.join(", "));
+            println!("   {} file(s) copied from the distro cache", added);
+        }
+        CacheAction::Forget { packages } => {
+            index.kept.retain(|p| !packages.contains(p));
+            index.save()?;
+            println!("{} No longer preserving: {}", "✓".green(), packages.join(", "));
+        }
+        CacheAction::List => {
+            let mut stored = cache::list();
+            stored.sort_by(|a, b| a.name.cmp(&b.name));
+            let total: u64 = stored.iter().map(|p| p.size).sum();
+
+            println!("{} Package cache ({})", "📦".bold(), cache::packages_dir().display());
+            println!();
+            if index.kept.is_empty() {
+                println!("No packages preserved. Add some with: eshu-trace cache keep <package>");
+            }
+            for name in &index.kept {
+                let versions: Vec<&str> = stored.iter().filter(|p| &p.name == name).map(|p| p.version.as_str()).collect();
+                if versions.is_empty() {
+                    println!("  {} {}", name.cyan(), "(no versions yet)".dimmed());
+                } else {
+                    println!("  {} {}", name.cyan(), versions.join(", "));
+                }
+            }
+            println!();
+            let limit = index
+                .max_bytes
+                .map(|m| format!(" of {}", snapshot::human_size(m)))
+                .unwrap_or_default();
+            println!("{} {}{}, up to {} version(s) per package", "Size:".cyan(), snapshot::human_size(total), limit, index.versions());
+        }
+        CacheAction::Sync { quiet } => {
+            let added = cache::sync(&index)?;
+            let removed = cache::prune(&index)?;
+            if !quiet {
+                println!("{} {} file(s) added, {} pruned", "✓".green(), added, removed.len());
+            }
+        }
+        CacheAction::Prune => {
+            let removed = cache::prune(&index)?;
+            for package in &removed {
+                println!("  {} {} {}", "-".red(), package.name, package.version.dimmed());
+            }
+            let freed: u64 = removed.iter().map(|p| p.size).sum();
+            println!("{} Freed {}", "✓".green(), snapshot::human_size(freed));
+        }
+        CacheAction::Limit { max_size, versions } => {
+            if let Some(size) = max_size {
+                index.max_bytes = Some(cache::parse_size(&size)?);
+            }
+            if versions.is_some() {
+                index.versions = versions;
+            }
+            index.save()?;
+            println!(
+                "{} Limit: {}, {} version(s) per package",
+                "✓".green(),
+                index.max_bytes.map(snapshot::human_size).unwrap_or_else(|| "no size limit".to_string()),
+                index.versions()
+            );
+        }
+        CacheAction::InstallHook => {
+            let path = cache::install_hook()?;
+            println!("{} Installed {}", "✓".green(), path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn audit_command(last: Option<usize>) -> Result<()> {
+    let entries = audit::entries()?;
+
+    println!("{} Audit log ({})", "📜".bold(), audit::log_path().display());
+    println!();
+
+    if entries.is_empty() {
+        println!("No system-modifying commands recorded yet");
+        return Ok(());
+    }
+
+    let skip = last.map(|n| entries.len().saturating_sub(n)).unwrap_or(0);
+    for entry in entries.iter().skip(skip) {
+        let exit = match entry.exit_code {
+            Some(0) => "exit 0".green(),
+            Some(code) => format!("exit {}", code).red(),
+            None => "killed".red(),
+        };
+        println!("{} {} {} ({})", entry.time.dimmed(), entry.action.cyan(), exit, entry.user);
+        println!("    {}", entry.command);
+        if !entry.output_sha256.is_empty() {
+            println!("    {} {}", "output sha256".dimmed(), entry.output_sha256.dimmed());
+        }
+    }
+
+    Ok(())
+}
+
 fn show_status() -> Result<()> {
     // Exciting header
     println!();
@@ -568,7 +3262,6 @@ fn show_status() -> Result<()> {
 
     // Check license
     let license = premium::get_license()?;
-    let is_premium = premium::is_premium()?;
 
     // Show what Eshu Trace can do
     println!("{}", "✨ What Eshu Trace Does:".green().bold());
@@ -578,6 +3271,17 @@ fn show_status() -> Result<()> {
     println!("   • {} - Identify the exact package that broke your system", "Precise".yellow());
     println!();
 
+    if let Some(pending) = premium::pending_activation() {
+        println!(
+            "{} {} (queued {}, {} attempt(s){})",
+            "Activation:".cyan(),
+            "waiting for the license server".yellow(),
+            pending.queued_at,
+            pending.attempts,
+            pending.last_error.map(|e| format!(", last error: {}", e)).unwrap_or_default()
+        );
+    }
+
     // License status
     match license.license_type {
         premium::LicenseType::Trial => {
@@ -609,6 +3313,11 @@ fn show_status() -> Result<()> {
         premium::LicenseType::Premium => {
             println!("{} {}", "License:".cyan(), "✅ Eshu Premium".green().bold());
             println!("{} {} (unlimited + automation)", "Traces Used:".cyan(), license.traces_used);
+            if let Some(days) = license.days_remaining() {
+                let remaining = format!("{} day(s)", days);
+                let remaining = if days <= 7 { remaining.yellow() } else { remaining.green() };
+                println!("{} {}", "Subscription:".cyan(), remaining);
+            }
             println!();
             println!("{}", "🎉 You have access to ALL Eshu features!".green());
             println!();
@@ -618,6 +3327,103 @@ fn show_status() -> Result<()> {
     println!("{}", "━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━".dimmed());
     println!();
 
+    // Processes still running replaced files
+    let stale = restart::detect();
+    if stale.is_empty() {
+        println!("{} {}", "Restart needed:".cyan(), "no".green());
+    } else {
+        println!("{} {} ({})", "Restart needed:".cyan(), "yes".yellow(), stale.source.dimmed());
+        if !stale.services.is_empty() {
+            println!("  Services: {}", stale.services.join(", "));
+        }
+        if !stale.other_pids.is_empty() {
+            println!("  Other processes: {}", stale.other_pids.len());
+        }
+        if stale.kernel {
+            println!("  Kernel: {}", "newer kernel installed, reboot to use it".yellow());
+        }
+    }
+    println!();
+
+    // Bisects in progress and fixes waiting for a reboot
+    if let Ok(Some(saved)) = bisect::load_saved() {
+        let left = saved.package_changes.get(saved.low..saved.high).unwrap_or_default();
+        println!(
+            "{} {} → {}, {} candidate change(s) left after {} step(s)",
+            "Bisect in progress:".cyan(),
+            saved.good,
+            saved.bad,
+            left.len(),
+            saved.history.len()
+        );
+        if left.len() <= 5 {
+            for change in left {
+                println!("  • {}", bisect::describe(change));
+            }
+        }
+        println!("  Resume with: {}", "eshu-trace bisect".white());
+    }
+    if let Some((good, bad, left)) = kernel::bisect_progress() {
+        println!(
+            "{} {} (good) → {} (bad), {} candidate(s) left",
+            "Kernel bisect in progress:".cyan(),
+            good,
+            bad,
+            left
+        );
+        println!("  Resume with: {}", "eshu-trace bisect --kernel".white());
+    }
+    if let Ok(Some(pending)) = reboot::PendingVerify::load() {
+        println!("{} {} (applied {})", "Fix awaiting reboot:".cyan(), pending.fix, pending.applied_at.dimmed());
+    }
+
+    // Recent culprits, newest first
+    let history = bisect::culprit_history();
+    if !history.is_empty() {
+        println!("{}", "Recent culprits:".cyan());
+        for record in history.iter().rev().take(5) {
+            let date = record.finished_at.get(..10).unwrap_or(&record.finished_at);
+            println!("  {} {} ({} → {})", date.dimmed(), bisect::describe(&record.culprit), record.good, record.bad);
+        }
+    }
+
+    // Pins created by eshu-trace
+    if let Ok(registry) = pins::PinRegistry::load() {
+        if !registry.pins.is_empty() {
+            println!("{}", "Pins:".cyan());
+            for pin in &registry.pins {
+                let bad = pin.bad_version.as_deref().map(|v| format!(", broken {}", v)).unwrap_or_default();
+                println!("  • {} at {}{} ({})", pin.package, pin.version, bad, pin.method.description().dimmed());
+            }
+        }
+    }
+
+    // Package manager hook, monitor timer and user hooks
+    let monitor_state = match (monitor::is_installed(), monitor::package_hook()) {
+        (true, Some(hook)) => format!("{} (timer + {})", "active".green(), hook),
+        (true, None) => format!("{} (timer only)", "active".green()),
+        (false, Some(hook)) => format!("{} ({} without timer)", "partial".yellow(), hook),
+        (false, None) => format!("{}", "not installed".dimmed()),
+    };
+    println!("{} {}", "Monitor:".cyan(), monitor_state);
+    let registered = hooks::registered();
+    if registered.is_empty() {
+        println!("{} {}", "Hooks:".cyan(), "none".dimmed());
+    } else {
+        let list: Vec<String> = registered.iter().map(|(hook, count)| format!("{} ({})", hook.name(), count)).collect();
+        println!("{} {}", "Hooks:".cyan(), list.join(", "));
+    }
+    println!();
+
+    // Pinned packages whose fix may have landed (repos are queried at most once a day)
+    if let Ok(fixed) = pins::check(true) {
+        if !fixed.is_empty() {
+            println!("{}", "Pinned packages with a fix available:".cyan());
+            print_fixed_pins(&fixed);
+            println!();
+        }
+    }
+
     // Check snapshot backend
     let snapshot_mgr = SnapshotManager::new()?;
     println!(
@@ -635,7 +3441,7 @@ fn show_status() -> Result<()> {
     // System info
     println!("{}", "System Information:".cyan());
 
-    if let Ok(output) = std::process::Command::new("uname").arg("-a").output() {
+    if let Ok(output) = std::process::Command::new("uname").arg("-a").run_output() {
         if let Ok(info) = String::from_utf8(output.stdout) {
             println!("  {}", info.trim().dimmed());
         }
@@ -0,0 +1,215 @@
+// Snapshot sources on removable or unmounted devices
+//
+// A configured snapshot directory or backup device may sit on a disk that is not
+// attached or not mounted. It is mounted read-only on first use (through udisks2
+// when not running as root) and unmounted again when the run ends.
+
+// Only the Timeshift and btrfs backends have sources on other devices
+#![cfg_attr(not(any(feature = "timeshift", feature = "btrfs")), allow(dead_code))]
+
+use colored::*;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::audit;
+use crate::exec::CommandExt;
+use crate::interrupt;
+use crate::test_runner::{is_root, which};
+
+/// Mount points for devices without an fstab entry
+const MEDIA_DIR: &str = "/run/eshu-trace/media";
+/// How long to wait for udev to create the device node after the user attaches a disk
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Mounts made by this run, in mount order
+static MOUNTED: Mutex<Vec<Mounted>> = Mutex::new(Vec::new());
+
+struct Mounted {
+    target: PathBuf,
+    device: PathBuf,
+    udisks: bool,
+}
+
+/// `path` itself if it exists, else the same path on its fstab device after mounting it
+pub fn attach_path(path: &Path) -> Option<PathBuf> {
+    if path.exists() {
+        return Some(path.to_path_buf());
+    }
+
+    let (spec, mount_point) = fstab_entry(path)?;
+    let relative = path.strip_prefix(&mount_point).ok()?;
+
+    // Desktop automounters may already have it somewhere else
+    if let Some(found) = mounted_targets(&spec).iter().map(|t| t.join(relative)).find(|p| p.exists()) {
+        return Some(found);
+    }
+
+    let target = attach_device(&spec, &mount_point.display().to_string(), "ro")?;
+    Some(target.join(relative)).filter(|p| p.exists())
+}
+
+/// Where the device `spec` (UUID=..., LABEL=..., PARTUUID=... or /dev/...) is mounted right now
+pub fn mounted_targets(spec: &str) -> Vec<PathBuf> {
+    Command::new("findmnt")
+        .args(["-rn", "-o", "TARGET", "-S", spec])
+        .run_output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).lines().map(unescape).map(PathBuf::from).collect())
+        .unwrap_or_default()
+}
+
+/// Mount the device `spec` with `options`, asking the user to connect it first if it is missing.
+/// Returns the mount point; `release_all` unmounts it.
+pub fn attach_device(spec: &str, label: &str, options: &str) -> Option<PathBuf> {
+    let device = device_path(spec);
+    if !device.exists() && !wait_for_device(&device, spec, label) {
+        return None;
+    }
+
+    let mounted = if is_root() {
+        mount_as_root(&device, spec, options)
+    } else if which("udisksctl") {
+        mount_with_udisks(&device, options)
+    } else {
+        None
+    };
+    let Some(mounted) = mounted else {
+        eprintln!("{} Could not mount {} ({}); run as root or install udisks2", "⚠".yellow(), label, spec);
+        return None;
+    };
+
+    interrupt::track_mount(&mounted.target);
+    let target = mounted.target.clone();
+    if let Ok(mut all) = MOUNTED.lock() {
+        all.push(mounted);
+    }
+    Some(target)
+}
+
+/// Unmount every device this run mounted
+pub fn release_all() {
+    let mounted = MOUNTED.lock().map(|mut m| std::mem::take(&mut *m)).unwrap_or_default();
+
+    for mounted in mounted.iter().rev() {
+        let mut cmd = if mounted.udisks {
+            let mut cmd = Command::new("udisksctl");
+            cmd.args(["unmount", "--no-user-interaction", "-b"]).arg(&mounted.device);
+            cmd
+        } else {
+            let mut cmd = Command::new("umount");
+            cmd.arg(&mounted.target);
+            cmd
+        };
+        let _ = audit::run("media: unmount snapshot source", &mut cmd);
+        interrupt::untrack_mount(&mounted.target);
+    }
+}
+
+fn mount_as_root(device: &Path, spec: &str, options: &str) -> Option<Mounted> {
+    let name: String = spec.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect();
+    let target = Path::new(MEDIA_DIR).join(name);
+    std::fs::create_dir_all(&target).ok()?;
+
+    let status = audit::run(
+        "media: mount snapshot source",
+        Command::new("mount").args(["-o", options]).arg(device).arg(&target),
+    )
+    .ok()?;
+    status.success().then(|| Mounted {
+        target,
+        device: device.to_path_buf(),
+        udisks: false,
+    })
+}
+
+/// udisks picks the mount point itself (/run/media/$USER/<label>) and reports it
+fn mount_with_udisks(device: &Path, options: &str) -> Option<Mounted> {
+    let output = Command::new("udisksctl")
+        .args(["mount", "--no-user-interaction", "-o", options, "-b"])
+        .arg(device)
+        .run_output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // "Mounted /dev/sdb1 at /run/media/user/Backup"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let target = stdout.rsplit_once(" at ")?.1.trim().trim_end_matches('.');
+    Some(Mounted {
+        target: PathBuf::from(target),
+        device: device.to_path_buf(),
+        udisks: true,
+    })
+}
+
+/// Ask to connect the device and wait for it to appear; false if it doesn't
+fn wait_for_device(device: &Path, spec: &str, label: &str) -> bool {
+    if !std::io::stdin().is_terminal() {
+        eprintln!("{} {} ({}) is not connected", "ℹ".cyan(), label, spec);
+        return false;
+    }
+
+    let attach = dialoguer::Confirm::new()
+        .with_prompt(format!("{} ({}) is not connected. Attach it now and continue?", label, spec))
+        .default(true)
+        .interact()
+        .unwrap_or(false);
+    if !attach {
+        return false;
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < ATTACH_TIMEOUT {
+        if device.exists() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+    eprintln!("{} {} did not appear", "✗".red(), device.display());
+    false
+}
+
+fn device_path(spec: &str) -> PathBuf {
+    for (prefix, dir) in [("UUID=", "by-uuid"), ("LABEL=", "by-label"), ("PARTUUID=", "by-partuuid")] {
+        if let Some(value) = spec.strip_prefix(prefix) {
+            return Path::new("/dev/disk").join(dir).join(value.trim_matches('"'));
+        }
+    }
+    PathBuf::from(spec)
+}
+
+/// (device spec, mount point) of the deepest unmounted /etc/fstab entry containing `path`
+fn fstab_entry(path: &Path) -> Option<(String, PathBuf)> {
+    let fstab = std::fs::read_to_string("/etc/fstab").ok()?;
+    let mounts = std::fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+    let is_mounted = |point: &Path| {
+        mounts
+            .lines()
+            .any(|l| l.split_whitespace().nth(1).map(unescape).is_some_and(|m| Path::new(&m) == point))
+    };
+
+    fstab
+        .lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .filter_map(|l| {
+            let mut fields = l.split_whitespace();
+            let spec = fields.next()?.to_string();
+            let point = PathBuf::from(unescape(fields.next()?));
+            Some((spec, point))
+        })
+        .filter(|(_, point)| point != Path::new("/") && point.is_absolute() && path.starts_with(point))
+        .filter(|(_, point)| !is_mounted(point))
+        .max_by_key(|(_, point)| point.components().count())
+}
+
+/// fstab and /proc/mounts escape spaces and tabs as \040 and \011, findmnt -r as \x20
+fn unescape(field: &str) -> String {
+    field
+        .replace("\\040", " ")
+        .replace("\\x20", " ")
+        .replace("\\011", "\t")
+        .replace("\\134", "\\")
+}
@@ -0,0 +1,354 @@
+// Continuous monitoring: a package manifest after every transaction plus a rolling health log
+//
+// `monitor install` adds a package manager hook and an hourly systemd timer that both
+// run `monitor record`. Manifests are full package lists, so any two of them can be
+// diffed or bisected later even if no snapshot was taken at the time. A test registered
+// with `monitor check` runs on the timer's records only, never inside a package
+// transaction; when it starts failing, the last passing manifest and the current one are
+// the bisect's good/bad pair.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::audit;
+use crate::exec::CommandExt;
+use crate::notify;
+use crate::package_diff;
+use crate::snapshot::Snapshot;
+use crate::test_runner::TestRunner;
+
+/// Shared by the root-run hook/timer and users reading the history
+const MONITOR_DIR: &str = "/var/lib/eshu-trace/monitor";
+/// Manifest ids start with this, so they can't be mistaken for snapshot ids
+pub const MANIFEST_PREFIX: &str = "manifest-";
+pub const MAX_MANIFESTS: usize = 500;
+const MAX_HEALTH_ENTRIES: usize = 2000;
+
+const SERVICE_PATH: &str = "/etc/systemd/system/eshu-trace-monitor.service";
+const TIMER_PATH: &str = "/etc/systemd/system/eshu-trace-monitor.timer";
+const PACMAN_HOOK: &str = "/etc/pacman.d/hooks/eshu-trace-monitor.hook";
+const APT_HOOK: &str = "/etc/apt/apt.conf.d/80eshu-trace-monitor";
+
+/// Installed packages at one point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub id: String,
+    pub created_at: DateTime<Local>,
+    pub kernel: String,
+    pub packages: HashMap<String, String>,
+}
+
+impl Manifest {
+    /// Usable wherever a snapshot is expected (diff, bisect, recent)
+    pub fn to_snapshot(&self) -> Snapshot {
+        Snapshot {
+            id: self.id.clone(),
+            created_at: Some(self.created_at),
+            description: Some("eshu-trace manifest".to_string()),
+            kind: None,
+            cleanup: None,
+            packages: Some(self.packages.clone()),
+            package_count: Some(self.packages.len()),
+            path: None,
+        }
+    }
+}
+
+/// One line of the health log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthEntry {
+    pub at: String,
+    pub boot_id: String,
+    pub kernel: String,
+    pub failed_units: Vec<String>,
+    /// Journal messages at priority err or worse in the current boot
+    pub boot_errors: usize,
+    /// Manifest current at the time of the check
+    pub manifest: Option<String>,
+    /// Result of the registered health check, if there is one
+    #[serde(default)]
+    pub check_passed: Option<bool>,
+}
+
+/// The user's test, run with every `monitor record`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthCheck {
+    pub command: String,
+    pub test_user: Option<String>,
+    pub added_at: String,
+}
+
+impl HealthCheck {
+    pub fn runner(&self) -> TestRunner {
+        TestRunner::new(Some(self.command.clone())).with_user(self.test_user.clone())
+    }
+}
+
+/// A health check that passed with one manifest and fails with a later one
+#[derive(Debug, Clone)]
+pub struct Regression {
+    pub good: String,
+    pub bad: String,
+    /// When the check last passed
+    pub last_pass: String,
+}
+
+pub fn manifests_dir() -> PathBuf {
+    Path::new(MONITOR_DIR).join("manifests")
+}
+
+fn health_log_path() -> PathBuf {
+    Path::new(MONITOR_DIR).join("health.jsonl")
+}
+
+fn check_path() -> PathBuf {
+    Path::new(MONITOR_DIR).join("check.json")
+}
+
+pub fn health_check() -> Option<HealthCheck> {
+    serde_json::from_str(&fs::read_to_string(check_path()).ok()?).ok()
+}
+
+/// Register (or with None, remove) the recurring health check
+pub fn set_health_check(check: Option<HealthCheck>) -> Result<()> {
+    match check {
+        Some(check) => {
+            fs::create_dir_all(MONITOR_DIR)?;
+            fs::write(check_path(), serde_json::to_string_pretty(&check)?)?;
+        }
+        None => {
+            let _ = fs::remove_file(check_path());
+        }
+    }
+    Ok(())
+}
+
+/// The last passing and the current manifest, if the health check fails now but passed before
+pub fn regression() -> Option<Regression> {
+    let log = health_log();
+    let latest = log.iter().rev().find(|e| e.check_passed.is_some())?;
+    if latest.check_passed != Some(false) {
+        return None;
+    }
+
+    let pass = log.iter().rev().find(|e| e.check_passed == Some(true))?;
+    Some(Regression {
+        good: pass.manifest.clone()?,
+        bad: log.last()?.manifest.clone()?,
+        last_pass: pass.at.clone(),
+    })
+}
+
+/// Record a manifest if packages changed since the last one, then append a health entry.
+/// The health check only runs with `run_check`: package hooks leave it to the timer, since
+/// the test can take arbitrarily long and would hold up the transaction.
+/// Returns the new manifest, if one was written.
+pub fn record(run_check: bool) -> Result<Option<Manifest>> {
+    let packages = package_diff::detect_current_packages()?;
+    let latest = manifests()?.pop();
+
+    let recorded = if latest.as_ref().is_some_and(|m| m.packages == packages) {
+        None
+    } else {
+        let now = Local::now();
+        let manifest = Manifest {
+            id: format!("{}{}", MANIFEST_PREFIX, now.format("%Y%m%d-%H%M%S")),
+            created_at: now,
+            kernel: kernel_release(),
+            packages,
+        };
+        let dir = manifests_dir();
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        fs::write(dir.join(format!("{}.json", manifest.id)), serde_json::to_string(&manifest)?)?;
+        prune_manifests()?;
+        Some(manifest)
+    };
+
+    let current = recorded.as_ref().or(latest.as_ref()).map(|m| m.id.clone());
+    let mut entry = check_health(current);
+    let previous = health_log().iter().rev().find_map(|e| e.check_passed);
+    if let Some(check) = health_check().filter(|_| run_check) {
+        entry.check_passed = Some(check.runner().run_test().unwrap_or(false));
+    }
+    let started_failing = previous == Some(true) && entry.check_passed == Some(false);
+    append_health(entry)?;
+
+    if started_failing {
+        eprintln!("eshu-trace: health check `{}` started failing", health_check().map(|c| c.command).unwrap_or_default());
+        notify::enable();
+        notify::send(
+            "Health check failing",
+            "It passed at the previous check. Run `eshu-trace monitor status` to bisect the change.",
+        );
+    }
+    Ok(recorded)
+}
+
+/// All manifests, oldest first
+pub fn manifests() -> Result<Vec<Manifest>> {
+    let mut manifests: Vec<Manifest> = match fs::read_dir(manifests_dir()) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|e| serde_json::from_str(&fs::read_to_string(e.path()).ok()?).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    manifests.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(manifests)
+}
+
+pub fn load_manifest(id: &str) -> Option<Manifest> {
+    let text = fs::read_to_string(manifests_dir().join(format!("{}.json", id))).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+fn prune_manifests() -> Result<()> {
+    let all = manifests()?;
+    if all.len() > MAX_MANIFESTS {
+        for old in &all[..all.len() - MAX_MANIFESTS] {
+            let _ = fs::remove_file(manifests_dir().join(format!("{}.json", old.id)));
+        }
+    }
+    Ok(())
+}
+
+pub fn health_log() -> Vec<HealthEntry> {
+    fs::read_to_string(health_log_path())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect()
+}
+
+fn append_health(entry: HealthEntry) -> Result<()> {
+    let mut entries = health_log();
+    entries.push(entry);
+    let keep = entries.len().saturating_sub(MAX_HEALTH_ENTRIES);
+
+    let mut text = String::new();
+    for entry in &entries[keep..] {
+        text.push_str(&serde_json::to_string(entry)?);
+        text.push('\n');
+    }
+    fs::create_dir_all(MONITOR_DIR)?;
+    fs::write(health_log_path(), text)?;
+    Ok(())
+}
+
+fn check_health(manifest: Option<String>) -> HealthEntry {
+    let failed_units = Command::new("systemctl")
+        .args(["--failed", "--plain", "--no-legend", "--no-pager"])
+        .run_output()
+        .map(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .lines()
+                .filter_map(|l| l.split_whitespace().next().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let boot_errors = Command::new("journalctl")
+        .args(["-b", "-p", "err", "-q", "--no-pager", "-o", "cat"])
+        .run_output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).lines().count())
+        .unwrap_or(0);
+
+    HealthEntry {
+        at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        boot_id: fs::read_to_string("/proc/sys/kernel/random/boot_id")
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default(),
+        kernel: kernel_release(),
+        failed_units,
+        boot_errors,
+        manifest,
+        check_passed: None,
+    }
+}
+
+fn kernel_release() -> String {
+    fs::read_to_string("/proc/sys/kernel/osrelease")
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Package manager hook plus an hourly timer (which also catches dnf/zypper transactions)
+pub fn install() -> Result<Vec<PathBuf>> {
+    let exe = std::env::current_exe()?.to_string_lossy().to_string();
+    let mut written = Vec::new();
+
+    if Path::new("/etc/pacman.d").is_dir() {
+        fs::create_dir_all("/etc/pacman.d/hooks")?;
+        fs::write(
+            PACMAN_HOOK,
+            format!(
+                "[Trigger]\nOperation = Install\nOperation = Upgrade\nOperation = Remove\nType = Package\nTarget = *\n\n\
+                 [Action]\nDescription = Recording package manifest (eshu-trace)...\n\
+                 When = PostTransaction\nExec = {} monitor record --quiet --no-check\n",
+                exe
+            ),
+        )?;
+        written.push(PathBuf::from(PACMAN_HOOK));
+    } else if Path::new("/etc/apt/apt.conf.d").is_dir() {
+        fs::write(APT_HOOK, format!("DPkg::Post-Invoke {{ \"{} monitor record --quiet --no-check || true\"; }};\n", exe))?;
+        written.push(PathBuf::from(APT_HOOK));
+    }
+
+    fs::write(
+        SERVICE_PATH,
+        format!(
+            "[Unit]\nDescription=Record package manifest and system health (eshu-trace)\n\n\
+             [Service]\nType=oneshot\nExecStart={} monitor record --quiet\nTimeoutStartSec=15min\n",
+            exe
+        ),
+    )?;
+    fs::write(
+        TIMER_PATH,
+        "[Unit]\nDescription=Hourly eshu-trace package manifest and health check\n\n\
+         [Timer]\nOnBootSec=5min\nOnUnitActiveSec=1h\nPersistent=true\n\n\
+         [Install]\nWantedBy=timers.target\n",
+    )?;
+    written.push(PathBuf::from(SERVICE_PATH));
+    written.push(PathBuf::from(TIMER_PATH));
+
+    audit::run("monitor: reload units", Command::new("systemctl").arg("daemon-reload"))?;
+    let enabled = audit::run(
+        "monitor: enable timer",
+        Command::new("systemctl").args(["enable", "--now", "eshu-trace-monitor.timer"]),
+    )?;
+    if !enabled.success() {
+        anyhow::bail!("systemctl could not enable eshu-trace-monitor.timer");
+    }
+
+    Ok(written)
+}
+
+/// Remove the hooks and timer; recorded manifests are kept
+pub fn uninstall() -> Result<()> {
+    let _ = audit::run(
+        "monitor: disable timer",
+        Command::new("systemctl").args(["disable", "--now", "eshu-trace-monitor.timer"]),
+    );
+    for path in [PACMAN_HOOK, APT_HOOK, SERVICE_PATH, TIMER_PATH] {
+        if Path::new(path).exists() {
+            fs::remove_file(path).with_context(|| format!("Failed to remove {}", path))?;
+        }
+    }
+    let _ = audit::run("monitor: reload units", Command::new("systemctl").arg("daemon-reload"));
+    Ok(())
+}
+
+pub fn is_installed() -> bool {
+    Path::new(TIMER_PATH).exists()
+}
+
+/// The package manager hook recording manifests after each transaction, if installed
+pub fn package_hook() -> Option<&'static str> {
+    [PACMAN_HOOK, APT_HOOK].into_iter().find(|path| Path::new(path).exists())
+}
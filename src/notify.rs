@@ -0,0 +1,135 @@
+// Freedesktop notifications for unattended automated runs, plus webhook/email reports
+
+use anyhow::{Context, Result};
+use colored::*;
+use serde_json::json;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::exec::CommandExt;
+use crate::http;
+use crate::test_runner::which;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Where the final report goes, from --notify
+static TARGET: Mutex<Option<Target>> = Mutex::new(None);
+
+/// Destination of the final report
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// Receives Slack/Matrix-compatible JSON ({"text": ...})
+    Webhook(String),
+    /// Sent through the local sendmail
+    Email(String),
+}
+
+/// clap value parser for --notify <url|mailto:address>
+pub fn parse_target(value: &str) -> Result<Target, String> {
+    if let Some(address) = value.strip_prefix("mailto:") {
+        if !address.contains('@') {
+            return Err(format!("Not an email address: {}", address));
+        }
+        Ok(Target::Email(address.to_string()))
+    } else if value.starts_with("https://") || value.starts_with("http://") {
+        Ok(Target::Webhook(value.to_string()))
+    } else {
+        Err("Expected an http(s):// webhook URL or mailto:address".to_string())
+    }
+}
+
+pub fn set_target(target: Option<Target>) {
+    if let Ok(mut current) = TARGET.lock() {
+        *current = target;
+    }
+}
+
+/// Turn notifications on for the rest of the run
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Show a desktop notification; silently does nothing without notify-send or a session bus
+pub fn send(summary: &str, body: &str) {
+    if !ENABLED.load(Ordering::Relaxed) || !which("notify-send") {
+        return;
+    }
+
+    let args = ["--app-name=eshu-trace", "--icon=system-search", summary, body];
+
+    // Under sudo, root has no session bus; deliver to the invoking user's desktop instead
+    let _ = match (std::env::var("SUDO_USER"), std::env::var("SUDO_UID")) {
+        (Ok(user), Ok(uid)) if crate::test_runner::is_root() => Command::new("sudo")
+            .args(["-u", &user, "env"])
+            .arg(format!("DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/{}/bus", uid))
+            .arg("notify-send")
+            .args(args)
+            .run_status(),
+        _ => Command::new("notify-send").args(args).run_status(),
+    };
+}
+
+/// Announce the end of a run on the desktop and to the --notify target, if any
+pub fn finished(summary: &str, report: &str) {
+    send(summary, report);
+
+    let target = match TARGET.lock().ok().and_then(|t| t.clone()) {
+        Some(t) => t,
+        None => return,
+    };
+    let delivered = match &target {
+        Target::Webhook(url) => post_webhook(url, summary, report),
+        Target::Email(address) => send_email(address, summary, report),
+    };
+    if let Err(e) = delivered {
+        println!("{} Could not deliver the report: {:#}", "⚠".yellow(), e);
+    }
+}
+
+fn post_webhook(url: &str, summary: &str, report: &str) -> Result<()> {
+    let hostname = hostname();
+    let text = format!("eshu-trace on {}: {}\n{}", hostname, summary, report);
+    // Slack reads "text"; Matrix hookshot reads "text"/"body"
+    let payload = json!({ "text": text, "body": text, "summary": summary, "host": hostname });
+
+    let response = http::post_json(url, &payload, Duration::from_secs(15)).context("Webhook request failed")?;
+    if !response.is_success() {
+        anyhow::bail!("Webhook answered HTTP {}", response.status);
+    }
+    Ok(())
+}
+
+fn send_email(address: &str, summary: &str, report: &str) -> Result<()> {
+    let sendmail = ["/usr/sbin/sendmail", "/usr/bin/sendmail"]
+        .into_iter()
+        .find(|p| std::path::Path::new(p).exists())
+        .context("sendmail not found; install a mail transfer agent (e.g. msmtp-mta) to use mailto:")?;
+
+    let mut child = Command::new(sendmail)
+        .args(["-t", "-oi"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run sendmail")?;
+    if let Some(stdin) = child.stdin.as_mut() {
+        write!(
+            stdin,
+            "To: {}\nSubject: [eshu-trace] {} ({})\nContent-Type: text/plain; charset=utf-8\n\n{}\n",
+            address,
+            summary,
+            hostname(),
+            report
+        )?;
+    }
+    if !child.wait()?.success() {
+        anyhow::bail!("sendmail failed");
+    }
+    Ok(())
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .unwrap_or_else(|_| "unknown host".to_string())
+}
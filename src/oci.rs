@@ -0,0 +1,177 @@
+// Export a snapshot's root filesystem as an OCI image archive (podman load / docker load)
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+
+use crate::audit;
+use crate::exec;
+use crate::snapshot::Snapshot;
+
+const MANIFEST_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const CONFIG_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+/// Uncompressed, so the diff ID is the layer digest and no gzip dependency is needed
+const LAYER_TYPE: &str = "application/vnd.oci.image.layer.v1.tar";
+
+/// Paths left out of the image: pseudo filesystems, user data, caches and host secrets
+const EXCLUDES: &[&str] = &[
+    "./proc/*",
+    "./sys/*",
+    "./dev/*",
+    "./run/*",
+    "./tmp/*",
+    "./var/tmp/*",
+    "./home/*",
+    "./root/*",
+    "./boot/*",
+    "./.snapshots",
+    "./timeshift",
+    "./var/cache/pacman/pkg/*",
+    "./var/cache/apt/archives/*.deb",
+    "./var/log/journal/*",
+    "./etc/shadow*",
+    "./etc/gshadow*",
+    "./etc/ssh/ssh_host_*",
+    "./etc/machine-id",
+    "./etc/NetworkManager/system-connections/*",
+    "./etc/ssl/private/*",
+];
+
+/// Name the image is loaded under
+pub fn image_name(snapshot: &Snapshot) -> String {
+    let tag: String = snapshot
+        .id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    format!("localhost/eshu-trace-good:{}", tag)
+}
+
+/// Write an OCI image archive of `snapshot` to `output`. Returns the image name.
+pub fn export(snapshot: &Snapshot, output: &Path) -> Result<String> {
+    let root = snapshot.path.as_deref().with_context(|| {
+        format!("Snapshot {} has no accessible filesystem path to export", snapshot.id)
+    })?;
+
+    // Staged next to the output: the layer is as large as the snapshot
+    let parent = output.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let staging = tempfile::Builder::new()
+        .prefix(".eshu-trace-oci")
+        .tempdir_in(parent)
+        .context("Failed to create a staging directory next to the output")?;
+    let blobs = staging.path().join("blobs/sha256");
+    fs::create_dir_all(&blobs)?;
+
+    let layer = staging.path().join("layer.tar");
+    let mut tar = Command::new("tar");
+    tar.arg("--create").arg("--file").arg(&layer).arg("--numeric-owner").arg("--xattrs");
+    for pattern in EXCLUDES {
+        tar.arg(format!("--exclude={}", pattern));
+    }
+    let status = audit::run("export: archive snapshot", tar.arg("-C").arg(root).arg("."))?;
+    if !exec::runner().applies_changes() {
+        return Ok(image_name(snapshot));
+    }
+    // 1 means some files changed while reading, which is fine for a snapshot
+    if !matches!(status.code(), Some(0) | Some(1)) {
+        anyhow::bail!("tar failed to archive {} (run as root to read every file)", root);
+    }
+
+    let (layer_digest, layer_size) = digest_file(&layer)?;
+    fs::rename(&layer, blobs.join(&layer_digest))?;
+
+    let config = json!({
+        "architecture": oci_arch(),
+        "os": "linux",
+        "created": chrono::Utc::now().to_rfc3339(),
+        "config": {
+            "Cmd": ["/bin/sh"],
+            "Labels": {
+                "org.opencontainers.image.title": "eshu-trace good state",
+                "org.opencontainers.image.description": format!("Root filesystem of snapshot {} ({})", snapshot.id, snapshot.date()),
+            },
+        },
+        "rootfs": { "type": "layers", "diff_ids": [format!("sha256:{}", layer_digest)] },
+        "history": [{ "created_by": format!("eshu-trace export --oci (snapshot {})", snapshot.id) }],
+    });
+    let (config_digest, config_size) = write_blob(&blobs, &serde_json::to_vec(&config)?)?;
+
+    let manifest = json!({
+        "schemaVersion": 2,
+        "mediaType": MANIFEST_TYPE,
+        "config": { "mediaType": CONFIG_TYPE, "digest": format!("sha256:{}", config_digest), "size": config_size },
+        "layers": [{ "mediaType": LAYER_TYPE, "digest": format!("sha256:{}", layer_digest), "size": layer_size }],
+    });
+    let (manifest_digest, manifest_size) = write_blob(&blobs, &serde_json::to_vec(&manifest)?)?;
+
+    let name = image_name(snapshot);
+    let index = json!({
+        "schemaVersion": 2,
+        "manifests": [{
+            "mediaType": MANIFEST_TYPE,
+            "digest": format!("sha256:{}", manifest_digest),
+            "size": manifest_size,
+            // podman reads the first, docker the second
+            "annotations": {
+                "org.opencontainers.image.ref.name": name,
+                "io.containerd.image.name": name,
+            },
+        }],
+    });
+    fs::write(staging.path().join("index.json"), serde_json::to_vec(&index)?)?;
+    fs::write(staging.path().join("oci-layout"), r#"{"imageLayoutVersion":"1.0.0"}"#)?;
+
+    let status = audit::run(
+        "export: write image",
+        Command::new("tar")
+            .arg("--create")
+            .arg("--file")
+            .arg(output)
+            .arg("-C")
+            .arg(staging.path())
+            .args(["oci-layout", "index.json", "blobs"]),
+    )?;
+    if !status.success() {
+        anyhow::bail!("Failed to write {}", output.display());
+    }
+
+    Ok(name)
+}
+
+fn write_blob(dir: &Path, data: &[u8]) -> Result<(String, u64)> {
+    let digest = format!("{:x}", Sha256::digest(data));
+    fs::write(dir.join(&digest), data)?;
+    Ok((digest, data.len() as u64))
+}
+
+/// Hex SHA-256 and size of a file, read in chunks
+fn digest_file(path: &Path) -> Result<(String, u64)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    let mut size = 0u64;
+
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        size += n as u64;
+    }
+
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+fn oci_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        other => other,
+    }
+}
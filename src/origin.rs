@@ -0,0 +1,242 @@
+// Detect packages whose repository origin changed between snapshots
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::exec::CommandExt;
+use crate::ownership::{dependency_name, desc_field, desc_list};
+use crate::package_diff::PackageDiff;
+
+/// A package that was installed from a different source in each snapshot
+#[derive(Debug, Clone)]
+pub struct OriginChange {
+    pub package: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// A removed package that was taken over by a differently-named provider
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    pub old: String,
+    pub new: String,
+}
+
+/// Packages present in both snapshots whose origin differs, even if the version did not
+pub fn origin_changes(good_root: &str, bad_root: &str) -> Vec<OriginChange> {
+    let before = package_origins(good_root);
+    let after = package_origins(bad_root);
+
+    let mut changes: Vec<OriginChange> = before
+        .iter()
+        .filter_map(|(name, old)| {
+            let new = after.get(name)?;
+            (old != new).then(|| OriginChange {
+                package: name.clone(),
+                old: old.clone(),
+                new: new.clone(),
+            })
+        })
+        .collect();
+
+    changes.sort_by(|a, b| a.package.cmp(&b.package));
+    changes
+}
+
+/// Removed packages that an added package provides or replaces
+pub fn replacements(bad_root: &str, diff: &PackageDiff) -> Vec<Replacement> {
+    let mut found = Vec::new();
+
+    for added in &diff.added {
+        let provides = provides_in(bad_root, &added.name);
+        for removed in &diff.removed {
+            if provides.iter().any(|p| p == &removed.name) {
+                found.push(Replacement {
+                    old: removed.name.clone(),
+                    new: added.name.clone(),
+                });
+            }
+        }
+    }
+
+    found
+}
+
+/// Map of package name to where it came from inside `root`
+fn package_origins(root: &str) -> HashMap<String, String> {
+    let root = Path::new(root);
+
+    if root.join("var/lib/pacman/local").exists() {
+        pacman_origins(root)
+    } else if root.join("var/lib/dpkg/status").exists() {
+        apt_origins(root)
+    } else if root.join("var/lib/rpm").exists() {
+        rpm_origins(root)
+    } else {
+        HashMap::new()
+    }
+}
+
+/// Packages inside `root` that no configured repository ships (AUR, makepkg). pacman -Qm
+/// knows the distro's repos; without it, official Arch packagers are told apart by address.
+pub fn foreign_packages(root: &str) -> Vec<String> {
+    if let Ok(output) = Command::new("pacman").arg("--root").arg(root).arg("-Qmq").run_output() {
+        // Exit status 1 with no output: nothing foreign
+        if output.status.success() || (output.stdout.is_empty() && output.stderr.is_empty()) {
+            return String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect();
+        }
+    }
+    pacman_origins(Path::new(root))
+        .into_iter()
+        .filter(|(_, origin)| origin != "official")
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Official Arch packages are signed and built by an archlinux.org packager;
+/// anything else was built locally (AUR helpers, makepkg)
+fn pacman_origins(root: &Path) -> HashMap<String, String> {
+    let mut origins = HashMap::new();
+
+    if let Ok(entries) = fs::read_dir(root.join("var/lib/pacman/local")) {
+        for entry in entries.flatten() {
+            let desc = fs::read_to_string(entry.path().join("desc")).unwrap_or_default();
+            let name = match desc_field(&desc, "%NAME%") {
+                Some(n) => n,
+                None => continue,
+            };
+            let packager = desc_field(&desc, "%PACKAGER%").unwrap_or_default();
+            let origin = if packager.contains("@archlinux.org") {
+                "official"
+            } else {
+                "foreign (AUR/local)"
+            };
+            origins.insert(name, origin.to_string());
+        }
+    }
+
+    origins
+}
+
+/// Match installed versions against the apt lists to find the archive that ships them
+fn apt_origins(root: &Path) -> HashMap<String, String> {
+    let mut installed = HashMap::new();
+    let status = fs::read_to_string(root.join("var/lib/dpkg/status")).unwrap_or_default();
+    for stanza in status.split("\n\n") {
+        if let (Some(name), Some(version)) = (stanza_field(stanza, "Package"), stanza_field(stanza, "Version")) {
+            installed.insert(name, version);
+        }
+    }
+
+    let mut origins = HashMap::new();
+    if let Ok(entries) = fs::read_dir(root.join("var/lib/apt/lists")) {
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !file_name.ends_with("_Packages") {
+                continue;
+            }
+            // deb.debian.org_debian_dists_bookworm_main_binary-amd64_Packages
+            let host = file_name.split('_').next().unwrap_or(&file_name).to_string();
+            let origin = if host.contains("launchpad") {
+                format!("PPA ({})", file_name.split('_').take(3).collect::<Vec<_>>().join("/"))
+            } else {
+                host
+            };
+
+            let list = fs::read_to_string(entry.path()).unwrap_or_default();
+            for stanza in list.split("\n\n") {
+                if let (Some(name), Some(version)) = (stanza_field(stanza, "Package"), stanza_field(stanza, "Version")) {
+                    if installed.get(&name) == Some(&version) {
+                        origins.entry(name).or_insert_with(|| origin.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    // Installed but not in any list: manually installed .deb
+    for name in installed.into_keys() {
+        origins.entry(name).or_insert_with(|| "local".to_string());
+    }
+
+    origins
+}
+
+/// RPM vendor identifies Fedora, RPM Fusion, COPR ("Fedora Copr - user foo") etc.
+fn rpm_origins(root: &Path) -> HashMap<String, String> {
+    let mut origins = HashMap::new();
+
+    if let Ok(output) = Command::new("rpm")
+        .arg("--root")
+        .arg(root)
+        .args(["-qa", "--qf", "%{NAME}\\t%{VENDOR}\\n"])
+        .run_output()
+    {
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some((name, vendor)) = line.split_once('\t') {
+                let vendor = if vendor == "(none)" { "local" } else { vendor };
+                origins.insert(name.to_string(), vendor.to_string());
+            }
+        }
+    }
+
+    origins
+}
+
+/// Names that `package` provides or replaces inside `root`
+fn provides_in(root: &str, package: &str) -> Vec<String> {
+    let root_path = Path::new(root);
+    let mut names = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(root_path.join("var/lib/pacman/local")) {
+        for entry in entries.flatten() {
+            let desc = fs::read_to_string(entry.path().join("desc")).unwrap_or_default();
+            if desc_field(&desc, "%NAME%").as_deref() == Some(package) {
+                for field in ["%PROVIDES%", "%REPLACES%"] {
+                    names.extend(desc_list(&desc, field).iter().map(|p| dependency_name(p).to_string()));
+                }
+            }
+        }
+    }
+
+    if let Ok(status) = fs::read_to_string(root_path.join("var/lib/dpkg/status")) {
+        for stanza in status.split("\n\n") {
+            if stanza_field(stanza, "Package").as_deref() == Some(package) {
+                for field in ["Provides", "Replaces"] {
+                    if let Some(list) = stanza_field(stanza, field) {
+                        names.extend(list.split(',').map(|p| dependency_name(p).to_string()));
+                    }
+                }
+            }
+        }
+    }
+
+    if root_path.join("var/lib/rpm").exists() {
+        for query in ["--provides", "--obsoletes"] {
+            if let Ok(output) = Command::new("rpm").arg("--root").arg(root).args(["-q", query, package]).run_output() {
+                if output.status.success() {
+                    names.extend(
+                        String::from_utf8_lossy(&output.stdout)
+                            .lines()
+                            .map(|p| dependency_name(p).to_string()),
+                    );
+                }
+            }
+        }
+    }
+
+    names.retain(|n| !n.is_empty() && n != package);
+    names.dedup();
+    names
+}
+
+/// Value of a `Field: value` line in a dpkg/apt stanza
+fn stanza_field(stanza: &str, field: &str) -> Option<String> {
+    let prefix = format!("{}: ", field);
+    stanza
+        .lines()
+        .find_map(|l| l.strip_prefix(&prefix))
+        .map(|v| v.trim().to_string())
+}
@@ -0,0 +1,32 @@
+// Dependencies left dangling by package removals
+
+use crate::ownership;
+use crate::package_diff::PackageDiff;
+
+/// A removed package that installed packages still require
+#[derive(Debug, Clone)]
+pub struct BrokenRequire {
+    pub removed: String,
+    pub dependents: Vec<String>,
+}
+
+/// Removed packages that nothing in `bad_root` provides anymore but something still depends on
+pub fn broken_requires(bad_root: &str, diff: &PackageDiff) -> Vec<BrokenRequire> {
+    let mut broken = Vec::new();
+
+    for pkg in &diff.removed {
+        if !ownership::providers_in(bad_root, &pkg.name).is_empty() {
+            continue;
+        }
+
+        let dependents = ownership::dependents_in(bad_root, &pkg.name, &[]);
+        if !dependents.is_empty() {
+            broken.push(BrokenRequire {
+                removed: pkg.name.clone(),
+                dependents,
+            });
+        }
+    }
+
+    broken
+}
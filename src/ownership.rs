@@ -0,0 +1,544 @@
+// Resolve which packages own files, binaries and systemd units
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::exec::CommandExt;
+
+/// Packages owning `path` on the live system, via the native package manager
+pub fn owning_packages(path: &str) -> Result<Vec<String>> {
+    // On merged-/usr systems the package database may record /bin instead of /usr/bin
+    for candidate in path_variants(path) {
+        let owners = query_owners(&candidate)?;
+        if !owners.is_empty() {
+            return Ok(owners);
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+fn path_variants(path: &str) -> Vec<String> {
+    let mut variants = vec![path.to_string()];
+
+    if let Ok(real) = std::fs::canonicalize(path) {
+        variants.push(real.to_string_lossy().to_string());
+    }
+    if let Some(rest) = path.strip_prefix("/usr") {
+        variants.push(rest.to_string());
+    } else if path.starts_with("/bin/") || path.starts_with("/sbin/") || path.starts_with("/lib") {
+        variants.push(format!("/usr{}", path));
+    }
+
+    variants.dedup();
+    variants
+}
+
+fn query_owners(path: &str) -> Result<Vec<String>> {
+    // pacman (Arch)
+    if let Ok(output) = Command::new("pacman").args(["-Qoq", path]).run_output() {
+        if output.status.success() {
+            return Ok(lines(&output.stdout));
+        }
+    }
+
+    // dpkg (Debian/Ubuntu): "pkg1, pkg2: /path"
+    if let Ok(output) = Command::new("dpkg").args(["-S", path]).run_output() {
+        if output.status.success() {
+            let mut owners = Vec::new();
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                if let Some((pkgs, _)) = line.split_once(": ") {
+                    for pkg in pkgs.split(", ") {
+                        // Strip the :arch qualifier
+                        let name = pkg.split(':').next().unwrap_or(pkg).trim().to_string();
+                        if !owners.contains(&name) {
+                            owners.push(name);
+                        }
+                    }
+                }
+            }
+            return Ok(owners);
+        }
+    }
+
+    // rpm (Fedora/RHEL)
+    if let Ok(output) = Command::new("rpm").args(["-qf", "--qf", "%{NAME}\\n", path]).run_output() {
+        if output.status.success() {
+            return Ok(lines(&output.stdout));
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+/// Files that make up a systemd unit: the unit file and its ExecStart binaries
+pub fn unit_files(unit: &str) -> Result<Vec<String>> {
+    let output = Command::new("systemctl")
+        .args(["show", "--no-pager", "-p", "FragmentPath", "-p", "ExecStart", unit])
+        .run_output()
+        .context("Failed to run systemctl")?;
+
+    let mut files = Vec::new();
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(path) = line.strip_prefix("FragmentPath=") {
+            if !path.is_empty() {
+                files.push(path.to_string());
+            }
+        } else if let Some(exec) = line.strip_prefix("ExecStart=") {
+            // "{ path=/usr/bin/sddm ; argv[]=/usr/bin/sddm ; ... }"
+            for part in exec.split(';') {
+                if let Some(path) = part.trim().trim_start_matches('{').trim().strip_prefix("path=") {
+                    files.push(path.trim().to_string());
+                }
+            }
+        }
+    }
+
+    if files.is_empty() {
+        anyhow::bail!("Unit not found: {}", unit);
+    }
+
+    Ok(files)
+}
+
+/// Resolve a command name to its full path using PATH
+pub fn resolve_path(target: &str) -> String {
+    if target.contains('/') {
+        return target.to_string();
+    }
+
+    Command::new("which")
+        .arg(target)
+        .run_output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_else(|| target.to_string())
+}
+
+fn lines(stdout: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(stdout)
+        .lines()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+/// Packages owning `path` inside another root (e.g. a snapshot), read from
+/// that root's package database instead of the live system's
+pub fn owning_packages_in(root: &str, path: &str) -> Result<Vec<String>> {
+    let root = Path::new(root);
+    let mut owners = Vec::new();
+
+    for candidate in path_variants(path) {
+        let relative = candidate.trim_start_matches('/');
+
+        // pacman: var/lib/pacman/local/<name>-<ver>-<rel>/files lists paths without leading '/'
+        let pacman_db = root.join("var/lib/pacman/local");
+        if let Ok(entries) = fs::read_dir(&pacman_db) {
+            for entry in entries.flatten() {
+                let files = fs::read_to_string(entry.path().join("files")).unwrap_or_default();
+                if files.lines().any(|l| l == relative) {
+                    let desc = fs::read_to_string(entry.path().join("desc")).unwrap_or_default();
+                    if let Some(name) = desc_field(&desc, "%NAME%") {
+                        owners.push(name);
+                    }
+                }
+            }
+        }
+
+        // dpkg: var/lib/dpkg/info/<name>[:arch].list lists absolute paths
+        let dpkg_info = root.join("var/lib/dpkg/info");
+        if let Ok(entries) = fs::read_dir(&dpkg_info) {
+            for entry in entries.flatten() {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                if let Some(pkg) = file_name.strip_suffix(".list") {
+                    let list = fs::read_to_string(entry.path()).unwrap_or_default();
+                    if list.lines().any(|l| l == candidate) {
+                        owners.push(pkg.split(':').next().unwrap_or(pkg).to_string());
+                    }
+                }
+            }
+        }
+
+        // rpm: query the root's database with the host rpm
+        if owners.is_empty() && root.join("var/lib/rpm").exists() {
+            if let Ok(output) = Command::new("rpm")
+                .arg("--root")
+                .arg(root)
+                .args(["-qf", "--qf", "%{NAME}\\n", &candidate])
+                .run_output()
+            {
+                if output.status.success() {
+                    owners.extend(lines(&output.stdout));
+                }
+            }
+        }
+
+        if !owners.is_empty() {
+            break;
+        }
+    }
+
+    owners.dedup();
+    Ok(owners)
+}
+
+/// Value of a `%FIELD%` section in a pacman desc file
+pub fn desc_field(desc: &str, field: &str) -> Option<String> {
+    let mut lines = desc.lines();
+    while let Some(line) = lines.next() {
+        if line == field {
+            return lines.next().map(|v| v.to_string());
+        }
+    }
+    None
+}
+
+/// All values of a multi-line `%FIELD%` section in a pacman desc file
+pub fn desc_list(desc: &str, field: &str) -> Vec<String> {
+    desc.lines()
+        .skip_while(|l| *l != field)
+        .skip(1)
+        .take_while(|l| !l.is_empty())
+        .map(|l| l.to_string())
+        .collect()
+}
+
+/// Files installed by `package` inside `root`, as absolute paths
+pub fn package_files_in(root: &str, package: &str) -> Vec<String> {
+    let root_path = Path::new(root);
+
+    // pacman
+    if let Some(entry) = pacman_entry(root_path, package) {
+        let files = fs::read_to_string(entry.join("files")).unwrap_or_default();
+        return desc_list(&files, "%FILES%").iter().map(|l| format!("/{}", l)).collect();
+    }
+
+    // dpkg: the .list file may carry an :arch qualifier
+    let dpkg_info = root_path.join("var/lib/dpkg/info");
+    if let Ok(entries) = fs::read_dir(&dpkg_info) {
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(pkg) = file_name.strip_suffix(".list") {
+                if pkg.split(':').next() == Some(package) {
+                    return lines(&fs::read(entry.path()).unwrap_or_default());
+                }
+            }
+        }
+    }
+
+    // rpm
+    if root_path.join("var/lib/rpm").exists() {
+        if let Ok(output) = Command::new("rpm").arg("--root").arg(root).args(["-ql", package]).run_output() {
+            if output.status.success() {
+                return lines(&output.stdout);
+            }
+        }
+    }
+
+    Vec::new()
+}
+
+/// Installed version of `package` inside `root`
+pub fn package_version_in(root: &str, package: &str) -> Option<String> {
+    let root_path = Path::new(root);
+
+    // pacman
+    if let Some(entry) = pacman_entry(root_path, package) {
+        return desc_field(&fs::read_to_string(entry.join("desc")).unwrap_or_default(), "%VERSION%");
+    }
+
+    // dpkg
+    if let Ok(status) = fs::read_to_string(root_path.join("var/lib/dpkg/status")) {
+        let stanza = status
+            .split("\n\n")
+            .find(|s| s.lines().any(|l| l.strip_prefix("Package: ").map(str::trim) == Some(package)))?;
+        return stanza
+            .lines()
+            .find_map(|l| l.strip_prefix("Version: "))
+            .map(|v| v.trim().to_string());
+    }
+
+    // rpm
+    if root_path.join("var/lib/rpm").exists() {
+        let output = Command::new("rpm")
+            .arg("--root")
+            .arg(root)
+            .args(["-q", "--qf", "%{VERSION}-%{RELEASE}\\n", package])
+            .run_output()
+            .ok()?;
+        if output.status.success() {
+            return lines(&output.stdout).into_iter().next();
+        }
+    }
+
+    None
+}
+
+/// Installed packages inside `root` that depend on `package` or on any of `sonames`
+pub fn dependents_in(root: &str, package: &str, sonames: &[String]) -> Vec<String> {
+    let root_path = Path::new(root);
+    let mut dependents = Vec::new();
+
+    // pacman: %DEPENDS% holds names or soname provides like "libicuuc.so=74-64"
+    if let Ok(entries) = fs::read_dir(root_path.join("var/lib/pacman/local")) {
+        for entry in entries.flatten() {
+            let desc = fs::read_to_string(entry.path().join("desc")).unwrap_or_default();
+            let depends = desc_list(&desc, "%DEPENDS%");
+            let matches = depends.iter().map(|d| dependency_name(d)).any(|dep| {
+                dep == package || sonames.iter().any(|s| crate::libs::soname_family(s) == dep)
+            });
+            if matches {
+                if let Some(name) = desc_field(&desc, "%NAME%") {
+                    dependents.push(name);
+                }
+            }
+        }
+    }
+
+    // dpkg: Depends/Pre-Depends fields in the status file
+    if let Ok(status) = fs::read_to_string(root_path.join("var/lib/dpkg/status")) {
+        for stanza in status.split("\n\n") {
+            let mut name = None;
+            let mut depends = false;
+            // Removed packages linger as "deinstall ok config-files"
+            let mut installed = false;
+            for line in stanza.lines() {
+                if let Some(n) = line.strip_prefix("Package: ") {
+                    name = Some(n.trim().to_string());
+                } else if let Some(state) = line.strip_prefix("Status: ") {
+                    installed = state.ends_with(" installed");
+                } else if let Some(list) = line
+                    .strip_prefix("Depends: ")
+                    .or_else(|| line.strip_prefix("Pre-Depends: "))
+                {
+                    depends |= list
+                        .split([',', '|'])
+                        .map(dependency_name)
+                        .any(|dep| dep == package);
+                }
+            }
+            if let (Some(name), true, true) = (name, depends, installed) {
+                dependents.push(name);
+            }
+        }
+    }
+
+    // rpm: requirements on the package or its soname capabilities
+    if root_path.join("var/lib/rpm").exists() {
+        let mut capabilities = vec![package.to_string()];
+        for soname in sonames {
+            capabilities.push(soname.clone());
+            capabilities.push(format!("{}()(64bit)", soname));
+        }
+        for capability in capabilities {
+            if let Ok(output) = Command::new("rpm")
+                .arg("--root")
+                .arg(root)
+                .args(["-q", "--qf", "%{NAME}\\n", "--whatrequires", &capability])
+                .run_output()
+            {
+                if output.status.success() {
+                    dependents.extend(lines(&output.stdout));
+                }
+            }
+        }
+    }
+
+    dependents.sort();
+    dependents.dedup();
+    dependents.retain(|d| d != package);
+    dependents
+}
+
+/// Installed packages inside `root` named `name` or providing it
+pub fn providers_in(root: &str, name: &str) -> Vec<String> {
+    let root_path = Path::new(root);
+    let mut providers = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(root_path.join("var/lib/pacman/local")) {
+        for entry in entries.flatten() {
+            let desc = fs::read_to_string(entry.path().join("desc")).unwrap_or_default();
+            let pkg = desc_field(&desc, "%NAME%").unwrap_or_default();
+            if pkg == name || desc_list(&desc, "%PROVIDES%").iter().any(|p| dependency_name(p) == name) {
+                providers.push(pkg);
+            }
+        }
+    }
+
+    if let Ok(status) = fs::read_to_string(root_path.join("var/lib/dpkg/status")) {
+        for stanza in status.split("\n\n") {
+            let mut pkg = None;
+            let mut provides = false;
+            let mut installed = false;
+            for line in stanza.lines() {
+                if let Some(n) = line.strip_prefix("Package: ") {
+                    pkg = Some(n.trim().to_string());
+                } else if let Some(list) = line.strip_prefix("Provides: ") {
+                    provides = list.split(',').any(|p| dependency_name(p) == name);
+                } else if let Some(state) = line.strip_prefix("Status: ") {
+                    installed = state.ends_with(" installed");
+                }
+            }
+            if let (Some(pkg), true) = (pkg, installed) {
+                if pkg == name || provides {
+                    providers.push(pkg);
+                }
+            }
+        }
+    }
+
+    if root_path.join("var/lib/rpm").exists() {
+        if let Ok(output) = Command::new("rpm")
+            .arg("--root")
+            .arg(root)
+            .args(["-q", "--qf", "%{NAME}\\n", "--whatprovides", name])
+            .run_output()
+        {
+            if output.status.success() {
+                providers.extend(lines(&output.stdout));
+            }
+        }
+    }
+
+    providers.sort();
+    providers.dedup();
+    providers
+}
+
+/// Every (dependent, dependency) pair inside `root` where the dependent requires one exact
+/// version of the dependency (`systemd-libs=255.6-1`, `libsystemd-shared (= 255.4-1)`),
+/// so the two can only be up- or downgraded together
+pub fn exact_dependencies_in(root: &str) -> Vec<(String, String)> {
+    let root_path = Path::new(root);
+    let mut pairs = Vec::new();
+    let exact = |dep: &str| dep.contains('=') && !dep.contains(['<', '>']);
+
+    if let Ok(entries) = fs::read_dir(root_path.join("var/lib/pacman/local")) {
+        for entry in entries.flatten() {
+            let desc = fs::read_to_string(entry.path().join("desc")).unwrap_or_default();
+            let Some(name) = desc_field(&desc, "%NAME%") else {
+                continue;
+            };
+            for dep in desc_list(&desc, "%DEPENDS%").iter().filter(|d| exact(d)) {
+                pairs.push((name.clone(), dependency_name(dep).to_string()));
+            }
+        }
+    }
+
+    if let Ok(status) = fs::read_to_string(root_path.join("var/lib/dpkg/status")) {
+        for stanza in status.split("\n\n") {
+            let mut name = None;
+            let mut deps = Vec::new();
+            let mut installed = false;
+            for line in stanza.lines() {
+                if let Some(n) = line.strip_prefix("Package: ") {
+                    name = Some(n.trim().to_string());
+                } else if let Some(state) = line.strip_prefix("Status: ") {
+                    installed = state.ends_with(" installed");
+                } else if let Some(list) = line.strip_prefix("Depends: ").or_else(|| line.strip_prefix("Pre-Depends: ")) {
+                    deps.extend(list.split(',').filter(|d| exact(d)).map(|d| dependency_name(d).to_string()));
+                }
+            }
+            if let (Some(name), true) = (name, installed) {
+                pairs.extend(deps.into_iter().map(|dep| (name.clone(), dep)));
+            }
+        }
+    }
+
+    // rpm: one query lists every requirement with its comparison flags
+    if root_path.join("var/lib/rpm").exists() {
+        if let Ok(output) = Command::new("rpm")
+            .arg("--root")
+            .arg(root)
+            .args(["-qa", "--qf", "[%{NAME} %{REQUIRENAME} %{REQUIREFLAGS:depflags}\\n]"])
+            .run_output()
+        {
+            for line in lines(&output.stdout) {
+                if let [name, dep, "="] = line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                    pairs.push((name.to_string(), dependency_name(dep).to_string()));
+                }
+            }
+        }
+    }
+
+    pairs.sort();
+    pairs.dedup();
+    pairs.retain(|(dependent, dependency)| dependent != dependency);
+    pairs
+}
+
+/// Direct dependencies of `package` inside `root`
+pub fn dependencies_in(root: &str, package: &str) -> Vec<String> {
+    let root_path = Path::new(root);
+    let mut deps = Vec::new();
+
+    if let Some(entry) = pacman_entry(root_path, package) {
+        let desc = fs::read_to_string(entry.join("desc")).unwrap_or_default();
+        deps.extend(desc_list(&desc, "%DEPENDS%").iter().map(|d| dependency_name(d).to_string()));
+    } else if let Ok(status) = fs::read_to_string(root_path.join("var/lib/dpkg/status")) {
+        let header = format!("Package: {}", package);
+        if let Some(stanza) = status.split("\n\n").find(|s| s.lines().next() == Some(header.as_str())) {
+            for line in stanza.lines() {
+                if let Some(list) = line.strip_prefix("Depends: ").or_else(|| line.strip_prefix("Pre-Depends: ")) {
+                    deps.extend(list.split([',', '|']).map(|d| dependency_name(d).to_string()));
+                }
+            }
+        }
+    } else if root_path.join("var/lib/rpm").exists() {
+        // Resolve capabilities to the packages that provide them
+        if let Ok(output) = Command::new("rpm")
+            .arg("--root")
+            .arg(root)
+            .args(["-q", "--requires", package])
+            .run_output()
+        {
+            for capability in lines(&output.stdout) {
+                if let Ok(provider) = Command::new("rpm")
+                    .arg("--root")
+                    .arg(root)
+                    .args(["-q", "--qf", "%{NAME}\\n", "--whatprovides", &capability])
+                    .run_output()
+                {
+                    if provider.status.success() {
+                        deps.extend(lines(&provider.stdout));
+                    }
+                }
+            }
+        }
+    }
+
+    deps.sort();
+    deps.dedup();
+    deps.retain(|d| !d.is_empty() && d != package);
+    deps
+}
+
+/// `foo>=1.2`, `foo (>= 1.2)`, `foo:amd64` -> `foo`
+pub fn dependency_name(dep: &str) -> &str {
+    let dep = dep.trim();
+    let end = dep.find(['<', '>', '=', ' ', '(', ':']).unwrap_or(dep.len());
+    &dep[..end]
+}
+
+/// The pacman desc file of `package` inside `root`
+pub fn pacman_desc_in(root: &str, package: &str) -> Option<String> {
+    fs::read_to_string(pacman_entry(Path::new(root), package)?.join("desc")).ok()
+}
+
+/// The pacman local database directory for `package` inside `root`
+fn pacman_entry(root: &Path, package: &str) -> Option<std::path::PathBuf> {
+    let entries = fs::read_dir(root.join("var/lib/pacman/local")).ok()?;
+
+    entries.flatten().map(|e| e.path()).find(|path| {
+        // Directory names are <name>-<pkgver>-<pkgrel>
+        let dir = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        dir.starts_with(&format!("{}-", package))
+            && desc_field(&fs::read_to_string(path.join("desc")).unwrap_or_default(), "%NAME%").as_deref()
+                == Some(package)
+    })
+}
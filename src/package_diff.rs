@@ -1,10 +1,17 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::fs;
+use std::path::Path;
 use std::process::Command;
 
-use crate::snapshot::Snapshot;
+use crate::error::TraceError;
+use crate::exec::CommandExt;
+use crate::ownership::desc_field;
+use crate::snapshot::{self, Snapshot};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Package {
@@ -18,7 +25,7 @@ impl fmt::Display for Package {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PackageChange {
     Added(Package),
     Removed(Package),
@@ -35,6 +42,39 @@ impl PackageChange {
             PackageChange::Downgraded(pkg, _, _) => &pkg.name,
         }
     }
+
+    /// An upgrade or downgrade that only changed the packaging release (pkgrel, Debian
+    /// revision, RPM release), rebuilding the same upstream version
+    pub fn is_rebuild(&self) -> bool {
+        match self {
+            PackageChange::Upgraded(_, old, new) | PackageChange::Downgraded(_, old, new) => is_rebuild(old, new),
+            PackageChange::Added(_) | PackageChange::Removed(_) => false,
+        }
+    }
+
+    /// Position of the change kind in canonical order
+    fn kind_rank(&self) -> u8 {
+        match self {
+            PackageChange::Added(_) => 0,
+            PackageChange::Removed(_) => 1,
+            PackageChange::Upgraded(..) => 2,
+            PackageChange::Downgraded(..) => 3,
+        }
+    }
+}
+
+/// Sort changes into canonical order: by package name, then added, removed, upgraded,
+/// downgraded. Bisect starts from this order so the same two snapshots always give the
+/// same splits, and saved sessions resume against the same list.
+pub fn sort_canonical(changes: &mut [PackageChange]) {
+    changes.sort_by(|a, b| a.name().cmp(b.name()).then_with(|| a.kind_rank().cmp(&b.kind_rank())));
+}
+
+/// Why a package is installed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallReason {
+    Explicit,
+    Dependency,
 }
 
 #[derive(Debug)]
@@ -43,9 +83,15 @@ pub struct PackageDiff {
     pub removed: Vec<Package>,
     pub upgraded: Vec<(Package, String, String)>,
     pub downgraded: Vec<(Package, String, String)>,
+    /// Install reason of each changed package, when the package database was readable
+    pub reasons: HashMap<String, InstallReason>,
 }
 
 impl PackageDiff {
+    pub fn reason(&self, name: &str) -> Option<InstallReason> {
+        self.reasons.get(name).copied()
+    }
+
     pub fn total_changes(&self) -> usize {
         self.added.len() + self.removed.len() + self.upgraded.len() + self.downgraded.len()
     }
@@ -79,134 +125,382 @@ impl PackageDiff {
 
         changes
     }
+
+    /// Names of every changed package, without cloning the changes
+    fn changed_names(&self) -> impl Iterator<Item = &str> {
+        self.added
+            .iter()
+            .chain(&self.removed)
+            .map(|p| p.name.as_str())
+            .chain(self.upgraded.iter().chain(&self.downgraded).map(|(p, _, _)| p.name.as_str()))
+    }
+
+    /// Look up install reasons, preferring the newer root (removed packages fall back to the older one)
+    fn record_reasons(&mut self, old_root: Option<&str>, new_root: Option<&str>) {
+        let mut reasons = HashMap::new();
+        {
+            let names: HashSet<&str> = self.changed_names().collect();
+            for root in [old_root, new_root].into_iter().flatten() {
+                for (name, reason) in install_reasons(root) {
+                    if names.contains(name.as_str()) {
+                        reasons.insert(name, reason);
+                    }
+                }
+            }
+        }
+        self.reasons = reasons;
+    }
 }
 
 pub fn compute_diff(snapshot1: &Snapshot, snapshot2: &Snapshot) -> Result<PackageDiff> {
     let packages1 = get_packages_for_snapshot(snapshot1)?;
     let packages2 = get_packages_for_snapshot(snapshot2)?;
 
-    let keys1: HashSet<_> = packages1.keys().collect();
-    let keys2: HashSet<_> = packages2.keys().collect();
+    let mut diff = diff_packages(&packages1, &packages2);
+    diff.record_reasons(snapshot1.path.as_deref(), snapshot2.path.as_deref());
+    Ok(diff)
+}
 
-    // Added packages (in snapshot2, not in snapshot1)
-    let added: Vec<Package> = keys2
-        .difference(&keys1)
-        .map(|name| Package {
-            name: (*name).clone(),
-            version: packages2[*name].clone(),
-        })
-        .collect();
+/// Diff a snapshot against the packages installed on the running system
+pub fn compute_diff_to_current(snapshot: &Snapshot) -> Result<PackageDiff> {
+    compute_diff(snapshot, &snapshot::current()?)
+}
 
-    // Removed packages (in snapshot1, not in snapshot2)
-    let removed: Vec<Package> = keys1
-        .difference(&keys2)
-        .map(|name| Package {
-            name: (*name).clone(),
-            version: packages1[*name].clone(),
-        })
-        .collect();
+/// Where each good → bad change stands on a third system
+#[derive(Debug)]
+pub struct ThreeWayDiff {
+    /// Still exactly as in the bad state
+    pub persisting: Vec<PackageChange>,
+    /// Back to the good state
+    pub reverted: Vec<PackageChange>,
+    /// Neither: the version the third system has now, None if it's not installed
+    pub drifted: Vec<(PackageChange, Option<String>)>,
+}
+
+/// Diff good against bad, then check every change against `current`
+pub fn compute_diff3(good: &Snapshot, bad: &Snapshot, current: &Snapshot) -> Result<ThreeWayDiff> {
+    let mut changes = compute_diff(good, bad)?.all_changes();
+    sort_canonical(&mut changes);
+    let now = get_packages_for_snapshot(current)?;
+
+    let mut diff3 = ThreeWayDiff {
+        persisting: Vec::new(),
+        reverted: Vec::new(),
+        drifted: Vec::new(),
+    };
+    for change in changes {
+        let (before, after) = match &change {
+            PackageChange::Added(pkg) => (None, Some(pkg.version.as_str())),
+            PackageChange::Removed(pkg) => (Some(pkg.version.as_str()), None),
+            PackageChange::Upgraded(_, old, new) | PackageChange::Downgraded(_, old, new) => {
+                (Some(old.as_str()), Some(new.as_str()))
+            }
+        };
+        let installed = now.get(change.name()).map(String::as_str);
+
+        if installed == after {
+            diff3.persisting.push(change);
+        } else if installed == before {
+            diff3.reverted.push(change);
+        } else {
+            let installed = installed.map(str::to_string);
+            diff3.drifted.push((change, installed));
+        }
+    }
+    Ok(diff3)
+}
 
-    // Version changes
-    let mut upgraded = Vec::new();
-    let mut downgraded = Vec::new();
+/// One difference between two package sets, borrowed from the sets being compared
+#[derive(Debug, Clone, Copy)]
+pub enum ChangeRef<'a> {
+    Added { name: &'a str, version: &'a str },
+    Removed { name: &'a str, version: &'a str },
+    Changed { name: &'a str, old: &'a str, new: &'a str },
+}
 
-    for name in keys1.intersection(&keys2) {
-        let ver1 = &packages1[*name];
-        let ver2 = &packages2[*name];
+/// Packages as (name, version) pairs sorted by name, borrowing from `packages`
+pub fn sorted_packages(packages: &HashMap<String, String>) -> Vec<(&str, &str)> {
+    let mut sorted: Vec<(&str, &str)> = packages.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+    sorted.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    sorted
+}
 
-        if ver1 != ver2 {
+/// Merge-join two name-sorted package lists, calling `emit` for each difference in name
+/// order. Nothing is cloned or collected, so memory stays flat however many packages
+/// the systems have.
+pub fn for_each_change<'a>(old: &[(&'a str, &'a str)], new: &[(&'a str, &'a str)], mut emit: impl FnMut(ChangeRef<'a>)) {
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        let ((old_name, old_ver), (new_name, new_ver)) = (old[i], new[j]);
+        match old_name.cmp(new_name) {
+            Ordering::Less => {
+                emit(ChangeRef::Removed { name: old_name, version: old_ver });
+                i += 1;
+            }
+            Ordering::Greater => {
+                emit(ChangeRef::Added { name: new_name, version: new_ver });
+                j += 1;
+            }
+            Ordering::Equal => {
+                if old_ver != new_ver {
+                    emit(ChangeRef::Changed { name: new_name, old: old_ver, new: new_ver });
+                }
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    for &(name, version) in &old[i..] {
+        emit(ChangeRef::Removed { name, version });
+    }
+    for &(name, version) in &new[j..] {
+        emit(ChangeRef::Added { name, version });
+    }
+}
+
+/// Changes come out of the merge-join in name order, so every section is sorted
+fn diff_packages(packages1: &HashMap<String, String>, packages2: &HashMap<String, String>) -> PackageDiff {
+    let old = sorted_packages(packages1);
+    let new = sorted_packages(packages2);
+
+    let mut diff = PackageDiff {
+        added: Vec::new(),
+        removed: Vec::new(),
+        upgraded: Vec::new(),
+        downgraded: Vec::new(),
+        reasons: HashMap::new(),
+    };
+
+    // Only changed packages are copied out of the borrowed lists
+    for_each_change(&old, &new, |change| match change {
+        ChangeRef::Added { name, version } => diff.added.push(Package {
+            name: name.to_string(),
+            version: version.to_string(),
+        }),
+        ChangeRef::Removed { name, version } => diff.removed.push(Package {
+            name: name.to_string(),
+            version: version.to_string(),
+        }),
+        ChangeRef::Changed { name, old, new } => {
             let pkg = Package {
-                name: (*name).clone(),
-                version: ver2.clone(),
+                name: name.to_string(),
+                version: new.to_string(),
             };
 
             // Simple version comparison (can be improved)
-            if version_compare(ver2, ver1) {
-                upgraded.push((pkg, ver1.clone(), ver2.clone()));
+            if version_compare(new, old) {
+                diff.upgraded.push((pkg, old.to_string(), new.to_string()));
             } else {
-                downgraded.push((pkg, ver1.clone(), ver2.clone()));
+                diff.downgraded.push((pkg, old.to_string(), new.to_string()));
             }
         }
+    });
+
+    diff
+}
+
+/// Explicit vs dependency install reason of every package in `root`'s package database
+pub fn install_reasons(root: &str) -> HashMap<String, InstallReason> {
+    let root_path = Path::new(root);
+    let mut reasons = HashMap::new();
+
+    // pacman: %REASON% is 1 for dependencies and absent for explicit installs
+    if let Ok(entries) = fs::read_dir(root_path.join("var/lib/pacman/local")) {
+        for entry in entries.flatten() {
+            let desc = fs::read_to_string(entry.path().join("desc")).unwrap_or_default();
+            if let Some(name) = desc_field(&desc, "%NAME%") {
+                let reason = match desc_field(&desc, "%REASON%").as_deref() {
+                    Some("1") => InstallReason::Dependency,
+                    _ => InstallReason::Explicit,
+                };
+                reasons.insert(name, reason);
+            }
+        }
+        return reasons;
+    }
+
+    // apt: everything in dpkg status is explicit unless extended_states marks it Auto-Installed
+    if let Ok(status) = fs::read_to_string(root_path.join("var/lib/dpkg/status")) {
+        for line in status.lines() {
+            if let Some(name) = line.strip_prefix("Package: ") {
+                reasons.insert(name.trim().to_string(), InstallReason::Explicit);
+            }
+        }
+
+        let states = fs::read_to_string(root_path.join("var/lib/apt/extended_states")).unwrap_or_default();
+        for stanza in states.split("\n\n") {
+            let name = stanza.lines().find_map(|l| l.strip_prefix("Package: "));
+            if let Some(name) = name {
+                if stanza.lines().any(|l| l.trim() == "Auto-Installed: 1") {
+                    reasons.insert(name.trim().to_string(), InstallReason::Dependency);
+                }
+            }
+        }
+        return reasons;
     }
 
-    Ok(PackageDiff {
-        added,
-        removed,
-        upgraded,
-        downgraded,
-    })
+    // dnf keeps the reason in its history database; ask dnf itself
+    if root_path.join("var/lib/rpm").exists() {
+        let all = Command::new("rpm").arg("--root").arg(root).args(["-qa", "--qf", "%{NAME}\\n"]).run_output();
+        let user = Command::new("dnf")
+            .arg("--installroot")
+            .arg(root)
+            .args(["-q", "repoquery", "--userinstalled", "--qf", "%{name}\\n"])
+            .run_output();
+
+        if let (Ok(all), Ok(user)) = (all, user) {
+            if user.status.success() {
+                let explicit: HashSet<String> = String::from_utf8_lossy(&user.stdout)
+                    .lines()
+                    .map(|l| l.trim().to_string())
+                    .collect();
+                for name in String::from_utf8_lossy(&all.stdout).lines() {
+                    let reason = if explicit.contains(name) {
+                        InstallReason::Explicit
+                    } else {
+                        InstallReason::Dependency
+                    };
+                    reasons.insert(name.to_string(), reason);
+                }
+            }
+        }
+    }
+
+    reasons
 }
 
-fn get_packages_for_snapshot(snapshot: &Snapshot) -> Result<HashMap<String, String>> {
+/// Borrows the package list a manifest snapshot already carries instead of copying it
+pub fn get_packages_for_snapshot(snapshot: &Snapshot) -> Result<Cow<'_, HashMap<String, String>>> {
     if let Some(ref packages) = snapshot.packages {
-        return Ok(packages.clone());
+        return Ok(Cow::Borrowed(packages));
+    }
+
+    if let Some(root) = snapshot.path.as_deref() {
+        let packages = packages_in(root);
+        if !packages.is_empty() {
+            return Ok(Cow::Owned(packages));
+        }
+    }
+
+    // Read on demand: parsing every generation's manifest would slow down each listing
+    #[cfg(feature = "guix")]
+    if snapshot.kind.as_deref() == Some(crate::guix::GENERATION) {
+        let packages = crate::guix::packages_of(snapshot);
+        if !packages.is_empty() {
+            return Ok(Cow::Owned(packages));
+        }
     }
 
-    // Detect package manager and get package list
-    // This is a simplified version - in production, we'd read from snapshot filesystem
-    detect_current_packages()
+    // Never the running system's packages instead: that would diff against the wrong state
+    Err(TraceError::NoPackageDatabase(snapshot.id.clone()).into())
 }
 
-fn detect_current_packages() -> Result<HashMap<String, String>> {
+/// Installed packages and versions recorded in the package database under `root`
+pub fn packages_in(root: &str) -> HashMap<String, String> {
+    let root_path = Path::new(root);
     let mut packages = HashMap::new();
 
-    // Try pacman first (Arch)
-    if let Ok(output) = Command::new("pacman").arg("-Q").output() {
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-
-            for line in stdout.lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    packages.insert(parts[0].to_string(), parts[1].to_string());
-                }
+    if let Ok(entries) = fs::read_dir(root_path.join("var/lib/pacman/local")) {
+        for entry in entries.flatten() {
+            let desc = fs::read_to_string(entry.path().join("desc")).unwrap_or_default();
+            if let (Some(name), Some(version)) = (desc_field(&desc, "%NAME%"), desc_field(&desc, "%VERSION%")) {
+                packages.insert(name, version);
             }
+        }
+        return packages;
+    }
 
-            return Ok(packages);
+    if let Ok(status) = fs::read_to_string(root_path.join("var/lib/dpkg/status")) {
+        for stanza in status.split("\n\n") {
+            let field = |key: &str| stanza.lines().find_map(|l| l.strip_prefix(key)).map(|v| v.trim().to_string());
+            // Removed packages keep a stanza in config-files state
+            if field("Status: ").as_deref() != Some("install ok installed") {
+                continue;
+            }
+            if let (Some(name), Some(version)) = (field("Package: "), field("Version: ")) {
+                packages.insert(name, version);
+            }
         }
+        return packages;
     }
 
-    // Try dpkg (Debian/Ubuntu)
-    if let Ok(output) = Command::new("dpkg").arg("-l").output() {
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
+    #[cfg(feature = "rpmdb")]
+    if let Some(Ok(rpm_packages)) = crate::rpmdb::packages_in(root_path) {
+        return rpm_packages;
+    }
 
-            for line in stdout.lines() {
-                if line.starts_with("ii") {
-                    let parts: Vec<&str> = line.split_whitespace().collect();
-                    if parts.len() >= 3 {
-                        packages.insert(parts[1].to_string(), parts[2].to_string());
-                    }
+    // Guix System: no package database, the current generation's profile manifest instead
+    #[cfg(feature = "guix")]
+    if crate::guix::is_guix(root) {
+        return crate::guix::current_packages(root);
+    }
+
+    // BerkeleyDB/ndb databases, or a build without the sqlite reader: ask the host's rpm
+    if root_path.join("var/lib/rpm").exists() {
+        let output = Command::new("rpm")
+            .arg("--root")
+            .arg(root)
+            .args(["-qa", "--qf", "%{NAME} %{VERSION}-%{RELEASE}\\n"])
+            .run_output();
+        if let Ok(output) = output {
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                if let Some((name, version)) = line.split_once(' ') {
+                    packages.insert(name.to_string(), version.to_string());
                 }
             }
-
-            return Ok(packages);
         }
     }
 
-    // Try rpm (Fedora/RHEL)
-    if let Ok(output) = Command::new("rpm").arg("-qa").output() {
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
+    packages
+}
 
-            for line in stdout.lines() {
-                // Parse "package-name-version-release.arch"
-                if let Some(pkg_info) = line.rsplitn(2, '-').nth(1) {
-                    if let Some(name) = pkg_info.rsplitn(2, '-').nth(1) {
-                        let version = line.strip_prefix(name).unwrap_or("").trim_start_matches('-');
-                        packages.insert(name.to_string(), version.to_string());
-                    }
-                }
-            }
+/// Packages on the running system, read from its package database like any snapshot root
+pub fn detect_current_packages() -> Result<HashMap<String, String>> {
+    #[cfg(feature = "fixtures")]
+    if let Some(packages) = crate::fixture::current_packages() {
+        return Ok(packages);
+    }
 
-            return Ok(packages);
-        }
+    Ok(packages_in("/"))
+}
+
+/// How far a version moved: the position of the first numeric component that differs
+/// (0 = major, 1 = minor, ...) and by how much. A lower position, then a larger amount,
+/// is a bigger jump; an epoch change counts as major. None when the versions have no
+/// differing numeric component.
+pub fn version_jump(old: &str, new: &str) -> Option<(usize, u64)> {
+    let split_epoch = |v: &str| -> (u64, Vec<u64>) {
+        let (epoch, rest) = v
+            .split_once(':')
+            .and_then(|(epoch, rest)| Some((epoch.parse().ok()?, rest)))
+            .unwrap_or((0, v));
+        (epoch, rest.split(&['.', '-', '_'][..]).filter_map(|s| s.parse().ok()).collect())
+    };
+    let ((old_epoch, old), (new_epoch, new)) = (split_epoch(old), split_epoch(new));
+    if old_epoch != new_epoch {
+        return Some((0, old_epoch.abs_diff(new_epoch)));
     }
 
-    Ok(packages)
+    old.iter()
+        .zip(new.iter())
+        .enumerate()
+        .find(|(_, (a, b))| a != b)
+        .map(|(i, (a, b))| (i, a.abs_diff(*b)))
+}
+
+/// Whether two versions share epoch and upstream version and differ only after the last
+/// '-' (1.2.3-1 -> 1.2.3-2, 1.2-3 -> 1.2-3+b1, 6.1-2.fc40 -> 6.1-3.fc40)
+pub fn is_rebuild(old: &str, new: &str) -> bool {
+    match (old.rsplit_once('-'), new.rsplit_once('-')) {
+        (Some((old_upstream, old_release)), Some((new_upstream, new_release))) => {
+            old_upstream == new_upstream && old_release != new_release
+        }
+        _ => false,
+    }
 }
 
-fn version_compare(v1: &str, v2: &str) -> bool {
+/// True if `v1` is newer than `v2`
+pub fn version_compare(v1: &str, v2: &str) -> bool {
     // Simple version comparison
     // In production, use a proper version comparison library
 
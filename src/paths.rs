@@ -0,0 +1,68 @@
+// XDG locations for eshu-trace's own files, migrated from the old ~/.cache directory
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const APP_DIR: &str = "eshu-trace";
+
+/// Files that must survive cache cleanups (license, activation)
+pub fn config_file(name: &str) -> PathBuf {
+    located(xdg_dir("XDG_CONFIG_HOME", ".config"), name)
+}
+
+/// Resumable working state (e.g. an in-progress kernel bisect)
+pub fn state_file(name: &str) -> PathBuf {
+    located(xdg_dir("XDG_STATE_HOME", ".local/state"), name)
+}
+
+/// Sockets and other files that only live as long as the login session
+pub fn runtime_file(name: &str) -> PathBuf {
+    match std::env::var("XDG_RUNTIME_DIR").ok().map(PathBuf::from).filter(|p| p.is_absolute()) {
+        Some(dir) => dir.join(APP_DIR).join(name),
+        None => state_file(name),
+    }
+}
+
+fn home() -> PathBuf {
+    PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| "/root".to_string()))
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    // The spec says relative values must be ignored
+    let base = std::env::var(var)
+        .ok()
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .unwrap_or_else(|| home().join(fallback));
+    base.join(APP_DIR)
+}
+
+/// Path of `name` inside `dir`, moving it over from ~/.cache/eshu-trace first if needed
+fn located(dir: PathBuf, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    let legacy = home().join(".cache").join(APP_DIR).join(name);
+
+    if path.exists() || !legacy.exists() {
+        return path;
+    }
+
+    match migrate(&legacy, &path) {
+        Ok(()) => path,
+        // Keep using the old file rather than losing it
+        Err(_) => legacy,
+    }
+}
+
+fn migrate(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // rename fails across filesystems (e.g. ~/.cache on tmpfs)
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+
+    Ok(())
+}
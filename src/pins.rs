@@ -0,0 +1,380 @@
+// Package pins applied by eshu-trace, recorded so they can be listed and lifted later
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::audit;
+use crate::exec::CommandExt;
+use crate::package_diff::version_compare;
+use crate::paths;
+use crate::test_runner::which;
+
+const APT_PREFERENCES_DIR: &str = "/etc/apt/preferences.d";
+/// How often `eshu-trace status` re-queries the repos for pinned packages
+const CHECK_HOURS: i64 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+pub enum PinMethod {
+    /// apt-mark hold: no updates at all for the package
+    Hold,
+    /// preferences.d pin (priority 1001) on the working version; other packages keep updating
+    Preferences,
+    /// preferences.d rule that refuses the broken version but accepts later fixes
+    Ceiling,
+    /// zypper addlock (openSUSE)
+    Lock,
+}
+
+impl PinMethod {
+    pub fn description(&self) -> &'static str {
+        match self {
+            PinMethod::Hold => "apt-mark hold (blocks every update of the package)",
+            PinMethod::Preferences => "preferences.d pin on the working version",
+            PinMethod::Ceiling => "preferences.d rule refusing only the broken version",
+            PinMethod::Lock => "zypper lock",
+        }
+    }
+
+    /// Method used when none is chosen explicitly
+    pub fn default_for(distro: &str) -> Option<Self> {
+        if is_apt(distro) {
+            Some(PinMethod::Preferences)
+        } else if is_zypper(distro) {
+            Some(PinMethod::Lock)
+        } else {
+            None
+        }
+    }
+
+    pub fn supported_on(&self, distro: &str) -> bool {
+        match self {
+            PinMethod::Hold | PinMethod::Preferences | PinMethod::Ceiling => is_apt(distro),
+            PinMethod::Lock => is_zypper(distro),
+        }
+    }
+}
+
+fn is_apt(distro: &str) -> bool {
+    matches!(distro, "ubuntu" | "debian" | "linuxmint" | "pop")
+}
+
+pub fn is_zypper(distro: &str) -> bool {
+    distro.starts_with("opensuse") || matches!(distro, "sles" | "sled")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pin {
+    pub package: String,
+    /// Version the package is kept at
+    pub version: String,
+    /// Version that caused the regression, when known
+    #[serde(default)]
+    pub bad_version: Option<String>,
+    pub method: PinMethod,
+    /// File written for the pin, removed when the pin is lifted
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    pub created_at: String,
+    /// Last time the repos were queried for a release newer than the broken version
+    #[serde(default)]
+    pub last_checked: Option<String>,
+    /// Newest repo version past the broken one, once one shows up
+    #[serde(default)]
+    pub fixed_version: Option<String>,
+    /// Test that reproduced the regression, for re-testing new releases
+    #[serde(default)]
+    pub test: Option<RecordedTest>,
+    /// Outcome of the last re-test of a new release
+    #[serde(default)]
+    pub retest: Option<Retest>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedTest {
+    pub command: String,
+    /// Snapshot the candidate version is applied on top of (the last known-good state)
+    pub snapshot: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Retest {
+    pub version: String,
+    pub passed: bool,
+    pub tested_at: String,
+}
+
+impl Pin {
+    fn needs_check(&self) -> bool {
+        let checked = self
+            .last_checked
+            .as_deref()
+            .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok());
+        match checked {
+            Some(t) => chrono::Local::now().signed_duration_since(t) > chrono::Duration::hours(CHECK_HOURS),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PinRegistry {
+    #[serde(default)]
+    pub pins: Vec<Pin>,
+}
+
+impl PinRegistry {
+    pub fn load() -> Result<Self> {
+        let path = registry_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = fs::read_to_string(&path).context("Failed to read pin registry")?;
+        serde_json::from_str(&data).context("Failed to parse pin registry")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = registry_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn find(&self, package: &str) -> Option<&Pin> {
+        self.pins.iter().find(|p| p.package == package)
+    }
+}
+
+fn registry_path() -> PathBuf {
+    paths::config_file("pins.json")
+}
+
+/// Pin `package` with `method` in the system at `root` (a mounted system in recovery) and
+/// record it in the registry. Recorded paths are as seen from that system once booted.
+pub fn apply(
+    root: &str,
+    package: &str,
+    version: &str,
+    bad_version: Option<&str>,
+    method: PinMethod,
+    test: Option<RecordedTest>,
+) -> Result<Pin> {
+    let mut registry = PinRegistry::load()?;
+    if let Some(existing) = registry.find(package).cloned() {
+        lift(&existing)?;
+        registry.pins.retain(|p| p.package != package);
+    }
+
+    let file = match method {
+        PinMethod::Hold => {
+            let mut cmd = Command::new("sudo");
+            if root != "/" {
+                cmd.args(["chroot", root]);
+            }
+            let status = audit::run(&format!("pin: hold {}", package), cmd.args(["apt-mark", "hold", package]))?;
+            if !status.success() {
+                anyhow::bail!("apt-mark hold {} failed", package);
+            }
+            None
+        }
+        PinMethod::Preferences => Some(write_preferences(root, package, version, "1001")?),
+        PinMethod::Ceiling => {
+            let bad = bad_version.context("A ceiling pin needs the broken version")?;
+            Some(write_preferences(root, package, bad, "-1")?)
+        }
+        PinMethod::Lock => {
+            let mut cmd = Command::new("sudo");
+            cmd.args(["zypper", "--non-interactive"]);
+            if root != "/" {
+                cmd.args(["--root", root]);
+            }
+            let status = audit::run(&format!("pin: lock {}", package), cmd.args(["addlock", package]))?;
+            if !status.success() {
+                anyhow::bail!("zypper addlock {} failed", package);
+            }
+            None
+        }
+    };
+
+    let pin = Pin {
+        package: package.to_string(),
+        version: version.to_string(),
+        bad_version: bad_version.map(String::from),
+        method,
+        file,
+        created_at: chrono::Local::now().to_rfc3339(),
+        last_checked: None,
+        fixed_version: None,
+        test,
+        retest: None,
+    };
+
+    registry.pins.push(pin.clone());
+    registry.save()?;
+    Ok(pin)
+}
+
+/// Remember how a re-test of `version` went
+pub fn record_retest(package: &str, version: &str, passed: bool) -> Result<()> {
+    let mut registry = PinRegistry::load()?;
+    if let Some(pin) = registry.pins.iter_mut().find(|p| p.package == package) {
+        pin.retest = Some(Retest {
+            version: version.to_string(),
+            passed,
+            tested_at: chrono::Local::now().to_rfc3339(),
+        });
+        registry.save()?;
+    }
+    Ok(())
+}
+
+/// Lift the pin on `package`, returning it if there was one
+pub fn remove(package: &str) -> Result<Option<Pin>> {
+    let mut registry = PinRegistry::load()?;
+    let pin = match registry.find(package).cloned() {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+
+    lift(&pin)?;
+    registry.pins.retain(|p| p.package != package);
+    registry.save()?;
+    Ok(Some(pin))
+}
+
+fn lift(pin: &Pin) -> Result<()> {
+    if pin.method == PinMethod::Hold {
+        audit::run(
+            &format!("pin: unhold {}", pin.package),
+            Command::new("sudo").args(["apt-mark", "unhold", &pin.package]),
+        )?;
+    }
+
+    if pin.method == PinMethod::Lock {
+        audit::run(
+            &format!("pin: unlock {}", pin.package),
+            Command::new("sudo").args(["zypper", "--non-interactive", "removelock", &pin.package]),
+        )?;
+    }
+
+    if let Some(file) = &pin.file {
+        if file.exists() {
+            audit::run(
+                &format!("pin: remove {}", pin.package),
+                Command::new("sudo").arg("rm").arg(file),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// /etc/apt/preferences.d/eshu-trace-<package> (apt ignores names with '+') pinning `version` at
+/// `priority`, written inside `root`. Returns the path as seen from that system.
+fn write_preferences(root: &str, package: &str, version: &str, priority: &str) -> Result<PathBuf> {
+    let path = Path::new(APT_PREFERENCES_DIR).join(format!("eshu-trace-{}", package.replace('+', "-")));
+    let target = Path::new(root).join(path.strip_prefix("/").unwrap_or(&path));
+    let contents = format!(
+        "# Written by eshu-trace; lift with: eshu-trace pin remove {0}\nPackage: {0}\nPin: version {1}\nPin-Priority: {2}\n",
+        package, version, priority
+    );
+
+    // Installed through sudo so this works without running eshu-trace itself as root
+    let staged = tempfile::NamedTempFile::new()?;
+    fs::write(staged.path(), contents)?;
+    let status = audit::run(
+        &format!("pin: write {}", target.display()),
+        Command::new("sudo").args(["install", "-m", "644"]).arg(staged.path()).arg(&target),
+    )?;
+    if !status.success() {
+        anyhow::bail!("Failed to write {}", target.display());
+    }
+
+    Ok(path)
+}
+
+/// Query the repos for each pin with a known broken version and record any newer release.
+/// With `due_only`, pins checked within the last day are skipped. Returns pins with a fix available.
+pub fn check(due_only: bool) -> Result<Vec<Pin>> {
+    let mut registry = PinRegistry::load()?;
+    let mut changed = false;
+
+    for pin in registry.pins.iter_mut() {
+        let bad = match &pin.bad_version {
+            Some(b) => b.clone(),
+            None => continue,
+        };
+        if due_only && !pin.needs_check() {
+            continue;
+        }
+
+        // A repo that cannot be queried right now is tried again next time
+        let newest = match newest_available(&pin.package) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        pin.fixed_version = newest.filter(|v| v != &bad && version_compare(v, &bad));
+        pin.last_checked = Some(chrono::Local::now().to_rfc3339());
+        changed = true;
+    }
+
+    if changed {
+        registry.save()?;
+    }
+
+    Ok(registry.pins.into_iter().filter(|p| p.fixed_version.is_some()).collect())
+}
+
+/// Newest version of `package` the configured repos offer, ignoring pins
+fn newest_available(package: &str) -> Result<Option<String>> {
+    let mut versions: Vec<String> = if which("apt-cache") {
+        // madison lists every repo version regardless of preferences.d pins
+        let output = Command::new("apt-cache").args(["madison", package]).run_output()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|l| l.split('|').nth(1))
+            .map(|v| v.trim().to_string())
+            .collect()
+    } else if which("zypper") {
+        // Locked packages are still listed by search
+        let output = Command::new("zypper")
+            .args(["--non-interactive", "--quiet", "search", "--details", "--match-exact", package])
+            .run_output()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|l| {
+                let columns: Vec<&str> = l.split('|').map(str::trim).collect();
+                (columns.len() > 3 && columns[1] == package).then(|| columns[3].to_string())
+            })
+            .collect()
+    } else if which("dnf") {
+        let output = Command::new("dnf")
+            .args(["-q", "repoquery", "--latest-limit", "1", "--qf", "%{evr}", package])
+            .run_output()?;
+        String::from_utf8_lossy(&output.stdout).lines().map(|l| l.trim().to_string()).collect()
+    } else if which("pacman") {
+        let output = Command::new("pacman").args(["-Si", package]).run_output()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|l| l.strip_prefix("Version"))
+            .filter_map(|l| l.split_once(':').map(|(_, v)| v.trim().to_string()))
+            .collect()
+    } else {
+        anyhow::bail!("No supported package manager found to query");
+    };
+
+    versions.retain(|v| !v.is_empty());
+    let mut newest: Option<String> = None;
+    for version in versions {
+        if newest.as_ref().map(|n| version_compare(&version, n)).unwrap_or(true) {
+            newest = Some(version);
+        }
+    }
+    Ok(newest)
+}
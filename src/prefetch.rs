@@ -0,0 +1,181 @@
+// Download every package version an automated bisect may install before it starts
+
+use anyhow::{Context, Result};
+use colored::*;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::audit;
+use crate::cache;
+use crate::error;
+use crate::fixer;
+use crate::http;
+use crate::package_diff::PackageChange;
+use crate::verify;
+
+const ARCH_ARCHIVE_URL: &str = "https://archive.archlinux.org/packages";
+const PACMAN_CACHE: &str = "/var/cache/pacman/pkg";
+const APT_CACHE: &str = "/var/cache/apt/archives";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Where each candidate package file came from
+#[derive(Debug, Default)]
+pub struct PrefetchReport {
+    pub cached: usize,
+    pub downloaded: usize,
+    /// name=version that could not be found anywhere
+    pub missing: Vec<String>,
+}
+
+/// Make sure the host package cache (shared with the test overlays) holds every version in `changes`
+pub fn prefetch(distro: &str, changes: &[PackageChange]) -> Result<PrefetchReport> {
+    let wanted: Vec<(&str, &str)> = changes
+        .iter()
+        .filter_map(|change| match change {
+            PackageChange::Added(pkg)
+            | PackageChange::Upgraded(pkg, _, _)
+            | PackageChange::Downgraded(pkg, _, _) => Some((pkg.name.as_str(), pkg.version.as_str())),
+            PackageChange::Removed(_) => None,
+        })
+        .collect();
+
+    // Names and versions come from the snapshots' package databases; one that could leave
+    // the cache directory (or pass as an apt-get option) is reported missing instead
+    let (wanted, invalid): (Vec<_>, Vec<_>) = wanted
+        .into_iter()
+        .partition(|(name, version)| fixer::is_package_token(name) && fixer::is_package_token(version));
+
+    let mut report = match distro {
+        "arch" | "manjaro" | "endeavouros" => prefetch_pacman(&wanted)?,
+        "ubuntu" | "debian" | "linuxmint" | "pop" => prefetch_apt(&wanted)?,
+        other => anyhow::bail!("Prefetching is not supported on {}", other),
+    };
+    report.missing.extend(invalid.iter().map(|(name, version)| format!("{}={}", name, version)));
+    Ok(report)
+}
+
+fn prefetch_pacman(wanted: &[(&str, &str)]) -> Result<PrefetchReport> {
+    let mut report = PrefetchReport::default();
+    let cached: Vec<String> = fs::read_dir(PACMAN_CACHE)
+        .map(|entries| entries.flatten().map(|e| e.file_name().to_string_lossy().to_string()).collect())
+        .unwrap_or_default();
+
+    for (name, version) in wanted {
+        let prefix = format!("{}-{}-", name, version);
+        // The prefix alone would also match e.g. foo-libs for foo; the rest must be just the arch
+        let in_cache = cached.iter().any(|f| {
+            f.strip_prefix(&prefix)
+                .map(|rest| !rest.contains('-') && rest.contains(".pkg.tar.") && !rest.ends_with(".sig"))
+                .unwrap_or(false)
+        });
+        if in_cache || restore_preserved(name, version, PACMAN_CACHE) {
+            report.cached += 1;
+            continue;
+        }
+
+        print!("  {} {} {} ", "↓".cyan(), name, version.dimmed());
+        let _ = std::io::stdout().flush();
+
+        match download_arch_package(name, version) {
+            Ok(file) => {
+                println!("{}", file.dimmed());
+                report.downloaded += 1;
+            }
+            Err(e) if error::find(&e).is_some() => {
+                println!("{}", "refused".red());
+                println!("    {} {:#}", "⚠".yellow(), e);
+                report.missing.push(format!("{}={}", name, version));
+            }
+            Err(_) => {
+                println!("{}", "not found".red());
+                report.missing.push(format!("{}={}", name, version));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Copy a version preserved by `eshu-trace cache keep` into the distro cache
+fn restore_preserved(name: &str, version: &str, distro_cache: &str) -> bool {
+    let path = match cache::find(name, version) {
+        Some(p) => p,
+        None => return false,
+    };
+    let file = path.file_name().unwrap_or_default();
+    let target = Path::new(distro_cache).join(file);
+
+    let sig = path.with_file_name(format!("{}.sig", file.to_string_lossy()));
+    if sig.exists() {
+        let _ = fs::copy(&sig, target.with_file_name(format!("{}.sig", file.to_string_lossy())));
+    }
+    fs::copy(&path, &target).is_ok()
+}
+
+/// archive.archlinux.org/packages/<first letter>/<name>/<name>-<version>-<arch>.pkg.tar.<ext>
+fn download_arch_package(name: &str, version: &str) -> Result<String> {
+    let first = name.chars().next().context("Empty package name")?;
+
+    for arch in ["x86_64", "any"] {
+        for ext in ["zst", "xz"] {
+            let file = format!("{}-{}-{}.pkg.tar.{}", name, version, arch, ext);
+            let url = format!("{}/{}/{}/{}", ARCH_ARCHIVE_URL, first, name, file.replace(':', "%3A"));
+
+            if !http::download(&url, &Path::new(PACMAN_CACHE).join(&file), DOWNLOAD_TIMEOUT)
+                .with_context(|| format!("Failed to download {} (run as root)", file))?
+            {
+                continue;
+            }
+
+            // pacman only checks a detached signature that is present, so insist on one here
+            let path = Path::new(PACMAN_CACHE).join(&file);
+            let sig = http::get(&format!("{}.sig", url), DOWNLOAD_TIMEOUT)?;
+            if sig.is_success() {
+                fs::write(verify::signature_path(&path), sig.bytes())?;
+            }
+            verify::pacman_package(&path)?;
+
+            return Ok(file);
+        }
+    }
+
+    anyhow::bail!("{} {} is not in the Arch Linux Archive", name, version)
+}
+
+fn prefetch_apt(wanted: &[(&str, &str)]) -> Result<PrefetchReport> {
+    let mut report = PrefetchReport::default();
+
+    for (name, version) in wanted {
+        // Debian file names escape the epoch colon as %3a
+        let file_prefix = format!("{}_{}_", name, version.replace(':', "%3a"));
+        let in_cache = fs::read_dir(APT_CACHE)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .any(|e| e.file_name().to_string_lossy().starts_with(&file_prefix))
+            })
+            .unwrap_or(false);
+        if in_cache || restore_preserved(name, version, APT_CACHE) {
+            report.cached += 1;
+            continue;
+        }
+
+        let status = audit::run(
+            "prefetch: download",
+            Command::new("apt-get")
+                .args(["download", "-q", &format!("{}={}", name, version)])
+                .current_dir(APT_CACHE),
+        )?;
+
+        if status.success() {
+            report.downloaded += 1;
+        } else {
+            report.missing.push(format!("{}={}", name, version));
+        }
+    }
+
+    Ok(report)
+}
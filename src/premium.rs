@@ -2,12 +2,38 @@
 // NOW WITH REAL GUMROAD API VALIDATION
 
 use anyhow::{Context, Result};
+use colored::*;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config;
+use crate::http;
+use crate::paths;
 
 const FREE_TRACE_LIMIT: u32 = 3;
 
+/// How often an Eshu Premium subscription is re-checked against the installer's license
+const REVERIFY_HOURS: i64 = 24;
+
+/// How long `activate` waits for the license server before queueing the activation
+const VALIDATION_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long a run waits at exit for the retry of a queued activation
+const RETRY_GRACE: Duration = Duration::from_secs(3);
+
+/// Offline license keys look like `ESHU1.<payload>.<signature>` (base64url, no padding)
+const OFFLINE_KEY_PREFIX: &str = "ESHU1.";
+
+/// Vendor Ed25519 public key that signs offline license keys
+const VENDOR_PUBLIC_KEY: [u8; 32] = [
+    0x87, 0x52, 0x8b, 0x43, 0x47, 0x95, 0x21, 0x9f, 0xc5, 0x22, 0xef, 0xae, 0xd2, 0xd2, 0x8a, 0x11,
+    0xc0, 0xc6, 0x73, 0x8e, 0xcf, 0xb3, 0x8e, 0xd6, 0xf4, 0x7c, 0xc6, 0x3a, 0xca, 0xca, 0x67, 0x4a,
+];
+
 #[derive(Debug, Deserialize)]
 struct GumroadResponse {
     success: bool,
@@ -23,6 +49,59 @@ struct GumroadPurchase {
     product_name: String,
 }
 
+/// Response of a self-hosted license server (see README)
+#[derive(Debug, Deserialize)]
+struct ServerResponse {
+    valid: bool,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// Signed payload of an offline license key
+#[derive(Debug, Deserialize)]
+struct OfflineLicense {
+    product: String,
+    email: String,
+    license_type: LicenseType,
+    #[serde(default)]
+    expires_at: Option<String>,
+}
+
+/// Outcome of `activate`
+pub enum Activation {
+    Activated(String),
+    Rejected(String),
+    /// The server could not be reached; retried on later runs
+    Queued(String),
+}
+
+/// An online activation waiting for the network
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingActivation {
+    pub license_key: String,
+    pub email: String,
+    pub queued_at: String,
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+/// Retry of a queued activation started by this run
+static RETRY: Mutex<Option<(PendingActivation, Receiver<Validation>)>> = Mutex::new(None);
+
+/// What a valid license check entitles to; the caller writes it to the license file
+#[derive(Debug)]
+enum Grant {
+    /// The key itself was accepted
+    Standalone,
+    /// Eshu Premium is active, with its subscription end
+    Premium(Option<String>),
+}
+
+/// Answer of a background license check: None if the key was rejected
+type Validation = Result<Option<Grant>>;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TraceLicense {
     pub license_key: Option<String>,
@@ -30,6 +109,12 @@ pub struct TraceLicense {
     pub email: Option<String>,
     pub activated_at: Option<String>,
     pub traces_used: u32,
+    /// End of the subscription period (RFC 3339); None for perpetual licenses
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    /// Last time the subscription was re-verified
+    #[serde(default)]
+    pub verified_at: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -39,6 +124,128 @@ pub enum LicenseType {
     Premium,         // Part of eshu-installer Premium
 }
 
+/// Capabilities that depend on the license
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Running a bisect at all (metered on the trial)
+    Trace,
+    /// Unattended bisect through a test driver
+    AutomatedBisect,
+    /// Plain-language culprit analysis by an LLM (Eshu Premium only)
+    AiAnalysis,
+    /// Checking pending upgrades against the community database (Eshu Premium only)
+    Oracle,
+}
+
+impl LicenseType {
+    /// Features unlocked by this license type
+    pub fn features(&self) -> &'static [Feature] {
+        match self {
+            LicenseType::Trial => &[Feature::Trace],
+            LicenseType::Standalone => &[Feature::Trace, Feature::AutomatedBisect],
+            LicenseType::Premium => &[Feature::Trace, Feature::AutomatedBisect, Feature::AiAnalysis, Feature::Oracle],
+        }
+    }
+}
+
+/// Decides which features a command may use
+///
+/// Commands take a gate instead of reading the license themselves, so other
+/// sources (fixed feature sets, time-limited unlocks) can be swapped in.
+pub trait FeatureGate {
+    fn is_enabled(&self, feature: Feature) -> bool;
+
+    /// Record one use of a metered feature
+    fn record_use(&mut self, feature: Feature) -> Result<()>;
+
+    /// Uses of a metered feature left, or None when they are unlimited
+    fn remaining_uses(&self, _feature: Feature) -> Option<u32> {
+        None
+    }
+
+    /// License behind the gate, for status banners; None when it isn't backed by one
+    fn license_type(&self) -> Option<&LicenseType> {
+        None
+    }
+}
+
+/// The gate commands run under: the license file, or everything unlocked for fixture runs
+pub fn gate() -> Result<Box<dyn FeatureGate>> {
+    #[cfg(feature = "fixtures")]
+    if crate::fixture::root().is_some() {
+        return Ok(Box::new(FixedGate::new(LicenseType::Premium.features())));
+    }
+    Ok(Box::new(LicenseGate::load()?))
+}
+
+/// Gate backed by the license file
+pub struct LicenseGate {
+    license: TraceLicense,
+}
+
+impl LicenseGate {
+    pub fn load() -> Result<Self> {
+        Ok(Self {
+            license: get_license()?,
+        })
+    }
+}
+
+impl FeatureGate for LicenseGate {
+    fn is_enabled(&self, feature: Feature) -> bool {
+        match feature {
+            // The trial unlocks tracing only until its free traces run out
+            Feature::Trace => self.license.can_trace(),
+            _ => self.license.license_type.features().contains(&feature),
+        }
+    }
+
+    fn record_use(&mut self, feature: Feature) -> Result<()> {
+        if feature == Feature::Trace {
+            self.license.increment_usage();
+            save_license(&self.license)?;
+        }
+        Ok(())
+    }
+
+    fn remaining_uses(&self, feature: Feature) -> Option<u32> {
+        match feature {
+            Feature::Trace => self.license.remaining_traces(),
+            _ => None,
+        }
+    }
+
+    fn license_type(&self) -> Option<&LicenseType> {
+        Some(&self.license.license_type)
+    }
+}
+
+/// Gate with a fixed feature set and nothing metered
+#[cfg(any(test, feature = "fixtures"))]
+pub struct FixedGate {
+    features: Vec<Feature>,
+}
+
+#[cfg(any(test, feature = "fixtures"))]
+impl FixedGate {
+    pub fn new(features: &[Feature]) -> Self {
+        Self {
+            features: features.to_vec(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "fixtures"))]
+impl FeatureGate for FixedGate {
+    fn is_enabled(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    fn record_use(&mut self, _feature: Feature) -> Result<()> {
+        Ok(())
+    }
+}
+
 impl Default for TraceLicense {
     fn default() -> Self {
         Self {
@@ -47,6 +254,8 @@ impl Default for TraceLicense {
             email: None,
             activated_at: None,
             traces_used: 0,
+            expires_at: None,
+            verified_at: None,
         }
     }
 }
@@ -75,6 +284,32 @@ impl TraceLicense {
     pub fn increment_usage(&mut self) {
         self.traces_used += 1;
     }
+
+    /// Whole days until the subscription ends, if it has an end date
+    pub fn days_remaining(&self) -> Option<i64> {
+        let expires = parse_time(self.expires_at.as_deref()?)?;
+        Some((expires - chrono::Utc::now()).num_days())
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .as_deref()
+            .and_then(parse_time)
+            .is_some_and(|t| t <= chrono::Utc::now())
+    }
+
+    fn needs_reverify(&self) -> bool {
+        match self.verified_at.as_deref().and_then(parse_time) {
+            Some(t) => chrono::Utc::now() - t > chrono::Duration::hours(REVERIFY_HOURS),
+            None => true,
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|t| t.with_timezone(&chrono::Utc))
 }
 
 pub fn get_license() -> Result<TraceLicense> {
@@ -90,12 +325,51 @@ pub fn get_license() -> Result<TraceLicense> {
     let data = fs::read_to_string(&license_path)
         .context("Failed to read license file")?;
 
-    let license: TraceLicense = serde_json::from_str(&data)
+    let mut license: TraceLicense = serde_json::from_str(&data)
         .context("Failed to parse license file")?;
 
+    if refresh_subscription(&mut license)? {
+        save_license(&license)?;
+    }
+
     Ok(license)
 }
 
+/// Re-check a Premium subscription and fall back to the trial once it lapses.
+/// Returns true if the license changed.
+fn refresh_subscription(license: &mut TraceLicense) -> Result<bool> {
+    if license.license_type == LicenseType::Trial {
+        return Ok(false);
+    }
+
+    let mut changed = false;
+
+    if license.license_type == LicenseType::Premium && license.needs_reverify() {
+        // A missing installer license is not proof of cancellation; rely on the stored expiry
+        if let Some(data) = eshu_premium_data()? {
+            if data.get("tier").and_then(|t| t.as_str()) == Some("premium") {
+                license.expires_at = subscription_expiry(&data);
+            } else {
+                license.expires_at = Some(chrono::Utc::now().to_rfc3339());
+            }
+            license.verified_at = Some(chrono::Utc::now().to_rfc3339());
+            changed = true;
+        }
+    }
+
+    if license.is_expired() {
+        eprintln!(
+            "Your Eshu Trace subscription has expired; continuing with the free trial. Renew at {}",
+            get_eshu_premium_url()
+        );
+        license.license_type = LicenseType::Trial;
+        license.expires_at = None;
+        changed = true;
+    }
+
+    Ok(changed)
+}
+
 pub fn save_license(license: &TraceLicense) -> Result<()> {
     let license_path = get_license_path();
 
@@ -110,70 +384,258 @@ pub fn save_license(license: &TraceLicense) -> Result<()> {
     Ok(())
 }
 
-pub fn is_premium() -> Result<bool> {
-    let license = get_license()?;
-    Ok(license.license_type == LicenseType::Standalone
-       || license.license_type == LicenseType::Premium)
-}
+pub fn activate_license(key: &str, email: &str) -> Result<Activation> {
+    // A new attempt replaces whatever was queued
+    clear_pending()?;
+
+    if is_offline_key(key) {
+        return activate_offline_license(key, email);
+    }
 
-pub fn check_can_trace() -> Result<bool> {
-    let license = get_license()?;
-    Ok(license.can_trace())
+    // Validate license key with the configured server, or Gumroad
+    let error = match validate_in_background(key, email).recv_timeout(VALIDATION_TIMEOUT) {
+        Ok(Ok(Some(grant))) => {
+            apply_grant(key, email, grant)?;
+            return Ok(Activation::Activated("License activated successfully!".to_string()));
+        }
+        Ok(Ok(None)) => return Ok(Activation::Rejected("Invalid license key".to_string())),
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => format!("no answer within {}s", VALIDATION_TIMEOUT.as_secs()),
+    };
+
+    if !http::available() {
+        anyhow::bail!(error);
+    }
+
+    save_pending(&PendingActivation {
+        license_key: key.to_string(),
+        email: email.to_string(),
+        queued_at: chrono::Utc::now().to_rfc3339(),
+        attempts: 1,
+        last_error: Some(error.clone()),
+    })?;
+    Ok(Activation::Queued(format!(
+        "Could not validate the license ({}). It will be retried automatically on the next runs.",
+        error
+    )))
 }
 
-pub fn increment_trace_usage() -> Result<()> {
+fn apply_standalone(key: &str, email: &str) -> Result<()> {
     let mut license = get_license()?;
-    license.increment_usage();
-    save_license(&license)?;
+    license.license_key = Some(key.to_string());
+    license.email = Some(email.to_string());
+    license.license_type = LicenseType::Standalone;
+    license.activated_at = Some(chrono::Utc::now().to_rfc3339());
+    // Standalone licenses don't lapse; a subscription end left from Premium would expire it
+    license.expires_at = None;
+    license.verified_at = None;
+    save_license(&license)
+}
+
+fn apply_grant(key: &str, email: &str, grant: Grant) -> Result<()> {
+    match grant {
+        Grant::Standalone => apply_standalone(key, email),
+        Grant::Premium(expires_at) => {
+            let mut license = get_license()?;
+            license.license_type = LicenseType::Premium;
+            license.expires_at = expires_at;
+            license.verified_at = Some(chrono::Utc::now().to_rfc3339());
+            save_license(&license)
+        }
+    }
+}
+
+/// Run the blocking validation on a worker thread so callers can give up on it.
+/// The worker only checks; it never writes the license file.
+fn validate_in_background(key: &str, email: &str) -> Receiver<Validation> {
+    let (tx, rx) = mpsc::channel();
+    let (key, email) = (key.to_string(), email.to_string());
+    std::thread::spawn(move || {
+        let _ = tx.send(validate_license(&key, &email));
+    });
+    rx
+}
+
+fn pending_path() -> PathBuf {
+    paths::config_file("pending-activation.json")
+}
+
+/// The activation queued while offline, if any
+pub fn pending_activation() -> Option<PendingActivation> {
+    let data = fs::read_to_string(pending_path()).ok()?;
+    serde_json::from_str(&data).ok()
+}
+
+fn save_pending(pending: &PendingActivation) -> Result<()> {
+    let path = pending_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(pending)?)?;
     Ok(())
 }
 
-pub fn activate_license(key: &str, email: &str) -> Result<(bool, String)> {
-    // Validate license key with Gumroad
-    if validate_gumroad_license(key, email)? {
-        let mut license = get_license()?;
-        license.license_key = Some(key.to_string());
-        license.email = Some(email.to_string());
-        license.license_type = LicenseType::Standalone;
-        license.activated_at = Some(chrono::Utc::now().to_rfc3339());
-        save_license(&license)?;
+fn clear_pending() -> Result<()> {
+    let path = pending_path();
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
 
-        Ok((true, "License activated successfully!".to_string()))
-    } else {
-        Ok((false, "Invalid license key".to_string()))
+/// Start retrying a queued activation while the command runs. Builds without network
+/// support can't validate anything, so they leave it queued.
+pub fn start_pending_retry() {
+    if !http::available() {
+        return;
     }
+    let Some(pending) = pending_activation() else {
+        return;
+    };
+    let rx = validate_in_background(&pending.license_key, &pending.email);
+    *RETRY.lock().unwrap_or_else(|e| e.into_inner()) = Some((pending, rx));
 }
 
-fn validate_gumroad_license(key: &str, email: &str) -> Result<bool> {
+/// Collect the retry started by `start_pending_retry`, waiting briefly if it is still running.
+/// The license file is only touched here, after the command is done with it.
+pub fn finish_pending_retry() {
+    let Some((mut pending, rx)) = RETRY.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+        return;
+    };
+    // `activate` ran in the meantime and settled it
+    if pending_activation().is_none_or(|p| p.license_key != pending.license_key) {
+        return;
+    }
+
+    let result = match rx.recv_timeout(RETRY_GRACE) {
+        Ok(Ok(Some(grant))) => apply_grant(&pending.license_key, &pending.email, grant).map(|_| {
+            eprintln!("{} Queued license activation succeeded - you now have unlimited traces", "✓".green());
+        }),
+        Ok(Ok(None)) => {
+            eprintln!("{} Queued license activation was rejected: invalid license key", "✗".red());
+            Ok(())
+        }
+        Ok(Err(e)) => {
+            pending.attempts += 1;
+            pending.last_error = Some(e.to_string());
+            let _ = save_pending(&pending);
+            return;
+        }
+        // Still waiting on the network; try again next run
+        Err(_) => return,
+    };
+
+    if result.is_ok() {
+        let _ = clear_pending();
+    }
+}
+
+/// True for signed keys that can be validated without the network
+pub fn is_offline_key(key: &str) -> bool {
+    key.starts_with(OFFLINE_KEY_PREFIX)
+}
+
+fn activate_offline_license(key: &str, email: &str) -> Result<Activation> {
+    let offline = match verify_offline_key(key) {
+        Ok(o) => o,
+        Err(e) => return Ok(Activation::Rejected(format!("Invalid offline license: {}", e))),
+    };
+
+    if offline.product != "eshu-trace" || offline.license_type == LicenseType::Trial {
+        return Ok(Activation::Rejected("This key is not an Eshu Trace license".to_string()));
+    }
+    if !email.is_empty() && !offline.email.eq_ignore_ascii_case(email) {
+        return Ok(Activation::Rejected("Email does not match the license".to_string()));
+    }
+
+    let mut license = get_license()?;
+    license.license_key = Some(key.to_string());
+    license.email = Some(offline.email.clone());
+    license.license_type = offline.license_type;
+    license.expires_at = offline.expires_at;
+    license.activated_at = Some(chrono::Utc::now().to_rfc3339());
+    save_license(&license)?;
+
+    Ok(Activation::Activated(format!("Offline license activated for {}", offline.email)))
+}
+
+/// Check the vendor signature and decode the payload
+fn verify_offline_key(key: &str) -> Result<OfflineLicense> {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let body = key.trim().strip_prefix(OFFLINE_KEY_PREFIX).context("missing ESHU1 prefix")?;
+    let (payload_b64, signature_b64) = body.split_once('.').context("malformed key")?;
+
+    let payload = URL_SAFE_NO_PAD.decode(payload_b64).context("malformed payload")?;
+    let signature = URL_SAFE_NO_PAD.decode(signature_b64).context("malformed signature")?;
+    let signature = Signature::from_slice(&signature).context("malformed signature")?;
+
+    let vendor_key = VerifyingKey::from_bytes(&VENDOR_PUBLIC_KEY).context("bad vendor key")?;
+    vendor_key
+        .verify(&payload, &signature)
+        .map_err(|_| anyhow::anyhow!("signature does not match"))?;
+
+    serde_json::from_slice(&payload).context("malformed payload")
+}
+
+/// None if the key was rejected
+fn validate_license(key: &str, email: &str) -> Result<Option<Grant>> {
     // First check if user has Eshu Premium (from eshu-installer)
-    if is_eshu_premium_active()? {
-        return Ok(true);
+    if let Some(grant) = eshu_premium_grant()? {
+        return Ok(Some(grant));
+    }
+
+    let valid = match config::license_server()? {
+        Some(server) => validate_server_license(&server, key, email)?,
+        None => validate_gumroad_license(key, email)?,
+    };
+    Ok(valid.then_some(Grant::Standalone))
+}
+
+/// Validate against a self-hosted server: POST <server>/v1/verify
+fn validate_server_license(server: &str, key: &str, email: &str) -> Result<bool> {
+    let url = format!("{}/v1/verify", server.trim_end_matches('/'));
+
+    let body = serde_json::json!({
+        "product": "eshu-trace",
+        "license_key": key,
+        "email": email,
+    });
+    let response = http::post_json(&url, &body, std::time::Duration::from_secs(10))
+        .with_context(|| format!("Could not connect to license server {}", server))?;
+
+    if !response.is_success() {
+        anyhow::bail!("License server {} returned HTTP {}", server, response.status);
     }
 
+    let result: ServerResponse = response
+        .json()
+        .context("Invalid response from license server")?;
+
+    if let (false, Some(message)) = (result.valid, &result.message) {
+        eprintln!("License server: {}", message);
+    }
+
+    Ok(result.valid)
+}
+
+fn validate_gumroad_license(key: &str, email: &str) -> Result<bool> {
     // REAL Gumroad API validation
     let product_permalink = "eshu-trace";
     let url = "https://api.gumroad.com/v2/licenses/verify";
 
-    let client = match reqwest::blocking::Client::builder()
-        .timeout(std::time::Duration::from_secs(10))
-        .build() {
-        Ok(c) => c,
-        Err(_) => {
-            // If we can't build client, fail with error
-            return Err(anyhow::anyhow!(
-                "Could not initialize HTTP client. Please check your system configuration."
-            ));
-        }
-    };
+    if !http::available() {
+        anyhow::bail!("This eshu-trace was built without network support; activate with a full build");
+    }
 
-    let response = match client
-        .post(url)
-        .form(&[
-            ("product_permalink", product_permalink),
-            ("license_key", key),
-            ("increment_uses_count", "false"),
-        ])
-        .send() {
+    let fields = [
+        ("product_permalink", product_permalink),
+        ("license_key", key),
+        ("increment_uses_count", "false"),
+    ];
+    let response = match http::post_form(url, &fields, std::time::Duration::from_secs(10)) {
         Ok(r) => r,
         Err(_) => {
             // Network error - fail with message
@@ -206,39 +668,48 @@ fn validate_gumroad_license(key: &str, email: &str) -> Result<bool> {
     Ok(true)
 }
 
-fn is_eshu_premium_active() -> Result<bool> {
+fn eshu_premium_grant() -> Result<Option<Grant>> {
     // Check if user has active Eshu Premium (from eshu-installer)
+    let license_data = match eshu_premium_data()? {
+        Some(d) => d,
+        None => return Ok(None),
+    };
+
+    // Check if tier is premium and license is valid
+    if license_data.get("tier").and_then(|t| t.as_str()) == Some("premium") {
+        let expires_at = subscription_expiry(&license_data);
+        if expires_at.as_deref().and_then(parse_time).is_some_and(|t| t <= chrono::Utc::now()) {
+            return Ok(None);
+        }
+
+        // Grant access via Eshu Premium
+        return Ok(Some(Grant::Premium(expires_at)));
+    }
+
+    Ok(None)
+}
+
+/// The eshu-installer license file, if present
+fn eshu_premium_data() -> Result<Option<serde_json::Value>> {
     let eshu_license_path = get_eshu_installer_license_path();
 
     if !eshu_license_path.exists() {
-        return Ok(false);
+        return Ok(None);
     }
 
     let data = fs::read_to_string(&eshu_license_path)?;
-    let license_data: serde_json::Value = serde_json::from_str(&data)?;
-
-    // Check if tier is premium and license is valid
-    if let Some(tier) = license_data.get("tier") {
-        if tier == "premium" {
-            // Grant access via Eshu Premium
-            let mut trace_license = get_license()?;
-            if trace_license.license_type != LicenseType::Premium {
-                trace_license.license_type = LicenseType::Premium;
-                save_license(&trace_license)?;
-            }
-            return Ok(true);
-        }
-    }
+    Ok(Some(serde_json::from_str(&data)?))
+}
 
-    Ok(false)
+/// Subscription end from the installer license (`expires_at`, RFC 3339)
+fn subscription_expiry(data: &serde_json::Value) -> Option<String> {
+    data.get("expires_at")
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_string())
 }
 
 fn get_license_path() -> PathBuf {
-    let home = std::env::var("HOME").unwrap_or_else(|_| "/root".to_string());
-    PathBuf::from(home)
-        .join(".cache")
-        .join("eshu-trace")
-        .join("license.json")
+    paths::config_file("license.json")
 }
 
 fn get_eshu_installer_license_path() -> PathBuf {
@@ -256,3 +727,56 @@ pub fn get_upgrade_url() -> &'static str {
 pub fn get_eshu_premium_url() -> &'static str {
     "https://eshuapps.gumroad.com/l/eshu-premium"
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trial(traces_used: u32) -> LicenseGate {
+        LicenseGate {
+            license: TraceLicense {
+                traces_used,
+                ..TraceLicense::default()
+            },
+        }
+    }
+
+    #[test]
+    fn trial_gate_meters_traces() {
+        let gate = trial(1);
+        assert!(gate.is_enabled(Feature::Trace));
+        assert!(!gate.is_enabled(Feature::AutomatedBisect));
+        assert_eq!(gate.remaining_uses(Feature::Trace), Some(FREE_TRACE_LIMIT - 1));
+        assert_eq!(gate.license_type(), Some(&LicenseType::Trial));
+
+        let spent = trial(FREE_TRACE_LIMIT);
+        assert!(!spent.is_enabled(Feature::Trace));
+        assert_eq!(spent.remaining_uses(Feature::Trace), Some(0));
+    }
+
+    #[test]
+    fn paid_gate_is_unmetered() {
+        let gate = LicenseGate {
+            license: TraceLicense {
+                license_type: LicenseType::Standalone,
+                traces_used: 50,
+                ..TraceLicense::default()
+            },
+        };
+        assert!(gate.is_enabled(Feature::Trace));
+        assert!(gate.is_enabled(Feature::AutomatedBisect));
+        assert!(!gate.is_enabled(Feature::Oracle));
+        assert_eq!(gate.remaining_uses(Feature::Trace), None);
+    }
+
+    #[test]
+    fn fixed_gate_unlocks_only_its_features() {
+        let mut gate = FixedGate::new(&[Feature::Trace, Feature::Oracle]);
+        assert!(gate.is_enabled(Feature::Oracle));
+        assert!(!gate.is_enabled(Feature::AiAnalysis));
+        gate.record_use(Feature::Trace).unwrap();
+        assert!(gate.is_enabled(Feature::Trace));
+        assert_eq!(gate.remaining_uses(Feature::Trace), None);
+        assert_eq!(gate.license_type(), None);
+    }
+}
@@ -0,0 +1,232 @@
+// Built-in test presets for common regressions
+
+use clap::ValueEnum;
+
+use crate::firmware;
+
+/// Compositors and display servers whose crashes indicate a broken desktop
+const COMPOSITORS: &str = "kwin_wayland|kwin_x11|gnome-shell|mutter|Xorg|Xwayland|sway|Hyprland|weston|labwc|wayfire|river|xfwm4|marco|muffin";
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum TestPreset {
+    /// Display manager active, Wayland/X socket present, no compositor crash
+    Graphical,
+    /// OpenGL/Vulkan initialise and no DRM errors in the kernel log
+    Gpu,
+    /// PipeWire is running and exposes audio sinks
+    Audio,
+    /// Wireless link is up and the default gateway answers
+    Wifi,
+}
+
+impl TestPreset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TestPreset::Graphical => "graphical",
+            TestPreset::Gpu => "gpu",
+            TestPreset::Audio => "audio",
+            TestPreset::Wifi => "wifi",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            TestPreset::Graphical => "Graphical session comes up without compositor crashes",
+            TestPreset::Gpu => "GPU acceleration works (glxinfo/vulkaninfo, no DRM errors)",
+            TestPreset::Audio => "PipeWire audio nodes are present",
+            TestPreset::Wifi => "Wi-Fi link is up and the gateway is reachable",
+        }
+    }
+
+    /// Whether a failure points at hardware (drivers, firmware) rather than userspace
+    pub fn is_hardware_level(&self) -> bool {
+        matches!(self, TestPreset::Gpu | TestPreset::Wifi)
+    }
+
+    /// Shell command implementing the preset; exits 0 when the system is healthy
+    pub fn command(&self) -> String {
+        match self {
+            TestPreset::Graphical => format!(
+                "systemctl is-active --quiet display-manager.service \
+                 && {{ ls /run/user/*/wayland-[0-9]* >/dev/null 2>&1 || ls /tmp/.X11-unix/X* >/dev/null 2>&1; }} \
+                 && ! journalctl -b -q --no-pager 2>/dev/null \
+                 | grep -Eiq '({}).*(segfault|dumped core|SIGSEGV|SIGABRT|crashed)'",
+                COMPOSITORS
+            ),
+            // Run with --test-user so glxinfo/vulkaninfo reach the user's display
+            TestPreset::Gpu => "{ glxinfo -B 2>/dev/null | grep -q 'direct rendering: Yes' \
+                 || vulkaninfo --summary >/dev/null 2>&1; } \
+                 && ! journalctl -k -b -q --no-pager -p err 2>/dev/null \
+                 | grep -Eiq '(drm|amdgpu|i915|xe|nouveau|nvidia).*(error|fail|timeout|hang)'"
+                .to_string(),
+            TestPreset::Audio => "{ pw-cli ls Node 2>/dev/null | grep -q 'Audio/Sink' \
+                 || wpctl status 2>/dev/null | grep -A5 'Sinks:' | grep -Eq '[0-9]+[.]'; }"
+                .to_string(),
+            TestPreset::Wifi => "iface=$(iw dev 2>/dev/null | awk '/Interface/ {print $2; exit}') \
+                 && [ -n \"$iface\" ] \
+                 && iw dev \"$iface\" link | grep -q '^Connected' \
+                 && gw=$(ip route show default dev \"$iface\" | awk '{print $3; exit}') \
+                 && [ -n \"$gw\" ] \
+                 && ping -c 2 -W 2 \"$gw\" >/dev/null"
+                .to_string(),
+        }
+    }
+}
+
+/// Package patterns making up the graphics driver stack
+const GRAPHICS_STACK: &[&str] = &[
+    "nvidia", "mesa", "vulkan-", "libdrm", "libglvnd", "libva", "xf86-video-", "xorg-server",
+    "xserver-xorg", "xwayland", "wayland", "egl-", "libegl", "libgl1", "libgles", "libgbm",
+    "linux-firmware", "kernel", "linux-image", "linux-headers", "linux",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum BisectScope {
+    /// NVIDIA/Mesa drivers, kernel and Xorg/Wayland stack only
+    Graphics,
+    /// Firmware, CPU microcode and kernel packages only
+    Hardware,
+}
+
+impl BisectScope {
+    pub fn description(&self) -> &'static str {
+        match self {
+            BisectScope::Graphics => "graphics drivers, kernel and display stack",
+            BisectScope::Hardware => "firmware, microcode and kernel",
+        }
+    }
+
+    /// Whether a changed package belongs to this scope
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            BisectScope::Graphics => {
+                let name = name.strip_prefix("lib32-").unwrap_or(name);
+                GRAPHICS_STACK.iter().any(|p| {
+                    // Bare "linux"/"kernel" only match kernel packages, not every linux-* tool
+                    if *p == "linux" || *p == "kernel" {
+                        name == *p || (name.starts_with(&format!("{}-", p)) && is_kernel_package(name))
+                    } else {
+                        name.starts_with(p) || name.contains(&format!("-{}", p))
+                    }
+                })
+            }
+            BisectScope::Hardware => firmware::is_firmware_package(name) || is_kernel(name),
+        }
+    }
+}
+
+/// Kernel images and their module/header packages
+pub fn is_kernel(name: &str) -> bool {
+    name == "linux" || name == "kernel" || is_kernel_package(name)
+}
+
+fn is_kernel_package(name: &str) -> bool {
+    ["linux-lts", "linux-zen", "linux-hardened", "linux-headers", "linux-image", "kernel-core", "kernel-modules"]
+        .iter()
+        .any(|k| name.starts_with(k))
+}
+
+/// Packages that only work as a set and must always be moved together
+/// (e.g. nvidia + nvidia-utils + lib32-nvidia-utils). Returns the set's key.
+pub fn coupling_key(name: &str) -> Option<&'static str> {
+    let base = name.strip_prefix("lib32-").unwrap_or(name);
+
+    if base.contains("nvidia") && !base.starts_with("nvidia-prime") {
+        Some("nvidia")
+    } else if base.starts_with("mesa")
+        || base.starts_with("libgl1-mesa")
+        || base.starts_with("libegl-mesa")
+        || base.starts_with("libglx-mesa")
+        || base.starts_with("libgbm")
+        || base.starts_with("vulkan-radeon")
+        || base.starts_with("vulkan-intel")
+        || base.starts_with("mesa-vulkan")
+    {
+        Some("mesa")
+    } else if base.starts_with("xorg-server") || base.starts_with("xserver-xorg-core") {
+        Some("xorg-server")
+    } else if base == "linux" || base == "linux-headers" {
+        Some("linux")
+    } else if base.starts_with("kernel") {
+        Some("kernel")
+    } else if base.starts_with("systemd") || base.starts_with("libsystemd") {
+        Some("systemd")
+    } else {
+        None
+    }
+}
+
+/// Desktop ecosystems released as one unit; a trailing '*' marks a bare prefix,
+/// other entries match the name itself or the name followed by '-'
+const ECOSYSTEMS: &[(&str, &[&str])] = &[
+    (
+        "KDE Plasma",
+        &[
+            "plasma", "plasma5support", "libplasma", "kwin", "kde", "breeze", "kscreen", "libkscreen",
+            "powerdevil", "bluedevil", "kinfocenter", "ksystemstats", "libksysguard", "systemsettings",
+            "polkit-kde-agent", "xdg-desktop-portal-kde", "kdecoration", "kpipewire", "kactivitymanagerd",
+            "kmenuedit", "kglobalacceld", "kwallet-pam", "ksshaskpass", "layer-shell-qt", "milou", "oxygen",
+            "sddm-kcm", "drkonqi", "discover", "spectacle", "aurorae",
+        ],
+    ),
+    (
+        "KDE Frameworks",
+        &[
+            "kf5", "kf6", "libkf5*", "libkf6*", "attica", "baloo", "bluez-qt", "frameworkintegration",
+            "karchive", "kauth", "kbookmarks", "kcmutils", "kcodecs", "kcolorscheme", "kcompletion", "kconfig",
+            "kconfigwidgets", "kcoreaddons", "kcrash", "kdbusaddons", "kdeclarative", "kded", "kdnssd",
+            "kdoctools", "kfilemetadata", "kglobalaccel", "kguiaddons", "kholidays", "ki18n", "kiconthemes",
+            "kidletime", "kimageformats", "kio", "kirigami", "kitemmodels", "kitemviews", "kjobwidgets",
+            "knewstuff", "knotifications", "knotifyconfig", "kpackage", "kparts", "kpeople", "kquickcharts",
+            "krunner", "kservice", "kstatusnotifieritem", "ksvg", "ktexteditor", "ktextwidgets",
+            "kunitconversion", "kuserfeedback", "kwallet", "kwidgetsaddons", "kwindowsystem", "kxmlgui",
+            "modemmanager-qt", "networkmanager-qt", "prison", "purpose", "qqc2-desktop-style", "solid",
+            "sonnet", "syntax-highlighting", "threadweaver", "extra-cmake-modules",
+        ],
+    ),
+    (
+        "GNOME",
+        &[
+            "gnome", "gdm", "mutter", "libmutter*", "gjs", "nautilus", "gvfs", "evolution-data-server",
+            "libadwaita", "libgnome*", "tracker3", "localsearch", "tinysparql", "gsettings-desktop-schemas",
+            "xdg-desktop-portal-gnome",
+        ],
+    ),
+    (
+        "Xfce",
+        &[
+            "xfce4", "xfwm4", "xfdesktop", "xfconf", "libxfce4*", "thunar", "garcon", "tumbler", "exo",
+            "xfce4-*",
+        ],
+    ),
+    ("Qt", &["qt5", "qt6", "qt5-*", "qt6-*", "libqt5*", "libqt6*", "qtbase", "qtdeclarative", "qtwayland"]),
+    ("GTK", &["gtk2", "gtk3", "gtk4", "gtk", "libgtk*", "gdk-pixbuf2", "libgdk-pixbuf*", "gtk-update-icon-cache"]),
+];
+
+/// Desktop ecosystem a package belongs to (KDE Plasma, GNOME, Xfce, Qt, GTK, ...)
+pub fn ecosystem(name: &str) -> Option<&'static str> {
+    let base = name.strip_prefix("lib32-").unwrap_or(name);
+    ECOSYSTEMS.iter().find_map(|(label, patterns)| {
+        patterns
+            .iter()
+            .any(|p| match p.strip_suffix('*') {
+                Some(prefix) => base.starts_with(prefix),
+                None => base == *p || base.strip_prefix(p).is_some_and(|rest| rest.starts_with('-')),
+            })
+            .then_some(*label)
+    })
+}
+
+/// Ecosystems with more than `threshold` of `names` in them, with their member counts,
+/// in order of first appearance; callers show these as one line instead of every package
+pub fn large_groups<'a>(names: impl IntoIterator<Item = &'a str>, threshold: usize) -> Vec<(&'static str, usize)> {
+    let mut groups: Vec<(&'static str, usize)> = Vec::new();
+    for label in names.into_iter().filter_map(ecosystem) {
+        match groups.iter_mut().find(|(l, _)| *l == label) {
+            Some((_, count)) => *count += 1,
+            None => groups.push((label, 1)),
+        }
+    }
+    groups.retain(|(_, count)| *count > threshold);
+    groups
+}
@@ -0,0 +1,174 @@
+// Prior likelihood of each changed package being the culprit, for the weighted bisect
+//
+// Four signals multiply: the package's category (kernels, drivers and core libraries
+// break systems far more often than fonts or documentation), how often it has been
+// reported as a culprit (community-reports.json in the state directory, plus this
+// machine's last bisect), whether it matches the symptom the user described, and
+// whether the change is only a rebuild of the same upstream version.
+// Weights are relative; only their ratios matter.
+
+use std::collections::HashMap;
+use std::fs;
+
+use crate::bisect;
+use crate::corelibs::{self, Severity};
+use crate::firmware;
+use crate::package_diff::PackageChange;
+use crate::paths;
+use crate::presets::{self, BisectScope};
+
+/// Multiplier for a package matching the described symptom
+const SYMPTOM_BOOST: f64 = 4.0;
+
+/// Multiplier for a rebuild of the same upstream version; toolchain or dependency
+/// rebuilds break things far less often than new upstream code
+const REBUILD_FACTOR: f64 = 0.3;
+
+/// Symptom tag, the words that indicate it, and the package name fragments it points at
+type Symptom = (&'static str, &'static [&'static str], &'static [&'static str]);
+
+const SYMPTOMS: &[Symptom] = &[
+    (
+        "audio",
+        &["audio", "sound", "speaker", "speakers", "headphone", "headphones", "microphone", "mic", "volume"],
+        &["pipewire", "wireplumber", "pulseaudio", "alsa", "sof-firmware", "jack"],
+    ),
+    (
+        "network",
+        &["wifi", "wi-fi", "wireless", "wlan", "network", "internet", "ethernet", "dns", "vpn"],
+        &["networkmanager", "network-manager", "iwd", "wpa_supplicant", "linux-firmware", "dhcp", "openssl", "gnutls"],
+    ),
+    (
+        "display",
+        &["display", "screen", "monitor", "black", "flicker", "tearing", "gpu", "graphics", "graphical", "wayland", "x11", "xorg", "login", "desktop", "compositor"],
+        &["mesa", "nvidia", "xorg", "xwayland", "wayland", "kwin", "mutter", "gnome-shell", "plasma-workspace", "sddm", "gdm", "lightdm", "libdrm", "vulkan"],
+    ),
+    (
+        "boot",
+        &["boot", "grub", "initramfs", "panic", "emergency", "unbootable"],
+        &["linux", "kernel", "systemd", "grub", "mkinitcpio", "dracut", "initramfs-tools", "lvm2", "cryptsetup", "btrfs-progs"],
+    ),
+    (
+        "suspend",
+        &["suspend", "resume", "sleep", "hibernate", "wake"],
+        &["linux", "kernel", "systemd", "firmware", "microcode"],
+    ),
+    ("bluetooth", &["bluetooth"], &["bluez", "linux-firmware"]),
+    ("printing", &["print", "printer", "printing", "scanner"], &["cups", "sane", "hplip", "gutenprint"]),
+    (
+        "input",
+        &["keyboard", "mouse", "touchpad", "trackpad", "input"],
+        &["libinput", "xf86-input", "libxkbcommon", "xkeyboard-config"],
+    ),
+];
+
+/// Relative culprit weight of every change, in the same order
+pub fn weights(changes: &[PackageChange], symptom: &str) -> Vec<f64> {
+    let reports = community_reports();
+    let fragments = symptom_fragments(symptom);
+
+    changes
+        .iter()
+        .map(|change| {
+            let name = change.name();
+            let reported = reports.get(name).copied().unwrap_or(0);
+            let matches_symptom = fragments.iter().any(|f| name.to_lowercase().contains(f));
+
+            category_weight(name)
+                * (1.0 + (reported as f64).ln_1p())
+                * if matches_symptom { SYMPTOM_BOOST } else { 1.0 }
+                * if change.is_rebuild() { REBUILD_FACTOR } else { 1.0 }
+        })
+        .collect()
+}
+
+/// How often a package of this kind is behind a regression
+fn category_weight(name: &str) -> f64 {
+    if presets::is_kernel(name) {
+        return 3.0;
+    }
+    if let Some(lib) = corelibs::classify(name) {
+        return match lib.severity {
+            Severity::Critical => 3.0,
+            Severity::High => 2.5,
+            Severity::Medium => 1.5,
+        };
+    }
+    if BisectScope::Graphics.matches(name) {
+        return 2.5;
+    }
+    if firmware::is_firmware_package(name) {
+        return 2.0;
+    }
+
+    // Data-only packages rarely break anything
+    let inert_suffixes = ["-doc", "-docs", "-man", "-lang", "-l10n", "-i18n", "-examples"];
+    let inert_prefixes = ["fonts-", "ttf-", "otf-", "man-pages", "texlive-", "hunspell-", "aspell-"];
+    if inert_suffixes.iter().any(|s| name.ends_with(s)) || inert_prefixes.iter().any(|p| name.starts_with(p)) {
+        return 0.3;
+    }
+
+    1.0
+}
+
+/// Package name fragments the symptom text points at
+fn symptom_fragments(symptom: &str) -> Vec<&'static str> {
+    matching_symptoms(symptom).flat_map(|(_, _, fragments)| fragments.iter().copied()).collect()
+}
+
+/// Tags for the kinds of problem the symptom text describes ("audio", "display", ...)
+pub fn symptom_tags(symptom: &str) -> Vec<&'static str> {
+    matching_symptoms(symptom).map(|(tag, _, _)| *tag).collect()
+}
+
+fn matching_symptoms(symptom: &str) -> impl Iterator<Item = &'static Symptom> {
+    let symptom = symptom.to_lowercase();
+    let words: Vec<String> = symptom
+        .split(|c: char| !c.is_alphanumeric() && c != '-')
+        .filter(|w| !w.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    SYMPTOMS
+        .iter()
+        .filter(move |(_, keywords, _)| keywords.iter().any(|k| words.iter().any(|w| w == k)))
+}
+
+/// Culprit counts per package: community-reports.json ({"package": count}) and this
+/// machine's last bisect
+pub fn community_reports() -> HashMap<String, u32> {
+    let mut reports: HashMap<String, u32> = fs::read_to_string(paths::state_file("community-reports.json"))
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default();
+
+    if let Ok(Some(report)) = bisect::load_report() {
+        if let Some(culprit) = report.culprit {
+            *reports.entry(culprit.name().to_string()).or_default() += 1;
+        }
+    }
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symptom_words_pick_tags() {
+        assert_eq!(symptom_tags("No sound after resume from suspend"), vec!["audio", "suspend"]);
+        assert_eq!(symptom_tags("Wi-Fi drops every few minutes"), vec!["network"]);
+        // Whole words only: "printed" is not "print"
+        assert!(symptom_tags("the log printed an error").is_empty());
+        assert!(symptom_fragments("black screen at login").contains(&"mesa"));
+    }
+
+    #[test]
+    fn categories_rank_packages() {
+        assert!(category_weight("linux") > category_weight("htop"));
+        assert!(category_weight("mesa") > category_weight("htop"));
+        assert_eq!(category_weight("htop"), 1.0);
+        assert!(category_weight("ttf-dejavu") < 1.0);
+        assert!(category_weight("python-docs") < 1.0);
+    }
+}
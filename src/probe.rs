@@ -0,0 +1,128 @@
+// Declarative network/service probes (HTTP, TCP port, DNS)
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::http;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub enum Probe {
+    Http(String),         // full URL, passes on 2xx/3xx
+    Tcp(String, u16),     // host, port
+    Dns(String),          // hostname that must resolve
+}
+
+impl FromStr for Probe {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .context(format!("Invalid probe (expected scheme://target): {}", s))?;
+
+        match scheme {
+            "http" | "https" => Ok(Probe::Http(s.to_string())),
+            "tcp" => {
+                let (host, port) = split_host_port(rest.trim_end_matches('/'));
+                let port = port
+                    .context(format!("TCP probe needs a port: {}", s))?
+                    .parse()
+                    .context(format!("Invalid port in probe: {}", s))?;
+                let host = if host.is_empty() { "localhost" } else { host };
+                Ok(Probe::Tcp(host.to_string(), port))
+            }
+            "dns" => {
+                let host = rest.trim_end_matches('/');
+                if host.is_empty() {
+                    anyhow::bail!("DNS probe needs a hostname: {}", s);
+                }
+                Ok(Probe::Dns(host.to_string()))
+            }
+            _ => anyhow::bail!("Unsupported probe scheme '{}' (use http, https, tcp or dns)", scheme),
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Probe::Http(url) => write!(f, "{}", url),
+            Probe::Tcp(host, port) if host.contains(':') => write!(f, "tcp://[{}]:{}", host, port),
+            Probe::Tcp(host, port) => write!(f, "tcp://{}:{}", host, port),
+            Probe::Dns(host) => write!(f, "dns://{}", host),
+        }
+    }
+}
+
+impl Probe {
+    /// Evaluate the probe, returning true if the service responded as expected
+    pub fn run(&self) -> bool {
+        match self {
+            Probe::Http(url) => probe_http(url),
+            Probe::Tcp(host, port) => probe_tcp(host, *port),
+            Probe::Dns(host) => (host.as_str(), 0)
+                .to_socket_addrs()
+                .map(|mut addrs| addrs.next().is_some())
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Split "host:port" or "[ipv6]:port" into the host (without brackets) and the port, if any
+pub fn split_host_port(authority: &str) -> (&str, Option<&str>) {
+    if let Some((host, rest)) = authority.strip_prefix('[').and_then(|a| a.split_once(']')) {
+        return (host, rest.strip_prefix(':'));
+    }
+    match authority.rsplit_once(':') {
+        // More than one colon is a bare IPv6 address
+        Some((host, port)) if !host.contains(':') => (host, Some(port)),
+        _ => (authority, None),
+    }
+}
+
+fn probe_http(url: &str) -> bool {
+    match http::get_once(url, PROBE_TIMEOUT) {
+        Ok(response) => response.is_success() || response.is_redirection(),
+        Err(_) => false,
+    }
+}
+
+fn probe_tcp(host: &str, port: u16) -> bool {
+    let addrs = match (host, port).to_socket_addrs() {
+        Ok(addrs) => addrs,
+        Err(_) => return false,
+    };
+
+    addrs
+        .into_iter()
+        .any(|addr| TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_host_and_port() {
+        assert_eq!(split_host_port("example.org:443"), ("example.org", Some("443")));
+        assert_eq!(split_host_port("[::1]:8080"), ("::1", Some("8080")));
+        assert_eq!(split_host_port("[fe80::1]"), ("fe80::1", None));
+        assert_eq!(split_host_port("::1"), ("::1", None));
+        assert_eq!(split_host_port("example.org"), ("example.org", None));
+    }
+
+    #[test]
+    fn parses_tcp_probes() {
+        let probe: Probe = "tcp://[::1]:631/".parse().unwrap();
+        assert!(matches!(&probe, Probe::Tcp(host, 631) if host == "::1"));
+        assert_eq!(probe.to_string(), "tcp://[::1]:631");
+
+        assert!(matches!("tcp://:22".parse().unwrap(), Probe::Tcp(host, 22) if host == "localhost"));
+        assert!("tcp://[::1]".parse::<Probe>().is_err());
+    }
+}
@@ -0,0 +1,211 @@
+// Rebooting after a fix, and verifying it on the first run afterwards
+//
+// Kernel and early-boot fixes only take effect on the next boot, and libraries replaced
+// under running programs only once those restart, so whether the fix worked can't be
+// answered in the session that applied it. The fix is remembered as pending verification
+// together with the boot it was applied in; the first `eshu-trace bisect` after a reboot (or
+// a systemd soft-reboot, which restarts userspace but keeps the kernel and boot id) re-runs
+// the recorded test and asks whether the issue is gone.
+
+use anyhow::{Context, Result};
+use colored::*;
+use dialoguer::{Confirm, Select};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::audit;
+use crate::exec::CommandExt;
+use crate::kernel;
+use crate::paths;
+use crate::pins::RecordedTest;
+use crate::test_runner::{which, TestRunner};
+
+/// First systemd release that counts soft-reboots (254 added `systemctl soft-reboot`, but
+/// without the counter a soft-reboot leaves nothing to tell the next run it happened)
+const SOFT_REBOOT_SYSTEMD: u32 = 256;
+
+/// A fix waiting for the reboot that makes it take effect
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingVerify {
+    /// What was done, e.g. "Downgrade mesa to 24.0.5-1"
+    pub fix: String,
+    pub package: String,
+    /// Test that reproduced the regression
+    pub test: Option<RecordedTest>,
+    pub applied_at: String,
+    boot_id: String,
+    /// systemd's soft-reboot counter at the time, where it has one
+    #[serde(default)]
+    soft_reboots: Option<u64>,
+}
+
+/// What it takes for a fix to take effect
+pub enum Needed {
+    /// New kernel, initramfs or boot entry
+    Boot,
+    /// Only programs still running replaced files
+    Userspace,
+}
+
+impl PendingVerify {
+    pub fn new(fix: String, package: String, test: Option<RecordedTest>) -> Self {
+        Self {
+            fix,
+            package,
+            test,
+            applied_at: chrono::Local::now().to_rfc3339(),
+            boot_id: boot_id(),
+            soft_reboots: soft_reboots(),
+        }
+    }
+
+    pub fn load() -> Result<Option<Self>> {
+        let path = state_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(&path).context("Failed to read the pending fix verification")?;
+        Ok(Some(serde_json::from_str(&data).context("Failed to parse the pending fix verification")?))
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = state_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The system went through a reboot or soft-reboot since the fix
+    pub fn rebooted(&self) -> bool {
+        self.boot_id != boot_id() || (self.soft_reboots.is_some() && self.soft_reboots != soft_reboots())
+    }
+}
+
+/// Remember `pending` and offer how to restart: a reboot, a soft-reboot when only
+/// userspace needs it, or booting the kept fallback kernel once
+pub fn offer(pending: PendingVerify, needed: Needed, fallback: Option<&str>, assume_yes: bool) -> Result<()> {
+    pending.save()?;
+
+    println!();
+    if assume_yes {
+        println!(
+            "{} Reboot for the fix to take effect; the next eshu-trace bisect checks whether it worked",
+            "ℹ".cyan()
+        );
+        return Ok(());
+    }
+
+    let mut options = vec!["Reboot now".to_string()];
+    let soft = matches!(needed, Needed::Userspace) && soft_reboot_supported();
+    if soft {
+        options.push("Soft-reboot now (restarts userspace only, faster)".to_string());
+    }
+    if let Some(release) = fallback {
+        options.push(format!("Reboot once into kernel {}, then back to the default", release));
+    }
+    options.push("Later, I'll reboot myself".to_string());
+
+    let choice = Select::new()
+        .with_prompt("The fix takes effect after a reboot")
+        .items(&options)
+        .default(0)
+        .interact()?;
+
+    let status = match (choice, soft, fallback) {
+        (0, _, _) => audit::run("fix: reboot", Command::new("systemctl").arg("reboot"))?,
+        (1, true, _) => audit::run("fix: soft-reboot", Command::new("systemctl").arg("soft-reboot"))?,
+        (i, _, Some(release)) if i == options.len() - 2 => {
+            kernel::boot_fallback_once(release)?;
+            audit::run("fix: reboot", Command::new("systemctl").arg("reboot"))?
+        }
+        _ => {
+            println!(
+                "{} After rebooting, run {} to check the fix",
+                "ℹ".cyan(),
+                "eshu-trace bisect".white()
+            );
+            return Ok(());
+        }
+    };
+    if !status.success() {
+        anyhow::bail!("Failed to reboot; reboot manually and run eshu-trace bisect afterwards");
+    }
+    Ok(())
+}
+
+/// After a reboot, check the fix waiting for it. Does nothing in the boot that applied it.
+/// Returns whether there was a fix to check.
+pub fn resume() -> Result<bool> {
+    let Some(pending) = PendingVerify::load()? else {
+        return Ok(false);
+    };
+    if !pending.rebooted() {
+        return Ok(false);
+    }
+
+    println!("{} Verifying the fix applied before the reboot: {}", "↻".cyan(), pending.fix.bold());
+
+    let mut prompt = Confirm::new().with_prompt(format!("Is the issue with {} gone?", pending.package));
+    if let Some(test) = &pending.test {
+        println!("{} Re-running the recorded test: {}", "→".dimmed(), test.command.dimmed());
+        let passed = TestRunner::new(Some(test.command.clone())).run_test()?;
+        if passed {
+            println!("{} The test passes", "✓".green());
+        } else {
+            println!("{} The test still fails", "✗".red());
+        }
+        prompt = prompt.default(passed);
+    }
+    let fixed = prompt.interact()?;
+    fs::remove_file(state_path()).ok();
+
+    if fixed {
+        println!("{} Fix confirmed", "✓".green().bold());
+    } else {
+        println!("{} The fix did not help", "⚠".yellow());
+        println!("   Run {} again from the current state to look further", "eshu-trace bisect".white());
+    }
+    println!();
+    Ok(true)
+}
+
+/// `systemctl soft-reboot` exists and counts soft-reboots (systemd 256+), and PID 1 is systemd
+fn soft_reboot_supported() -> bool {
+    if !which("systemctl") || !std::path::Path::new("/run/systemd/system").exists() {
+        return false;
+    }
+    Command::new("systemctl")
+        .arg("--version")
+        .run_output()
+        .ok()
+        .and_then(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .split_whitespace()
+                .nth(1)
+                .and_then(|v| v.parse::<u32>().ok())
+        })
+        .is_some_and(|version| version >= SOFT_REBOOT_SYSTEMD)
+}
+
+fn boot_id() -> String {
+    fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map(|id| id.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Soft-reboots keep the boot id; systemd 256+ counts them instead
+fn soft_reboots() -> Option<u64> {
+    Command::new("systemctl")
+        .args(["show", "--value", "-p", "SoftRebootsCount"])
+        .run_output()
+        .ok()
+        .and_then(|o| String::from_utf8_lossy(&o.stdout).trim().parse().ok())
+}
+
+fn state_path() -> PathBuf {
+    paths::state_file("pending-verify.json")
+}
@@ -0,0 +1,426 @@
+// Guided recovery (`eshu-trace recover`): from a live USB, find and mount the broken
+// system, pick snapshots and run the bisect inside it
+
+use anyhow::{Context, Result};
+use colored::*;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::audit;
+use crate::exec::CommandExt;
+use crate::interrupt;
+use crate::recovery::{RecoveryContext, RecoveryType};
+use crate::test_runner::{is_root, which};
+
+/// Where the broken system is mounted; /mnt is what RecoveryContext looks at first
+const TARGET: &str = "/mnt";
+/// Top level of a btrfs filesystem, for picking subvolumes
+const TOP_LEVEL: &str = "/run/eshu-trace/top";
+const LUKS_NAME: &str = "eshu-trace-root";
+/// Binary copied into the target so the bisect runs with its package manager
+/// (not /tmp, which arch-chroot mounts over)
+const CHROOT_BINARY: &str = "/var/tmp/eshu-trace";
+/// Where eshu-trace finds Timeshift btrfs snapshots; the chroot shares the live /run
+const TIMESHIFT_BACKUP: &str = "/run/timeshift/backup";
+
+/// Root filesystems worth offering
+const ROOT_FSTYPES: &[&str] = &["btrfs", "ext4", "ext3", "xfs", "f2fs", "jfs", "reiserfs", "crypto_LUKS", "LVM2_member"];
+
+#[derive(Debug, Deserialize)]
+struct Lsblk {
+    blockdevices: Vec<BlockDevice>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct BlockDevice {
+    name: String,
+    fstype: Option<String>,
+    size: Option<String>,
+    label: Option<String>,
+    mountpoint: Option<String>,
+    #[serde(default)]
+    children: Vec<BlockDevice>,
+}
+
+impl BlockDevice {
+    fn fstype(&self) -> &str {
+        self.fstype.as_deref().unwrap_or("")
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} {} {}{}",
+            self.name,
+            self.fstype(),
+            self.size.as_deref().unwrap_or(""),
+            self.label.as_deref().map(|l| format!(" \"{}\"", l)).unwrap_or_default()
+        )
+    }
+}
+
+/// A snapshot found on the mounted system, by the ID `eshu-trace bisect` will see in the chroot
+struct FoundSnapshot {
+    id: String,
+    date: String,
+    description: String,
+}
+
+pub fn run() -> Result<()> {
+    println!("{}", "🛟 Eshu-Trace Recovery".cyan().bold());
+    println!("{}", "   We'll mount your installed system and find what broke it, step by step.".dimmed());
+    println!();
+
+    if !is_root() {
+        anyhow::bail!("Recovery needs root to unlock and mount disks; run: sudo eshu-trace recover");
+    }
+
+    let ctx = RecoveryContext::detect()?;
+    if !matches!(ctx.recovery_type, RecoveryType::LiveUSB) {
+        println!("{} This does not look like a live USB session.", "⚠".yellow());
+        println!("   If your system boots, run {} instead.", "eshu-trace bisect".green());
+        if !confirm("Continue anyway?", false)? {
+            return Ok(());
+        }
+    }
+
+    let target = Path::new(TARGET);
+    if target.join("etc/os-release").exists() {
+        println!("{} A system is already mounted at {}", "✓".green(), TARGET);
+    } else {
+        // Find the root partition, unlock it, mount it
+        println!("{}", "Step 1: Find your system disk".yellow().bold());
+        let device = choose_device()?;
+        let device = unlock(device)?;
+        println!();
+        println!("{}", "Step 2: Mount it".yellow().bold());
+        mount_root(&device, target)?;
+    }
+
+    // Make sure it is the right system
+    println!();
+    println!("{}", "Step 3: Check it".yellow().bold());
+    let name = os_name(target)
+        .ok_or_else(|| anyhow::anyhow!("{} has no /etc/os-release; this is not a root filesystem", TARGET))?;
+    println!("{} Found {}", "✓".green(), name.bold());
+    if !confirm("Is this the system that broke?", true)? {
+        println!("   Unmount it with {} and run recover again.", format!("umount -R {}", TARGET).green());
+        return Ok(());
+    }
+
+    // Snapshot IDs as the bisect inside the chroot will list them
+    println!();
+    println!("{}", "Step 4: Pick snapshots".yellow().bold());
+    let snapshots = find_snapshots(target);
+    let (good, bad) = if snapshots.is_empty() {
+        println!("{} No snapshots found under {}/.snapshots or Timeshift's folder", "⚠".yellow(), TARGET);
+        println!("   The bisect will ask for them once it starts.");
+        (None, None)
+    } else {
+        let good = pick_snapshot(&snapshots, "Snapshot from when the system was WORKING")?;
+        let bad = pick_snapshot(&snapshots, "Snapshot from when it was BROKEN")?;
+        (good, bad)
+    };
+
+    // Hand off to the bisect inside the installed system
+    println!();
+    println!("{}", "Step 5: Find the breaking package".yellow().bold());
+    let mut args = vec!["bisect".to_string()];
+    if let Some(good) = good {
+        args.extend(["--good".to_string(), good]);
+    }
+    if let Some(bad) = bad {
+        args.extend(["--bad".to_string(), bad]);
+    }
+    let status = run_in_chroot(target, &args)?;
+
+    println!();
+    if status.success() {
+        println!("{} Done. Reboot into your system when you're ready.", "✓".green().bold());
+    } else {
+        println!("{} The bisect did not finish. Your system is still mounted at {}.", "⚠".yellow(), TARGET);
+        println!("   Re-run {} to try again.", "sudo eshu-trace recover".green());
+    }
+    println!("   Unmount first with: {}", format!("umount -R {}", TARGET).green());
+
+    Ok(())
+}
+
+fn confirm(prompt: &str, default: bool) -> Result<bool> {
+    Ok(dialoguer::Confirm::new().with_prompt(prompt).default(default).interact()?)
+}
+
+fn lsblk(device: Option<&str>) -> Result<Vec<BlockDevice>> {
+    let mut cmd = Command::new("lsblk");
+    cmd.args(["-J", "-p", "-o", "NAME,FSTYPE,SIZE,LABEL,MOUNTPOINT"]);
+    if let Some(device) = device {
+        cmd.arg(device);
+    }
+    let output = cmd.run_output().context("Failed to run lsblk")?;
+    let parsed: Lsblk = serde_json::from_slice(&output.stdout).context("Unexpected lsblk output")?;
+    Ok(parsed.blockdevices)
+}
+
+/// Unmounted partitions with a filesystem a root could live on
+fn root_candidates(devices: &[BlockDevice], out: &mut Vec<BlockDevice>) {
+    for device in devices {
+        if device.mountpoint.is_none() && ROOT_FSTYPES.contains(&device.fstype()) {
+            out.push(device.clone());
+        }
+        // An unlocked LUKS or LVM device shows its contents as children
+        if !matches!(device.fstype(), "crypto_LUKS" | "LVM2_member") || !device.children.is_empty() {
+            root_candidates(&device.children, out);
+        }
+    }
+}
+
+fn choose_device() -> Result<BlockDevice> {
+    let mut candidates = Vec::new();
+    root_candidates(&lsblk(None)?, &mut candidates);
+    // Already-opened containers are reached through their children
+    candidates.retain(|d| d.children.is_empty() || !matches!(d.fstype(), "crypto_LUKS" | "LVM2_member"));
+
+    if candidates.is_empty() {
+        anyhow::bail!("No unmounted Linux partitions found. Is the disk connected?");
+    }
+
+    let items: Vec<String> = candidates.iter().map(BlockDevice::describe).collect();
+    let selection = dialoguer::Select::new()
+        .with_prompt("Which partition holds your system? (encrypted ones show crypto_LUKS)")
+        .items(&items)
+        .default(0)
+        .interact()?;
+
+    Ok(candidates[selection].clone())
+}
+
+/// Open LUKS and activate LVM until a plain filesystem is left
+fn unlock(device: BlockDevice) -> Result<BlockDevice> {
+    match device.fstype() {
+        "crypto_LUKS" => {
+            println!("{} {} is encrypted; enter its passphrase", "🔒".bold(), device.name);
+            let status = audit::run(
+                "recover: unlock LUKS",
+                Command::new("cryptsetup").args(["open", &device.name, LUKS_NAME]),
+            )?;
+            if !status.success() {
+                anyhow::bail!("Could not unlock {} (wrong passphrase?)", device.name);
+            }
+            let opened = format!("/dev/mapper/{}", LUKS_NAME);
+            let inner = lsblk(Some(&opened))?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("{} did not appear after unlocking", opened))?;
+            unlock(inner)
+        }
+        "LVM2_member" => {
+            if which("vgchange") {
+                audit::run("recover: activate LVM", Command::new("vgchange").arg("-ay"))?;
+            }
+            let mut volumes = Vec::new();
+            for pv in lsblk(Some(&device.name))? {
+                root_candidates(&pv.children, &mut volumes);
+            }
+            if volumes.is_empty() {
+                anyhow::bail!("No logical volumes found on {}", device.name);
+            }
+            let items: Vec<String> = volumes.iter().map(BlockDevice::describe).collect();
+            let selection = dialoguer::Select::new()
+                .with_prompt("Which logical volume is the root?")
+                .items(&items)
+                .default(0)
+                .interact()?;
+            unlock(volumes[selection].clone())
+        }
+        _ => Ok(device),
+    }
+}
+
+fn mount(action: &str, args: &[&str]) -> Result<()> {
+    let status = audit::run(action, Command::new("mount").args(args))?;
+    if !status.success() {
+        anyhow::bail!("mount {} failed", args.join(" "));
+    }
+    Ok(())
+}
+
+fn mount_root(device: &BlockDevice, target: &Path) -> Result<()> {
+    fs::create_dir_all(target)?;
+    let target_str = target.to_string_lossy();
+
+    if device.fstype() != "btrfs" {
+        mount("recover: mount root", &[&device.name, &target_str])?;
+        println!("{} Mounted {} at {}", "✓".green(), device.name, target_str);
+        return Ok(());
+    }
+
+    // btrfs: look at the top level to find the root subvolume
+    fs::create_dir_all(TOP_LEVEL)?;
+    mount("recover: mount btrfs top level", &["-o", "subvolid=5", &device.name, TOP_LEVEL])?;
+
+    let top = Path::new(TOP_LEVEL);
+    let mut roots: Vec<String> = fs::read_dir(top)?
+        .flatten()
+        .filter(|e| e.path().join("etc/os-release").exists())
+        .filter_map(|e| e.file_name().to_str().map(String::from))
+        .collect();
+    roots.sort_by_key(|name| name != "@");
+
+    let subvol = match roots.len() {
+        0 if top.join("etc/os-release").exists() => None,
+        0 => anyhow::bail!("No root subvolume found on {}", device.name),
+        1 => Some(roots[0].clone()),
+        _ => {
+            let selection = dialoguer::Select::new()
+                .with_prompt("Which subvolume is the root?")
+                .items(&roots)
+                .default(0)
+                .interact()?;
+            Some(roots[selection].clone())
+        }
+    };
+
+    match &subvol {
+        Some(subvol) => mount("recover: mount root subvolume", &["-o", &format!("subvol={}", subvol), &device.name, &target_str])?,
+        None => mount("recover: mount root", &["-o", "subvolid=5", &device.name, &target_str])?,
+    }
+    println!(
+        "{} Mounted {}{} at {}",
+        "✓".green(),
+        device.name,
+        subvol.as_deref().map(|s| format!(" ({})", s)).unwrap_or_default(),
+        target_str
+    );
+
+    // Arch-style layout keeps snapper snapshots in a sibling subvolume
+    let snapshots = target.join(".snapshots");
+    if top.join("@snapshots").is_dir() && snapshots.is_dir() && is_empty_dir(&snapshots) {
+        mount(
+            "recover: mount snapshots subvolume",
+            &["-o", "subvol=@snapshots", &device.name, &snapshots.to_string_lossy()],
+        )?;
+    }
+
+    // Timeshift keeps its snapshots at the top level; expose them where eshu-trace looks
+    if top.join("timeshift-btrfs").is_dir() && !interrupt::is_mountpoint(Path::new(TIMESHIFT_BACKUP)) {
+        fs::create_dir_all(TIMESHIFT_BACKUP)?;
+        mount("recover: expose Timeshift snapshots", &["--bind", TOP_LEVEL, TIMESHIFT_BACKUP])?;
+    }
+
+    Ok(())
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path).map(|mut d| d.next().is_none()).unwrap_or(false)
+}
+
+fn os_name(root: &Path) -> Option<String> {
+    let release = fs::read_to_string(root.join("etc/os-release")).ok()?;
+    let field = |key: &str| {
+        release
+            .lines()
+            .find_map(|l| l.strip_prefix(key))
+            .map(|v| v.trim_matches('"').to_string())
+    };
+    field("PRETTY_NAME=").or_else(|| field("NAME="))
+}
+
+/// Snapper (.snapshots/N/info.xml) and Timeshift snapshots of the mounted system
+fn find_snapshots(root: &Path) -> Vec<FoundSnapshot> {
+    let mut found = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(root.join(".snapshots")) {
+        for entry in entries.flatten() {
+            let id = entry.file_name().to_string_lossy().to_string();
+            let info = fs::read_to_string(entry.path().join("info.xml")).unwrap_or_default();
+            if id.parse::<u64>().is_err() {
+                continue;
+            }
+            found.push(FoundSnapshot {
+                date: xml_field(&info, "date").unwrap_or_default(),
+                description: xml_field(&info, "description").unwrap_or_default(),
+                id,
+            });
+        }
+    }
+
+    let timeshift_dirs = [
+        Path::new(TIMESHIFT_BACKUP).join("timeshift-btrfs/snapshots"),
+        root.join("timeshift/snapshots"),
+    ];
+    for dir in timeshift_dirs {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let id = entry.file_name().to_string_lossy().to_string();
+                found.push(FoundSnapshot {
+                    // Timeshift names snapshots 2024-05-01_10-00-01
+                    date: id.replacen('_', " ", 1),
+                    description: "Timeshift".to_string(),
+                    id,
+                });
+            }
+        }
+    }
+
+    // Newest first, like `eshu-trace snapshots`
+    found.sort_by(|a, b| b.date.cmp(&a.date));
+    found
+}
+
+fn xml_field(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].trim().to_string())
+}
+
+/// Returns None when the user wants the bisect to ask instead
+fn pick_snapshot(snapshots: &[FoundSnapshot], prompt: &str) -> Result<Option<String>> {
+    let mut items: Vec<String> = snapshots
+        .iter()
+        .map(|s| format!("{} - {} {}", s.id, s.date, s.description.dimmed()))
+        .collect();
+    items.push("Not sure - choose later".to_string());
+
+    let selection = dialoguer::Select::new()
+        .with_prompt(prompt)
+        .items(&items)
+        .default(0)
+        .interact()?;
+
+    Ok(snapshots.get(selection).map(|s| s.id.clone()))
+}
+
+/// Run this binary inside the mounted system, where its package manager and snapshots are
+fn run_in_chroot(target: &Path, args: &[String]) -> Result<std::process::ExitStatus> {
+    let exe = std::env::current_exe().context("Cannot locate the eshu-trace binary")?;
+    let inside = target.join(CHROOT_BINARY.trim_start_matches('/'));
+    fs::create_dir_all(inside.parent().unwrap_or(target))?;
+    fs::copy(&exe, &inside).with_context(|| format!("Failed to copy eshu-trace into {}", target.display()))?;
+
+    // arch-chroot sets up /proc, /sys, /dev and resolv.conf itself
+    if which("arch-chroot") {
+        return audit::run_interactive(
+            "recover: bisect in chroot",
+            Command::new("arch-chroot").arg(target).arg(CHROOT_BINARY).args(args),
+        );
+    }
+
+    for (source, dest) in [("/proc", "proc"), ("/sys", "sys"), ("/dev", "dev"), ("/dev/pts", "dev/pts"), ("/run", "run")] {
+        let dest: PathBuf = target.join(dest);
+        if interrupt::is_mountpoint(&dest) {
+            continue;
+        }
+        fs::create_dir_all(&dest)?;
+        mount("recover: prepare chroot", &["--rbind", source, &dest.to_string_lossy()])?;
+    }
+    // Downgrades need to download packages
+    let _ = fs::copy("/etc/resolv.conf", target.join("etc/resolv.conf"));
+
+    // The bisect inside prompts for verdicts, so it needs the terminal
+    audit::run_interactive(
+        "recover: bisect in chroot",
+        Command::new("chroot").arg(target).arg(CHROOT_BINARY).args(args),
+    )
+}
+
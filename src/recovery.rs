@@ -4,11 +4,23 @@ use anyhow::Result;
 use std::path::Path;
 use std::process::Command;
 
+use crate::exec::CommandExt;
+
 pub struct RecoveryContext {
+    #[allow(dead_code)]
     pub is_recovery: bool,
     pub is_chroot: bool,
     pub recovery_type: RecoveryType,
     pub system_root: String,
+    pub overlay: OverlayKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OverlayKind {
+    None,              // Root is a regular filesystem
+    LiveOverlay,       // Live session, changes kept in RAM
+    LivePersistence,   // Live session with a persistence partition/file (casper-rw, persistence.conf)
+    OverlayRoot,       // Installed system booted with a read-only lower root (overlayroot)
 }
 
 #[derive(Debug)]
@@ -22,8 +34,9 @@ pub enum RecoveryType {
 
 impl RecoveryContext {
     pub fn detect() -> Result<Self> {
+        let overlay = Self::detect_overlay();
         let is_chroot = Self::detect_chroot();
-        let recovery_type = Self::detect_recovery_type(is_chroot);
+        let recovery_type = Self::detect_recovery_type(is_chroot, &overlay);
         let system_root = Self::find_system_root(&recovery_type);
 
         Ok(Self {
@@ -31,13 +44,14 @@ impl RecoveryContext {
             is_chroot,
             recovery_type,
             system_root,
+            overlay,
         })
     }
 
     fn detect_chroot() -> bool {
         // Check if we're in a chroot by comparing root inode
         // In chroot, / inode != 2 (standard root inode)
-        if let Ok(stat) = std::fs::metadata("/") {
+        if let Ok(_stat) = std::fs::metadata("/") {
             // In chroot or container, root inode is often different
             // This is a simple heuristic
             if Path::new("/proc/1/root").exists() {
@@ -52,11 +66,44 @@ impl RecoveryContext {
         std::env::var("CHROOT").is_ok()
     }
 
-    fn detect_recovery_type(is_chroot: bool) -> RecoveryType {
-        // Check for live USB
-        if Path::new("/run/archiso").exists() || // Arch live
-           Path::new("/cdrom").exists() ||        // Ubuntu live
-           Path::new("/lib/live").exists() {      // Debian live
+    fn detect_overlay() -> OverlayKind {
+        let cmdline = std::fs::read_to_string("/proc/cmdline").unwrap_or_default();
+        let args: Vec<&str> = cmdline.split_whitespace().collect();
+
+        let live = Self::is_live_session(&args);
+        let persistent = args.iter().any(|a| {
+            *a == "persistent" || *a == "persistence" || a.starts_with("rd.live.overlay=")
+        }) || Path::new("/run/live/persistence").exists() || Path::new("/var/log/casper-persistent").exists();
+
+        if live {
+            return if persistent { OverlayKind::LivePersistence } else { OverlayKind::LiveOverlay };
+        }
+
+        let root_fstype = Command::new("findmnt")
+            .args(["-n", "-o", "FSTYPE", "/"])
+            .run_output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+            .unwrap_or_default();
+        if root_fstype == "overlay" && !Path::new("/.dockerenv").exists() && !Path::new("/run/.containerenv").exists() {
+            return OverlayKind::OverlayRoot;
+        }
+
+        OverlayKind::None
+    }
+
+    fn is_live_session(args: &[&str]) -> bool {
+        args.iter().any(|a| {
+            *a == "boot=casper" || *a == "boot=live" || *a == "rd.live.image" || a.starts_with("archisobasedir=")
+        }) ||
+        Path::new("/run/archiso").exists() ||     // Arch live
+        Path::new("/cdrom/.disk").exists() ||     // Ubuntu live (an empty /cdrom is left on installs)
+        Path::new("/run/live/medium").exists() || // Debian live
+        Path::new("/lib/live/mount").exists()
+    }
+
+    fn detect_recovery_type(is_chroot: bool, overlay: &OverlayKind) -> RecoveryType {
+        // Check for live USB; persistence makes the live system look installed, so trust the overlay check
+        if matches!(overlay, OverlayKind::LiveOverlay | OverlayKind::LivePersistence) {
             return RecoveryType::LiveUSB;
         }
 
@@ -68,7 +115,7 @@ impl RecoveryContext {
         // Check for recovery mode (runlevel 1 or rescue.target)
         if let Ok(target) = Command::new("systemctl")
             .arg("get-default")
-            .output() {
+            .run_output() {
             let target_str = String::from_utf8_lossy(&target.stdout);
             if target_str.contains("rescue") || target_str.contains("emergency") {
                 return RecoveryType::RecoveryMode;
@@ -84,18 +131,120 @@ impl RecoveryContext {
     }
 
     fn is_snapshot_boot() -> bool {
-        // Check if current boot is from a snapshot
-        // BTRFS: check if mounted subvolume is a snapshot
-        if let Ok(output) = Command::new("findmnt")
-            .args(&["-n", "-o", "SOURCE", "/"])
-            .output() {
-            let source = String::from_utf8_lossy(&output.stdout);
-            // Timeshift snapshots are in /@timeshift/snapshots/
-            if source.contains("@timeshift") || source.contains("snapshots") {
-                return true;
+        // openSUSE runs from @/.snapshots/N/snapshot all the time: it is the default
+        // subvolume, so a snapshot path only means a snapshot boot when it isn't the default
+        let default = Self::default_subvolume();
+        let booted_snapshot = |path: &str| {
+            Self::is_snapshot_path(path) && default.as_deref() != Some(path.trim_start_matches('/'))
+        };
+
+        // Timeshift / grub-btrfs: the mounted root subvolume lives under a snapshot directory
+        if Self::root_subvolume().is_some_and(|root| booted_snapshot(&root)) {
+            return true;
+        }
+
+        // systemd-boot (snapper-boot) and Limine snapshot entries pass the snapshot on the command line
+        if Self::cmdline_subvolume().is_some_and(|subvol| booted_snapshot(&subvol)) {
+            return true;
+        }
+
+        Self::booted_snapper_entry() || Self::is_readonly_root() || Self::is_off_default_subvolume(default.as_deref())
+    }
+
+    /// Timeshift snapshots are in /@timeshift/snapshots/, snapper's in /.snapshots/N/snapshot
+    /// (or /@snapshots/N/snapshot with a flat layout)
+    fn is_snapshot_path(path: &str) -> bool {
+        path.contains("@timeshift")
+            || path.contains(".snapshots/")
+            || path.contains("@snapshots/")
+            || path.contains("timeshift-btrfs/snapshots")
+    }
+
+    /// Subvolume mounted at /, e.g. "/@" or "/@/.snapshots/1/snapshot"
+    fn root_subvolume() -> Option<String> {
+        let output = Command::new("findmnt")
+            .args(["-n", "-o", "FSTYPE,FSROOT", "/"])
+            .run_output()
+            .ok()?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        let (fstype, root) = text.trim().split_once(char::is_whitespace)?;
+        (fstype == "btrfs").then(|| root.trim().to_string())
+    }
+
+    /// Path of the btrfs default subvolume, without a leading slash
+    fn default_subvolume() -> Option<String> {
+        let output = Command::new("btrfs")
+            .args(["subvolume", "get-default", "/"])
+            .run_output()
+            .ok()?;
+        // "ID 270 gen 1234 top level 257 path @/.snapshots/5/snapshot"
+        let text = String::from_utf8_lossy(&output.stdout);
+        let (_, path) = text.trim().split_once(" path ")?;
+        Some(path.trim_start_matches('/').to_string())
+    }
+
+    /// Root subvolume requested via rootflags=subvol= or rootflags=subvolid=
+    fn cmdline_subvolume() -> Option<String> {
+        let cmdline = std::fs::read_to_string("/proc/cmdline").ok()?;
+        let flags = cmdline
+            .split_whitespace()
+            .find_map(|arg| arg.strip_prefix("rootflags="))?;
+
+        for flag in flags.split(',') {
+            if let Some(path) = flag.strip_prefix("subvol=") {
+                return Some(path.to_string());
+            }
+            if let Some(id) = flag.strip_prefix("subvolid=") {
+                let output = Command::new("btrfs")
+                    .args(["inspect-internal", "subvolid-resolve", id, "/"])
+                    .run_output()
+                    .ok()?;
+                let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                return (output.status.success() && !path.is_empty()).then_some(path);
             }
         }
-        false
+        None
+    }
+
+    /// systemd-boot records the chosen entry in an EFI variable; snapper-boot names its entries after snapper
+    fn booted_snapper_entry() -> bool {
+        const VAR: &str = "/sys/firmware/efi/efivars/LoaderEntrySelected-4a67b082-0a4c-41cf-b6c7-440b29bb8c4f";
+        let Ok(raw) = std::fs::read(VAR) else {
+            return false;
+        };
+
+        // 4 bytes of attributes, then a NUL-terminated UTF-16LE string
+        let units: Vec<u16> = raw
+            .get(4..)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&u| u != 0)
+            .collect();
+        let entry = String::from_utf16_lossy(&units).to_lowercase();
+        entry.contains("snapper") || entry.contains("snapshot")
+    }
+
+    /// Snapper snapshots are read-only; booting one (grub-btrfs, Limine) leaves / read-only on btrfs
+    fn is_readonly_root() -> bool {
+        Command::new("btrfs")
+            .args(["property", "get", "-ts", "/", "ro"])
+            .run_output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim() == "ro=true")
+            .unwrap_or(false)
+    }
+
+    /// After `snapper rollback` the default subvolume points at a snapshot; running from
+    /// any other subvolume means the boot picked something other than the default
+    fn is_off_default_subvolume(default: Option<&str>) -> bool {
+        let Some(default) = default.filter(|d| Self::is_snapshot_path(&format!("/{}", d))) else {
+            return false;
+        };
+        let Some(root) = Self::root_subvolume() else {
+            return false;
+        };
+        let root = root.trim_start_matches('/');
+        !root.is_empty() && root != default
     }
 
     fn find_system_root(recovery_type: &RecoveryType) -> String {
@@ -108,12 +257,37 @@ impl RecoveryContext {
                         return path.to_string();
                     }
                 }
+
+                // Disks auto-mounted by the live desktop: /media/<user>/<label>, /run/media/<user>/<label>
+                for base in &["/media", "/run/media"] {
+                    for user in Self::subdirs(Path::new(base)) {
+                        for mount in Self::subdirs(&user) {
+                            if mount.join("etc/os-release").exists() && !Self::is_live_storage(&mount) {
+                                return mount.to_string_lossy().to_string();
+                            }
+                        }
+                    }
+                }
                 "/mnt".to_string() // Default
             }
             _ => "/".to_string()
         }
     }
 
+    fn subdirs(dir: &Path) -> Vec<std::path::PathBuf> {
+        std::fs::read_dir(dir)
+            .map(|entries| entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect())
+            .unwrap_or_default()
+    }
+
+    /// The live medium or its persistence store, which also carry an os-release
+    fn is_live_storage(path: &Path) -> bool {
+        path.join("casper").exists() ||
+        path.join("live").join("filesystem.squashfs").exists() ||
+        path.join("persistence.conf").exists() ||
+        (path.join("upper").exists() && path.join("work").exists())
+    }
+
     pub fn show_recovery_banner(&self) {
         use colored::*;
 
@@ -125,6 +299,9 @@ impl RecoveryContext {
                 println!();
                 println!("{} Your broken system is mounted at: {}", "✓".green(), self.system_root.yellow());
                 println!("{} Eshu-Trace will analyze the mounted system", "ℹ".cyan());
+                if self.overlay == OverlayKind::LivePersistence {
+                    println!("{} Persistence is on: the live session itself is not the system being traced", "ℹ".cyan());
+                }
                 println!();
             }
             RecoveryType::Chroot => {
@@ -151,7 +328,13 @@ impl RecoveryContext {
                 println!("{} Will analyze differences to find breaking package", "ℹ".cyan());
                 println!();
             }
-            RecoveryType::Normal => {}
+            RecoveryType::Normal => {
+                if self.overlay == OverlayKind::OverlayRoot {
+                    println!("{} Root is an overlay filesystem: fixes applied now are lost on reboot", "⚠".yellow());
+                    println!("   Disable overlayroot (or remount the lower root read-write) to make them stick");
+                    println!();
+                }
+            }
         }
     }
 
@@ -185,6 +368,9 @@ pub fn show_recovery_instructions() {
     println!();
 
     println!("{}", "OPTION 1: Boot from Live USB (Easiest)".yellow().bold());
+    println!("  Shortcut: after installing eshu-trace on the live USB (step 4), run");
+    println!("  {} and it walks you through steps 3 and 5.", "sudo eshu-trace recover".green());
+    println!();
     println!("  1. Boot from Ubuntu/Arch/Fedora live USB");
     println!("  2. Open terminal");
     println!("  3. Mount your broken system:");
@@ -209,8 +395,8 @@ pub fn show_recovery_instructions() {
     println!("  5. Run: {}", "eshu-trace bisect".green());
     println!();
 
-    println!("{}", "OPTION 3: Boot into Old Snapshot (If using BTRFS/Timeshift)".yellow().bold());
-    println!("  1. Reboot and select old snapshot from GRUB");
+    println!("{}", "OPTION 3: Boot into Old Snapshot (If using BTRFS/Timeshift/Snapper)".yellow().bold());
+    println!("  1. Reboot and select old snapshot from GRUB, systemd-boot or Limine");
     println!("  2. System boots normally (from old state)");
     println!("  3. Run: {}", "eshu-trace bisect".green());
     println!("  4. It will compare old (working) vs new (broken)");
@@ -0,0 +1,345 @@
+// Standalone shell scripts that re-apply a chosen fix on other identical machines
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::error::TraceError;
+use crate::integrity::{self, Integrity};
+use crate::paths;
+use crate::pins;
+
+const ARCH_ARCHIVE_URL: &str = "https://archive.archlinux.org/packages";
+
+/// The fix a script reproduces
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Remedy {
+    /// Packages and the versions to go back to, in one transaction
+    Downgrade(Vec<(String, String)>),
+    Remove(String),
+    /// Package, version to hold it at
+    Pin(String, String),
+}
+
+impl Remedy {
+    fn package(&self) -> &str {
+        match self {
+            Remedy::Downgrade(targets) => &targets[0].0,
+            Remedy::Remove(package) | Remedy::Pin(package, _) => package,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Remedy::Downgrade(targets) => {
+                let list: Vec<String> = targets.iter().map(|(p, v)| format!("{} {}", p, v)).collect();
+                format!("downgrade to {}", list.join(", "))
+            }
+            Remedy::Remove(package) => format!("remove {}", package),
+            Remedy::Pin(package, version) => format!("pin {} at {}", package, version),
+        }
+    }
+}
+
+/// The fix applied most recently, kept for `eshu-trace fix --export-*`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LastFix {
+    pub distro: String,
+    pub remedy: Remedy,
+    pub applied_at: String,
+}
+
+impl LastFix {
+    pub fn load() -> Result<Option<Self>> {
+        let path = last_fix_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let data = fs::read_to_string(&path).context("Failed to read the last fix")?;
+        Ok(Some(serde_json::from_str(&data).context("Failed to parse the last fix")?))
+    }
+}
+
+/// Remember `remedy` as the last applied fix
+pub fn record(distro: &str, remedy: &Remedy) -> Result<()> {
+    let last = LastFix {
+        distro: distro.to_string(),
+        remedy: remedy.clone(),
+        applied_at: chrono::Local::now().to_rfc3339(),
+    };
+
+    let path = last_fix_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(&last)?)?;
+    Ok(())
+}
+
+fn last_fix_path() -> PathBuf {
+    paths::state_file("last-fix.json")
+}
+
+/// Shell script performing `remedy` on a machine running `distro`
+pub fn script(distro: &str, remedy: &Remedy) -> Result<String> {
+    let supported = matches!(
+        distro,
+        "arch" | "manjaro" | "endeavouros" | "ubuntu" | "debian" | "linuxmint" | "pop" | "fedora" | "rhel" | "centos"
+    ) || pins::is_zypper(distro);
+    if !supported {
+        return Err(TraceError::UnsupportedDistro(distro.to_string()).into());
+    }
+
+    let mut out = format!(
+        "#!/bin/sh\n\
+         # Generated by eshu-trace on {date}\n\
+         # Fix: {fix}\n\
+         set -e\n\n\
+         [ \"$(id -u)\" -eq 0 ] || exec sudo sh \"$0\" \"$@\"\n\n\
+         . /etc/os-release\n\
+         [ \"$ID\" = {distro} ] || {{ echo \"This fix was made for {distro}, not $ID\" >&2; exit 1; }}\n\n",
+        date = chrono::Local::now().format("%Y-%m-%d %H:%M"),
+        fix = remedy.describe(),
+        distro = distro,
+    );
+
+    out.push_str(&commands(distro, remedy)?);
+    out.push_str(&format!("\necho \"eshu-trace fix applied: {}\"\n", remedy.describe()));
+    Ok(out)
+}
+
+fn commands(distro: &str, remedy: &Remedy) -> Result<String> {
+    let arch = matches!(distro, "arch" | "manjaro" | "endeavouros");
+    let apt = matches!(distro, "ubuntu" | "debian" | "linuxmint" | "pop");
+    let zypper = pins::is_zypper(distro);
+
+    let text = match remedy {
+        Remedy::Downgrade(targets) if arch => {
+            // Local cache first, then the Arch Linux Archive
+            let mut s = String::from(
+                "fetch() {\n  \
+                 for f in /var/cache/pacman/pkg/$1-$2-*.pkg.tar.*; do\n    \
+                 case \"$f\" in *.sig) continue ;; esac\n    \
+                 [ -e \"$f\" ] && { echo \"$f\"; return; }\n  \
+                 done\n  \
+                 for a in $(uname -m) any; do for e in zst xz; do\n    \
+                 url=\"$3/$1-$(echo \"$2\" | sed 's/:/%3A/')-$a.pkg.tar.$e\"\n    \
+                 f=\"/var/cache/pacman/pkg/${url##*/}\"\n    \
+                 if curl -fsSLo \"$f\" \"$url\"; then\n      \
+                 if ! curl -fsSLo \"$f.sig\" \"$url.sig\" || ! pacman-key --verify \"$f.sig\" \"$f\" >&2; then\n        \
+                 rm -f \"$f\" \"$f.sig\"; echo \"Refusing $1 $2: signature verification failed\" >&2; exit 1\n      \
+                 fi\n      \
+                 echo \"$f\"; return\n    \
+                 fi\n  \
+                 done; done\n  \
+                 echo \"$1 $2 is not in the cache or the archive\" >&2; exit 1\n}\n\n",
+            );
+            let files: Vec<String> = targets
+                .iter()
+                .map(|(p, v)| {
+                    let first = p.chars().next().unwrap_or('_');
+                    format!("\"$(fetch {0} {1} {2}/{3}/{0})\"", p, v, ARCH_ARCHIVE_URL, first)
+                })
+                .collect();
+            s.push_str(&format!("pacman -U --noconfirm {}\n", files.join(" ")));
+            s
+        }
+        Remedy::Downgrade(targets) if apt => {
+            let specs: Vec<String> = targets.iter().map(|(p, v)| format!("{}={}", p, v)).collect();
+            format!(
+                "apt-get update\nDEBIAN_FRONTEND=noninteractive apt-get install -y --allow-downgrades {}\n",
+                specs.join(" ")
+            )
+        }
+        Remedy::Downgrade(targets) if zypper => {
+            let specs: Vec<String> = targets.iter().map(|(p, v)| format!("{}={}", p, v)).collect();
+            format!("zypper --non-interactive install --oldpackage {}\n", specs.join(" "))
+        }
+        Remedy::Downgrade(targets) => {
+            let specs: Vec<String> = targets.iter().map(|(p, v)| format!("{}-{}", p, v)).collect();
+            format!("dnf downgrade -y {}\n", specs.join(" "))
+        }
+        Remedy::Remove(package) if arch => format!("pacman -R --noconfirm {}\n", package),
+        Remedy::Remove(package) if apt => format!("apt-get remove -y {}\n", package),
+        Remedy::Remove(package) if zypper => format!("zypper --non-interactive remove {}\n", package),
+        Remedy::Remove(package) => format!("dnf remove -y {}\n", package),
+        Remedy::Pin(package, _) if arch => format!(
+            "grep -q '^IgnorePkg.*\\b{0}\\b' /etc/pacman.conf || sed -i '/^\\[options\\]/a IgnorePkg = {0}' /etc/pacman.conf\n",
+            package
+        ),
+        Remedy::Pin(package, version) if apt => format!(
+            "cat > /etc/apt/preferences.d/eshu-trace-{file} <<'EOF'\n\
+             # Written by an eshu-trace fix script; delete this file to lift the pin\n\
+             Package: {0}\nPin: version {1}\nPin-Priority: 1001\nEOF\n",
+            package,
+            version,
+            file = package.replace('+', "-")
+        ),
+        Remedy::Pin(package, _) if zypper => format!("zypper --non-interactive addlock {}\n", package),
+        // Add to an existing exclude= list rather than shadowing it with a second line
+        Remedy::Pin(package, _) => format!(
+            "if grep -q '^exclude=' /etc/dnf/dnf.conf; then\n  \
+             grep -q '^exclude=.*\\b{0}\\b' /etc/dnf/dnf.conf || sed -i '/^exclude=/ s/$/ {0}/' /etc/dnf/dnf.conf\n\
+             else\n  \
+             sed -i '/^\\[main\\]/a exclude={0}' /etc/dnf/dnf.conf\n\
+             fi\n",
+            package
+        ),
+    };
+
+    Ok(text)
+}
+
+/// Default file name for the script of `remedy`
+pub fn script_name(remedy: &Remedy) -> String {
+    format!("eshu-trace-fix-{}.sh", remedy.package())
+}
+
+/// Write the script for `remedy` to `path` as an executable file, with an integrity line
+/// (hash, and signature with `sign`) so the receiving side can check it
+pub fn save(path: &Path, distro: &str, remedy: &Remedy, sign: bool) -> Result<Integrity> {
+    let (text, integrity) = integrity::seal_text(&script(distro, remedy)?, sign)?;
+    fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(integrity)
+}
+
+/// Ansible playbook applying `remedy` with the package module of `distro`
+pub fn playbook(distro: &str, remedy: &Remedy) -> Result<String> {
+    let (family, tasks) = if matches!(distro, "arch" | "manjaro" | "endeavouros") {
+        ("Archlinux", arch_tasks(remedy))
+    } else if matches!(distro, "ubuntu" | "debian" | "linuxmint" | "pop") {
+        ("Debian", apt_tasks(remedy))
+    } else if matches!(distro, "fedora" | "rhel" | "centos") {
+        ("RedHat", dnf_tasks(remedy))
+    } else if pins::is_zypper(distro) {
+        ("Suse", zypper_tasks(remedy))
+    } else {
+        return Err(TraceError::UnsupportedDistro(distro.to_string()).into());
+    };
+
+    Ok(format!(
+        "# Generated by eshu-trace on {date}\n\
+         # Fix: {fix} (made on {distro}; versions are specific to that release)\n\
+         - name: \"eshu-trace fix: {fix}\"\n  \
+         hosts: all\n  \
+         become: true\n  \
+         tasks:\n    \
+         - name: Only run on {family} hosts\n      \
+         ansible.builtin.assert:\n        \
+         that: ansible_facts['os_family'] == '{family}'\n        \
+         fail_msg: \"This fix was made for {distro}\"\n\n{tasks}",
+        date = chrono::Local::now().format("%Y-%m-%d %H:%M"),
+        fix = remedy.describe(),
+        distro = distro,
+        family = family,
+        tasks = tasks,
+    ))
+}
+
+fn task(name: &str, body: &str) -> String {
+    let body: String = body
+        .lines()
+        .map(|l| if l.is_empty() { "\n".to_string() } else { format!("      {}\n", l) })
+        .collect();
+    format!("    - name: \"{}\"\n{}\n", name, body)
+}
+
+/// Task running the fix script's commands for `distro`, for fixes no module can express
+fn shell_task(distro: &str, remedy: &Remedy) -> String {
+    let script: String = commands(distro, remedy)
+        .unwrap_or_default()
+        .lines()
+        .map(|l| if l.is_empty() { "\n".to_string() } else { format!("\n  {}", l) })
+        .collect();
+    task(&remedy.describe(), &format!("ansible.builtin.shell: |{}", script))
+}
+
+fn arch_tasks(remedy: &Remedy) -> String {
+    match remedy {
+        // community.general.pacman cannot pick versions; fetch the archive files like the
+        // script does, for the host's architecture and whichever compression was used then
+        Remedy::Downgrade(_) => shell_task("arch", remedy),
+        Remedy::Remove(package) => task(
+            &remedy.describe(),
+            &format!("community.general.pacman:\n  name: {}\n  state: absent", package),
+        ),
+        Remedy::Pin(package, _) => task(
+            &remedy.describe(),
+            &format!(
+                "ansible.builtin.lineinfile:\n  path: /etc/pacman.conf\n  insertafter: '^\\[options\\]'\n  line: IgnorePkg = {}",
+                package
+            ),
+        ),
+    }
+}
+
+fn apt_tasks(remedy: &Remedy) -> String {
+    match remedy {
+        Remedy::Downgrade(targets) => {
+            let names: Vec<String> = targets.iter().map(|(p, v)| format!("    - \"{}={}\"", p, v)).collect();
+            task(
+                &remedy.describe(),
+                &format!(
+                    "ansible.builtin.apt:\n  name:\n{}\n  allow_downgrade: true\n  update_cache: true",
+                    names.join("\n")
+                ),
+            )
+        }
+        Remedy::Remove(package) => task(
+            &remedy.describe(),
+            &format!("ansible.builtin.apt:\n  name: {}\n  state: absent", package),
+        ),
+        Remedy::Pin(package, version) => task(
+            &remedy.describe(),
+            &format!(
+                "ansible.builtin.copy:\n  dest: /etc/apt/preferences.d/eshu-trace-{}\n  mode: \"0644\"\n  content: |\n    Package: {}\n    Pin: version {}\n    Pin-Priority: 1001",
+                package.replace('+', "-"),
+                package,
+                version
+            ),
+        ),
+    }
+}
+
+fn dnf_tasks(remedy: &Remedy) -> String {
+    match remedy {
+        Remedy::Downgrade(targets) => {
+            let names: Vec<String> = targets.iter().map(|(p, v)| format!("    - \"{}-{}\"", p, v)).collect();
+            task(
+                &remedy.describe(),
+                &format!("ansible.builtin.dnf:\n  name:\n{}\n  allow_downgrade: true", names.join("\n")),
+            )
+        }
+        Remedy::Remove(package) => task(
+            &remedy.describe(),
+            &format!("ansible.builtin.dnf:\n  name: {}\n  state: absent", package),
+        ),
+        // lineinfile would replace the exclude= list instead of adding to it
+        Remedy::Pin(..) => shell_task("fedora", remedy),
+    }
+}
+
+fn zypper_tasks(remedy: &Remedy) -> String {
+    match remedy {
+        Remedy::Downgrade(targets) => {
+            let names: Vec<String> = targets.iter().map(|(p, v)| format!("    - \"{}={}\"", p, v)).collect();
+            task(
+                &remedy.describe(),
+                &format!("community.general.zypper:\n  name:\n{}\n  oldpackage: true", names.join("\n")),
+            )
+        }
+        Remedy::Remove(package) => task(
+            &remedy.describe(),
+            &format!("community.general.zypper:\n  name: {}\n  state: absent", package),
+        ),
+        // The zypper module has no lock support
+        Remedy::Pin(package, _) => task(
+            &remedy.describe(),
+            &format!("ansible.builtin.command: zypper --non-interactive addlock {}", package),
+        ),
+    }
+}
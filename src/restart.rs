@@ -0,0 +1,165 @@
+// Detect services still running replaced libraries after an upgrade
+
+use anyhow::Result;
+use std::fs;
+use std::process::Command;
+
+use crate::audit;
+use crate::exec::CommandExt;
+use crate::test_runner::which;
+
+/// Services (and other processes) that need a restart to pick up upgraded files
+#[derive(Debug, Clone, Default)]
+pub struct StaleProcesses {
+    pub services: Vec<String>,
+    /// Processes outside any system service (user sessions etc.)
+    pub other_pids: Vec<u32>,
+    /// The running kernel is older than the installed one
+    pub kernel: bool,
+    /// Tool that produced the result
+    pub source: String,
+}
+
+impl StaleProcesses {
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty() && self.other_pids.is_empty() && !self.kernel
+    }
+}
+
+/// Ask needrestart, checkrestart or dnf, falling back to scanning /proc
+pub fn detect() -> StaleProcesses {
+    if which("needrestart") {
+        if let Some(result) = needrestart() {
+            return result;
+        }
+    }
+    if which("dnf") {
+        if let Some(result) = dnf_needs_restarting() {
+            return result;
+        }
+    }
+    if which("checkrestart") {
+        if let Some(result) = checkrestart() {
+            return result;
+        }
+    }
+
+    scan_proc()
+}
+
+/// Restart the given systemd services
+pub fn restart_services(services: &[String]) -> Result<bool> {
+    let status = audit::run("restart stale services", Command::new("systemctl").arg("restart").args(services))?;
+    Ok(status.success())
+}
+
+/// `needrestart -b`: NEEDRESTART-SVC: foo.service / NEEDRESTART-KSTA: 1..3 (1 = current)
+fn needrestart() -> Option<StaleProcesses> {
+    let output = Command::new("needrestart").args(["-b", "-r", "l"]).run_output().ok()?;
+    let mut result = StaleProcesses {
+        source: "needrestart".to_string(),
+        ..Default::default()
+    };
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(svc) = line.strip_prefix("NEEDRESTART-SVC: ") {
+            result.services.push(svc.trim().to_string());
+        } else if let Some(state) = line.strip_prefix("NEEDRESTART-KSTA: ") {
+            result.kernel = state.trim() != "1" && state.trim() != "0";
+        }
+    }
+
+    Some(result)
+}
+
+/// `dnf needs-restarting -s` prints one service per line
+fn dnf_needs_restarting() -> Option<StaleProcesses> {
+    let output = Command::new("dnf").args(["-q", "needs-restarting", "-s"]).run_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(StaleProcesses {
+        services: String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| l.ends_with(".service"))
+            .collect(),
+        source: "dnf needs-restarting".to_string(),
+        ..Default::default()
+    })
+}
+
+/// debian-goodies checkrestart: "systemctl restart foo" / "service foo restart" hints
+fn checkrestart() -> Option<StaleProcesses> {
+    let output = Command::new("checkrestart").run_output().ok()?;
+    let mut result = StaleProcesses {
+        source: "checkrestart".to_string(),
+        ..Default::default()
+    };
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["systemctl", "restart", svc] | ["service", svc, "restart"] => {
+                result.services.push(svc.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    Some(result)
+}
+
+/// Processes mapping deleted shared objects, grouped by their systemd service
+fn scan_proc() -> StaleProcesses {
+    let mut result = StaleProcesses {
+        source: "/proc scan".to_string(),
+        ..Default::default()
+    };
+
+    let entries = match fs::read_dir("/proc") {
+        Ok(e) => e,
+        Err(_) => return result,
+    };
+
+    for entry in entries.flatten() {
+        let pid: u32 = match entry.file_name().to_string_lossy().parse() {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+
+        let maps = fs::read_to_string(entry.path().join("maps")).unwrap_or_default();
+        let stale = maps
+            .lines()
+            .any(|l| l.ends_with("(deleted)") && l.contains(".so") && !l.contains("/memfd:"));
+        if !stale {
+            continue;
+        }
+
+        // 0::/system.slice/sddm.service
+        let cgroup = fs::read_to_string(entry.path().join("cgroup")).unwrap_or_default();
+        let service = cgroup
+            .lines()
+            .filter_map(|l| l.rsplit('/').next())
+            .find(|unit| unit.ends_with(".service") && cgroup.contains("/system.slice/"));
+
+        match service {
+            Some(svc) => {
+                if !result.services.iter().any(|s| s == svc) {
+                    result.services.push(svc.to_string());
+                }
+            }
+            None => result.other_pids.push(pid),
+        }
+    }
+
+    // Upgrading the kernel package removes the running kernel's modules
+    let modules = std::path::Path::new("/lib/modules");
+    if let (true, Ok(release)) = (modules.exists(), fs::read_to_string("/proc/sys/kernel/osrelease")) {
+        result.kernel = !modules.join(release.trim()).exists();
+    }
+
+    result.services.sort();
+    result
+}
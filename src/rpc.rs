@@ -0,0 +1,337 @@
+// JSON-RPC 2.0 over a Unix socket, for Eshu tools that integrate with eshu-trace
+//
+// One request per line, one response line back. Methods:
+//   snapshots.list                                  -> [snapshot]
+//   diff.compute    {good, bad}                     -> [change]
+//   bisect.start    {good, bad, suspects?, symptom?, uniform?} -> bisect state
+//   bisect.status                                   -> bisect state
+//   bisect.verdict  {issue}                         -> bisect state (with culprit once done)
+//   bisect.abort                                    -> null
+//   fix.apply       {action, package, version?, bad_version?} -> {success}
+//
+// A bisect state is {total, low, high, apply, culprit, group}: `apply` lists the
+// changes the caller should apply on top of the good snapshot before testing
+// and reporting a verdict; it is null once the bisect has finished.
+
+use anyhow::{Context, Result};
+use colored::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use crate::bisect::BisectSession;
+use crate::bisect_engine;
+use crate::error;
+use crate::fixer::{self, PackageFixer};
+use crate::lock::SessionLock;
+use crate::package_diff;
+use crate::premium::{self, Feature};
+use crate::recovery::RecoveryContext;
+use crate::snapshot::SnapshotManager;
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Application errors; `data` carries the eshu-trace error code and hint
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Failure of a single call, turned into a JSON-RPC error object
+enum CallError {
+    InvalidParams(String),
+    MethodNotFound(String),
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for CallError {
+    fn from(e: anyhow::Error) -> Self {
+        CallError::Failed(e)
+    }
+}
+
+/// The bisect being driven by clients, with the lock that keeps CLI bisects out
+struct ActiveBisect {
+    session: BisectSession,
+    _lock: SessionLock,
+}
+
+#[derive(Default)]
+struct Server {
+    bisect: Option<ActiveBisect>,
+}
+
+/// Listen on `socket` and answer requests until interrupted
+pub fn serve(socket: &Path) -> Result<()> {
+    let parent = socket.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)?;
+    // A socket left behind by a killed server would make bind fail
+    if socket.exists() {
+        fs::remove_file(socket)?;
+    }
+
+    // Fixes run with this process's privileges; keep other users out. The socket is bound
+    // in a private (0700) directory and only moved into place once it is 0600, so it is
+    // never reachable with the umask's permissions.
+    let private = tempfile::Builder::new().prefix(".eshu-trace-rpc").tempdir_in(parent)?;
+    fs::set_permissions(private.path(), fs::Permissions::from_mode(0o700))?;
+    let staged = private.path().join("socket");
+    let listener = UnixListener::bind(&staged).with_context(|| format!("Cannot bind {}", socket.display()))?;
+    fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+    fs::rename(&staged, socket).with_context(|| format!("Cannot create {}", socket.display()))?;
+    drop(private);
+
+    println!("{} JSON-RPC listening on {}", "🔌".bold(), socket.display().to_string().cyan());
+    println!("{}", "   Press Ctrl-C to stop".dimmed());
+
+    let mut server = Server::default();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = server.handle(stream) {
+                    eprintln!("{} Connection closed: {}", "⚠".yellow(), e);
+                }
+            }
+            Err(e) => eprintln!("{} Accept failed: {}", "⚠".yellow(), e),
+        }
+    }
+
+    Ok(())
+}
+
+impl Server {
+    fn handle(&mut self, stream: UnixStream) -> Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => {
+                    let result = self.call(&request.method, request.params);
+                    respond(request.id, result)
+                }
+                Err(e) => error_response(Value::Null, PARSE_ERROR, &e.to_string(), None),
+            };
+            writeln!(writer, "{}", response)?;
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, CallError> {
+        match method {
+            "snapshots.list" => Ok(json!(SnapshotManager::new()?.list_snapshots()?)),
+            "diff.compute" => {
+                let p: SnapshotPair = parse(params)?;
+                let mgr = SnapshotManager::for_ids(&[&p.good, &p.bad])?;
+                let diff = package_diff::compute_diff(&mgr.get_snapshot(&p.good)?, &mgr.get_snapshot(&p.bad)?)?;
+                Ok(json!(diff.all_changes()))
+            }
+            "bisect.start" => {
+                let p: BisectStart = parse(params)?;
+                self.start_bisect(p)?;
+                self.bisect_state()
+            }
+            "bisect.status" => self.bisect_state(),
+            "bisect.verdict" => {
+                let p: Verdict = parse(params)?;
+                let active = self.active()?;
+                let verdict = match p.skip {
+                    true => bisect_engine::Verdict::Skip,
+                    false => bisect_engine::Verdict::from_issue(p.issue),
+                };
+                active.session.record(verdict)?;
+                self.bisect_state()
+            }
+            "bisect.abort" => {
+                self.bisect = None;
+                Ok(Value::Null)
+            }
+            "fix.apply" => {
+                let p: Fix = parse(params)?;
+                apply_fix(p)
+            }
+            other => Err(CallError::MethodNotFound(other.to_string())),
+        }
+    }
+
+    fn start_bisect(&mut self, p: BisectStart) -> Result<(), CallError> {
+        let gate = premium::gate()?;
+        if !gate.is_enabled(Feature::Trace) {
+            return Err(anyhow::anyhow!("Trial limit reached. Please purchase a license to continue.").into());
+        }
+
+        // Replacing a session started over this socket is fine; another process's is not
+        self.bisect = None;
+        let lock = SessionLock::acquire(false)?;
+
+        let mgr = SnapshotManager::for_ids(&[&p.good, &p.bad])?;
+        let mut session = BisectSession::new(mgr.get_snapshot(&p.good)?, mgr.get_snapshot(&p.bad)?)?;
+        if !p.suspects.is_empty() {
+            session.restrict_to_packages(&p.suspects)?;
+        }
+        if !p.uniform {
+            session.weigh(&p.symptom);
+        }
+
+        self.bisect = Some(ActiveBisect { session, _lock: lock });
+        Ok(())
+    }
+
+    fn active(&mut self) -> Result<&mut ActiveBisect, CallError> {
+        self.bisect
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("No bisect running; call bisect.start first").into())
+    }
+
+    /// Current range and next step; finishes the bisect once one candidate is left
+    fn bisect_state(&mut self) -> Result<Value, CallError> {
+        let active = self.active()?;
+        let total = active.session.total_packages();
+        let (low, high) = active.session.range();
+
+        if let Some(apply) = active.session.next_step() {
+            return Ok(json!({
+                "total": total,
+                "low": low,
+                "high": high,
+                "apply": apply,
+                "culprit": null,
+                "group": [],
+            }));
+        }
+
+        let culprit = active.session.finish().cloned();
+        let group = active.session.get_culprit_group();
+        premium::gate()?.record_use(Feature::Trace)?;
+        // Releases the lock
+        self.bisect = None;
+
+        Ok(json!({
+            "total": total,
+            "low": low,
+            "high": high,
+            "apply": null,
+            "culprit": culprit,
+            "group": group,
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+struct SnapshotPair {
+    good: String,
+    bad: String,
+}
+
+#[derive(Deserialize)]
+struct BisectStart {
+    good: String,
+    bad: String,
+    #[serde(default)]
+    suspects: Vec<String>,
+    /// Free-text description of the problem; matching packages are tested first
+    #[serde(default)]
+    symptom: String,
+    /// Plain binary search without prior weights
+    #[serde(default)]
+    uniform: bool,
+}
+
+#[derive(Deserialize)]
+struct Verdict {
+    /// Whether the issue occurred with the last `apply` set installed
+    #[serde(default)]
+    issue: bool,
+    /// The last `apply` set could not be tested; another split is offered instead
+    #[serde(default)]
+    skip: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FixKind {
+    Downgrade,
+    Remove,
+    Pin,
+}
+
+#[derive(Deserialize)]
+struct Fix {
+    action: FixKind,
+    package: String,
+    /// Target version for downgrade and pin
+    version: Option<String>,
+    bad_version: Option<String>,
+}
+
+fn apply_fix(p: Fix) -> Result<Value, CallError> {
+    // Anything a client sends ends up on a package manager command line run as root
+    let fields = [
+        ("package", Some(&p.package)),
+        ("version", p.version.as_ref()),
+        ("bad_version", p.bad_version.as_ref()),
+    ];
+    for (field, value) in fields {
+        if value.is_some_and(|v| !fixer::is_package_token(v)) {
+            return Err(CallError::InvalidParams(format!("{} is not a valid package name or version", field)));
+        }
+    }
+
+    let fixer = PackageFixer::new(RecoveryContext::detect()?).assume_yes();
+    let version = || {
+        p.version
+            .clone()
+            .ok_or_else(|| CallError::InvalidParams("version is required for this action".into()))
+    };
+
+    let success = match p.action {
+        FixKind::Downgrade => fixer.downgrade_packages(&[(p.package.clone(), version()?)])?,
+        FixKind::Remove => fixer.remove_package(&p.package)?,
+        FixKind::Pin => {
+            fixer.pin_package(&p.package, &version()?, p.bad_version.as_deref())?;
+            true
+        }
+    };
+
+    Ok(json!({ "success": success }))
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, CallError> {
+    serde_json::from_value(params).map_err(|e| CallError::InvalidParams(e.to_string()))
+}
+
+fn respond(id: Value, result: Result<Value, CallError>) -> Value {
+    match result {
+        Ok(value) => json!({ "jsonrpc": "2.0", "id": id, "result": value }),
+        Err(CallError::InvalidParams(message)) => error_response(id, INVALID_PARAMS, &message, None),
+        Err(CallError::MethodNotFound(method)) => {
+            error_response(id, METHOD_NOT_FOUND, &format!("Unknown method: {}", method), None)
+        }
+        Err(CallError::Failed(e)) => {
+            let data = error::to_json(&e)["error"].clone();
+            error_response(id, SERVER_ERROR, &format!("{:#}", e), Some(data))
+        }
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str, data: Option<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message, "data": data },
+    })
+}
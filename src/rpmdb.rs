@@ -0,0 +1,159 @@
+// Reading rpm's sqlite package database directly (feature "rpmdb")
+//
+// rpm 4.16+ keeps one header blob per installed package in the Packages table of
+// rpmdb.sqlite. Decoding the headers ourselves works on any snapshot root without an rpm
+// binary and without running the snapshot's own tools. The older BerkeleyDB and ndb
+// formats aren't read here; callers fall back to `rpm --root` for those.
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OpenFlags};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const DATABASES: [&str; 2] = ["usr/lib/sysimage/rpm/rpmdb.sqlite", "var/lib/rpm/rpmdb.sqlite"];
+
+const TAG_NAME: u32 = 1000;
+const TAG_VERSION: u32 = 1001;
+const TAG_RELEASE: u32 = 1002;
+
+const TYPE_STRING: u32 = 6;
+
+/// The sqlite package database under `root`, if the system uses one
+pub fn database(root: &Path) -> Option<PathBuf> {
+    DATABASES.iter().map(|db| root.join(db)).find(|db| db.is_file())
+}
+
+/// Installed packages as name → version-release (what `rpm -qa --qf '%{VERSION}-%{RELEASE}'`
+/// prints), or None when `root` has no sqlite database
+pub fn packages_in(root: &Path) -> Option<Result<HashMap<String, String>>> {
+    let db = database(root)?;
+    Some(read_packages(&db))
+}
+
+fn read_packages(db: &Path) -> Result<HashMap<String, String>> {
+    let conn = open(db)?;
+    let mut stmt = conn
+        .prepare("SELECT blob FROM Packages")
+        .with_context(|| format!("{} is not an rpm database", db.display()))?;
+    let blobs = stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+
+    let mut packages = HashMap::new();
+    for blob in blobs {
+        let blob = blob?;
+        let Some(header) = Header::parse(&blob) else {
+            continue;
+        };
+        if let (Some(name), Some(version), Some(release)) =
+            (header.string(TAG_NAME), header.string(TAG_VERSION), header.string(TAG_RELEASE))
+        {
+            packages.insert(name.to_string(), format!("{}-{}", version, release));
+        }
+    }
+    Ok(packages)
+}
+
+/// Open read-only. A database in WAL mode on a read-only snapshot can't create its -shm
+/// file, so retry as immutable, which skips locking and the WAL entirely.
+fn open(db: &Path) -> Result<Connection> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let readable = |conn: &Connection| conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(())).is_ok();
+
+    if let Ok(conn) = Connection::open_with_flags(db, flags) {
+        if readable(&conn) {
+            return Ok(conn);
+        }
+    }
+
+    let uri = format!(
+        "file:{}?immutable=1",
+        db.to_string_lossy().replace('%', "%25").replace('?', "%3f").replace('#', "%23")
+    );
+    Connection::open_with_flags(uri, flags | OpenFlags::SQLITE_OPEN_URI)
+        .with_context(|| format!("Failed to open rpm database {}", db.display()))
+}
+
+/// An rpm header as stored in the database: entry count, data length, 16-byte index
+/// entries (tag, type, offset, count; all big-endian), then the data store
+struct Header<'a> {
+    index: &'a [u8],
+    data: &'a [u8],
+}
+
+impl<'a> Header<'a> {
+    fn parse(blob: &'a [u8]) -> Option<Self> {
+        let entries = be_u32(blob, 0)? as usize;
+        let data_len = be_u32(blob, 4)? as usize;
+        let index_end = entries.checked_mul(16)?.checked_add(8)?;
+        let data_end = index_end.checked_add(data_len)?;
+        if data_end > blob.len() {
+            return None;
+        }
+
+        Some(Self {
+            index: &blob[8..index_end],
+            data: &blob[index_end..data_end],
+        })
+    }
+
+    /// Value of a plain string tag
+    fn string(&self, tag: u32) -> Option<&'a str> {
+        let entry = self
+            .index
+            .chunks_exact(16)
+            .find(|entry| be_u32(entry, 0) == Some(tag) && be_u32(entry, 4) == Some(TYPE_STRING))?;
+
+        let offset = be_u32(entry, 8)? as usize;
+        let rest = self.data.get(offset..)?;
+        let end = rest.iter().position(|&b| b == 0)?;
+        std::str::from_utf8(&rest[..end]).ok()
+    }
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    let raw = bytes.get(at..at + 4)?;
+    Some(u32::from_be_bytes([raw[0], raw[1], raw[2], raw[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header blob holding `tags` as string entries
+    fn header(tags: &[(u32, &str)]) -> Vec<u8> {
+        let mut index = Vec::new();
+        let mut data = Vec::new();
+        for (tag, value) in tags {
+            for field in [*tag, TYPE_STRING, data.len() as u32, 1] {
+                index.extend_from_slice(&field.to_be_bytes());
+            }
+            data.extend_from_slice(value.as_bytes());
+            data.push(0);
+        }
+
+        let mut blob = Vec::new();
+        blob.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        blob.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        blob.extend(index);
+        blob.extend(data);
+        blob
+    }
+
+    #[test]
+    fn reads_string_tags() {
+        let blob = header(&[(TAG_NAME, "bash"), (TAG_VERSION, "5.2.26"), (TAG_RELEASE, "3.fc40")]);
+        let parsed = Header::parse(&blob).unwrap();
+        assert_eq!(parsed.string(TAG_NAME), Some("bash"));
+        assert_eq!(parsed.string(TAG_VERSION), Some("5.2.26"));
+        assert_eq!(parsed.string(TAG_RELEASE), Some("3.fc40"));
+        assert_eq!(parsed.string(1004), None);
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        let blob = header(&[(TAG_NAME, "bash")]);
+        assert!(Header::parse(&blob[..blob.len() - 1]).is_none());
+        assert!(Header::parse(&[0, 0, 0]).is_none());
+        // An entry count that would overflow the index size
+        assert!(Header::parse(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]).is_none());
+    }
+}
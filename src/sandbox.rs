@@ -0,0 +1,82 @@
+// Confinement for user-supplied test commands
+
+use anyhow::Result;
+use clap::ValueEnum;
+use std::path::Path;
+
+use crate::test_runner::which;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SandboxKind {
+    /// bubblewrap: read-only filesystem, private /tmp, no network
+    Bubblewrap,
+    /// Transient systemd unit with ProtectSystem=strict and PrivateNetwork
+    SystemdRun,
+}
+
+/// How test commands are confined
+#[derive(Debug, Clone, Copy)]
+pub struct Sandbox {
+    pub kind: SandboxKind,
+    /// Keep network access (needed for tests that talk to remote services)
+    pub network: bool,
+}
+
+impl Sandbox {
+    pub fn new(kind: SandboxKind, network: bool) -> Result<Self> {
+        let program = match kind {
+            SandboxKind::Bubblewrap => "bwrap",
+            SandboxKind::SystemdRun => "systemd-run",
+        };
+        if !which(program) {
+            anyhow::bail!("{} not found; install it or run without --sandbox", program);
+        }
+
+        Ok(Self { kind, network })
+    }
+
+    /// bwrap arguments up to (not including) the command; `root` replaces chroot
+    pub fn bwrap_args(&self, root: Option<&Path>) -> Vec<String> {
+        let root = root.map(|r| r.to_string_lossy().to_string());
+        let mut args: Vec<String> = vec!["bwrap".into(), "--die-with-parent".into()];
+
+        args.extend(["--ro-bind".into(), root.clone().unwrap_or_else(|| "/".into()), "/".into()]);
+        // GPU and input devices must stay reachable for graphical tests
+        args.extend(["--dev-bind", "/dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"].map(String::from));
+
+        // Session sockets and sysfs come from the running system, not the snapshot
+        if root.is_some() {
+            args.extend(["--ro-bind", "/run", "/run", "--ro-bind", "/sys", "/sys"].map(String::from));
+        }
+
+        if !self.network {
+            args.push("--unshare-net".into());
+        }
+
+        args
+    }
+
+    /// systemd-run properties that confine the transient unit
+    pub fn systemd_properties(&self, root: Option<&Path>) -> Vec<String> {
+        let mut props: Vec<String> = [
+            "ProtectSystem=strict",
+            "ProtectHome=read-only",
+            "PrivateTmp=yes",
+            "NoNewPrivileges=yes",
+            "ProtectKernelModules=yes",
+            "ProtectKernelTunables=yes",
+        ]
+        .iter()
+        .map(|p| format!("--property={}", p))
+        .collect();
+
+        if !self.network {
+            props.push("--property=PrivateNetwork=yes".to_string());
+        }
+        if let Some(root) = root {
+            props.push(format!("--property=RootDirectory={}", root.display()));
+        }
+
+        props
+    }
+}
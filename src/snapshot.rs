@@ -1,29 +1,181 @@
 use anyhow::{Context, Result};
-use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use chrono::{DateTime, Local, Utc};
+#[cfg(any(feature = "timeshift", feature = "snapper", feature = "btrfs"))]
+use chrono::TimeZone;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
 use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+use crate::audit;
+use crate::config;
+use crate::error::TraceError;
+use crate::exec::CommandExt;
+use crate::integrity;
+#[cfg(any(feature = "timeshift", feature = "btrfs"))]
+use crate::media;
+use crate::monitor;
+use crate::paths;
+use crate::recovery::RecoveryContext;
+
+/// Where `diff --receive` unpacks btrfs send streams (must be on btrfs)
+pub const RECEIVE_DIR: &str = "/var/lib/eshu-trace/received";
+
+/// Snapshot id that stands for the live system (the mounted installed system in recovery)
+pub const CURRENT: &str = "current";
+
+/// Where btrfs snapshots live unless config.json lists `btrfs_snapshot_dirs`
+const DEFAULT_BTRFS_DIR: &str = "/.snapshots";
+
+/// Dates are shown like this unless config.json sets `date_format` (strftime syntax)
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Snapshot {
     pub id: String,
-    pub created_at: String,
+    /// None when the backend reported a date that could not be parsed
+    #[serde(default)]
+    pub created_at: Option<DateTime<Local>>,
     pub description: Option<String>,
+    /// Snapper snapshot type: "single", or "pre"/"post" around a package transaction;
+    /// "generation" for Guix System generations
+    #[serde(default)]
+    pub kind: Option<String>,
+    /// Snapper cleanup algorithm ("number", "timeline"); such snapshots are deleted automatically
+    #[serde(default)]
+    pub cleanup: Option<String>,
     pub packages: Option<HashMap<String, String>>,
     pub package_count: Option<usize>,
+    /// Root filesystem of the snapshot, when it is reachable from this system
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+impl Snapshot {
+    /// Creation time for display, in the configured format
+    pub fn date(&self) -> String {
+        match &self.created_at {
+            Some(time) => format_time(time),
+            None => "unknown date".to_string(),
+        }
+    }
+}
+
+/// Render a time the same way everywhere (`date_format` in config.json, else YYYY-MM-DD HH:MM)
+pub fn format_time(time: &DateTime<Local>) -> String {
+    static FORMAT: OnceLock<String> = OnceLock::new();
+    let format = FORMAT.get_or_init(|| {
+        config::load()
+            .ok()
+            .and_then(|c| c.date_format)
+            // An invalid format would make chrono fail while printing
+            .filter(|f| !chrono::format::StrftimeItems::new(f).any(|i| i == chrono::format::Item::Error))
+            .unwrap_or_else(|| DEFAULT_DATE_FORMAT.to_string())
+    });
+    time.format(format).to_string()
 }
 
 pub struct SnapshotManager {
     backend: SnapshotBackend,
 }
 
+/// Order for `snapshots --sort`; both put the newest first
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SnapshotSort {
+    Date,
+    /// Numeric where the backend numbers its snapshots (Snapper), else by name
+    Id,
+}
+
+impl SnapshotSort {
+    pub fn apply(&self, snapshots: &mut [Snapshot]) {
+        match self {
+            SnapshotSort::Date => snapshots.sort_by_key(|s| std::cmp::Reverse(s.created_at)),
+            SnapshotSort::Id => snapshots.sort_by(|a, b| {
+                (b.id.parse::<u64>().ok(), &b.id).cmp(&(a.id.parse::<u64>().ok(), &a.id))
+            }),
+        }
+    }
+}
+
+/// Backends present on this system; detection runs `which` and is done once per run
+static BACKENDS: OnceLock<Vec<SnapshotBackend>> = OnceLock::new();
+/// Set by the global `--backend` flag; replaces auto-detection for the whole run
+static FORCED_BACKEND: OnceLock<SnapshotBackend> = OnceLock::new();
+/// Listings already fetched in this run, by backend name (`timeshift --list` needs sudo)
+static LISTINGS: Mutex<Vec<(&'static str, Vec<Snapshot>)>> = Mutex::new(Vec::new());
+
+/// Each backend can be left out of the build with its cargo feature
+#[derive(Clone, Copy)]
 enum SnapshotBackend {
+    #[cfg(feature = "timeshift")]
     Timeshift,
+    #[cfg(feature = "snapper")]
     Snapper,
+    #[cfg(feature = "btrfs")]
     Btrfs,
+    /// Guix System generations
+    #[cfg(feature = "guix")]
+    Guix,
+    /// Package manifests recorded by `eshu-trace monitor`
+    Manifest,
+    /// Fake snapshot roots of the test harness
+    #[cfg(feature = "fixtures")]
+    Fixture,
+    #[allow(dead_code)]
     Lvm,
 }
 
+impl SnapshotBackend {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            #[cfg(feature = "timeshift")]
+            "timeshift" => Some(SnapshotBackend::Timeshift),
+            #[cfg(feature = "snapper")]
+            "snapper" => Some(SnapshotBackend::Snapper),
+            #[cfg(feature = "btrfs")]
+            "btrfs" => Some(SnapshotBackend::Btrfs),
+            #[cfg(feature = "guix")]
+            "guix" => Some(SnapshotBackend::Guix),
+            "manifest" => Some(SnapshotBackend::Manifest),
+            #[cfg(feature = "fixtures")]
+            "fixture" => Some(SnapshotBackend::Fixture),
+            _ => None,
+        }
+    }
+}
+
+/// Value parser for `--backend`: a snapshot source compiled into this build
+pub fn parse_backend(value: &str) -> Result<String, String> {
+    match SnapshotBackend::from_name(value) {
+        Some(_) => Ok(value.to_lowercase()),
+        None => {
+            let mut known = Vec::new();
+            #[cfg(feature = "timeshift")]
+            known.push("timeshift");
+            #[cfg(feature = "snapper")]
+            known.push("snapper");
+            #[cfg(feature = "btrfs")]
+            known.push("btrfs");
+            #[cfg(feature = "guix")]
+            known.push("guix");
+            known.push("manifest");
+            #[cfg(feature = "fixtures")]
+            known.push("fixture");
+            Err(format!("Unknown snapshot backend {} (expected one of: {})", value, known.join(", ")))
+        }
+    }
+}
+
+/// Use this backend instead of auto-detecting one, for the rest of the run
+pub fn force_backend(name: &str) {
+    if let Some(backend) = SnapshotBackend::from_name(name) {
+        let _ = FORCED_BACKEND.set(backend);
+    }
+}
+
 impl SnapshotManager {
     pub fn new() -> Result<Self> {
         let backend = Self::detect_backend()?;
@@ -31,59 +183,155 @@ impl SnapshotManager {
         Ok(Self { backend })
     }
 
+    /// Like `new`, but also works on a system without snapshots when every id names
+    /// something else: "current", a manifest file or a `monitor` manifest
+    pub fn for_ids(ids: &[&str]) -> Result<Self> {
+        match Self::new() {
+            Err(_) if ids.iter().all(|id| !needs_backend(id)) => Ok(Self {
+                backend: SnapshotBackend::Manifest,
+            }),
+            result => result,
+        }
+    }
+
+    /// Managers for every snapshot backend present on this system
+    pub fn all() -> Vec<Self> {
+        Self::detect_backends()
+            .into_iter()
+            .map(|backend| Self { backend })
+            .collect()
+    }
+
     fn detect_backend() -> Result<SnapshotBackend> {
+        Self::detect_backends()
+            .into_iter()
+            .next()
+            .ok_or_else(|| TraceError::NoBackend.into())
+    }
+
+    fn detect_backends() -> Vec<SnapshotBackend> {
+        if let Some(forced) = FORCED_BACKEND.get() {
+            return vec![*forced];
+        }
+        BACKENDS.get_or_init(Self::probe_backends).clone()
+    }
+
+    fn probe_backends() -> Vec<SnapshotBackend> {
+        #[allow(unused_mut)]
+        let mut backends = Vec::new();
+
+        // The test harness replaces every real backend
+        #[cfg(feature = "fixtures")]
+        if crate::fixture::root().is_some() {
+            return vec![SnapshotBackend::Fixture];
+        }
+
         // Check for Timeshift
+        #[cfg(feature = "timeshift")]
         if Command::new("which")
             .arg("timeshift")
-            .output()
+            .run_output()
             .map(|o| o.status.success())
             .unwrap_or(false)
         {
-            return Ok(SnapshotBackend::Timeshift);
+            backends.push(SnapshotBackend::Timeshift);
         }
 
         // Check for Snapper
+        #[cfg(feature = "snapper")]
         if Command::new("which")
             .arg("snapper")
-            .output()
+            .run_output()
             .map(|o| o.status.success())
             .unwrap_or(false)
         {
-            return Ok(SnapshotBackend::Snapper);
+            backends.push(SnapshotBackend::Snapper);
+        }
+
+        // Check for BTRFS (snapper also keeps its snapshots in /.snapshots)
+        // Configured directories count even if their disk isn't mounted yet
+        #[cfg(feature = "btrfs")]
+        if (config::load().is_ok_and(|c| !c.btrfs_snapshot_dirs.is_empty())
+            || btrfs_snapshot_dirs().iter().any(|d| d.exists()))
+            && backends.is_empty()
+        {
+            backends.push(SnapshotBackend::Btrfs);
         }
 
-        // Check for BTRFS
-        if std::path::Path::new("/.snapshots").exists() {
-            return Ok(SnapshotBackend::Btrfs);
+        // Guix System keeps its own generations
+        #[cfg(feature = "guix")]
+        if crate::guix::is_guix("/") {
+            backends.push(SnapshotBackend::Guix);
         }
 
-        anyhow::bail!("No snapshot backend detected. Please install Timeshift, Snapper, or use BTRFS/LVM snapshots");
+        backends
     }
 
-    pub fn backend_name(&self) -> &str {
+    pub fn backend_name(&self) -> &'static str {
         match self.backend {
+            #[cfg(feature = "timeshift")]
             SnapshotBackend::Timeshift => "Timeshift",
+            #[cfg(feature = "snapper")]
             SnapshotBackend::Snapper => "Snapper",
+            #[cfg(feature = "btrfs")]
             SnapshotBackend::Btrfs => "BTRFS",
+            #[cfg(feature = "guix")]
+            SnapshotBackend::Guix => "Guix",
+            SnapshotBackend::Manifest => "Manifest",
+            #[cfg(feature = "fixtures")]
+            SnapshotBackend::Fixture => "Fixture",
             SnapshotBackend::Lvm => "LVM",
         }
     }
 
+    /// Snapshots of this backend; the backend command runs at most once per run
     pub fn list_snapshots(&self) -> Result<Vec<Snapshot>> {
+        let name = self.backend_name();
+        let mut listings = LISTINGS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, snapshots)) = listings.iter().find(|(n, _)| *n == name) {
+            return Ok(snapshots.clone());
+        }
+
+        let snapshots = match load_disk_cache(name) {
+            Some(snapshots) => snapshots,
+            None => {
+                let snapshots = self.query_backend()?;
+                save_disk_cache(name, &snapshots);
+                snapshots
+            }
+        };
+        listings.push((name, snapshots.clone()));
+        Ok(snapshots)
+    }
+
+    fn query_backend(&self) -> Result<Vec<Snapshot>> {
         match self.backend {
+            #[cfg(feature = "timeshift")]
             SnapshotBackend::Timeshift => self.list_timeshift_snapshots(),
+            #[cfg(feature = "snapper")]
             SnapshotBackend::Snapper => self.list_snapper_snapshots(),
+            #[cfg(feature = "btrfs")]
             SnapshotBackend::Btrfs => self.list_btrfs_snapshots(),
+            #[cfg(feature = "guix")]
+            SnapshotBackend::Guix => crate::guix::generations(),
+            #[cfg(feature = "fixtures")]
+            SnapshotBackend::Fixture => crate::fixture::snapshots(),
+            SnapshotBackend::Manifest => {
+                let mut snapshots: Vec<Snapshot> = monitor::manifests()?.iter().map(|m| m.to_snapshot()).collect();
+                snapshots.reverse();
+                Ok(snapshots)
+            }
             SnapshotBackend::Lvm => self.list_lvm_snapshots(),
         }
     }
 
+    #[cfg(feature = "timeshift")]
     fn list_timeshift_snapshots(&self) -> Result<Vec<Snapshot>> {
         let output = Command::new("sudo")
             .arg("timeshift")
             .arg("--list")
-            .output()
-            .context("Failed to run timeshift")?;
+            .run_output()
+            .map_err(|source| TraceError::BackendFailed { tool: "timeshift", source })?;
 
         let stdout = String::from_utf8_lossy(&output.stdout);
 
@@ -95,14 +343,23 @@ impl SnapshotManager {
 
                 if parts.len() >= 2 {
                     let id = parts[0].trim_start_matches('@').to_string();
-                    let date = parts[1..].join(" ");
+                    // Timeshift names snapshots after their local creation time (2024-05-01_10-00-01)
+                    let date = crate::timeline::parse_timestamp(&parts[1..].join(" "))
+                        .or_else(|| crate::timeline::parse_timestamp(&id))
+                        // An ambiguous time at a DST change resolves to the earlier reading
+                        .and_then(|naive| Local.from_local_datetime(&naive).earliest());
+
+                    let path = timeshift_snapshot_path(&id);
 
                     snapshots.push(Snapshot {
                         id: id.clone(),
                         created_at: date,
                         description: None,
+                        kind: None,
+                        cleanup: None,
                         packages: None,
                         package_count: None,
+                        path,
                     });
                 }
             }
@@ -111,12 +368,13 @@ impl SnapshotManager {
         Ok(snapshots)
     }
 
+    #[cfg(feature = "snapper")]
     fn list_snapper_snapshots(&self) -> Result<Vec<Snapshot>> {
+        // UTC in ISO form, rather than a locale-dependent local date
         let output = Command::new("sudo")
-            .arg("snapper")
-            .arg("list")
-            .output()
-            .context("Failed to run snapper")?;
+            .args(["snapper", "--utc", "--iso", "list"])
+            .run_output()
+            .map_err(|source| TraceError::BackendFailed { tool: "snapper", source })?;
 
         let stdout = String::from_utf8_lossy(&output.stdout);
 
@@ -128,19 +386,27 @@ impl SnapshotManager {
 
             if parts.len() >= 5 {
                 let id = parts[0].to_string();
-                let date = parts[3].to_string();
+                let date = crate::timeline::parse_timestamp(parts[3])
+                    .map(|naive| Utc.from_utc_datetime(&naive).with_timezone(&Local));
                 let description = if !parts[4].is_empty() {
                     Some(parts[4].to_string())
                 } else {
                     None
                 };
 
+                let path = existing_path(&[format!("/.snapshots/{}/snapshot", id)]);
+                let info = SnapperInfo::read(&std::path::Path::new("/.snapshots").join(&id));
+                let kind = Some(parts[1].to_string()).filter(|k| !k.is_empty());
+
                 snapshots.push(Snapshot {
                     id,
                     created_at: date,
                     description,
+                    kind: info.as_ref().and_then(|i| i.kind.clone()).or(kind),
+                    cleanup: info.and_then(|i| i.cleanup),
                     packages: None,
                     package_count: None,
+                    path,
                 });
             }
         }
@@ -148,43 +414,58 @@ impl SnapshotManager {
         Ok(snapshots)
     }
 
+    #[cfg(feature = "btrfs")]
     fn list_btrfs_snapshots(&self) -> Result<Vec<Snapshot>> {
-        // List snapshots in /.snapshots
-        let snapshot_dir = std::path::Path::new("/.snapshots");
+        let mut snapshots = Vec::new();
 
-        if !snapshot_dir.exists() {
-            return Ok(Vec::new());
-        }
+        for (index, snapshot_dir) in btrfs_snapshot_dirs().iter().enumerate() {
+            // A directory on an unmounted disk is mounted for the rest of the run
+            let Some(dir) = media::attach_path(snapshot_dir) else {
+                continue;
+            };
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let (Some(name), true) = (path.file_name().and_then(|n| n.to_str()), path.is_dir()) else {
+                    continue;
+                };
+                // Snapper's info.xml has the real creation time; the directory time changes
+                // when the snapshot is copied or received, so it's only a fallback
+                let info = SnapperInfo::read(&path);
+                let created = match info.as_ref().and_then(|i| i.created_at) {
+                    Some(time) => time,
+                    None => match path.metadata().and_then(|m| m.created()) {
+                        Ok(created) => created.into(),
+                        Err(_) => continue,
+                    },
+                };
 
-        let mut snapshots = Vec::new();
+                let root = path.join("snapshot");
+                let root = if root.is_dir() { root } else { path.clone() };
 
-        for entry in std::fs::read_dir(snapshot_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-
-            if path.is_dir() {
-                if let Some(name) = path.file_name() {
-                    if let Some(name_str) = name.to_str() {
-                        // Get metadata for creation time
-                        if let Ok(metadata) = path.metadata() {
-                            if let Ok(created) = metadata.created() {
-                                let datetime: DateTime<Utc> = created.into();
-
-                                snapshots.push(Snapshot {
-                                    id: name_str.to_string(),
-                                    created_at: datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
-                                    description: None,
-                                    packages: None,
-                                    package_count: None,
-                                });
-                            }
-                        }
-                    }
-                }
+                // Names repeat across directories (snapper numbers per subvolume), so only
+                // the first directory's snapshots keep their bare name
+                let id = if index == 0 { name.to_string() } else { snapshot_dir.join(name).to_string_lossy().to_string() };
+
+                let info = info.unwrap_or_default();
+                snapshots.push(Snapshot {
+                    id,
+                    created_at: Some(created),
+                    description: info.description,
+                    kind: info.kind,
+                    cleanup: info.cleanup,
+                    packages: None,
+                    package_count: None,
+                    path: Some(root.to_string_lossy().to_string()),
+                });
             }
         }
 
-        snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.created_at));
 
         Ok(snapshots)
     }
@@ -195,24 +476,39 @@ impl SnapshotManager {
     }
 
     pub fn get_snapshot(&self, id: &str) -> Result<Snapshot> {
+        if id == CURRENT {
+            return current();
+        }
+
+        if is_manifest_file(id) {
+            return from_file(Path::new(id));
+        }
+
+        // Package manifests recorded by `eshu-trace monitor` stand in for snapshots
+        if id.starts_with(monitor::MANIFEST_PREFIX) {
+            return monitor::load_manifest(id)
+                .map(|m| m.to_snapshot())
+                .ok_or_else(|| TraceError::SnapshotNotFound(id.to_string()).into());
+        }
+
         let snapshots = self.list_snapshots()?;
 
         snapshots
             .into_iter()
             .find(|s| s.id == id)
-            .context(format!("Snapshot not found: {}", id))
+            .ok_or_else(|| TraceError::SnapshotNotFound(id.to_string()).into())
     }
 
     pub fn select_snapshot(&self, prompt: &str) -> Result<Snapshot> {
         let snapshots = self.list_snapshots()?;
 
         if snapshots.is_empty() {
-            anyhow::bail!("No snapshots available");
+            return Err(TraceError::NoSnapshots.into());
         }
 
         let items: Vec<String> = snapshots
             .iter()
-            .map(|s| format!("{} - {}", s.id, s.created_at))
+            .map(|s| format!("{} - {}", s.id, s.date()))
             .collect();
 
         let selection = dialoguer::Select::new()
@@ -222,4 +518,452 @@ impl SnapshotManager {
 
         Ok(snapshots[selection].clone())
     }
+
+    /// Exclusive disk usage per snapshot ID, where the backend can report it
+    pub fn usage(&self, snapshots: &[Snapshot]) -> HashMap<String, u64> {
+        let mut usage = HashMap::new();
+
+        let rsync: Vec<&Snapshot> = snapshots
+            .iter()
+            .filter(|s| s.path.as_deref().is_some_and(|p| !p.contains("timeshift-btrfs") && p.contains("timeshift")))
+            .collect();
+
+        if !rsync.is_empty() {
+            // Timeshift rsync snapshots share unchanged files via hardlinks; du counts each
+            // inode once, in the first directory it sees, which yields per-snapshot deltas
+            let mut ordered = rsync.clone();
+            ordered.sort_by_key(|s| s.created_at);
+            let output = Command::new("du")
+                .arg("-sb")
+                .args(ordered.iter().filter_map(|s| s.path.as_deref()))
+                .run_output();
+            if let Ok(output) = output {
+                for line in String::from_utf8_lossy(&output.stdout).lines() {
+                    if let Some((bytes, path)) = line.split_once('\t') {
+                        if let (Ok(bytes), Some(snap)) =
+                            (bytes.parse(), ordered.iter().find(|s| s.path.as_deref() == Some(path)))
+                        {
+                            usage.insert(snap.id.clone(), bytes);
+                        }
+                    }
+                }
+            }
+        }
+
+        // btrfs subvolumes: exclusive bytes from the subvolume's qgroup (needs quotas enabled)
+        for snap in snapshots {
+            if usage.contains_key(&snap.id) {
+                continue;
+            }
+            let path = match &snap.path {
+                Some(p) => p,
+                None => continue,
+            };
+            if let Some(bytes) = qgroup_exclusive(path) {
+                usage.insert(snap.id.clone(), bytes);
+            }
+        }
+
+        usage
+    }
+
+    /// Number of snapshots the backend keeps before its cleanup deletes the oldest
+    pub fn retention_limit(&self) -> Option<usize> {
+        match self.backend {
+            #[cfg(feature = "snapper")]
+            SnapshotBackend::Snapper => snapper_number_limit(),
+            #[cfg(feature = "btrfs")]
+            SnapshotBackend::Btrfs => snapper_number_limit(),
+            #[cfg(feature = "timeshift")]
+            SnapshotBackend::Timeshift => {
+                let config = std::fs::read_to_string("/etc/timeshift/timeshift.json").ok()?;
+                let json: serde_json::Value = serde_json::from_str(&config).ok()?;
+                let mut limit = 0;
+                for kind in ["monthly", "weekly", "daily", "hourly", "boot"] {
+                    let enabled = json[format!("schedule_{}", kind)].as_str() == Some("true");
+                    let count: usize = json[format!("count_{}", kind)].as_str().and_then(|c| c.parse().ok()).unwrap_or(0);
+                    if enabled {
+                        limit += count;
+                    }
+                }
+                (limit > 0).then_some(limit)
+            }
+            // Generations stay until `guix system delete-generations`
+            #[cfg(feature = "guix")]
+            SnapshotBackend::Guix => None,
+            SnapshotBackend::Manifest => Some(monitor::MAX_MANIFESTS),
+            #[cfg(feature = "fixtures")]
+            SnapshotBackend::Fixture => None,
+            SnapshotBackend::Lvm => None,
+        }
+    }
+
+    /// Snapshots the next cleanup run will delete (oldest first)
+    pub fn next_to_expire<'a>(&self, snapshots: &'a [Snapshot]) -> Vec<&'a Snapshot> {
+        let limit = match self.retention_limit() {
+            Some(l) => l,
+            None => return Vec::new(),
+        };
+        if snapshots.len() < limit {
+            return Vec::new();
+        }
+
+        let mut oldest: Vec<&Snapshot> = snapshots.iter().collect();
+        oldest.sort_by_key(|s| s.created_at);
+        oldest.truncate(snapshots.len() + 1 - limit);
+        oldest
+    }
+}
+
+/// Snapper's NUMBER_LIMIT="50", or the upper end of a range like "2-10"
+#[cfg(any(feature = "snapper", feature = "btrfs"))]
+fn snapper_number_limit() -> Option<usize> {
+    let config = std::fs::read_to_string("/etc/snapper/configs/root").ok()?;
+    config.lines().find_map(|l| {
+        let value = l.strip_prefix("NUMBER_LIMIT=")?.trim_matches('"');
+        value.rsplit('-').next()?.parse().ok()
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct DiskCache {
+    /// Unix seconds
+    saved_at: i64,
+    snapshots: Vec<Snapshot>,
+}
+
+fn disk_cache_path(backend: &str) -> std::path::PathBuf {
+    paths::runtime_file(&format!("snapshots-{}.json", backend.to_lowercase()))
+}
+
+/// Metadata snapper keeps next to each snapshot in <dir>/<number>/info.xml
+#[cfg(any(feature = "snapper", feature = "btrfs"))]
+#[derive(Debug, Default)]
+struct SnapperInfo {
+    created_at: Option<DateTime<Local>>,
+    kind: Option<String>,
+    description: Option<String>,
+    cleanup: Option<String>,
+}
+
+#[cfg(any(feature = "snapper", feature = "btrfs"))]
+impl SnapperInfo {
+    fn read(snapshot_dir: &std::path::Path) -> Option<Self> {
+        let xml = std::fs::read_to_string(snapshot_dir.join("info.xml")).ok()?;
+        let tag = |name: &str| -> Option<String> {
+            let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+            let end = start + xml[start..].find(&format!("</{}>", name))?;
+            let value = xml[start..end]
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&");
+            Some(value.trim().to_string()).filter(|v| !v.is_empty())
+        };
+
+        Some(Self {
+            // Snapper writes the date in UTC
+            created_at: tag("date")
+                .and_then(|d| crate::timeline::parse_timestamp(&d))
+                .map(|naive| Utc.from_utc_datetime(&naive).with_timezone(&Local)),
+            kind: tag("type"),
+            description: tag("description"),
+            cleanup: tag("cleanup"),
+        })
+    }
+}
+
+/// Configured btrfs snapshot directories, in order; /.snapshots when none are set
+pub fn btrfs_snapshot_dirs() -> Vec<std::path::PathBuf> {
+    let dirs = config::load().map(|c| c.btrfs_snapshot_dirs).unwrap_or_default();
+    if dirs.is_empty() {
+        return vec![std::path::PathBuf::from(DEFAULT_BTRFS_DIR)];
+    }
+    dirs.into_iter().map(std::path::PathBuf::from).collect()
+}
+
+/// A listing saved by an earlier run, if `snapshot_cache_seconds` is set and it is recent enough
+fn load_disk_cache(backend: &str) -> Option<Vec<Snapshot>> {
+    let max_age = config::load().ok()?.snapshot_cache_seconds?;
+    let data = std::fs::read_to_string(disk_cache_path(backend)).ok()?;
+    let cache: DiskCache = serde_json::from_str(&data).ok()?;
+
+    let age = Utc::now().timestamp() - cache.saved_at;
+    (0..max_age as i64).contains(&age).then_some(cache.snapshots)
+}
+
+fn save_disk_cache(backend: &str, snapshots: &[Snapshot]) {
+    if !config::load().is_ok_and(|c| c.snapshot_cache_seconds.is_some()) {
+        return;
+    }
+
+    let path = disk_cache_path(backend);
+    let cache = DiskCache { saved_at: Utc::now().timestamp(), snapshots: snapshots.to_vec() };
+    if let (Some(parent), Ok(data)) = (path.parent(), serde_json::to_string(&cache)) {
+        let _ = std::fs::create_dir_all(parent);
+        let _ = std::fs::write(&path, data);
+    }
+}
+
+fn qgroup_exclusive(path: &str) -> Option<u64> {
+    let output = Command::new("btrfs")
+        .args(["qgroup", "show", "-f", "--raw", path])
+        .run_output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // qgroupid  rfer  excl
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .rev()
+        .find_map(|l| {
+            let cols: Vec<&str> = l.split_whitespace().collect();
+            match cols.as_slice() {
+                [id, _, excl, ..] if id.starts_with("0/") => excl.parse().ok(),
+                _ => None,
+            }
+        })
+}
+
+/// Format a byte count like `1.4 GiB`
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+#[cfg(feature = "timeshift")]
+fn timeshift_snapshot_path(id: &str) -> Option<String> {
+    let candidates = |base: &str| {
+        [
+            format!("{}/timeshift-btrfs/snapshots/{}/@", base, id),
+            format!("{}/timeshift/snapshots/{}/localhost", base, id),
+        ]
+    };
+    let mut paths = candidates("/run/timeshift/backup").to_vec();
+    paths.push(format!("/timeshift/snapshots/{}/localhost", id));
+    existing_path(&paths).or_else(|| {
+        // Backups on another disk: only mounted while Timeshift itself runs
+        let backup = timeshift_backup_root()?;
+        existing_path(&candidates(&backup.to_string_lossy()))
+    })
+}
+
+/// Timeshift's backup device from /etc/timeshift/timeshift.json: (UUID, btrfs mode)
+#[cfg(feature = "timeshift")]
+fn timeshift_backup_device() -> Option<(String, bool)> {
+    let config = std::fs::read_to_string("/etc/timeshift/timeshift.json").ok()?;
+    let json: serde_json::Value = serde_json::from_str(&config).ok()?;
+    let uuid = json["backup_device_uuid"].as_str().filter(|u| !u.is_empty())?;
+    Some((uuid.to_string(), json["btrfs_mode"].as_str() == Some("true")))
+}
+
+/// A directory holding Timeshift's `timeshift`/`timeshift-btrfs` trees on the backup device.
+/// Mounts the device read-only if it isn't mounted anywhere (see media.rs).
+#[cfg(feature = "timeshift")]
+fn timeshift_backup_root() -> Option<std::path::PathBuf> {
+    static ROOT: OnceLock<Option<std::path::PathBuf>> = OnceLock::new();
+    ROOT.get_or_init(|| {
+        let (uuid, btrfs_mode) = timeshift_backup_device()?;
+        let has_backups = |dir: &std::path::Path| dir.join("timeshift").is_dir() || dir.join("timeshift-btrfs").is_dir();
+
+        // Already mounted (a btrfs subvolume mount may hide the top level, so check each target)
+        if let Some(target) = media::mounted_targets(&format!("UUID={}", uuid)).into_iter().find(|t| has_backups(t)) {
+            return Some(target);
+        }
+
+        let options = if btrfs_mode { "ro,subvolid=5" } else { "ro" };
+        media::attach_device(&format!("UUID={}", uuid), "Timeshift backup device", options)
+    })
+    .clone()
+}
+
+#[cfg(any(feature = "timeshift", feature = "snapper"))]
+fn existing_path(candidates: &[String]) -> Option<String> {
+    candidates
+        .iter()
+        .find(|p| std::path::Path::new(p).join("etc").is_dir())
+        .cloned()
+}
+
+/// A snapshot from another machine: a received subvolume directory, or a `btrfs send`
+/// stream file ("-" for stdin) that is unpacked into `into` first
+pub fn receive(source: &str, into: &std::path::Path) -> Result<Snapshot> {
+    let source_path = std::path::Path::new(source);
+    let root = if source != "-" && source_path.is_dir() {
+        source_path.to_path_buf()
+    } else {
+        std::fs::create_dir_all(into)?;
+        let before: Vec<_> = std::fs::read_dir(into)?.flatten().map(|e| e.file_name()).collect();
+
+        let mut cmd = Command::new("btrfs");
+        cmd.arg("receive");
+        if source != "-" {
+            cmd.arg("-f").arg(source);
+        }
+        let status = audit::run("diff: btrfs receive", cmd.arg(into))?;
+        if !status.success() {
+            let reason = format!("receive failed ({} must be on a btrfs filesystem)", into.display());
+            return Err(TraceError::BackendFailed { tool: "btrfs", source: std::io::Error::other(reason) }.into());
+        }
+
+        std::fs::read_dir(into)?
+            .flatten()
+            .find(|e| !before.contains(&e.file_name()))
+            .map(|e| e.path())
+            .ok_or_else(|| TraceError::SnapshotNotFound(into.display().to_string()))
+            .with_context(|| format!("btrfs receive did not create a subvolume in {}", into.display()))?
+    };
+
+    // Snapper snapshots keep the root one level down
+    let root = if root.join("snapshot/etc").is_dir() { root.join("snapshot") } else { root };
+    if !root.join("etc").is_dir() {
+        return Err(TraceError::SnapshotNotFound(root.display().to_string()))
+            .with_context(|| format!("{} does not look like a root filesystem", root.display()));
+    }
+
+    let name = root
+        .components()
+        .rev()
+        .find(|c| c.as_os_str() != "snapshot")
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .unwrap_or_default();
+    let created: Option<DateTime<Local>> = root.metadata().and_then(|m| m.modified()).ok().map(Into::into);
+
+    Ok(Snapshot {
+        id: format!("received:{}", name),
+        created_at: created,
+        description: Some(format!("received from {}", source)),
+        kind: None,
+        cleanup: None,
+        packages: None,
+        package_count: None,
+        path: Some(root.to_string_lossy().to_string()),
+    })
+}
+
+/// The running system as a pseudo-snapshot. Its packages are read from the package
+/// database under its root like any snapshot's; in recovery that is the installed system.
+pub fn current() -> Result<Snapshot> {
+    #[cfg(feature = "fixtures")]
+    let root = match crate::fixture::current_root() {
+        Some(root) => root,
+        None => RecoveryContext::detect()?.system_root,
+    };
+    #[cfg(not(feature = "fixtures"))]
+    let root = RecoveryContext::detect()?.system_root;
+
+    let description = if root == "/" {
+        "live system".to_string()
+    } else {
+        format!("system mounted at {}", root)
+    };
+
+    Ok(Snapshot {
+        id: CURRENT.to_string(),
+        created_at: Some(Local::now()),
+        description: Some(description),
+        kind: None,
+        cleanup: None,
+        packages: None,
+        package_count: None,
+        path: Some(root),
+    })
+}
+
+/// Whether resolving `id` needs a snapshot backend's listing
+fn needs_backend(id: &str) -> bool {
+    id != CURRENT && !id.starts_with(monitor::MANIFEST_PREFIX) && !is_manifest_file(id)
+}
+
+/// Whether a snapshot argument names a package list file rather than a snapshot id
+fn is_manifest_file(id: &str) -> bool {
+    let path = Path::new(id);
+    let looks_like_file = id.contains('/') || matches!(path.extension().and_then(|e| e.to_str()), Some("json" | "txt"));
+    looks_like_file && path.is_file()
+}
+
+/// A package list exported elsewhere (another machine, a container, a CI image) as a
+/// snapshot. Accepts a `monitor` manifest (or any JSON with a "packages" object), a bare
+/// {"name": "version"} object, or text with one "name version" pair per line as printed
+/// by `pacman -Q` or `dpkg-query -W`.
+pub fn from_file(path: &Path) -> Result<Snapshot> {
+    #[derive(Deserialize)]
+    struct Exported {
+        packages: HashMap<String, String>,
+        #[serde(default)]
+        created_at: Option<DateTime<Local>>,
+        #[serde(default)]
+        description: Option<String>,
+    }
+
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let modified = path.metadata().and_then(|m| m.modified()).ok().map(Into::into);
+
+    let exported = if text.trim_start().starts_with('{') {
+        // Exports carry a content hash; a manifest altered in transit must not drive a bisect
+        if let Ok(Some((content, sealed))) = integrity::split_json(&text) {
+            integrity::check(&content, &sealed)
+                .map_err(|e| {
+                    let reason = e.to_string();
+                    anyhow::Error::from(TraceError::VerificationFailed {
+                        file: path.display().to_string(),
+                        reason: reason.clone(),
+                    })
+                    .context(format!("{} failed verification ({})", path.display(), reason))
+                })?;
+        }
+        serde_json::from_str::<Exported>(&text)
+            .or_else(|_| {
+                serde_json::from_str::<HashMap<String, String>>(&text).map(|packages| Exported {
+                    packages,
+                    created_at: None,
+                    description: None,
+                })
+            })
+            .with_context(|| format!("{} is not a package manifest", path.display()))?
+    } else {
+        let packages: HashMap<String, String> = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| {
+                let mut fields = l.split_whitespace();
+                Some((fields.next()?.to_string(), fields.next()?.to_string()))
+            })
+            .collect();
+        if packages.is_empty() {
+            return Err(TraceError::NoPackageDatabase(path.display().to_string()))
+                .with_context(|| format!("{} lists no \"name version\" lines", path.display()));
+        }
+        Exported {
+            packages,
+            created_at: None,
+            description: None,
+        }
+    };
+
+    Ok(Snapshot {
+        id: path.to_string_lossy().to_string(),
+        created_at: exported.created_at.or(modified),
+        description: exported.description.or_else(|| Some("package manifest file".to_string())),
+        kind: None,
+        cleanup: None,
+        package_count: Some(exported.packages.len()),
+        packages: Some(exported.packages),
+        path: None,
+    })
 }
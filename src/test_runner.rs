@@ -1,20 +1,295 @@
-// Test runner for automated bisect (Premium feature)
+// Test runner for bisect steps and the `test` command
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use colored::*;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+
+use crate::exec::CommandExt;
+use crate::probe::Probe;
+use crate::sandbox::{Sandbox, SandboxKind};
 
 pub struct TestRunner {
     test_command: Option<String>,
+    test_user: Option<String>,
+    probes: Vec<Probe>,
+    benchmark: Option<Benchmark>,
+    sandbox: Option<Sandbox>,
+}
+
+/// Benchmark command that fails the test when it runs slower than the threshold
+pub struct Benchmark {
+    pub command: String,
+    pub max_seconds: f64,
+}
+
+/// Session environment of the desktop user a test runs as
+struct UserSession {
+    name: String,
+    home: String,
+    runtime_dir: String,
 }
 
 impl TestRunner {
     pub fn new(test_command: Option<String>) -> Self {
-        Self { test_command }
+        Self {
+            test_command,
+            test_user: None,
+            probes: Vec::new(),
+            benchmark: None,
+            sandbox: None,
+        }
+    }
+
+    pub fn with_user(mut self, test_user: Option<String>) -> Self {
+        self.test_user = test_user;
+        self
+    }
+
+    pub fn with_probes(mut self, probes: Vec<Probe>) -> Self {
+        self.probes = probes;
+        self
+    }
+
+    pub fn with_benchmark(mut self, benchmark: Option<Benchmark>) -> Self {
+        self.benchmark = benchmark;
+        self
+    }
+
+    pub fn with_sandbox(mut self, sandbox: Option<Sandbox>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    pub fn command(&self) -> Option<&str> {
+        self.test_command.as_deref().filter(|c| !c.is_empty())
+    }
+
+    pub fn has_command(&self) -> bool {
+        self.test_command.as_deref().map(|c| !c.is_empty()).unwrap_or(false)
+    }
+
+    /// Whether there is anything to evaluate automatically
+    pub fn has_test(&self) -> bool {
+        self.has_command() || !self.probes.is_empty() || self.benchmark.is_some()
+    }
+
+    pub fn test_user(&self) -> Option<&str> {
+        self.test_user.as_deref()
+    }
+
+    #[cfg(feature = "vm")]
+    pub fn has_probes(&self) -> bool {
+        !self.probes.is_empty()
+    }
+
+    #[cfg(feature = "vm")]
+    pub fn benchmark(&self) -> Option<&Benchmark> {
+        self.benchmark.as_ref()
     }
 
+    /// Run the test command, probes and benchmark, returning true if all of them passed
     pub fn run_test(&self) -> Result<bool> {
-        // Premium feature - automated testing
-        // Would boot VM, run test, check exit code
+        self.run_test_in(None)
+    }
+
+    /// Like `run_test`, but commands run chrooted into `root` when given
+    pub fn run_test_in(&self, root: Option<&Path>) -> Result<bool> {
+        if !self.has_test() {
+            anyhow::bail!("No test command, probe or benchmark configured");
+        }
+
+        let mut passed = true;
+
+        if let Some(cmd) = self.test_command.as_deref().filter(|c| !c.is_empty()) {
+            let status = self
+                .build_command(cmd, root)?
+                .run_status()
+                .context("Failed to run test command")?;
+
+            passed &= status.success();
+        }
+
+        for probe in &self.probes {
+            let ok = probe.run();
+            if ok {
+                println!("  {} probe {}", "✓".green(), probe);
+            } else {
+                println!("  {} probe {}", "✗".red(), probe);
+            }
+            passed &= ok;
+        }
+
+        if let Some(bench) = &self.benchmark {
+            passed &= self.run_benchmark(bench, root)?;
+        }
 
-        anyhow::bail!("Automated testing is a Premium feature")
+        Ok(passed)
     }
+
+    fn run_benchmark(&self, bench: &Benchmark, root: Option<&Path>) -> Result<bool> {
+        let start = Instant::now();
+        let status = self
+            .build_command(&bench.command, root)?
+            .run_status()
+            .context("Failed to run benchmark command")?;
+        let elapsed = start.elapsed().as_secs_f64();
+
+        if !status.success() {
+            println!("  {} benchmark failed after {:.2}s", "✗".red(), elapsed);
+            return Ok(false);
+        }
+
+        if elapsed > bench.max_seconds {
+            println!(
+                "  {} benchmark took {:.2}s (limit {:.2}s)",
+                "✗".red(),
+                elapsed,
+                bench.max_seconds
+            );
+            Ok(false)
+        } else {
+            println!(
+                "  {} benchmark took {:.2}s (limit {:.2}s)",
+                "✓".green(),
+                elapsed,
+                bench.max_seconds
+            );
+            Ok(true)
+        }
+    }
+
+    fn build_command(&self, cmd: &str, root: Option<&Path>) -> Result<Command> {
+        let argv = self.build_argv(cmd, root)?;
+
+        // Run inside the given root filesystem (e.g. a chroot test driver);
+        // sandboxes mount the root themselves
+        let command = match root.filter(|_| self.sandbox.is_none()) {
+            Some(root) => {
+                let mut command = Command::new("chroot");
+                command.arg(root);
+                command.args(&argv);
+                command
+            }
+            None => {
+                let mut command = Command::new(&argv[0]);
+                command.args(&argv[1..]);
+                command
+            }
+        };
+
+        Ok(command)
+    }
+
+    fn build_argv(&self, cmd: &str, root: Option<&Path>) -> Result<Vec<String>> {
+        let shell = vec!["sh".to_string(), "-c".to_string(), cmd.to_string()];
+        let session = self.test_user.as_deref().map(UserSession::lookup).transpose()?;
+
+        // A confining systemd unit also takes care of switching user
+        if let Some(sandbox) = self.sandbox.filter(|s| s.kind == SandboxKind::SystemdRun) {
+            let mut argv = Vec::new();
+            if !is_root() {
+                argv.push("sudo".to_string());
+            }
+            argv.extend(["systemd-run", "--quiet", "--wait", "--pipe", "--collect"].map(String::from));
+            argv.extend(sandbox.systemd_properties(root));
+            if let Some(session) = &session {
+                argv.push(format!("--uid={}", session.name));
+                argv.extend(session.env().into_iter().map(|var| format!("--setenv={}", var)));
+            }
+            argv.extend(shell);
+            return Ok(argv);
+        }
+
+        // bwrap runs innermost, after the user switch, so it never holds root's capabilities
+        let shell = match self.sandbox {
+            Some(sandbox) => [sandbox.bwrap_args(root), shell].concat(),
+            None => shell,
+        };
+
+        let session = match session {
+            Some(s) => s,
+            None => return Ok(shell),
+        };
+
+        let mut argv = Vec::new();
+
+        // runuser needs root; systemd-run can reach the user's session bus on its own
+        if root.is_some() || (is_root() && which("runuser")) {
+            argv.extend(["runuser", "-u", &session.name, "--", "env"].map(String::from));
+            argv.extend(session.env());
+        } else if which("systemd-run") {
+            if !is_root() {
+                argv.push("sudo".to_string());
+            }
+            argv.extend(["systemd-run", "--quiet", "--wait", "--pipe", "--collect"].map(String::from));
+            argv.push(format!("--uid={}", session.name));
+            argv.extend(session.env().into_iter().map(|var| format!("--setenv={}", var)));
+        } else {
+            argv.extend(["sudo", "-u", &session.name, "env"].map(String::from));
+            argv.extend(session.env());
+        }
+
+        argv.extend(shell);
+        Ok(argv)
+    }
+}
+
+impl UserSession {
+    fn lookup(user: &str) -> Result<Self> {
+        let output = Command::new("getent")
+            .args(["passwd", user])
+            .run_output()
+            .context("Failed to run getent")?;
+
+        let entry = String::from_utf8_lossy(&output.stdout);
+        let fields: Vec<&str> = entry.trim().split(':').collect();
+
+        if !output.status.success() || fields.len() < 6 {
+            anyhow::bail!("Unknown test user: {}", user);
+        }
+
+        Ok(Self {
+            name: fields[0].to_string(),
+            home: fields[5].to_string(),
+            runtime_dir: format!("/run/user/{}", fields[2]),
+        })
+    }
+
+    /// Environment needed to reach the user's graphical session and session bus
+    fn env(&self) -> Vec<String> {
+        let mut env = vec![
+            format!("HOME={}", self.home),
+            format!("USER={}", self.name),
+            format!("LOGNAME={}", self.name),
+            format!("XDG_RUNTIME_DIR={}", self.runtime_dir),
+            format!("DBUS_SESSION_BUS_ADDRESS=unix:path={}/bus", self.runtime_dir),
+        ];
+
+        if Path::new(&self.runtime_dir).join("wayland-0").exists() {
+            env.push("WAYLAND_DISPLAY=wayland-0".to_string());
+        }
+
+        let display = std::env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string());
+        env.push(format!("DISPLAY={}", display));
+
+        env
+    }
+}
+
+pub fn is_root() -> bool {
+    Command::new("id")
+        .arg("-u")
+        .run_output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim() == "0")
+        .unwrap_or(false)
+}
+
+pub fn which(program: &str) -> bool {
+    Command::new("which")
+        .arg(program)
+        .run_output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
 }
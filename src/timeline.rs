@@ -0,0 +1,294 @@
+// Unified timeline of snapshots, package transactions, reboots and kernel changes
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use regex::Regex;
+use std::fs;
+use std::process::Command;
+
+use crate::exec::CommandExt;
+use crate::snapshot::SnapshotManager;
+
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    Snapshot {
+        backend: String,
+        id: String,
+        description: Option<String>,
+    },
+    Transaction(Vec<TransactionItem>),
+    Reboot,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransactionItem {
+    pub action: String, // installed, upgraded, downgraded, removed, reinstalled
+    pub package: String,
+    pub detail: String, // version or "old -> new"
+}
+
+#[derive(Debug, Clone)]
+pub struct TimelineEvent {
+    pub time: NaiveDateTime,
+    pub kind: EventKind,
+}
+
+impl TimelineEvent {
+    /// True for transactions that touched a kernel package
+    pub fn is_kernel_change(&self) -> bool {
+        match &self.kind {
+            EventKind::Transaction(items) => items.iter().any(|i| is_kernel(&i.package)),
+            _ => false,
+        }
+    }
+}
+
+/// Collect all events, oldest first
+pub fn collect(since: Option<NaiveDateTime>) -> Result<Vec<TimelineEvent>> {
+    let mut events = Vec::new();
+
+    for mgr in SnapshotManager::all() {
+        if let Ok(snapshots) = mgr.list_snapshots() {
+            for snap in snapshots {
+                if let Some(time) = snap.created_at.map(|t| t.naive_local()) {
+                    events.push(TimelineEvent {
+                        time,
+                        kind: EventKind::Snapshot {
+                            backend: mgr.backend_name().to_string(),
+                            id: snap.id,
+                            description: snap.description,
+                        },
+                    });
+                }
+            }
+        }
+    }
+
+    events.extend(package_transactions());
+    events.extend(reboots());
+
+    if let Some(since) = since {
+        events.retain(|e| e.time >= since);
+    }
+
+    events.sort_by_key(|e| e.time);
+    Ok(events)
+}
+
+/// A point in time given as a date (YYYY-MM-DD [HH:MM]) or an age before now (30m, 12h, 2d, 1w)
+pub fn parse_since(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim();
+    if let Some(time) = parse_timestamp(text).or_else(|| parse_timestamp(&format!("{} 00:00", text))) {
+        return Some(time);
+    }
+
+    let unit = text.chars().last()?;
+    let amount: i64 = text[..text.len() - unit.len_utf8()].parse().ok()?;
+    // Out-of-range amounts ("99999999999d") are no date rather than a panic
+    let age = match unit {
+        'm' => chrono::Duration::try_minutes(amount),
+        'h' => chrono::Duration::try_hours(amount),
+        'd' => chrono::Duration::try_days(amount),
+        'w' => chrono::Duration::try_weeks(amount),
+        _ => return None,
+    }?;
+    chrono::Local::now().naive_local().checked_sub_signed(age)
+}
+
+/// Find the first `YYYY-MM-DD HH:MM[:SS]`-like timestamp in free-form text
+/// (also accepts `T`/`_` separators and `-` between time fields, as Timeshift uses)
+pub fn parse_timestamp(text: &str) -> Option<NaiveDateTime> {
+    let re = Regex::new(r"(\d{4})-(\d{2})-(\d{2})[ T_](\d{2})[:\-](\d{2})(?:[:\-](\d{2}))?").ok()?;
+    let c = re.captures(text)?;
+
+    let normalized = format!(
+        "{}-{}-{} {}:{}:{}",
+        &c[1],
+        &c[2],
+        &c[3],
+        &c[4],
+        &c[5],
+        c.get(6).map(|m| m.as_str()).unwrap_or("00")
+    );
+
+    NaiveDateTime::parse_from_str(&normalized, "%Y-%m-%d %H:%M:%S").ok()
+}
+
+fn package_transactions() -> Vec<TimelineEvent> {
+    let mut events = Vec::new();
+    events.extend(pacman_transactions());
+    events.extend(apt_transactions());
+    events.extend(dnf_transactions());
+    events
+}
+
+/// /var/log/pacman.log: `[2024-06-01T10:00:00+0200] [ALPM] upgraded linux (6.9.6 -> 6.9.7)`
+fn pacman_transactions() -> Vec<TimelineEvent> {
+    let log = match fs::read_to_string("/var/log/pacman.log") {
+        Ok(l) => l,
+        Err(_) => return Vec::new(),
+    };
+
+    let re = Regex::new(
+        r"^\[([^\]]+)\] \[ALPM\] (installed|upgraded|downgraded|removed|reinstalled) (\S+) \((.*)\)",
+    )
+    .unwrap();
+
+    let mut events: Vec<TimelineEvent> = Vec::new();
+
+    for line in log.lines() {
+        let c = match re.captures(line) {
+            Some(c) => c,
+            None => continue,
+        };
+        let time = match parse_timestamp(&c[1]) {
+            Some(t) => t,
+            None => continue,
+        };
+        let item = TransactionItem {
+            action: c[2].to_string(),
+            package: c[3].to_string(),
+            detail: c[4].to_string(),
+        };
+
+        push_item(&mut events, time, item);
+    }
+
+    events
+}
+
+/// /var/log/apt/history.log blocks with Start-Date and Install/Upgrade/Remove lines
+fn apt_transactions() -> Vec<TimelineEvent> {
+    let log = match fs::read_to_string("/var/log/apt/history.log") {
+        Ok(l) => l,
+        Err(_) => return Vec::new(),
+    };
+
+    let entry_re = Regex::new(r"([^\s,]+?)(?::\w+)? \(([^)]*)\)").unwrap();
+    let mut events = Vec::new();
+    let mut current: Option<NaiveDateTime> = None;
+
+    for line in log.lines() {
+        if let Some(date) = line.strip_prefix("Start-Date: ") {
+            current = parse_timestamp(date.trim().replacen("  ", " ", 1).as_str());
+            continue;
+        }
+
+        let time = match current {
+            Some(t) => t,
+            None => continue,
+        };
+
+        let (action, list) = match line.split_once(": ") {
+            Some(("Install", list)) => ("installed", list),
+            Some(("Upgrade", list)) => ("upgraded", list),
+            Some(("Downgrade", list)) => ("downgraded", list),
+            Some(("Remove", list)) | Some(("Purge", list)) => ("removed", list),
+            Some(("Reinstall", list)) => ("reinstalled", list),
+            _ => continue,
+        };
+
+        for c in entry_re.captures_iter(list) {
+            let detail = c[2].replace(", automatic", "").replace(", ", " -> ");
+            push_item(
+                &mut events,
+                time,
+                TransactionItem {
+                    action: action.to_string(),
+                    package: c[1].to_string(),
+                    detail,
+                },
+            );
+        }
+    }
+
+    events
+}
+
+/// /var/log/dnf.rpm.log: `2024-06-01T10:00:00+0000 SUBDEBUG Upgrade: kernel-core-6.8.9-300.fc40.x86_64`
+fn dnf_transactions() -> Vec<TimelineEvent> {
+    let log = match fs::read_to_string("/var/log/dnf.rpm.log") {
+        Ok(l) => l,
+        Err(_) => return Vec::new(),
+    };
+
+    let re = Regex::new(r"^(\S+) SUBDEBUG (Installed|Upgrade|Downgrade|Erase|Reinstall): (\S+)").unwrap();
+    let mut events = Vec::new();
+
+    for line in log.lines() {
+        let c = match re.captures(line) {
+            Some(c) => c,
+            None => continue,
+        };
+        let time = match parse_timestamp(&c[1]) {
+            Some(t) => t,
+            None => continue,
+        };
+        let action = match &c[2] {
+            "Installed" => "installed",
+            "Upgrade" => "upgraded",
+            "Downgrade" => "downgraded",
+            "Erase" => "removed",
+            _ => "reinstalled",
+        };
+
+        // name-version-release.arch
+        let nevra = &c[3];
+        let (package, detail) = match nevra.rsplitn(3, '-').collect::<Vec<_>>().as_slice() {
+            [rel, ver, name] => (name.to_string(), format!("{}-{}", ver, rel)),
+            _ => (nevra.to_string(), String::new()),
+        };
+
+        push_item(
+            &mut events,
+            time,
+            TransactionItem {
+                action: action.to_string(),
+                package,
+                detail,
+            },
+        );
+    }
+
+    events
+}
+
+/// Append to the previous transaction if it happened within the same minute
+fn push_item(events: &mut Vec<TimelineEvent>, time: NaiveDateTime, item: TransactionItem) {
+    if let Some(TimelineEvent { time: last, kind: EventKind::Transaction(items) }) = events.last_mut() {
+        if (time - *last).num_seconds().abs() < 60 {
+            items.push(item);
+            return;
+        }
+    }
+
+    events.push(TimelineEvent {
+        time,
+        kind: EventKind::Transaction(vec![item]),
+    });
+}
+
+fn reboots() -> Vec<TimelineEvent> {
+    let output = match Command::new("journalctl")
+        .args(["--list-boots", "--no-pager", "-q"])
+        .run_output() {
+        Ok(o) if o.status.success() => o,
+        _ => return Vec::new(),
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_timestamp)
+        .map(|time| TimelineEvent {
+            time,
+            kind: EventKind::Reboot,
+        })
+        .collect()
+}
+
+fn is_kernel(name: &str) -> bool {
+    matches!(name, "linux" | "linux-lts" | "linux-zen" | "linux-hardened")
+        || name.starts_with("linux-image-")
+        || name.starts_with("kernel-core")
+        || name == "kernel"
+}
@@ -0,0 +1,402 @@
+// Searching the distro bug tracker for existing reports about a culprit
+//
+// Arch packaging issues on GitLab, Launchpad for Ubuntu, the Debian BTS (SOAP) and the
+// Fedora and openSUSE Bugzillas (REST). Open reports are returned, plus closed ones that
+// name the culprit's version and were fixed, those mentioning the version first. The user
+// can add to an existing report instead of filing a duplicate, and the fixer can offer
+// the fixed release instead of a downgrade.
+
+use anyhow::Result;
+use regex::Regex;
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::http;
+use crate::pins;
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Reports shown at most
+const MAX_REPORTS: usize = 8;
+
+const ARCH_GITLAB_API: &str = "https://gitlab.archlinux.org/api/v4/projects/archlinux%2Fpackaging%2Fpackages%2F";
+const LAUNCHPAD_API: &str = "https://api.launchpad.net/1.0/ubuntu/+source/";
+const DEBIAN_SOAP: &str = "https://bugs.debian.org/cgi-bin/soap.cgi";
+const FEDORA_BUGZILLA: &str = "https://bugzilla.redhat.com";
+const OPENSUSE_BUGZILLA: &str = "https://bugzilla.opensuse.org";
+
+/// Labels the Arch bug wranglers give issues closed because a release fixed them; issues
+/// closed as duplicates, upstream or not-a-bug carry other resolutions
+const ARCH_FIXED_LABELS: [&str; 2] = ["resolution::fixed", "resolution::completed"];
+
+/// Launchpad task statuses worth showing: everything still open, and shipped fixes
+const LAUNCHPAD_STATUSES: [&str; 7] =
+    ["New", "Incomplete", "Confirmed", "Triaged", "In Progress", "Fix Committed", "Fix Released"];
+
+/// A report in a distro bug tracker
+#[derive(Debug, Clone)]
+pub struct BugReport {
+    pub tracker: &'static str,
+    pub id: String,
+    pub title: String,
+    /// The tracker's own status ("opened", "Confirmed", "ASSIGNED", ...)
+    pub status: String,
+    pub url: String,
+    /// Title, description or found-in versions name the culprit's version
+    pub mentions_version: bool,
+    /// The tracker says a fix has shipped
+    pub fix_released: bool,
+    /// Version the fix shipped in, when the tracker records it
+    pub fixed_in: Option<String>,
+}
+
+/// Reports about `package` in the tracker of `distro`; `version` is the broken one
+pub fn search(distro: &str, package: &str, version: &str) -> Result<Vec<BugReport>> {
+    let upstream = upstream_version(version);
+    let mut reports = match distro {
+        "arch" | "manjaro" | "endeavouros" => search_arch(package, upstream)?,
+        "ubuntu" | "linuxmint" | "pop" => search_launchpad(package, upstream)?,
+        "debian" => search_debian(package, upstream)?,
+        "fedora" | "rhel" | "centos" => {
+            search_bugzilla(FEDORA_BUGZILLA, package, &format!("product=Fedora&component={}", http::encode(package)), upstream)?
+        }
+        other if pins::is_zypper(other) => {
+            search_bugzilla(OPENSUSE_BUGZILLA, package, &format!("quicksearch={}", http::encode(package)), upstream)?
+        }
+        _ => Vec::new(),
+    };
+
+    // Stable: each tracker already lists newest first
+    reports.sort_by_key(|r| !r.mentions_version);
+    reports.truncate(MAX_REPORTS);
+    Ok(reports)
+}
+
+/// The version without epoch and distro release ("1:24.1.0-2ubuntu1" → "24.1.0")
+fn upstream_version(version: &str) -> &str {
+    let without_epoch = version.split_once(':').map_or(version, |(_, rest)| rest);
+    without_epoch.rsplit_once('-').map_or(without_epoch, |(upstream, _)| upstream)
+}
+
+fn mentions(text: &str, upstream: &str) -> bool {
+    !upstream.is_empty() && text.contains(upstream)
+}
+
+#[derive(Deserialize)]
+struct GitlabIssue {
+    iid: u64,
+    title: String,
+    #[serde(default)]
+    description: Option<String>,
+    state: String,
+    web_url: String,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+fn search_arch(package: &str, upstream: &str) -> Result<Vec<BugReport>> {
+    // GitLab project names spell "+" out
+    let project = package.replace('+', "plus");
+    let url = format!("{}{}/issues?state=all&per_page=50", ARCH_GITLAB_API, http::encode(&project));
+    let response = http::get(&url, TIMEOUT)?;
+    if response.status == 404 {
+        return Ok(Vec::new());
+    }
+    if !response.is_success() {
+        anyhow::bail!("Arch GitLab answered HTTP {}", response.status);
+    }
+
+    let issues: Vec<GitlabIssue> = response.json()?;
+    Ok(issues
+        .into_iter()
+        .map(|issue| arch_report(issue, upstream))
+        // A closed issue only matters if it was fixed and is about this version
+        .filter(|report| report.status != "closed" || (report.fix_released && report.mentions_version))
+        .collect())
+}
+
+fn arch_report(issue: GitlabIssue, upstream: &str) -> BugReport {
+    // Closing alone doesn't mean fixed: duplicates and upstream bugs are closed too
+    let fixed = issue.state == "closed" && issue.labels.iter().any(|l| ARCH_FIXED_LABELS.contains(&l.as_str()));
+    BugReport {
+        tracker: "Arch GitLab",
+        id: format!("#{}", issue.iid),
+        mentions_version: mentions(&issue.title, upstream)
+            || issue.description.as_deref().is_some_and(|d| mentions(d, upstream)),
+        title: issue.title,
+        status: issue.state,
+        url: issue.web_url,
+        fix_released: fixed,
+        fixed_in: None,
+    }
+}
+
+#[derive(Deserialize)]
+struct LaunchpadTasks {
+    entries: Vec<LaunchpadTask>,
+}
+
+#[derive(Deserialize)]
+struct LaunchpadTask {
+    /// "Bug #2061234 in mesa (Ubuntu): \"the title\""
+    title: String,
+    status: String,
+    web_link: String,
+}
+
+fn search_launchpad(package: &str, upstream: &str) -> Result<Vec<BugReport>> {
+    let statuses: String = LAUNCHPAD_STATUSES.iter().map(|s| format!("&status={}", http::encode(s))).collect();
+    let url = format!("{}{}?ws.op=searchTasks&ws.size=50{}", LAUNCHPAD_API, http::encode(package), statuses);
+    let response = http::get(&url, TIMEOUT)?;
+    if response.status == 404 {
+        return Ok(Vec::new());
+    }
+    if !response.is_success() {
+        anyhow::bail!("Launchpad answered HTTP {}", response.status);
+    }
+
+    let tasks: LaunchpadTasks = response.json()?;
+    Ok(tasks
+        .entries
+        .into_iter()
+        .map(|task| {
+            let title = task
+                .title
+                .split_once(": ")
+                .map_or(task.title.as_str(), |(_, t)| t)
+                .trim_matches('"')
+                .to_string();
+            BugReport {
+                tracker: "Launchpad",
+                id: format!("#{}", task.web_link.rsplit('/').next().unwrap_or_default()),
+                mentions_version: mentions(&title, upstream),
+                title,
+                fix_released: task.status == "Fix Released",
+                status: task.status,
+                url: task.web_link,
+                fixed_in: None,
+            }
+        })
+        .filter(|report| !report.fix_released || report.mentions_version)
+        .collect())
+}
+
+/// The BTS only speaks SOAP: get_bugs for the open bug numbers, then get_status for them
+fn search_debian(package: &str, upstream: &str) -> Result<Vec<BugReport>> {
+    let bugs_body = format!(
+        "<get_bugs xmlns=\"Debbugs/SOAP\"><k xsi:type=\"xsd:string\">package</k><v xsi:type=\"xsd:string\">{}</v>\
+         <k xsi:type=\"xsd:string\">status</k><v xsi:type=\"xsd:string\">open</v></get_bugs>",
+        xml_escape(package)
+    );
+    let numbers: Vec<String> = Regex::new(r"<item[^>]*>(\d+)</item>")?
+        .captures_iter(&soap_call(&bugs_body)?)
+        .map(|c| c[1].to_string())
+        .collect();
+    if numbers.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Newest first, and only as many as could be shown
+    let mut numbers: Vec<u64> = numbers.iter().filter_map(|n| n.parse().ok()).collect();
+    numbers.sort_unstable_by(|a, b| b.cmp(a));
+    numbers.truncate(50);
+    let items: String = numbers.iter().map(|n| format!("<item xsi:type=\"xsd:int\">{}</item>", n)).collect();
+    let status_body = format!(
+        "<get_status xmlns=\"Debbugs/SOAP\"><bugs xsi:type=\"soapenc:Array\" soapenc:arrayType=\"xsd:int[{}]\">{}</bugs></get_status>",
+        numbers.len(),
+        items
+    );
+    parse_status(&soap_call(&status_body)?, upstream)
+}
+
+/// Reports out of a get_status response
+fn parse_status(response: &str, upstream: &str) -> Result<Vec<BugReport>> {
+    let subject = Regex::new(r"(?s)<subject[^>]*>(.*?)</subject>")?;
+    let found = Regex::new(r"(?s)<found_versions[^>]*>(.*?)</found_versions>")?;
+    let fixed = Regex::new(r"(?s)<fixed_versions[^>]*>(.*?)</fixed_versions>")?;
+    let pending = Regex::new(r"<pending[^>]*>([^<]*)</pending>")?;
+    let item = Regex::new(r"<item[^>]*>([^<]*)</item>")?;
+    let number = Regex::new(r"^(?:[^>]*>)?(\d+)<")?;
+
+    // One <key>number</key><value>...</value> pair per bug ("<keywords>" is a status field)
+    let mut reports = Vec::new();
+    for part in Regex::new(r"<key[ >]")?.split(response).skip(1) {
+        let Some(number) = number.captures(part).map(|c| c[1].to_string()) else {
+            continue;
+        };
+        let title = subject.captures(part).map(|c| xml_unescape(&c[1])).unwrap_or_default();
+        let found_in = found.captures(part).map(|c| c[1].to_string()).unwrap_or_default();
+        // "source/version"; an open bug with a fixed version is fixed in unstable or a point release
+        let fixed_in = fixed.captures(part).and_then(|c| {
+            let last = item.captures_iter(&c[1]).last()?;
+            Some(last[1].rsplit('/').next().unwrap_or_default().to_string())
+        });
+        reports.push(BugReport {
+            tracker: "Debian BTS",
+            id: format!("#{}", number),
+            mentions_version: mentions(&title, upstream) || mentions(&found_in, upstream),
+            title,
+            status: pending.captures(part).map(|c| c[1].to_string()).unwrap_or_else(|| "pending".to_string()),
+            url: format!("https://bugs.debian.org/{}", number),
+            fix_released: fixed_in.is_some(),
+            fixed_in,
+        });
+    }
+    Ok(reports)
+}
+
+fn soap_call(body: &str) -> Result<String> {
+    let envelope = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <soap:Envelope xmlns:soap=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         xmlns:soapenc=\"http://schemas.xmlsoap.org/soap/encoding/\" \
+         xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+         xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\"><soap:Body>{}</soap:Body></soap:Envelope>",
+        body
+    );
+    let headers = [("Content-Type", "text/xml; charset=utf-8"), ("SOAPAction", "\"Debbugs/SOAP\"")];
+    let response = http::post_body(DEBIAN_SOAP, &headers, envelope.into_bytes(), TIMEOUT)?;
+    if !response.is_success() {
+        anyhow::bail!("Debian BTS answered HTTP {}", response.status);
+    }
+    Ok(response.text())
+}
+
+#[derive(Deserialize)]
+struct BugzillaBugs {
+    bugs: Vec<BugzillaBug>,
+}
+
+#[derive(Deserialize)]
+struct BugzillaBug {
+    id: u64,
+    summary: String,
+    status: String,
+    #[serde(default)]
+    resolution: String,
+    /// Red Hat Bugzilla's "Fixed In Version", e.g. "mesa-24.1.1-1.fc40"
+    #[serde(default)]
+    cf_fixed_in: String,
+    #[serde(default)]
+    is_open: Option<bool>,
+}
+
+fn search_bugzilla(base: &str, package: &str, query: &str, upstream: &str) -> Result<Vec<BugReport>> {
+    // Open bugs, and ones closed because an update fixed them
+    let url = format!(
+        "{}/rest/bug?{}&resolution=---&resolution=ERRATA&resolution=CURRENTRELEASE&order=bug_id%20DESC&limit=50\
+         &include_fields=id,summary,status,resolution,cf_fixed_in,is_open",
+        base, query
+    );
+    let response = http::get(&url, TIMEOUT)?;
+    if !response.is_success() {
+        anyhow::bail!("{} answered HTTP {}", base, response.status);
+    }
+
+    let bugs: BugzillaBugs = response.json()?;
+    Ok(bugs
+        .bugs
+        .into_iter()
+        .map(|bug| {
+            let fixed_in = fixed_version(&bug.cf_fixed_in, package);
+            BugReport {
+                tracker: "Bugzilla",
+                id: format!("#{}", bug.id),
+                mentions_version: mentions(&bug.summary, upstream),
+                title: bug.summary,
+                fix_released: bug.is_open == Some(false) || matches!(bug.resolution.as_str(), "ERRATA" | "CURRENTRELEASE"),
+                status: match bug.resolution.as_str() {
+                    "" => bug.status,
+                    resolution => format!("{} {}", bug.status, resolution),
+                },
+                url: format!("{}/show_bug.cgi?id={}", base, bug.id),
+                fixed_in,
+            }
+        })
+        .filter(|report| !report.fix_released || report.mentions_version)
+        .collect())
+}
+
+/// The version out of a "Fixed In Version" field, which holds free text or package NVRs
+/// ("mesa-24.1.1-1.fc40 mesa-24.1.1-1.fc39")
+fn fixed_version(field: &str, package: &str) -> Option<String> {
+    let first = field.split([' ', ',']).find(|w| !w.is_empty())?;
+    let version = first.strip_prefix(package).and_then(|v| v.strip_prefix('-')).unwrap_or(first);
+    version.starts_with(|c: char| c.is_ascii_digit()).then(|| version.to_string())
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issue(state: &str, labels: &[&str]) -> GitlabIssue {
+        GitlabIssue {
+            iid: 12,
+            title: "mesa 24.1.0 breaks Xwayland".to_string(),
+            description: None,
+            state: state.to_string(),
+            web_url: "https://gitlab.archlinux.org/archlinux/packaging/packages/mesa/-/issues/12".to_string(),
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn closed_arch_issues_need_a_fixed_resolution() {
+        let fixed = arch_report(issue("closed", &["resolution::fixed"]), "24.1.0");
+        assert!(fixed.fix_released && fixed.mentions_version);
+        assert!(!arch_report(issue("closed", &["resolution::duplicate"]), "24.1.0").fix_released);
+        assert!(!arch_report(issue("opened", &[]), "24.1.0").fix_released);
+    }
+
+    #[test]
+    fn versions_from_trackers() {
+        assert_eq!(upstream_version("1:24.1.0-2ubuntu1"), "24.1.0");
+        assert_eq!(upstream_version("24.1.0"), "24.1.0");
+        assert_eq!(fixed_version("mesa-24.1.1-1.fc40 mesa-24.1.1-1.fc39", "mesa").as_deref(), Some("24.1.1-1.fc40"));
+        assert_eq!(fixed_version("24.1.1", "mesa").as_deref(), Some("24.1.1"));
+        assert_eq!(fixed_version("next release", "mesa"), None);
+    }
+
+    #[test]
+    fn reads_debian_status() {
+        let response = r#"<soap:Envelope><soap:Body><get_statusResponse><s-gensym3 xsi:type="apachens:Map">
+<item><key xsi:type="xsd:int">1068123</key><value>
+<subject xsi:type="xsd:string">mesa: crash in 24.1.0 with &lt;radeonsi&gt;</subject>
+<keywords xsi:type="xsd:string"></keywords>
+<pending xsi:type="xsd:string">done</pending>
+<found_versions soapenc:arrayType="xsd:anyType[1]"><item xsi:type="xsd:string">mesa/24.1.0-1</item></found_versions>
+<fixed_versions soapenc:arrayType="xsd:anyType[2]"><item xsi:type="xsd:string">mesa/24.1.1-1</item><item xsi:type="xsd:string">mesa/24.1.2-1</item></fixed_versions>
+</value></item>
+<item><key xsi:type="xsd:int">1070001</key><value>
+<subject xsi:type="xsd:string">mesa: typo in description</subject>
+<pending xsi:type="xsd:string">pending</pending>
+<found_versions soapenc:arrayType="xsd:anyType[0]"></found_versions>
+<fixed_versions soapenc:arrayType="xsd:anyType[0]"></fixed_versions>
+</value></item>
+</s-gensym3></get_statusResponse></soap:Body></soap:Envelope>"#;
+
+        let reports = parse_status(response, "24.1.0").unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].id, "#1068123");
+        assert_eq!(reports[0].title, "mesa: crash in 24.1.0 with <radeonsi>");
+        assert!(reports[0].mentions_version && reports[0].fix_released);
+        assert_eq!(reports[0].fixed_in.as_deref(), Some("24.1.2-1"));
+        assert_eq!(reports[0].status, "done");
+        assert!(!reports[1].mentions_version && !reports[1].fix_released);
+        assert_eq!(reports[1].fixed_in, None);
+    }
+}
@@ -0,0 +1,241 @@
+// Upgrades the package manager would install next, for checks before upgrading
+//
+// Each package manager is asked without changing anything: checkupdates (or pacman -Qu),
+// apt list --upgradable, dnf check-update and zypper list-updates. The list can also come
+// from a file, in the "name old -> new" form checkupdates prints.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Read;
+use std::process::{Command, Output};
+
+use crate::exec::CommandExt;
+use crate::package_diff;
+use crate::pins;
+
+/// One package the next upgrade would change
+#[derive(Debug, Clone)]
+pub struct PendingUpgrade {
+    pub name: String,
+    /// Installed version, when known
+    pub from: Option<String>,
+    pub to: String,
+}
+
+/// Pending upgrades on the running system
+pub fn pending(distro: &str) -> Result<Vec<PendingUpgrade>> {
+    match distro {
+        "arch" | "manjaro" | "endeavouros" => {
+            // checkupdates syncs a throwaway database copy, so the list is fresh without root.
+            // It exits 2 when there is nothing to upgrade and 1 when it failed.
+            match Command::new("checkupdates").run_output() {
+                Ok(output) if output.status.code() == Some(2) => return Ok(Vec::new()),
+                Ok(output) if output.status.code() != Some(127) => {
+                    let output = succeeded("checkupdates", output)?;
+                    return Ok(parse_arrow_list(&String::from_utf8_lossy(&output.stdout)));
+                }
+                // Not installed (it's in pacman-contrib)
+                _ => {}
+            }
+
+            // pacman -Qu exits 1 both when nothing is upgradable and when it fails
+            let output = Command::new("pacman").arg("-Qu").run_output().context("Failed to run pacman -Qu")?;
+            if output.status.code() == Some(1) && output.stdout.is_empty() && output.stderr.is_empty() {
+                return Ok(Vec::new());
+            }
+            let output = succeeded("pacman -Qu", output)?;
+            Ok(parse_arrow_list(&String::from_utf8_lossy(&output.stdout)))
+        }
+        "ubuntu" | "debian" | "linuxmint" | "pop" => {
+            let output = Command::new("apt")
+                .args(["list", "--upgradable"])
+                .env("LC_ALL", "C")
+                .run_output()
+                .context("Failed to run apt list --upgradable")?;
+            let output = succeeded("apt list --upgradable", output)?;
+            Ok(parse_apt(&String::from_utf8_lossy(&output.stdout)))
+        }
+        "fedora" | "rhel" | "centos" => {
+            // Exit code 100 means updates are available
+            let output = Command::new("dnf")
+                .args(["check-update", "-q"])
+                .run_output()
+                .context("Failed to run dnf check-update")?;
+            if !matches!(output.status.code(), Some(0) | Some(100)) {
+                anyhow::bail!("dnf check-update failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+            let installed = package_diff::detect_current_packages()?;
+            Ok(parse_dnf(&String::from_utf8_lossy(&output.stdout), |name| installed.get(name).cloned()))
+        }
+        other if pins::is_zypper(other) => {
+            let output = Command::new("zypper")
+                .args(["--non-interactive", "--quiet", "list-updates"])
+                .run_output()
+                .context("Failed to run zypper list-updates")?;
+            let output = succeeded("zypper list-updates", output)?;
+            Ok(parse_zypper(&String::from_utf8_lossy(&output.stdout)))
+        }
+        other => anyhow::bail!("Don't know how to list pending upgrades on {}; pass the list as a file", other),
+    }
+}
+
+/// `output` if the command exited 0, else an error with what it printed to stderr
+fn succeeded(command: &str, output: Output) -> Result<Output> {
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} failed ({}): {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output)
+}
+
+/// Pending upgrades from a file ("-" for stdin): "name old -> new" or "name new" per line
+pub fn from_file(path: &str) -> Result<Vec<PendingUpgrade>> {
+    let text = if path == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?
+    };
+    Ok(parse_arrow_list(&text))
+}
+
+fn parse_arrow_list(text: &str) -> Vec<PendingUpgrade> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [name, from, "->", to, ..] => Some(PendingUpgrade {
+                    name: name.to_string(),
+                    from: Some(from.to_string()),
+                    to: to.to_string(),
+                }),
+                [name, to] => Some(PendingUpgrade {
+                    name: name.to_string(),
+                    from: None,
+                    to: to.to_string(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// `apt list --upgradable`: "name/suite new arch [upgradable from: old]"
+fn parse_apt(text: &str) -> Vec<PendingUpgrade> {
+    text.lines()
+        .filter_map(|line| {
+            let (name, rest) = line.split_once('/')?;
+            let to = rest.split_whitespace().nth(1)?;
+            let from = line
+                .split_once("[upgradable from: ")
+                .map(|(_, old)| old.trim_end_matches(']').to_string());
+            Some(PendingUpgrade {
+                name: name.to_string(),
+                from,
+                to: to.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// `dnf check-update`: "name.arch version repo", until the obsoletes section
+fn parse_dnf(text: &str, installed: impl Fn(&str) -> Option<String>) -> Vec<PendingUpgrade> {
+    text.lines()
+        .take_while(|line| !line.starts_with("Obsoleting"))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name_arch, to, _repo] = fields.as_slice() else {
+                return None;
+            };
+            let name = name_arch.rsplit_once('.').map_or(*name_arch, |(name, _)| name);
+            Some(PendingUpgrade {
+                name: name.to_string(),
+                from: installed(name),
+                to: to.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// `zypper list-updates`: "S | Repository | Name | Current Version | Available Version | Arch"
+fn parse_zypper(text: &str) -> Vec<PendingUpgrade> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+            match fields.as_slice() {
+                ["v", _, name, from, to, ..] => Some(PendingUpgrade {
+                    name: name.to_string(),
+                    from: Some(from.to_string()),
+                    to: to.to_string(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triples(upgrades: &[PendingUpgrade]) -> Vec<(&str, Option<&str>, &str)> {
+        upgrades.iter().map(|u| (u.name.as_str(), u.from.as_deref(), u.to.as_str())).collect()
+    }
+
+    #[test]
+    fn checkupdates_and_pacman() {
+        let text = "linux 6.8.9.arch1-1 -> 6.9.1.arch1-1\nmesa 1:24.0.5-1 -> 1:24.0.6-1 [ignored]\nfirefox 126.0\n";
+        assert_eq!(
+            triples(&parse_arrow_list(text)),
+            vec![
+                ("linux", Some("6.8.9.arch1-1"), "6.9.1.arch1-1"),
+                ("mesa", Some("1:24.0.5-1"), "1:24.0.6-1"),
+                ("firefox", None, "126.0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn apt_list() {
+        let text = "Listing...\n\
+                    libc6/stable-security 2.36-9+deb12u7 amd64 [upgradable from: 2.36-9+deb12u4]\n\
+                    firefox-esr/stable 115.11.0esr-1~deb12u1 amd64\n";
+        assert_eq!(
+            triples(&parse_apt(text)),
+            vec![
+                ("libc6", Some("2.36-9+deb12u4"), "2.36-9+deb12u7"),
+                ("firefox-esr", None, "115.11.0esr-1~deb12u1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn dnf_check_update() {
+        let text = "\n\
+                    kernel-core.x86_64    6.8.10-300.fc40    updates\n\
+                    python3.11.x86_64     3.11.9-1.fc40      updates\n\
+                    Obsoleting Packages\n\
+                    grub2-tools.x86_64    1:2.06-121.fc40    updates\n";
+        let upgrades = parse_dnf(text, |name| (name == "kernel-core").then(|| "6.8.9-300.fc40".to_string()));
+        assert_eq!(
+            triples(&upgrades),
+            vec![
+                ("kernel-core", Some("6.8.9-300.fc40"), "6.8.10-300.fc40"),
+                ("python3.11", None, "3.11.9-1.fc40"),
+            ]
+        );
+    }
+
+    #[test]
+    fn zypper_list_updates() {
+        let text = "S | Repository | Name   | Current Version | Available Version | Arch\n\
+                    --+------------+--------+-----------------+-------------------+-------\n\
+                    v | repo-oss   | Mesa   | 24.0.5-1.1      | 24.0.6-1.1        | x86_64\n";
+        assert_eq!(triples(&parse_zypper(text)), vec![("Mesa", Some("24.0.5-1.1"), "24.0.6-1.1")]);
+    }
+}
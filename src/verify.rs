@@ -0,0 +1,58 @@
+// Signature checks for package files eshu-trace downloads itself
+//
+// Packages the package manager fetches (apt-get download, dnf downgrade) are already
+// checked against the signed repo metadata. Files eshu-trace pulls straight from the Arch
+// Linux Archive are not: pacman only checks a detached signature when one sits next to the
+// file, and the kernel bisect unpacks kernels without pacman at all. So a downloaded file
+// must come with its .sig and pass `pacman-key --verify` against the pacman keyring, or it
+// is deleted and refused.
+
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::TraceError;
+use crate::exec::CommandExt;
+
+/// Detached signature path: `<file>.sig`
+pub fn signature_path(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+/// Check a downloaded pacman package against its detached signature; on failure both
+/// files are removed so nothing unverified is left in the cache
+pub fn pacman_package(file: &Path) -> Result<()> {
+    let sig = signature_path(file);
+    let reason = if !sig.exists() {
+        Some("the archive has no signature for it".to_string())
+    } else {
+        match Command::new("pacman-key").arg("--verify").arg(&sig).arg(file).run_output() {
+            Ok(output) if output.status.success() => None,
+            Ok(output) => Some(
+                String::from_utf8_lossy(&output.stderr)
+                    .lines()
+                    .find(|l| !l.trim().is_empty())
+                    .unwrap_or("bad signature")
+                    .trim()
+                    .to_string(),
+            ),
+            Err(e) => Some(format!("pacman-key could not run: {}", e)),
+        }
+    };
+
+    match reason {
+        None => Ok(()),
+        Some(reason) => {
+            let _ = fs::remove_file(file);
+            let _ = fs::remove_file(&sig);
+            Err(TraceError::VerificationFailed {
+                file: file.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                reason,
+            }
+            .into())
+        }
+    }
+}
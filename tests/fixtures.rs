@@ -0,0 +1,122 @@
+// diff and bisect --machine against the fake snapshot roots in fixtures/
+//
+// Runs the real binary with ESHU_TRACE_FIXTURE_ROOT pointing at one distro's good/bad
+// pair and a throwaway HOME, so nothing on the host is read or written.
+
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn eshu_trace(fixture: &str, home: &Path) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_eshu-trace"));
+    cmd.env("ESHU_TRACE_FIXTURE_ROOT", Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(fixture))
+        .env("HOME", home)
+        .env_remove("XDG_STATE_HOME")
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("XDG_CACHE_HOME")
+        .env_remove("ESHU_TRACE_COMMAND_FIXTURES")
+        .env("NO_COLOR", "1");
+    cmd
+}
+
+fn diff(fixture: &str) -> String {
+    let home = tempfile::tempdir().unwrap();
+    let output: Output = eshu_trace(fixture, home.path()).args(["diff", "good", "bad"]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Name of a change as the machine protocol serializes it ({"Upgraded": [package, old, new]}, {"Added": package}, ...)
+fn change_name(change: &Value) -> &str {
+    let (_, inner) = change.as_object().and_then(|o| o.iter().next()).expect("change");
+    let package = if inner.is_array() { &inner[0] } else { inner };
+    package["name"].as_str().expect("package name")
+}
+
+/// Drive `bisect --machine`, answering "issue" whenever `culprit` is among the applied
+/// changes. Returns the final event and the number of steps answered.
+fn bisect(fixture: &str, culprit: &str) -> (Value, usize) {
+    let home = tempfile::tempdir().unwrap();
+    let mut child = eshu_trace(fixture, home.path())
+        .args(["bisect", "--machine", "--good", "good", "--bad", "bad", "--no-notify", "--no-prefetch", "--no-ai"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+
+    let mut issue = false;
+    let mut steps = 0;
+    let mut last = Value::Null;
+    for line in stdout.lines() {
+        let event: Value = serde_json::from_str(&line.unwrap()).expect("one JSON object per line");
+        match event["event"].as_str() {
+            Some("step") => {
+                let apply = event["apply"].as_array().expect("apply");
+                issue = apply.iter().any(|c| change_name(c) == culprit);
+            }
+            Some("verdict_request") => {
+                steps += 1;
+                writeln!(stdin, "{}", if issue { "issue" } else { "ok" }).unwrap();
+            }
+            _ => {}
+        }
+        last = event;
+    }
+
+    assert!(child.wait().unwrap().success());
+    (last, steps)
+}
+
+#[test]
+fn diff_arch() {
+    let out = diff("arch");
+    assert!(out.contains("+ vulkan-radeon 1:24.1.0-1"), "{}", out);
+    assert!(out.contains("mesa 1:24.0.6-1 → 1:24.1.0-1"), "{}", out);
+    assert!(out.contains("linux 6.8.9.arch1-1 → 6.9.1.arch1-1"), "{}", out);
+}
+
+#[test]
+fn diff_debian() {
+    let out = diff("debian");
+    assert!(out.contains("+ firmware-amd-graphics 20230210-5"), "{}", out);
+    assert!(out.contains("openssl 3.0.11-1~deb12u2 → 3.0.13-1~deb12u1"), "{}", out);
+}
+
+#[cfg(feature = "rpmdb")]
+#[test]
+fn diff_fedora_rpmdb() {
+    let out = diff("fedora");
+    assert!(out.contains("+ wireplumber 0.5.3-1.fc40"), "{}", out);
+    assert!(out.contains("kernel-core 6.8.11-300.fc40 → 6.9.4-200.fc40"), "{}", out);
+    assert!(out.contains("Total changes: 5"), "{}", out);
+}
+
+#[test]
+fn bisect_machine_arch() {
+    let (finished, steps) = bisect("arch", "linux");
+    assert_eq!(finished["event"], "finished");
+    assert_eq!(change_name(&finished["culprit"]), "linux");
+    assert!(steps >= 1);
+}
+
+#[test]
+fn bisect_machine_debian() {
+    let (finished, _) = bisect("debian", "openssl");
+    assert_eq!(finished["event"], "finished");
+    assert_eq!(change_name(&finished["culprit"]), "openssl");
+}
+
+#[cfg(feature = "rpmdb")]
+#[test]
+fn bisect_machine_fedora_rpmdb() {
+    // The mesa packages are coupled: the first of the set is reported
+    let (finished, _) = bisect("fedora", "mesa-vulkan-drivers");
+    assert_eq!(finished["event"], "finished");
+    assert_eq!(change_name(&finished["culprit"]), "mesa-dri-drivers");
+    let group: Vec<&str> = finished["group"].as_array().unwrap().iter().map(change_name).collect();
+    assert_eq!(group, ["mesa-dri-drivers", "mesa-vulkan-drivers"]);
+}